@@ -0,0 +1,173 @@
+use crate::audio::AudioError;
+use crate::stt::{SttError, SttProvider};
+use crate::{BotConfig, BotError};
+use tracing::{error, warn};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+
+/// Conversions failing this many times in a row point at ffmpeg or the
+/// host rather than at the files.
+const CONVERSION_FAILURE_ALERT: u32 = 3;
+
+/// Operational problems sent to `ADMIN_CHAT_ID`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alert {
+    ProviderAuth(SttProvider, String),
+    ConversionFailures(u32, String),
+    QueueFull,
+    DiskFull(String),
+    LowDiskSpace(u64, u64),
+}
+
+impl Alert {
+    /// Alerts with the same key are deduplicated.
+    fn key(&self) -> String {
+        match self {
+            Alert::ProviderAuth(provider, _) => format!("auth:{}", provider.as_str()),
+            Alert::ConversionFailures(..) => "conversion".to_string(),
+            Alert::QueueFull => "queue-full".to_string(),
+            Alert::DiskFull(_) => "disk-full".to_string(),
+            Alert::LowDiskSpace(..) => "disk-low".to_string(),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Alert::ProviderAuth(provider, error) => format!(
+                "🔑 Provider '{}' rejected its credentials ({}). Check its API key.",
+                provider.as_str(),
+                error
+            ),
+            Alert::ConversionFailures(count, error) => format!(
+                "🎛 {} audio conversions failed in a row, last: {}. Check ffmpeg on the host.",
+                count, error
+            ),
+            Alert::QueueFull => "🚦 The queue is full; new files are being turned away or dropped.".to_string(),
+            Alert::DiskFull(error) => format!("💾 Out of disk space: {}. Media can't be downloaded or converted.", error),
+            Alert::LowDiskSpace(free_mb, min_mb) => format!(
+                "💾 Only {} MB of disk space left, under MIN_FREE_DISK_MB ({} MB); new files are being turned away.",
+                free_mb, min_mb
+            ),
+        }
+    }
+}
+
+/// When each alert was last sent and how many repeats were held back since.
+#[derive(Debug, Default)]
+struct Throttle {
+    sent: HashMap<String, (Instant, u32)>,
+}
+
+impl Throttle {
+    /// Whether an alert with `key` goes out now; returns the number of
+    /// repeats suppressed since the last one sent.
+    fn admit(&mut self, key: String, now: Instant, interval: Duration) -> Option<u32> {
+        match self.sent.get_mut(&key) {
+            Some((last, suppressed)) if now.duration_since(*last) < interval => {
+                *suppressed += 1;
+                None
+            }
+            Some((last, suppressed)) => {
+                *last = now;
+                Some(std::mem::take(suppressed))
+            }
+            None => {
+                self.sent.insert(key, (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+static THROTTLE: Mutex<Option<Throttle>> = Mutex::new(None);
+static CONVERSION_FAILURES: AtomicU32 = AtomicU32::new(0);
+
+/// Sends `alert` to the admin chat, unless the same one went out within
+/// `ALERT_INTERVAL_MINUTES`.
+pub async fn send(bot: &Bot, config: &BotConfig, alert: Alert) {
+    let Some(chat_id) = config.admin_chat_id else {
+        return;
+    };
+    let suppressed = {
+        let mut throttle = THROTTLE.lock().expect("alert throttle lock poisoned");
+        throttle.get_or_insert_with(Throttle::default).admit(
+            alert.key(),
+            Instant::now(),
+            Duration::from_secs(config.alert_interval_minutes * 60),
+        )
+    };
+    let Some(suppressed) = suppressed else {
+        return;
+    };
+
+    let mut text = alert.text();
+    if suppressed > 0 {
+        text.push_str(&format!(" (repeated {} more time{} since the last alert)", suppressed, if suppressed == 1 { "" } else { "s" }));
+    }
+    warn!("Admin alert: {}", text);
+    if let Err(e) = bot.send_message(chat_id, text).await {
+        error!("Failed to send alert to admin chat {}: {}", chat_id, e);
+    }
+}
+
+fn is_disk_full(error: &BotError) -> bool {
+    let io_error = match error {
+        BotError::Io(e) | BotError::Audio(AudioError::Io(e)) => e,
+        BotError::Download(teloxide::DownloadError::Io(e)) => e,
+        _ => return false,
+    };
+    io_error.kind() == std::io::ErrorKind::StorageFull
+}
+
+/// Raises an alert if a failed download or transcription points at an
+/// operational problem: bad provider credentials, disk space, or ffmpeg.
+pub async fn on_failure(bot: &Bot, config: &BotConfig, error: &BotError, provider: Option<SttProvider>) {
+    let alert = match error {
+        _ if is_disk_full(error) => Some(Alert::DiskFull(error.to_string())),
+        BotError::Stt(SttError::Authentication) => provider.map(|p| Alert::ProviderAuth(p, error.to_string())),
+        BotError::Audio(AudioError::ConversionFailed(_) | AudioError::Timeout(_) | AudioError::FfmpegNotFound) => {
+            let count = CONVERSION_FAILURES.fetch_add(1, Ordering::Relaxed) + 1;
+            (count >= CONVERSION_FAILURE_ALERT).then(|| Alert::ConversionFailures(count, error.to_string()))
+        }
+        _ => None,
+    };
+    if let Some(alert) = alert {
+        send(bot, config, alert).await;
+    }
+}
+
+/// Ends a run of conversion failures.
+pub fn on_success() {
+    CONVERSION_FAILURES.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::default();
+        let now = Instant::now();
+        let interval = Duration::from_secs(600);
+
+        assert_eq!(throttle.admit("queue-full".to_string(), now, interval), Some(0));
+        assert_eq!(throttle.admit("queue-full".to_string(), now + Duration::from_secs(10), interval), None);
+        assert_eq!(throttle.admit("queue-full".to_string(), now + Duration::from_secs(20), interval), None);
+        // Other alerts aren't held back by it
+        assert_eq!(throttle.admit("disk-full".to_string(), now, interval), Some(0));
+        assert_eq!(throttle.admit("queue-full".to_string(), now + interval, interval), Some(2));
+        assert_eq!(throttle.admit("queue-full".to_string(), now + interval * 2, interval), Some(0));
+    }
+
+    #[test]
+    fn test_disk_full() {
+        let full = std::io::Error::from(std::io::ErrorKind::StorageFull);
+        assert!(is_disk_full(&BotError::Io(full)));
+        assert!(!is_disk_full(&BotError::Io(std::io::Error::from(std::io::ErrorKind::NotFound))));
+        assert!(!is_disk_full(&BotError::QueueFull));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{BotError, CurrentProvider, SharedConfig, audio, cost, persistence, quota, stt};
+use tracing::{error, info, warn};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// Upload limit when `MAX_FILE_SIZE_MB` is unset; the body is held in memory.
+const DEFAULT_MAX_UPLOAD_MB: u64 = 200;
+
+/// API requests are transcribed one at a time, next to the bot's queue.
+static API_SLOTS: Semaphore = Semaphore::const_new(1);
+
+/// What `POST /api/transcribe` needs from the running bot.
+#[derive(Clone)]
+pub struct ApiState {
+    pub config: SharedConfig,
+    pub current_provider: CurrentProvider,
+    pub costs: cost::CostStore,
+}
+
+#[derive(Deserialize, Default)]
+struct TranscribeQuery {
+    /// Original file name; its extension helps ffmpeg guess the format.
+    filename: Option<String>,
+    /// ISO 639-1 code of the spoken language; auto-detected when unset.
+    language: Option<String>,
+    /// Provider to use instead of the current one.
+    provider: Option<String>,
+}
+
+/// A failed request: status code and message for the JSON body.
+#[derive(Debug, PartialEq)]
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn reply(self) -> warp::reply::Response {
+        warp::reply::with_status(warp::reply::json(&serde_json::json!({ "error": self.1 })), self.0).into_response()
+    }
+}
+
+impl From<BotError> for ApiError {
+    fn from(e: BotError) -> Self {
+        let status = match &e {
+            BotError::Audio(audio::AudioError::UnsupportedFormat(_)) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            BotError::Audio(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            BotError::BudgetExhausted => StatusCode::SERVICE_UNAVAILABLE,
+            BotError::TooLong(..) => StatusCode::PAYLOAD_TOO_LARGE,
+            BotError::Stt(stt::SttError::RateLimit) => StatusCode::TOO_MANY_REQUESTS,
+            BotError::Stt(stt::SttError::Timeout) => StatusCode::GATEWAY_TIMEOUT,
+            BotError::Stt(_) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, e.to_string())
+    }
+}
+
+/// A request turned away before its body is read.
+#[derive(Debug)]
+struct Refused(ApiError);
+
+impl warp::reject::Reject for Refused {}
+
+/// Checks an `Authorization: Bearer <API_TOKEN>` header. The API is off
+/// while no token is configured.
+fn authorize(expected: Option<&str>, header: Option<&str>) -> Result<(), ApiError> {
+    let Some(expected) = expected else {
+        return Err(ApiError(StatusCode::NOT_FOUND, "the API is disabled; set API_TOKEN to enable it".to_string()));
+    };
+    match header.and_then(|h| h.strip_prefix("Bearer ")) {
+        Some(token) if token.trim() == expected => Ok(()),
+        _ => Err(ApiError(StatusCode::UNAUTHORIZED, "missing or wrong bearer token".to_string())),
+    }
+}
+
+/// `POST /api/transcribe`: the request body is the media file, and the
+/// reply is the transcript as JSON. The token is checked before the body
+/// is read into memory.
+pub fn routes(state: ApiState, max_file_size_mb: Option<u32>) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    let max_bytes = max_file_size_mb.map_or(DEFAULT_MAX_UPLOAD_MB, u64::from) * 1024 * 1024;
+    let config = state.config.clone();
+    let authorized = warp::header::optional::<String>("authorization")
+        .and_then(move |auth: Option<String>| {
+            let config = config.clone();
+            async move {
+                authorize(config.read().await.api_token.as_deref(), auth.as_deref())
+                    .map_err(|e| warp::reject::custom(Refused(e)))
+            }
+        })
+        .untuple_one();
+    warp::path!("api" / "transcribe")
+        .and(warp::post())
+        .and(authorized)
+        .and(warp::query::<TranscribeQuery>())
+        .and(warp::body::content_length_limit(max_bytes))
+        .and(warp::body::bytes())
+        .then(move |query: TranscribeQuery, body: bytes::Bytes| {
+            let state = state.clone();
+            async move {
+                match transcribe(&state, query, body).await {
+                    Ok(reply) => warp::reply::json(&reply).into_response(),
+                    Err(e) => e.reply(),
+                }
+            }
+        })
+        .recover(|rejection: Rejection| async move {
+            match rejection.find::<Refused>() {
+                Some(Refused(ApiError(status, message))) => Ok(ApiError(*status, message.clone()).reply()),
+                None => Err(rejection),
+            }
+        })
+        .unify()
+}
+
+async fn transcribe(state: &ApiState, query: TranscribeQuery, body: bytes::Bytes) -> Result<serde_json::Value, ApiError> {
+    let config = state.config.read().await.clone();
+    if body.is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "the request body must be the media file".to_string()));
+    }
+
+    let language = match query.language.as_deref().map(str::trim).filter(|l| !l.is_empty() && *l != "auto") {
+        Some(language) => Some(
+            stt::language::code(language)
+                .ok_or_else(|| ApiError(StatusCode::BAD_REQUEST, format!("unknown language '{}'", language)))?,
+        ),
+        None => None,
+    };
+    let preferred = match query.provider.as_deref() {
+        Some(name) => stt::SttProvider::from_str(name)
+            .filter(|&p| config.has_provider_key(p))
+            .ok_or_else(|| ApiError(StatusCode::BAD_REQUEST, format!("unknown or unconfigured provider '{}'", name)))?,
+        None => *state.current_provider.read().await,
+    };
+    let provider = {
+        let mut costs = state.costs.write().await;
+        costs.roll_month(&quota::current_month());
+        costs.choose_provider(preferred, &config).ok_or(BotError::BudgetExhausted)?
+    };
+
+    let _slot = API_SLOTS.acquire().await.map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+    let filename = query.filename.unwrap_or_else(|| "upload".to_string());
+    info!("API transcription of {} ({} bytes) via {}", filename, body.len(), provider.as_str());
+
+    let input = crate::janitor::temp_file().map_err(BotError::Io)?;
+    tokio::fs::write(input.path(), &body).await.map_err(BotError::Io)?;
+    let probed_secs = match audio::analyze::probe_duration(input.path(), config.ffmpeg_timeout()).await {
+        Ok(secs) => Some(secs),
+        Err(e) => {
+            warn!("Failed to probe the duration of API upload {}: {}", filename, e);
+            None
+        }
+    };
+    if let Some(max_secs) = config.max_duration_secs
+        && let Some(secs) = probed_secs
+        && secs.round() as u32 > max_secs
+    {
+        return Err(BotError::TooLong(secs.round() as u32, max_secs).into());
+    }
+    let converted = audio::convert_for_stt(
+        input.path(),
+        &filename,
+        provider,
+        audio::ConversionOptions {
+            timeout: config.ffmpeg_timeout(),
+            preprocess: config.audio_preprocess,
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(BotError::Audio)?;
+    // samples_from_converted keeps one channel, and can't read FLAC
+    let duration_secs = probed_secs.unwrap_or_else(|| {
+        audio::analyze::samples_from_converted(&converted)
+            .map_or(0.0, |samples| samples.len() as f32 / converted.sample_rate as f32)
+    });
+
+    let options = stt::SttOptions { language, ..stt::SttOptions::from_config(&config) };
+    let transcription = stt::transcribe(&converted, provider, &config, &options).await.map_err(BotError::Stt)?;
+
+    {
+        let mut costs = state.costs.write().await;
+        let estimate = costs.record(provider, duration_secs.ceil() as u64, &config);
+        info!("Estimated cost for API transcription: ${:.4} via {}", estimate, provider.as_str());
+        if let Err(e) = persistence::save_costs(&costs).await {
+            error!("Failed to save cost tracking for an API transcription: {}", e);
+        }
+    }
+
+    let mut reply = serde_json::to_value(&transcription).map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    reply["provider"] = provider.as_str().into();
+    reply["model"] = provider.model_for(&options).into();
+    reply["duration_secs"] = duration_secs.into();
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        assert_eq!(authorize(None, Some("Bearer x")).unwrap_err().0, StatusCode::NOT_FOUND);
+        assert_eq!(authorize(Some("s3cret"), None).unwrap_err().0, StatusCode::UNAUTHORIZED);
+        assert_eq!(authorize(Some("s3cret"), Some("Bearer nope")).unwrap_err().0, StatusCode::UNAUTHORIZED);
+        assert_eq!(authorize(Some("s3cret"), Some("s3cret")).unwrap_err().0, StatusCode::UNAUTHORIZED);
+        assert!(authorize(Some("s3cret"), Some("Bearer s3cret")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_token_checked_before_body() {
+        let config = crate::BotConfig { api_token: Some("s3cret".to_string()), ..crate::BotConfig::for_tests() };
+        let state = ApiState {
+            config: std::sync::Arc::new(tokio::sync::RwLock::new(config)),
+            current_provider: std::sync::Arc::new(tokio::sync::RwLock::new(stt::SttProvider::Whisper)),
+            costs: Default::default(),
+        };
+        let routes = routes(state, Some(1));
+
+        // Over the upload limit, but refused for the token first
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/transcribe")
+            .body(vec![0u8; 2 * 1024 * 1024])
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/transcribe")
+            .header("authorization", "Bearer s3cret")
+            .body(vec![0u8; 2 * 1024 * 1024])
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_error_status() {
+        let unsupported = BotError::Audio(audio::AudioError::UnsupportedFormat("txt".to_string()));
+        assert_eq!(ApiError::from(unsupported).0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(ApiError::from(BotError::Stt(stt::SttError::RateLimit)).0, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(ApiError::from(BotError::BudgetExhausted).0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
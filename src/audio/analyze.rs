@@ -0,0 +1,416 @@
+use super::{AudioError, ConvertedAudio, TimeRange};
+use super::convert::run_ffmpeg;
+use tracing::debug;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Sample rate used for content analysis; energy heuristics don't need more.
+pub const ANALYSIS_SAMPLE_RATE: u32 = 8000;
+
+/// Frames are 20 ms long; LSTER windows span one second (50 frames).
+const FRAMES_PER_SECOND: usize = 50;
+
+/// Below this level (dBFS) a frame is treated as silence.
+const SILENCE_FLOOR_DB: f32 = -50.0;
+
+/// Music rarely drops far below its local average energy, speech does so
+/// between every few syllables. Below this LSTER a clip is considered music.
+const MUSIC_LSTER_THRESHOLD: f32 = 0.12;
+
+/// Clips shorter than this are too short to classify reliably.
+const MIN_CLASSIFY_SECONDS: f32 = 8.0;
+
+/// Recordings quieter than this overall (dBFS) are treated as silent
+/// unless `SILENCE_THRESHOLD_DB` says otherwise.
+pub const DEFAULT_SILENCE_THRESHOLD_DB: f32 = -55.0;
+
+/// Recordings with less non-silent audio than this are treated as silent.
+const MIN_ACTIVE_SECONDS: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioStats {
+    pub duration_secs: f32,
+    /// Overall RMS level in dBFS.
+    pub rms_db: f32,
+    /// Fraction of frames above the silence floor.
+    pub active_ratio: f32,
+    /// Low short-time energy ratio averaged over non-silent one-second windows.
+    pub low_energy_ratio: f32,
+    /// Hash of the decoded samples; copies of a clip match however they
+    /// were sent.
+    pub fingerprint: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentClass {
+    Speech,
+    Music,
+}
+
+/// In a call recording each party is much louder on their own channel.
+/// A frame counts as one-sided when the channels differ by at least this much.
+const CHANNEL_DOMINANCE_DB: f32 = 10.0;
+
+/// Share of active frames that must be one-sided to treat channels as
+/// separate speakers.
+const MIN_ONE_SIDED_RATIO: f32 = 0.6;
+
+/// Decodes any input ffmpeg understands into mono s16le samples at
+/// `ANALYSIS_SAMPLE_RATE`.
+pub async fn decode_for_analysis(
+    input_path: &Path,
+    time_range: Option<TimeRange>,
+    timeout: Option<Duration>,
+) -> Result<Vec<i16>, AudioError> {
+    decode_pcm(input_path, time_range, 1, timeout).await
+}
+
+/// Like `decode_for_analysis` but keeps two channels, returned separately.
+/// Mono sources come back as two identical channels.
+pub async fn decode_stereo_for_analysis(
+    input_path: &Path,
+    time_range: Option<TimeRange>,
+    timeout: Option<Duration>,
+) -> Result<(Vec<i16>, Vec<i16>), AudioError> {
+    let interleaved = decode_pcm(input_path, time_range, 2, timeout).await?;
+    Ok(interleaved
+        .chunks_exact(2)
+        .map(|frame| (frame[0], frame[1]))
+        .unzip())
+}
+
+/// Length of the media in seconds, from ffprobe, or by decoding it where
+/// ffprobe isn't installed.
+pub async fn probe_duration(input_path: &Path, timeout: Option<Duration>) -> Result<f32, AudioError> {
+    let mut cmd = Command::new("ffprobe");
+    cmd.arg("-v").arg("error")
+        .arg("-show_entries").arg("format=duration")
+        .arg("-of").arg("default=noprint_wrappers=1:nokey=1")
+        .arg(input_path);
+    match run_ffmpeg(cmd, timeout).await {
+        Err(AudioError::FfmpegNotFound) => {}
+        output => {
+            let output = output?;
+            return String::from_utf8_lossy(&output.stdout).trim().parse::<f32>().map_err(|_| {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                AudioError::ConversionFailed(format!("FFprobe found no duration: {}", stderr.trim()))
+            });
+        }
+    }
+
+    let samples = decode_for_analysis(input_path, None, timeout).await?;
+    Ok(samples.len() as f32 / ANALYSIS_SAMPLE_RATE as f32)
+}
+
+async fn decode_pcm(
+    input_path: &Path,
+    time_range: Option<TimeRange>,
+    channels: u8,
+    timeout: Option<Duration>,
+) -> Result<Vec<i16>, AudioError> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-hide_banner")
+        .arg("-loglevel").arg("error");
+
+    if let Some(range) = time_range {
+        range.apply_input_args(&mut cmd);
+    }
+
+    cmd.arg("-i").arg(input_path)
+        .arg("-vn")
+        .arg("-ac").arg(channels.to_string())
+        .arg("-ar").arg(ANALYSIS_SAMPLE_RATE.to_string())
+        .arg("-f").arg("s16le")
+        .arg("-");
+    let output = match run_ffmpeg(cmd, timeout).await {
+        Err(AudioError::FfmpegNotFound) => {
+            let input_path = input_path.to_path_buf();
+            return super::decode::run_without_ffmpeg(move || {
+                super::decode::decode(&input_path, None, time_range, ANALYSIS_SAMPLE_RATE, channels, None)
+            }).await;
+        }
+        output => output?,
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AudioError::ConversionFailed(format!("FFmpeg analysis decode failed: {}", stderr)));
+    }
+
+    Ok(pcm_to_samples(&output.stdout))
+}
+
+pub fn pcm_to_samples(pcm: &[u8]) -> Vec<i16> {
+    pcm.chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Extracts 16-bit samples from converted audio without another ffmpeg run.
+/// Returns None for compressed formats (e.g. FLAC).
+pub fn samples_from_converted(audio: &ConvertedAudio) -> Option<Vec<i16>> {
+    let samples = match audio.format.as_str() {
+        "pcm" => pcm_to_samples(&audio.data),
+        "wav" => pcm_to_samples(wav_data_chunk(&audio.data)?),
+        _ => return None,
+    };
+
+    // Analysis only needs one channel
+    if audio.channels > 1 {
+        return Some(samples.into_iter().step_by(audio.channels as usize).collect());
+    }
+    Some(samples)
+}
+
+/// Averages two channels into one.
+pub fn downmix(left: &[i16], right: &[i16]) -> Vec<i16> {
+    left.iter()
+        .zip(right)
+        .map(|(&l, &r)| ((l as i32 + r as i32) / 2) as i16)
+        .collect()
+}
+
+/// Returns the payload of the `data` chunk of a RIFF/WAVE file.
+fn wav_data_chunk(wav: &[u8]) -> Option<&[u8]> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut pos = 12;
+    while pos + 8 <= wav.len() {
+        let id = &wav[pos..pos + 4];
+        let size = u32::from_le_bytes([wav[pos + 4], wav[pos + 5], wav[pos + 6], wav[pos + 7]]) as usize;
+        let body = pos + 8;
+        if id == b"data" {
+            // ffmpeg writes a placeholder size when streaming; clamp to what we have
+            return Some(&wav[body..wav.len().min(body.saturating_add(size))]);
+        }
+        pos = body + size + (size & 1);
+    }
+    None
+}
+
+/// Mean-square energy (normalized to 0..1) of each 20 ms frame.
+fn frame_energies(samples: &[i16], sample_rate: u32) -> Vec<f32> {
+    let frame_len = (sample_rate as usize / FRAMES_PER_SECOND).max(1);
+    samples
+        .chunks(frame_len)
+        .map(|frame| {
+            let sum: f32 = frame
+                .iter()
+                .map(|&s| {
+                    let v = s as f32 / i16::MAX as f32;
+                    v * v
+                })
+                .sum();
+            sum / frame.len() as f32
+        })
+        .collect()
+}
+
+fn to_db(energy: f32) -> f32 {
+    10.0 * energy.max(1e-10).log10()
+}
+
+pub fn compute_stats(samples: &[i16], sample_rate: u32) -> AudioStats {
+    let energies = frame_energies(samples, sample_rate);
+    let duration_secs = samples.len() as f32 / sample_rate as f32;
+
+    if energies.is_empty() {
+        return AudioStats {
+            duration_secs,
+            rms_db: to_db(0.0),
+            active_ratio: 0.0,
+            low_energy_ratio: 0.0,
+            fingerprint: fingerprint(samples),
+        };
+    }
+
+    let mean_energy = energies.iter().sum::<f32>() / energies.len() as f32;
+    let active = energies.iter().filter(|&&e| to_db(e) > SILENCE_FLOOR_DB).count();
+
+    let mut lster_sum = 0.0;
+    let mut windows = 0;
+    for window in energies.chunks(FRAMES_PER_SECOND) {
+        let avg = window.iter().sum::<f32>() / window.len() as f32;
+        if to_db(avg) <= SILENCE_FLOOR_DB {
+            continue;
+        }
+        let low = window.iter().filter(|&&e| e < 0.5 * avg).count();
+        lster_sum += low as f32 / window.len() as f32;
+        windows += 1;
+    }
+
+    AudioStats {
+        duration_secs,
+        rms_db: to_db(mean_energy),
+        active_ratio: active as f32 / energies.len() as f32,
+        low_energy_ratio: if windows > 0 { lster_sum / windows as f32 } else { 0.0 },
+        fingerprint: fingerprint(samples),
+    }
+}
+
+/// FNV-1a over the samples, without their lowest bits so decoder dither
+/// doesn't tell copies apart.
+fn fingerprint(samples: &[i16]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for sample in samples {
+        for byte in (sample >> 4).to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// True when a recording is quieter overall than `threshold_db` or too short
+/// on activity to contain speech.
+pub fn is_effectively_silent(stats: &AudioStats, threshold_db: f32) -> bool {
+    stats.rms_db < threshold_db || stats.active_ratio * stats.duration_secs < MIN_ACTIVE_SECONDS
+}
+
+/// True when the two channels carry different speakers, as in call-recorder
+/// apps that put each party on its own channel.
+pub fn has_separate_speakers(left: &[i16], right: &[i16], sample_rate: u32) -> bool {
+    let left = frame_energies(left, sample_rate);
+    let right = frame_energies(right, sample_rate);
+
+    let mut active = [0usize; 2];
+    let mut one_sided = 0usize;
+    let mut either_active = 0usize;
+    for (&l, &r) in left.iter().zip(&right) {
+        let (l_db, r_db) = (to_db(l), to_db(r));
+        let l_active = l_db > SILENCE_FLOOR_DB;
+        let r_active = r_db > SILENCE_FLOOR_DB;
+        active[0] += l_active as usize;
+        active[1] += r_active as usize;
+        if l_active || r_active {
+            either_active += 1;
+            if (l_db - r_db).abs() >= CHANNEL_DOMINANCE_DB {
+                one_sided += 1;
+            }
+        }
+    }
+
+    // Both parties must actually speak, otherwise it's just a one-sided mix
+    let min_active_frames = (MIN_ACTIVE_SECONDS * FRAMES_PER_SECOND as f32) as usize;
+    if active.iter().any(|&frames| frames < min_active_frames) || either_active == 0 {
+        return false;
+    }
+
+    debug!("Channel stats: one-sided={}/{} active frames", one_sided, either_active);
+    one_sided as f32 / either_active as f32 >= MIN_ONE_SIDED_RATIO
+}
+
+pub fn classify(stats: &AudioStats) -> ContentClass {
+    debug!(
+        "Content stats: duration={:.1}s rms={:.1}dB active={:.2} lster={:.3}",
+        stats.duration_secs, stats.rms_db, stats.active_ratio, stats.low_energy_ratio
+    );
+
+    if stats.duration_secs >= MIN_CLASSIFY_SECONDS
+        && stats.active_ratio > 0.9
+        && stats.low_energy_ratio < MUSIC_LSTER_THRESHOLD
+    {
+        ContentClass::Music
+    } else {
+        ContentClass::Speech
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(seconds: f32, gate: impl Fn(f32) -> bool) -> Vec<i16> {
+        let rate = ANALYSIS_SAMPLE_RATE as f32;
+        (0..(seconds * rate) as usize)
+            .map(|i| {
+                let t = i as f32 / rate;
+                if gate(t) {
+                    ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_continuous_tone_is_music() {
+        let samples = tone(10.0, |_| true);
+        let stats = compute_stats(&samples, ANALYSIS_SAMPLE_RATE);
+        assert_eq!(classify(&stats), ContentClass::Music);
+    }
+
+    #[test]
+    fn test_bursty_signal_is_speech() {
+        // 200 ms "syllables" separated by 150 ms pauses
+        let samples = tone(10.0, |t| (t % 0.35) < 0.2);
+        let stats = compute_stats(&samples, ANALYSIS_SAMPLE_RATE);
+        assert!(stats.low_energy_ratio > MUSIC_LSTER_THRESHOLD);
+        assert_eq!(classify(&stats), ContentClass::Speech);
+    }
+
+    #[test]
+    fn test_silence_detection() {
+        let silent = vec![0i16; ANALYSIS_SAMPLE_RATE as usize * 5];
+        assert!(is_effectively_silent(&compute_stats(&silent, ANALYSIS_SAMPLE_RATE), DEFAULT_SILENCE_THRESHOLD_DB));
+
+        // A single 100 ms click in five seconds of silence
+        let click = tone(5.0, |t| (1.0..1.1).contains(&t));
+        assert!(is_effectively_silent(&compute_stats(&click, ANALYSIS_SAMPLE_RATE), DEFAULT_SILENCE_THRESHOLD_DB));
+
+        let speech = tone(5.0, |t| (t % 0.35) < 0.2);
+        let stats = compute_stats(&speech, ANALYSIS_SAMPLE_RATE);
+        assert!(!is_effectively_silent(&stats, DEFAULT_SILENCE_THRESHOLD_DB));
+        // A stricter threshold turns away quiet speech too
+        assert!(is_effectively_silent(&stats, stats.rms_db + 1.0));
+    }
+
+    #[test]
+    fn test_samples_from_converted_wav() {
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+        wav.extend_from_slice(b"fmt \x10\0\0\0");
+        wav.extend_from_slice(&[0u8; 16]);
+        wav.extend_from_slice(b"data\x04\0\0\0");
+        wav.extend_from_slice(&[1, 0, 0xff, 0xff]);
+
+        let audio = ConvertedAudio { data: wav, format: "wav".to_string(), sample_rate: 16000, channels: 1 };
+        assert_eq!(samples_from_converted(&audio), Some(vec![1, -1]));
+
+        let flac = ConvertedAudio { data: vec![0; 8], format: "flac".to_string(), sample_rate: 16000, channels: 1 };
+        assert_eq!(samples_from_converted(&flac), None);
+    }
+
+    #[test]
+    fn test_separate_speakers() {
+        // Alternating turns, each party only on their own channel
+        let caller = tone(10.0, |t| (t % 2.0) < 1.0);
+        let callee = tone(10.0, |t| (t % 2.0) >= 1.0);
+        assert!(has_separate_speakers(&caller, &callee, ANALYSIS_SAMPLE_RATE));
+
+        // Mono upmixed to stereo
+        assert!(!has_separate_speakers(&caller, &caller, ANALYSIS_SAMPLE_RATE));
+
+        // Only one side has audio
+        let silent = vec![0i16; caller.len()];
+        assert!(!has_separate_speakers(&caller, &silent, ANALYSIS_SAMPLE_RATE));
+    }
+
+    #[test]
+    fn test_short_clip_is_not_classified_as_music() {
+        let samples = tone(3.0, |_| true);
+        let stats = compute_stats(&samples, ANALYSIS_SAMPLE_RATE);
+        assert_eq!(classify(&stats), ContentClass::Speech);
+    }
+    #[tokio::test]
+    async fn test_probe_duration() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &crate::selftest::sample_wav()).unwrap();
+        let secs = probe_duration(file.path(), Some(Duration::from_secs(30))).await.unwrap();
+        assert!((secs - 2.0).abs() < 0.05, "{}", secs);
+    }
+}
@@ -0,0 +1,138 @@
+use super::{AudioError, TimeRange};
+use super::convert::run_ffmpeg;
+use tracing::debug;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Quieter than this counts as a pause between phrases.
+const SILENCE_NOISE_DB: i32 = -35;
+/// Pauses shorter than this are too risky to cut in.
+const SILENCE_MIN_SECS: f32 = 0.4;
+
+/// A pause found by ffmpeg's `silencedetect`, in seconds from the start of
+/// the transcribed range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Silence {
+    pub start_secs: f32,
+    pub end_secs: f32,
+}
+
+impl Silence {
+    fn midpoint(&self) -> f32 {
+        (self.start_secs + self.end_secs) / 2.0
+    }
+}
+
+/// Finds pauses in the source media with ffmpeg's `silencedetect` filter.
+pub async fn detect_silences(
+    input_path: &Path,
+    time_range: Option<TimeRange>,
+    timeout: Option<Duration>,
+) -> Result<Vec<Silence>, AudioError> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-hide_banner")
+        .arg("-nostats");
+
+    if let Some(range) = time_range {
+        range.apply_input_args(&mut cmd);
+    }
+
+    // silencedetect reports on stderr at info level
+    cmd.arg("-i").arg(input_path)
+        .arg("-vn")
+        .arg("-af").arg(format!("silencedetect=noise={}dB:d={}", SILENCE_NOISE_DB, SILENCE_MIN_SECS))
+        .arg("-f").arg("null")
+        .arg("-");
+    let output = run_ffmpeg(cmd, timeout).await?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(AudioError::ConversionFailed(format!("FFmpeg silence detection failed: {}", stderr)));
+    }
+
+    let silences = parse_silencedetect(&stderr);
+    debug!("Found {} pauses", silences.len());
+    Ok(silences)
+}
+
+/// Pairs `silence_start`/`silence_end` lines from ffmpeg's log.
+fn parse_silencedetect(log: &str) -> Vec<Silence> {
+    let value_after = |line: &str, key: &str| -> Option<f32> {
+        let rest = &line[line.find(key)? + key.len()..];
+        rest.split_whitespace().next()?.trim_end_matches('|').parse().ok()
+    };
+
+    let mut silences = Vec::new();
+    let mut start = None;
+    for line in log.lines() {
+        if let Some(s) = value_after(line, "silence_start: ") {
+            start = Some(s.max(0.0));
+        } else if let Some(end) = value_after(line, "silence_end: ")
+            && let Some(start_secs) = start.take()
+        {
+            silences.push(Silence { start_secs, end_secs: end });
+        }
+    }
+    silences
+}
+
+/// Cut points that keep every chunk within `max_secs`, placed in the middle
+/// of the latest pause in the second half of each chunk. Without a suitable
+/// pause the chunk is cut hard at `max_secs`.
+pub fn plan_cuts(silences: &[Silence], duration_secs: f32, max_secs: u32) -> Vec<f32> {
+    let max_secs = max_secs as f32;
+    let mut cuts = Vec::new();
+    let mut chunk_start = 0.0;
+
+    while duration_secs - chunk_start > max_secs {
+        let limit = chunk_start + max_secs;
+        let cut = silences
+            .iter()
+            .map(Silence::midpoint)
+            .filter(|&mid| mid > chunk_start + max_secs / 2.0 && mid <= limit)
+            .next_back()
+            .unwrap_or(limit);
+        cuts.push(cut);
+        chunk_start = cut;
+    }
+    cuts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_silencedetect() {
+        let log = "\
+[silencedetect @ 0x55d0c8a0] silence_start: 12.504
+[silencedetect @ 0x55d0c8a0] silence_end: 13.21 | silence_duration: 0.706
+size=N/A time=00:01:00.00 bitrate=N/A speed= 500x
+[silencedetect @ 0x55d0c8a0] silence_start: -0.01
+[silencedetect @ 0x55d0c8a0] silence_end: 0.8 | silence_duration: 0.81
+[silencedetect @ 0x55d0c8a0] silence_start: 58.1";
+
+        assert_eq!(
+            parse_silencedetect(log),
+            [
+                Silence { start_secs: 12.504, end_secs: 13.21 },
+                Silence { start_secs: 0.0, end_secs: 0.8 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_cuts() {
+        let silences = [
+            Silence { start_secs: 20.0, end_secs: 21.0 },
+            Silence { start_secs: 45.0, end_secs: 46.0 },
+            Silence { start_secs: 95.0, end_secs: 96.0 },
+        ];
+
+        // Latest pause before each limit; hard cut where none is late enough
+        assert_eq!(plan_cuts(&silences, 150.0, 60), [45.5, 95.5]);
+        assert_eq!(plan_cuts(&[], 130.0, 60), [60.0, 120.0]);
+        assert!(plan_cuts(&silences, 60.0, 60).is_empty());
+    }
+}
@@ -1,11 +1,12 @@
 use super::AudioError;
 use crate::stt::SttProvider;
-use log::{debug, info};
-use std::process::Command;
-use tempfile::NamedTempFile;
-use std::io::Write;
+use tracing::{debug, info};
+use std::path::Path;
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
 use std::fs;
 
+#[derive(Clone)]
 pub struct ConvertedAudio {
     pub data: Vec<u8>,
     pub format: String,
@@ -13,89 +14,191 @@ pub struct ConvertedAudio {
     pub channels: u8,
 }
 
-pub async fn convert_for_stt(
-    input_data: &[u8],
-    original_filename: &str,
-    provider: SttProvider,
-) -> Result<ConvertedAudio, AudioError> {
-    // Determine input format from filename
-    let _input_extension = get_file_extension(original_filename);
+/// Telephone audio is band-limited to 8 kHz sampling; upsampling it only
+/// hurts phone-call models.
+pub const TELEPHONE_SAMPLE_RATE: u32 = 8000;
 
-    info!("Converting {} ({} bytes) for {:?} provider",
-        original_filename, input_data.len(), provider);
+/// Knobs for a single conversion beyond the provider's target format.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConversionOptions {
+    pub time_range: Option<TimeRange>,
+    /// Keep 8 kHz sampling for providers that have phone-call models.
+    pub telephone: bool,
+    /// Boost quiet recordings with dynamic normalization.
+    pub auto_gain: bool,
+    /// Keep only this source channel (0 = left) instead of downmixing.
+    pub channel: Option<u8>,
+    /// Kill ffmpeg if the conversion takes longer than this.
+    pub timeout: Option<Duration>,
+    /// Cleanup filters chosen by `AUDIO_PREPROCESS` or `/preprocess`.
+    pub preprocess: Preprocess,
+}
 
-    // Create temporary input file
-    let mut input_temp = NamedTempFile::new()
-        .map_err(|e| AudioError::TempFile(format!("Failed to create input temp file: {}", e)))?;
+impl ConversionOptions {
+    /// ffmpeg `-af` filters implied by these options.
+    pub fn audio_filters(&self) -> Vec<String> {
+        let mut filters = Vec::new();
+        if let Some(channel) = self.channel {
+            filters.push(format!("pan=mono|c0=c{}", channel));
+        }
+        if self.preprocess.highpass {
+            filters.push("highpass=f=100".to_string());
+        }
+        if self.preprocess.lowpass {
+            filters.push("lowpass=f=7000".to_string());
+        }
+        if self.preprocess.denoise {
+            filters.push("afftdn=nf=-25".to_string());
+        }
+        // Normalize last so denoised audio isn't boosted back up; loudnorm
+        // already covers what auto gain would do
+        if self.preprocess.loudnorm {
+            filters.push("loudnorm=I=-16:TP=-1.5:LRA=11".to_string());
+        } else if self.auto_gain {
+            // Up to 40 dB of gain, adapting over ~5 s windows
+            filters.push("dynaudnorm=f=250:g=21:p=0.95:m=100".to_string());
+        }
+        filters
+    }
+}
 
-    input_temp.write_all(input_data)
-        .map_err(|e| AudioError::TempFile(format!("Failed to write input data: {}", e)))?;
+/// Optional cleanup run before transcription to help with noisy voice notes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Preprocess {
+    /// EBU R128 loudness normalization.
+    pub loudnorm: bool,
+    /// Cut rumble and handling noise below 100 Hz.
+    pub highpass: bool,
+    /// Cut hiss above 7 kHz, past where speech carries information.
+    pub lowpass: bool,
+    /// FFT-based broadband noise reduction (`afftdn`).
+    pub denoise: bool,
+}
 
-    let input_path = input_temp.path();
+impl Preprocess {
+    pub const FILTERS: [&'static str; 4] = ["loudnorm", "highpass", "lowpass", "denoise"];
 
-    // Determine output format and parameters based on STT provider
-    let (output_format, sample_rate, channels, codec) = match provider {
-        SttProvider::ElevenLabs | SttProvider::Deepgram => {
-            // Both expect PCM s16le 16kHz mono
-            ("pcm", 16000, 1, "pcm_s16le")
-        }
-        SttProvider::Whisper => {
-            // Whisper accepts MP3, but let's use WAV for consistency
-            ("wav", 16000, 1, "pcm_s16le")
+    /// Parses filter names separated by commas or spaces, or `off`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut preprocess = Self::default();
+        if matches!(s.trim().to_lowercase().as_str(), "off" | "none") {
+            return Some(preprocess);
         }
-        SttProvider::Google => {
-            // Google Cloud STT prefers FLAC or linear16
-            ("flac", 16000, 1, "flac")
+
+        let mut any = false;
+        for name in s.split([',', ' ']).map(str::trim).filter(|n| !n.is_empty()) {
+            match name.to_lowercase().as_str() {
+                "loudnorm" => preprocess.loudnorm = true,
+                "highpass" => preprocess.highpass = true,
+                "lowpass" => preprocess.lowpass = true,
+                "denoise" | "afftdn" => preprocess.denoise = true,
+                _ => return None,
+            }
+            any = true;
         }
-    };
+        any.then_some(preprocess)
+    }
 
-    // Create temporary output file
-    let output_temp = NamedTempFile::new()
-        .map_err(|e| AudioError::TempFile(format!("Failed to create output temp file: {}", e)))?;
+    pub fn is_off(&self) -> bool {
+        *self == Self::default()
+    }
 
-    let output_path = output_temp.path();
+    /// `loudnorm, denoise` or `off`.
+    pub fn describe(&self) -> String {
+        let enabled = [self.loudnorm, self.highpass, self.lowpass, self.denoise];
+        let names: Vec<&str> = Self::FILTERS
+            .iter()
+            .zip(enabled)
+            .filter_map(|(name, on)| on.then_some(*name))
+            .collect();
+        if names.is_empty() { "off".to_string() } else { names.join(", ") }
+    }
+}
 
-    // Check if ffmpeg is available
-    if !is_ffmpeg_available() {
-        return Err(AudioError::FfmpegNotFound);
+/// A `[start, end)` slice of the source media, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TimeRange {
+    pub start_secs: u32,
+    pub end_secs: u32,
+}
+
+impl TimeRange {
+    /// Parses `12:30-18:00`, `1:02:00-1:10:30` or `90-120`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (start, end) = s.trim().split_once('-')?;
+        let start_secs = parse_timestamp(start)?;
+        let end_secs = parse_timestamp(end)?;
+        (end_secs > start_secs).then_some(Self { start_secs, end_secs })
     }
 
-    // Build ffmpeg command
-    let mut cmd = Command::new("ffmpeg");
-    cmd.arg("-y") // Overwrite output file
-        .arg("-hide_banner")
-        .arg("-loglevel").arg("error")
-        .arg("-i").arg(input_path)
-        .arg("-acodec").arg(codec)
-        .arg("-ar").arg(sample_rate.to_string())
-        .arg("-ac").arg(channels.to_string());
+    pub fn duration_secs(&self) -> u32 {
+        self.end_secs - self.start_secs
+    }
 
-    // Add format-specific options
-    match provider {
-        SttProvider::ElevenLabs | SttProvider::Deepgram => {
-            // For PCM, we need raw format
-            cmd.arg("-f").arg("s16le");
-        }
-        SttProvider::Whisper => {
-            // Standard WAV format
-            cmd.arg("-f").arg("wav");
-        }
-        SttProvider::Google => {
-            // FLAC format
-            cmd.arg("-f").arg("flac");
+    /// Adds `-ss`/`-t` around the input so only the range is decoded.
+    pub(crate) fn apply_input_args(&self, cmd: &mut Command) {
+        cmd.arg("-ss").arg(self.start_secs.to_string())
+            .arg("-t").arg(self.duration_secs().to_string());
+    }
+}
+
+/// Parses `[[h:]m:]s` into seconds.
+fn parse_timestamp(s: &str) -> Option<u32> {
+    let parts: Vec<&str> = s.trim().split(':').collect();
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+
+    let mut total: u32 = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let value: u32 = part.parse().ok()?;
+        if i > 0 && value >= 60 {
+            return None;
         }
+        total = total.checked_mul(60)?.checked_add(value)?;
     }
+    Some(total)
+}
 
-    cmd.arg(output_path);
+pub async fn convert_for_stt(
+    input_path: &Path,
+    original_filename: &str,
+    provider: SttProvider,
+    options: ConversionOptions,
+) -> Result<ConvertedAudio, AudioError> {
+    info!("Converting {} ({}) for {:?} provider",
+        original_filename, input_path.display(), provider);
+
+    let target = OutputTarget::for_provider(provider, &options);
+
+    // Create temporary output file
+    let output_temp = crate::janitor::temp_file()
+        .map_err(|e| AudioError::TempFile(format!("Failed to create output temp file: {}", e)))?;
+
+    let output_path = output_temp.path();
+    let cmd = ffmpeg_command(input_path, output_path, &target, &options, "error");
 
     debug!("Running ffmpeg command: {:?}", cmd);
 
-    // Execute ffmpeg
-    let output = cmd.output()
-        .map_err(|e| AudioError::ConversionFailed(format!("Failed to execute ffmpeg: {}", e)))?;
+    // Execute ffmpeg, or decode common audio formats natively without it
+    let output = match run_ffmpeg(cmd, options.timeout).await {
+        Err(AudioError::FfmpegNotFound) => {
+            info!("ffmpeg not found, decoding {} with Symphonia", original_filename);
+            let (input_path, original_filename) = (input_path.to_path_buf(), original_filename.to_string());
+            return super::decode::run_without_ffmpeg(move || {
+                super::decode::convert_without_ffmpeg(&input_path, &original_filename, &target, &options)
+            }).await;
+        }
+        output => output?,
+    };
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
+        // e.g. a GIF-style animation without sound
+        if stderr.contains("does not contain any stream") {
+            return Err(AudioError::UnsupportedFormat("No audio track found".to_string()));
+        }
         return Err(AudioError::ConversionFailed(format!("FFmpeg failed: {}", stderr)));
     }
 
@@ -103,22 +206,195 @@ pub async fn convert_for_stt(
     let converted_data = fs::read(output_path)
         .map_err(|e| AudioError::ConversionFailed(format!("Failed to read converted file: {}", e)))?;
 
-    info!("Successfully converted audio: {} bytes -> {} bytes",
-        input_data.len(), converted_data.len());
+    info!("Successfully converted audio: {} -> {} bytes",
+        original_filename, converted_data.len());
 
-    Ok(ConvertedAudio {
-        data: converted_data,
-        format: output_format.to_string(),
-        sample_rate,
-        channels,
-    })
+    Ok(target.wrap(converted_data))
+}
+
+/// The ffmpeg run converting `input_path` to `target`, logging at `loglevel`.
+fn ffmpeg_command(
+    input_path: &Path,
+    output_path: &Path,
+    target: &OutputTarget,
+    options: &ConversionOptions,
+    loglevel: &str,
+) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-y") // Overwrite output file
+        .arg("-hide_banner")
+        .arg("-loglevel").arg(loglevel);
+
+    if let Some(range) = options.time_range {
+        range.apply_input_args(&mut cmd);
+    }
+
+    cmd.arg("-i").arg(input_path);
+    target.apply_output_args(&mut cmd, options);
+    cmd.arg("-f").arg(target.muxer);
+
+    cmd.arg(output_path);
+    cmd
+}
+
+/// `DEBUG_AUDIO`: whether the worker sends back the converted audio and
+/// how it was made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebugAudio {
+    #[default]
+    Off,
+    /// Before the transcript.
+    On,
+    /// Instead of transcribing; no provider is called.
+    Only,
+}
+
+impl DebugAudio {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "0" | "false" | "off" | "no" => Some(Self::Off),
+            "1" | "true" | "on" | "yes" => Some(Self::On),
+            "only" => Some(Self::Only),
+            _ => None,
+        }
+    }
 }
 
-fn get_file_extension(filename: &str) -> &str {
-    filename.rsplit('.').next().unwrap_or("")
+/// A conversion for `DEBUG_AUDIO`, with what the converter reported.
+pub struct DebugConversion {
+    pub result: Result<ConvertedAudio, AudioError>,
+    /// `ffmpeg`, or `symphonia` when ffmpeg isn't installed.
+    pub converter: &'static str,
+    pub codec: &'static str,
+    /// The ffmpeg command line and its log at `info` level; empty for Symphonia.
+    pub log: String,
+    pub elapsed: Duration,
 }
 
-fn is_ffmpeg_available() -> bool {
+/// Converts as `convert_for_stt` does, keeping ffmpeg's log rather than
+/// just its errors.
+pub async fn convert_for_debug(
+    input_path: &Path,
+    original_filename: &str,
+    provider: SttProvider,
+    options: ConversionOptions,
+) -> DebugConversion {
+    let started = Instant::now();
+    let target = OutputTarget::for_provider(provider, &options);
+    let mut debug = DebugConversion {
+        result: Err(AudioError::FfmpegNotFound),
+        converter: "ffmpeg",
+        codec: target.codec,
+        log: String::new(),
+        elapsed: Duration::ZERO,
+    };
+
+    let output_temp = match crate::janitor::temp_file() {
+        Ok(file) => file,
+        Err(e) => {
+            debug.result = Err(AudioError::TempFile(format!("Failed to create output temp file: {}", e)));
+            return debug;
+        }
+    };
+    let cmd = ffmpeg_command(input_path, output_temp.path(), &target, &options, "info");
+    debug.log = format!("{:?}\n\n", cmd);
+
+    debug.result = match run_ffmpeg(cmd, options.timeout).await {
+        Err(AudioError::FfmpegNotFound) => {
+            debug.converter = "symphonia";
+            // Symphonia writes WAV where ffmpeg would encode FLAC
+            debug.codec = "pcm_s16le";
+            debug.log.clear();
+            let (input_path, original_filename) = (input_path.to_path_buf(), original_filename.to_string());
+            super::decode::run_without_ffmpeg(move || {
+                super::decode::convert_without_ffmpeg(&input_path, &original_filename, &target, &options)
+            }).await
+        }
+        Err(e) => Err(e),
+        Ok(output) => {
+            debug.log.push_str(&String::from_utf8_lossy(&output.stderr));
+            if output.status.success() {
+                fs::read(output_temp.path())
+                    .map(|data| target.wrap(data))
+                    .map_err(|e| AudioError::ConversionFailed(format!("Failed to read converted file: {}", e)))
+            } else {
+                Err(AudioError::ConversionFailed(format!("FFmpeg exited with {}", output.status)))
+            }
+        }
+    };
+    debug.elapsed = started.elapsed();
+    debug
+}
+
+/// Whether audio converted for `a` can be sent to `b` as is.
+pub fn same_output_format(a: SttProvider, b: SttProvider, options: &ConversionOptions) -> bool {
+    let (a, b) = (OutputTarget::for_provider(a, options), OutputTarget::for_provider(b, options));
+    a.format == b.format && a.sample_rate == b.sample_rate && a.channels == b.channels
+}
+
+/// Output format and encoding a provider expects.
+pub(crate) struct OutputTarget {
+    pub format: &'static str,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub codec: &'static str,
+    /// ffmpeg muxer (`-f`) that produces `format`.
+    pub muxer: &'static str,
+}
+
+impl OutputTarget {
+    /// The format of the engine registered for `provider`, at the telephone
+    /// rate for call recordings where the engine takes it.
+    pub(crate) fn for_provider(provider: SttProvider, options: &ConversionOptions) -> Self {
+        let target = crate::stt::engine::engine(provider).audio_format();
+        let sample_rate = if options.telephone && target.telephone_rate { TELEPHONE_SAMPLE_RATE } else { target.sample_rate };
+        Self { format: target.format, sample_rate, channels: target.channels, codec: target.codec, muxer: target.muxer }
+    }
+
+    /// Adds filters and encoding arguments; the caller picks the muxer.
+    pub(crate) fn apply_output_args(&self, cmd: &mut Command, options: &ConversionOptions) {
+        let filters = options.audio_filters();
+        if !filters.is_empty() {
+            cmd.arg("-af").arg(filters.join(","));
+        }
+
+        cmd.arg("-vn")
+            .arg("-acodec").arg(self.codec)
+            .arg("-ar").arg(self.sample_rate.to_string())
+            .arg("-ac").arg(self.channels.to_string());
+    }
+
+    pub(crate) fn wrap(&self, data: Vec<u8>) -> ConvertedAudio {
+        ConvertedAudio {
+            data,
+            format: self.format.to_string(),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+        }
+    }
+}
+
+/// Runs ffmpeg without blocking the runtime. The process is killed once
+/// `timeout` passes, so a corrupted file can't wedge the queue.
+pub(crate) async fn run_ffmpeg(cmd: Command, timeout: Option<Duration>) -> Result<Output, AudioError> {
+    let mut cmd = tokio::process::Command::from(cmd);
+    cmd.kill_on_drop(true);
+
+    // Dropping the output future on timeout kills the child
+    let output = match timeout {
+        Some(limit) => tokio::time::timeout(limit, cmd.output())
+            .await
+            .map_err(|_| AudioError::Timeout(limit.as_secs()))?,
+        None => cmd.output().await,
+    };
+
+    output.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => AudioError::FfmpegNotFound,
+        _ => AudioError::ConversionFailed(format!("Failed to execute ffmpeg: {}", e)),
+    })
+}
+
+pub(crate) fn is_ffmpeg_available() -> bool {
     Command::new("ffmpeg")
         .arg("-version")
         .output()
@@ -131,11 +407,93 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_get_file_extension() {
-        assert_eq!(get_file_extension("test.mp3"), "mp3");
-        assert_eq!(get_file_extension("voice.ogg"), "ogg");
-        assert_eq!(get_file_extension("file.with.dots.wav"), "wav");
-        assert_eq!(get_file_extension("noextension"), "");
+    fn test_time_range_parse() {
+        assert_eq!(TimeRange::parse("12:30-18:00"), Some(TimeRange { start_secs: 750, end_secs: 1080 }));
+        assert_eq!(TimeRange::parse("1:02:00-1:10:30"), Some(TimeRange { start_secs: 3720, end_secs: 4230 }));
+        assert_eq!(TimeRange::parse(" 90-120 "), Some(TimeRange { start_secs: 90, end_secs: 120 }));
+        assert_eq!(TimeRange::parse("18:00-12:30"), None);
+        assert_eq!(TimeRange::parse("1:75-2:00"), None);
+        assert_eq!(TimeRange::parse("12:30"), None);
+        assert_eq!(TimeRange::parse("a-b"), None);
+    }
+
+    #[test]
+    fn test_same_output_format() {
+        let options = ConversionOptions::default();
+        assert!(same_output_format(SttProvider::Deepgram, SttProvider::ElevenLabs, &options));
+        assert!(!same_output_format(SttProvider::Deepgram, SttProvider::Whisper, &options));
+
+        // Telephone audio stays at 8 kHz for Deepgram but not for ElevenLabs
+        let telephone = ConversionOptions { telephone: true, ..options };
+        assert!(!same_output_format(SttProvider::Deepgram, SttProvider::ElevenLabs, &telephone));
+    }
+
+    #[test]
+    fn test_audio_filters() {
+        assert!(ConversionOptions::default().audio_filters().is_empty());
+        let boosted = ConversionOptions { auto_gain: true, ..Default::default() };
+        assert!(boosted.audio_filters()[0].starts_with("dynaudnorm"));
+
+        // Channel selection must happen before normalization
+        let right = ConversionOptions { auto_gain: true, channel: Some(1), ..Default::default() };
+        assert_eq!(right.audio_filters()[0], "pan=mono|c0=c1");
+        assert_eq!(right.audio_filters().len(), 2);
+
+        // loudnorm replaces auto gain and runs after the cleanup filters
+        let cleaned = ConversionOptions {
+            auto_gain: true,
+            preprocess: Preprocess { loudnorm: true, highpass: true, denoise: true, ..Default::default() },
+            ..Default::default()
+        };
+        let filters = cleaned.audio_filters();
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters[0], "highpass=f=100");
+        assert!(filters[1].starts_with("afftdn"));
+        assert!(filters[2].starts_with("loudnorm"));
+    }
+
+    #[test]
+    fn test_preprocess_parse() {
+        let parsed = Preprocess::parse("loudnorm, afftdn highpass").unwrap();
+        assert_eq!(parsed, Preprocess { loudnorm: true, highpass: true, denoise: true, lowpass: false });
+        assert_eq!(parsed.describe(), "loudnorm, highpass, denoise");
+        assert!(Preprocess::parse("OFF").unwrap().is_off());
+        assert_eq!(Preprocess::parse("off").unwrap().describe(), "off");
+        assert_eq!(Preprocess::parse("loudnorm,reverb"), None);
+        assert_eq!(Preprocess::parse(" "), None);
+    }
+
+    #[tokio::test]
+    async fn test_convert_for_debug() {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut input, &crate::selftest::sample_wav()).unwrap();
+
+        let debug = convert_for_debug(input.path(), "sample.wav", SttProvider::Whisper, ConversionOptions::default()).await;
+        let converted = debug.result.unwrap();
+        assert_eq!((converted.format.as_str(), converted.sample_rate, debug.codec), ("wav", 16000, "pcm_s16le"));
+        match debug.converter {
+            "ffmpeg" => assert!(debug.log.contains("-loglevel") && debug.log.contains("info")),
+            _ => assert!(debug.log.is_empty()),
+        }
+
+        assert_eq!(DebugAudio::from_str("1"), Some(DebugAudio::On));
+        assert_eq!(DebugAudio::from_str(" Only "), Some(DebugAudio::Only));
+        assert_eq!(DebugAudio::from_str(""), Some(DebugAudio::Off));
+        assert_eq!(DebugAudio::from_str("verbose"), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_ffmpeg_timeout() {
+        if !is_ffmpeg_available() {
+            return;
+        }
+        // -re reads a minute of silence in real time
+        let mut cmd = Command::new("ffmpeg");
+        cmd.args(["-re", "-f", "lavfi", "-i", "anullsrc=d=60", "-f", "null", "-"]);
+        let started = std::time::Instant::now();
+        let result = run_ffmpeg(cmd, Some(Duration::from_millis(500))).await;
+        assert!(matches!(result, Err(AudioError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(10));
     }
 
     #[test]
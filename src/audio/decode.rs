@@ -0,0 +1,244 @@
+use super::{AudioError, ConversionOptions, ConvertedAudio, OutputTarget, TimeRange};
+use tracing::{info, warn};
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Pure-Rust stand-in for ffmpeg when it isn't installed. Handles MP3, WAV,
+/// FLAC and Ogg Vorbis; video containers and other codecs (Opus included,
+/// which Symphonia can't decode) still need ffmpeg.
+pub fn convert_without_ffmpeg(
+    input_path: &Path,
+    original_filename: &str,
+    target: &OutputTarget,
+    options: &ConversionOptions,
+) -> Result<ConvertedAudio, AudioError> {
+    if options.auto_gain || !options.preprocess.is_off() {
+        warn!("Skipping gain and preprocessing filters for {}, they need ffmpeg", original_filename);
+    }
+
+    let extension = original_filename.rsplit_once('.').map(|(_, ext)| ext);
+    let samples = decode(input_path, extension, options.time_range, target.sample_rate, 1, options.channel)?;
+
+    // Without an encoder FLAC targets get WAV, which every provider takes
+    let data = if target.format == "pcm" {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    } else {
+        wav_bytes(&samples, target.sample_rate, 1)
+    };
+
+    info!("Decoded {} without ffmpeg: {} bytes", original_filename, data.len());
+    Ok(ConvertedAudio {
+        data,
+        format: if target.format == "pcm" { "pcm" } else { "wav" }.to_string(),
+        sample_rate: target.sample_rate,
+        channels: 1,
+    })
+}
+
+/// Runs a Symphonia decode off the async runtime. Inputs it can't handle
+/// are reported as needing ffmpeg, which is the actual problem.
+pub(crate) async fn run_without_ffmpeg<T: Send + 'static>(
+    decode: impl FnOnce() -> Result<T, AudioError> + Send + 'static,
+) -> Result<T, AudioError> {
+    match tokio::task::spawn_blocking(decode).await {
+        Ok(Err(AudioError::UnsupportedFormat(e))) => {
+            warn!("{}", e);
+            Err(AudioError::FfmpegNotFound)
+        }
+        Ok(result) => result,
+        Err(e) => Err(AudioError::ConversionFailed(format!("Decoder task failed: {}", e))),
+    }
+}
+
+/// Decodes the first audio track into interleaved s16 samples at
+/// `sample_rate`. `channels` is 1 (downmixed) or 2 (mono is duplicated);
+/// `pick_channel` keeps a single source channel instead of downmixing.
+pub fn decode(
+    input_path: &Path,
+    extension: Option<&str>,
+    time_range: Option<TimeRange>,
+    sample_rate: u32,
+    channels: u8,
+    pick_channel: Option<u8>,
+) -> Result<Vec<i16>, AudioError> {
+    let unsupported = |e: SymphoniaError| AudioError::UnsupportedFormat(format!("Can't decode without ffmpeg: {}", e));
+
+    let file = std::fs::File::open(input_path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(unsupported)?
+        .format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| AudioError::UnsupportedFormat("No audio track found".to_string()))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(unsupported)?;
+
+    // One Vec per source channel
+    let mut source: Vec<Vec<f32>> = Vec::new();
+    let mut source_rate = 0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(AudioError::ConversionFailed(format!("Failed to read packet: {}", e))),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet loses a few milliseconds, not the file
+            Err(SymphoniaError::DecodeError(e)) => {
+                warn!("Skipping undecodable packet: {}", e);
+                continue;
+            }
+            Err(e) => return Err(AudioError::ConversionFailed(format!("Decoding failed: {}", e))),
+        };
+
+        let spec = *decoded.spec();
+        source_rate = spec.rate;
+        let count = spec.channels.count();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+
+        source.resize_with(count, Vec::new);
+        for frame in buffer.samples().chunks_exact(count) {
+            for (channel, sample) in frame.iter().enumerate() {
+                source[channel].push(*sample);
+            }
+        }
+    }
+
+    if source.is_empty() || source_rate == 0 {
+        return Err(AudioError::ConversionFailed("No audio decoded".to_string()));
+    }
+
+    if let Some(range) = time_range {
+        let start = range.start_secs as usize * source_rate as usize;
+        let end = range.end_secs as usize * source_rate as usize;
+        for channel in &mut source {
+            channel.truncate(end);
+            channel.drain(..start.min(channel.len()));
+        }
+    }
+
+    let output: Vec<Vec<f32>> = match (pick_channel, channels) {
+        (Some(pick), _) => {
+            let pick = (pick as usize).min(source.len() - 1);
+            vec![source.swap_remove(pick)]
+        }
+        (None, 1) => vec![downmix(&source)],
+        (None, _) => {
+            let right = source.get(1).unwrap_or(&source[0]).clone();
+            vec![source.swap_remove(0), right]
+        }
+    };
+
+    let output: Vec<Vec<f32>> = output.iter().map(|c| resample(c, source_rate, sample_rate)).collect();
+    let frames = output[0].len();
+    Ok((0..frames)
+        .flat_map(|i| output.iter().map(move |c| (c[i].clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+        .collect())
+}
+
+fn downmix(channels: &[Vec<f32>]) -> Vec<f32> {
+    let len = channels.iter().map(Vec::len).min().unwrap_or(0);
+    (0..len)
+        .map(|i| channels.iter().map(|c| c[i]).sum::<f32>() / channels.len() as f32)
+        .collect()
+}
+
+/// Linear interpolation; plenty for speech headed to an STT model.
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = from as f64 / to as f64;
+    let len = (samples.len() as f64 / ratio).round() as usize;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let index = pos as usize;
+            let frac = (pos - index as f64) as f32;
+            let a = samples[index.min(samples.len() - 1)];
+            let b = samples[(index + 1).min(samples.len() - 1)];
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+/// 16-bit PCM WAV file around interleaved samples.
+pub fn wav_bytes(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_resample() {
+        assert_eq!(resample(&[0.0, 1.0], 8000, 8000), [0.0, 1.0]);
+        assert_eq!(resample(&[0.0, 1.0, 0.0, -1.0], 16000, 8000), [0.0, 0.0]);
+        assert_eq!(resample(&[0.0, 1.0], 8000, 16000), [0.0, 0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_decode_wav() {
+        // One second of stereo at 8 kHz: left loud, right silent
+        let frames: Vec<i16> = (0..8000).flat_map(|i| [if i % 2 == 0 { 16000 } else { -16000 }, 0]).collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&wav_bytes(&frames, 8000, 2)).unwrap();
+
+        let mono = decode(file.path(), Some("wav"), None, 16000, 1, None).unwrap();
+        assert_eq!(mono.len(), 16000);
+        assert!(mono.iter().all(|s| s.abs() <= 8001));
+
+        let left = decode(file.path(), None, Some(TimeRange { start_secs: 0, end_secs: 1 }), 8000, 1, Some(0)).unwrap();
+        assert_eq!(left.len(), 8000);
+        assert!(left.iter().any(|s| s.abs() > 15000));
+
+        let stereo = decode(file.path(), None, None, 8000, 2, None).unwrap();
+        assert_eq!(stereo.len(), 16000);
+        assert!(stereo.iter().skip(1).step_by(2).all(|&s| s == 0));
+    }
+}
@@ -1,4 +1,9 @@
 pub mod convert;
+pub mod analyze;
+pub mod decode;
+pub mod segment;
+pub mod chunk;
+pub mod sniff;
 
 pub use convert::*;
 
@@ -12,6 +17,8 @@ pub enum AudioError {
     ConversionFailed(String),
     #[error("FFmpeg not found or not executable")]
     FfmpegNotFound,
+    #[error("FFmpeg was stopped after running for {0}s")]
+    Timeout(u64),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Temp file error: {0}")]
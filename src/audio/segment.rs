@@ -0,0 +1,150 @@
+use super::{AudioError, ConversionOptions, ConvertedAudio, OutputTarget};
+use super::convert::is_ffmpeg_available;
+use crate::stt::SttProvider;
+use tracing::{debug, info};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::AsyncReadExt;
+
+/// How often to check for newly finished segments while ffmpeg runs.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Extracts audio in fixed-length segments using ffmpeg's segment muxer.
+/// ffmpeg keeps demuxing in the background while finished segments are
+/// handed out, so transcription can start before extraction is done.
+pub struct SegmentedExtraction {
+    child: tokio::process::Child,
+    target: OutputTarget,
+    dir: TempDir,
+    list_path: PathBuf,
+    emitted: usize,
+    finished: bool,
+}
+
+/// Where the segment muxer cuts.
+#[derive(Debug, Clone)]
+pub enum SegmentPlan {
+    /// Fixed-length segments.
+    Every(u32),
+    /// Cut at these offsets in seconds, e.g. chosen pauses.
+    At(Vec<f32>),
+}
+
+pub fn start_segmented_extraction(
+    input_path: &Path,
+    original_filename: &str,
+    provider: SttProvider,
+    options: ConversionOptions,
+    plan: SegmentPlan,
+) -> Result<SegmentedExtraction, AudioError> {
+    info!("Extracting {} ({}) in segments ({:?}) for {:?} provider",
+        original_filename, input_path.display(), plan, provider);
+
+    let dir = crate::janitor::temp_dir()
+        .map_err(|e| AudioError::TempFile(format!("Failed to create segment directory: {}", e)))?;
+
+    if !is_ffmpeg_available() {
+        return Err(AudioError::FfmpegNotFound);
+    }
+
+    let target = OutputTarget::for_provider(provider, &options);
+    let list_path = dir.path().join("segments.txt");
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-y")
+        .arg("-hide_banner")
+        .arg("-loglevel").arg("error");
+
+    if let Some(range) = options.time_range {
+        range.apply_input_args(&mut cmd);
+    }
+
+    cmd.arg("-i").arg(input_path);
+    target.apply_output_args(&mut cmd, &options);
+
+    // ffmpeg appends to the list only once a segment is complete
+    cmd.arg("-f").arg("segment")
+        .arg("-segment_format").arg(target.muxer);
+    match plan {
+        SegmentPlan::Every(secs) => {
+            cmd.arg("-segment_time").arg(secs.to_string());
+        }
+        SegmentPlan::At(cuts) => {
+            let times = cuts.iter().map(|t| format!("{:.3}", t)).collect::<Vec<_>>().join(",");
+            cmd.arg("-segment_times").arg(times);
+        }
+    }
+    cmd.arg("-segment_list").arg(&list_path)
+        .arg("-segment_list_type").arg("flat")
+        .arg(dir.path().join(format!("segment%05d.{}", target.format)));
+
+    debug!("Running ffmpeg command: {:?}", cmd);
+
+    let child = tokio::process::Command::from(cmd)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| AudioError::ConversionFailed(format!("Failed to execute ffmpeg: {}", e)))?;
+
+    Ok(SegmentedExtraction {
+        child,
+        target,
+        dir,
+        list_path,
+        emitted: 0,
+        finished: false,
+    })
+}
+
+impl SegmentedExtraction {
+    /// Waits for the next finished segment. Returns `None` once ffmpeg has
+    /// exited and every segment has been handed out.
+    pub async fn next_segment(&mut self) -> Result<Option<ConvertedAudio>, AudioError> {
+        loop {
+            if let Some(name) = self.listed_segments().await.into_iter().nth(self.emitted) {
+                let path = self.dir.path().join(name);
+                let data = tokio::fs::read(&path).await
+                    .map_err(|e| AudioError::ConversionFailed(format!("Failed to read segment: {}", e)))?;
+                tokio::fs::remove_file(&path).await.ok();
+
+                self.emitted += 1;
+                debug!("Segment {} ready ({} bytes)", self.emitted, data.len());
+                return Ok(Some(self.target.wrap(data)));
+            }
+
+            if self.finished {
+                return Ok(None);
+            }
+
+            if let Some(status) = self.child.try_wait()? {
+                // Check the list once more: the last segment may have landed
+                // between the read above and ffmpeg exiting
+                self.finished = true;
+                if !status.success() {
+                    let mut stderr = String::new();
+                    if let Some(mut pipe) = self.child.stderr.take() {
+                        pipe.read_to_string(&mut stderr).await.ok();
+                    }
+                    return Err(AudioError::ConversionFailed(format!("FFmpeg failed: {}", stderr)));
+                }
+                continue;
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn listed_segments(&self) -> Vec<String> {
+        tokio::fs::read_to_string(&self.list_path)
+            .await
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
@@ -0,0 +1,107 @@
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Enough of the start of a file for every signature `detect` knows.
+const HEADER_LEN: usize = 16;
+
+/// File extensions accepted for media sent as documents.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp3", "m4a", "mp4", "ogg", "oga", "opus", "wav", "flac", "aac", "amr", "wma", "webm", "mkv", "mov", "avi", "3gp",
+];
+
+/// The part of `filename` after its last dot, or "" if it has none.
+pub fn get_file_extension(filename: &str) -> &str {
+    filename.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("")
+}
+
+/// Whether a document's declared MIME type or file name says it is audio or
+/// video. Checked before downloading.
+pub fn is_media_document(mime_type: Option<&str>, file_name: Option<&str>) -> bool {
+    let mime_ok = mime_type.is_some_and(|mime| {
+        let mime = mime.to_lowercase();
+        mime.starts_with("audio/") || mime.starts_with("video/") || mime == "application/ogg"
+    });
+    let extension_ok = file_name
+        .map(get_file_extension)
+        .is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    mime_ok || extension_ok
+}
+
+/// Identifies the container from the file's first bytes, so a renamed PDF
+/// is turned away before it reaches ffmpeg. None for anything that isn't a
+/// known audio or video format.
+pub fn detect(data: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| data.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+
+    if starts(b"OggS") {
+        Some("ogg")
+    } else if starts(b"fLaC") {
+        Some("flac")
+    } else if starts(b"RIFF") && at(8, b"WAVE") {
+        Some("wav")
+    } else if starts(b"RIFF") && at(8, b"AVI ") {
+        Some("avi")
+    } else if at(4, b"ftyp") {
+        Some("mp4")
+    } else if starts(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some("matroska")
+    } else if starts(b"ID3") {
+        Some("mp3")
+    } else if starts(b"#!AMR") {
+        Some("amr")
+    } else if starts(&[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        Some("asf")
+    } else if data.len() >= 2 && data[0] == 0xFF && data[1] & 0xF6 == 0xF0 {
+        // ADTS header: 12-bit sync word, layer bits 00
+        Some("aac")
+    } else if data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0 {
+        // MPEG audio frame sync without an ID3 tag
+        Some("mp3")
+    } else {
+        None
+    }
+}
+
+/// `detect` on the first bytes of a downloaded file.
+pub async fn detect_file(path: &Path) -> std::io::Result<Option<&'static str>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    (&mut file).take(HEADER_LEN as u64).read_to_end(&mut header).await?;
+    Ok(detect(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_file_extension() {
+        assert_eq!(get_file_extension("test.mp3"), "mp3");
+        assert_eq!(get_file_extension("voice.ogg"), "ogg");
+        assert_eq!(get_file_extension("file.with.dots.wav"), "wav");
+        assert_eq!(get_file_extension("noextension"), "");
+    }
+
+    #[test]
+    fn test_is_media_document() {
+        assert!(is_media_document(Some("audio/mpeg"), None));
+        assert!(is_media_document(Some("application/octet-stream"), Some("Meeting.M4A")));
+        assert!(is_media_document(None, Some("call.opus")));
+        assert!(!is_media_document(Some("application/pdf"), Some("report.pdf")));
+        assert!(!is_media_document(None, Some("mp3")));
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"OggS\x00\x02"), Some("ogg"));
+        assert_eq!(detect(b"RIFF\x24\x00\x00\x00WAVEfmt "), Some("wav"));
+        assert_eq!(detect(b"\x00\x00\x00\x20ftypM4A "), Some("mp4"));
+        assert_eq!(detect(b"ID3\x04\x00"), Some("mp3"));
+        assert_eq!(detect(&[0xFF, 0xFB, 0x90, 0x64]), Some("mp3"));
+        assert_eq!(detect(&[0xFF, 0xF1, 0x50, 0x80]), Some("aac"));
+        assert_eq!(detect(b"%PDF-1.7\n"), None);
+        assert_eq!(detect(b"PK\x03\x04"), None);
+        assert_eq!(detect(b""), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! Zip archives of media files: each file is queued on its own and the
+//! results come back as one report.
+
+use crate::i18n::{self, Locale};
+use crate::queue::MediaFile;
+use crate::{BotError, Result, audio, persistence};
+use tracing::{info, warn};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{InputFile, MessageId};
+use tokio::sync::Mutex;
+
+/// Media files queued from one archive; later ones are skipped.
+pub const MAX_FILES: usize = 20;
+/// Total unpacked size of an archive's media. Sizes are counted while
+/// inflating, so a zip bomb stops here whatever its headers claim.
+const MAX_UNPACKED_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Not a readable zip archive: {0}")]
+    Invalid(String),
+    #[error("No audio or video files in the archive")]
+    Empty,
+    #[error("Archive unpacks to more than {0} MB")]
+    TooLarge(u64),
+}
+
+/// Whether a document's MIME type or name says it is a zip archive.
+pub fn is_archive(mime_type: Option<&str>, file_name: Option<&str>) -> bool {
+    mime_type.is_some_and(|mime| matches!(mime, "application/zip" | "application/x-zip-compressed"))
+        || file_name.is_some_and(|name| name.to_lowercase().ends_with(".zip"))
+}
+
+/// A media file taken out of an archive, stored where queued media lives.
+pub struct UnpackedFile {
+    pub id: String,
+    /// Path inside the archive, e.g. `day 1/call.mp3`.
+    pub name: String,
+    pub media: MediaFile,
+}
+
+pub struct Unpacked {
+    pub files: Vec<UnpackedFile>,
+    /// Entries left out: not media, over the size limit or past `MAX_FILES`.
+    pub skipped: usize,
+}
+
+/// Unpacks the media files of the zip at `archive` into the queue directory
+/// `queue_dir`. Each file may be up to `max_file_bytes`; bigger ones are
+/// skipped.
+pub async fn unpack(archive: &Path, max_file_bytes: Option<u64>, queue_dir: &Path) -> Result<Unpacked> {
+    let archive = archive.to_path_buf();
+    let queue_dir = queue_dir.to_path_buf();
+    let mut unpacked = tokio::task::spawn_blocking(move || {
+        unpack_into(&archive, max_file_bytes, |id| persistence::pending_media_path(&queue_dir, id))
+    })
+        .await
+        .map_err(|e| BotError::Io(std::io::Error::other(e)))??;
+
+    // Named like media isn't the same as being media
+    let mut files = Vec::with_capacity(unpacked.files.len());
+    for file in unpacked.files {
+        match audio::sniff::detect_file(file.media.path()).await.map_err(BotError::Io)? {
+            Some(_) => files.push(file),
+            None => {
+                info!("Skipping {} from the archive, it doesn't contain audio or video", file.name);
+                unpacked.skipped += 1;
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(ArchiveError::Empty.into());
+    }
+    unpacked.files = files;
+    Ok(unpacked)
+}
+
+fn unpack_into(archive: &Path, max_file_bytes: Option<u64>, media_path: impl Fn(&str) -> PathBuf) -> Result<Unpacked> {
+    let invalid = |e: zip::result::ZipError| BotError::from(ArchiveError::Invalid(e.to_string()));
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(archive).map_err(BotError::Io)?).map_err(invalid)?;
+    let mut unpacked = Unpacked { files: Vec::new(), skipped: 0 };
+    let mut total_bytes = 0;
+
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).map_err(invalid)?;
+        if entry.is_dir() {
+            continue;
+        }
+        // Entries escaping the archive's root, and macOS metadata, are never media
+        let name = match entry.enclosed_name() {
+            Some(path) if !path.starts_with("__MACOSX") => path.to_string_lossy().replace('\\', "/"),
+            _ => {
+                unpacked.skipped += 1;
+                continue;
+            }
+        };
+        let base_name = name.rsplit('/').next().unwrap_or_default();
+        if base_name.starts_with('.')
+            || !audio::sniff::is_media_document(None, Some(base_name))
+            || unpacked.files.len() == MAX_FILES
+        {
+            unpacked.skipped += 1;
+            continue;
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let path = media_path(&id);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(BotError::Io)?;
+        }
+        let mut file = std::fs::File::create(&path).map_err(BotError::Io)?;
+        // Removes the file again if it's skipped
+        let mut media = MediaFile::new(path, 0);
+        let limit = max_file_bytes.unwrap_or(MAX_UNPACKED_BYTES).min(MAX_UNPACKED_BYTES - total_bytes);
+        let size = std::io::copy(&mut (&mut entry).take(limit + 1), &mut file).map_err(BotError::Io)?;
+
+        if size > limit {
+            if max_file_bytes.is_some_and(|max| size > max) {
+                info!("Skipping {} from the archive, it's over the file size limit", name);
+                unpacked.skipped += 1;
+                continue;
+            }
+            return Err(ArchiveError::TooLarge(MAX_UNPACKED_BYTES / (1024 * 1024)).into());
+        }
+        total_bytes += size;
+        media.set_size(size);
+        unpacked.files.push(UnpackedFile { id, name, media });
+    }
+    Ok(unpacked)
+}
+
+/// How a file from an archive turned out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Transcript(String),
+    NoSpeech,
+    Failed(String),
+}
+
+/// The files queued from one archive. Their results are collected here
+/// and the report goes out once the last one is in.
+pub struct Batch {
+    archive_name: String,
+    chat_id: ChatId,
+    reply_to: Option<MessageId>,
+    /// "Queued N files" notice, removed when the report is sent.
+    status_message: Option<MessageId>,
+    locale: Locale,
+    skipped: usize,
+    /// Forum topic the archive was posted in.
+    topic: Option<i32>,
+    results: Mutex<Results>,
+}
+
+struct Results {
+    entries: Vec<(String, Option<Outcome>)>,
+    reported: bool,
+}
+
+/// An item's place in its batch.
+#[derive(Clone)]
+pub struct Slot {
+    pub batch: Arc<Batch>,
+    pub index: usize,
+}
+
+impl Slot {
+    pub async fn finish(&self, bot: &Bot, outcome: Outcome) {
+        self.batch.finish(bot, self.index, outcome).await;
+    }
+}
+
+impl Batch {
+    pub fn new(
+        archive_name: String,
+        chat_id: ChatId,
+        reply_to: Option<MessageId>,
+        status_message: Option<MessageId>,
+        locale: Locale,
+        names: Vec<String>,
+        skipped: usize,
+    ) -> Self {
+        Self {
+            archive_name,
+            chat_id,
+            reply_to,
+            status_message,
+            locale,
+            skipped,
+            topic: None,
+            results: Mutex::new(Results { entries: names.into_iter().map(|name| (name, None)).collect(), reported: false }),
+        }
+    }
+
+    /// Sends the report into forum topic `topic`.
+    pub fn in_topic(self, topic: Option<i32>) -> Self {
+        Self { topic, ..self }
+    }
+
+    /// Records the outcome of file `index` and sends the report once every
+    /// file has one.
+    async fn finish(&self, bot: &Bot, index: usize, outcome: Outcome) {
+        let (summary, report, count) = {
+            let mut results = self.results.lock().await;
+            match results.entries.get_mut(index) {
+                Some(entry) => entry.1 = Some(outcome),
+                None => return,
+            }
+            if results.reported || results.entries.iter().any(|(_, outcome)| outcome.is_none()) {
+                return;
+            }
+            results.reported = true;
+            let entries: Vec<_> =
+                results.entries.iter().map(|(name, outcome)| (name.as_str(), outcome.clone().unwrap_or(Outcome::NoSpeech))).collect();
+            (
+                summary(self.locale, &self.archive_name, &entries),
+                report(self.locale, &self.archive_name, &entries, self.skipped),
+                entries.len(),
+            )
+        };
+
+        info!("All {} files from {} are done, sending the report", count, self.archive_name);
+        let stem = self.archive_name.rsplit_once('.').map_or(self.archive_name.as_str(), |(stem, _)| stem);
+        let file = InputFile::memory(report.into_bytes()).file_name(format!("{}.txt", stem));
+        let mut request = bot.send_document(self.chat_id, file).caption(summary);
+        if let Some(topic) = self.topic {
+            request = request.message_thread_id(topic);
+        }
+        if let Some(reply_to) = self.reply_to {
+            request = request.reply_to_message_id(reply_to);
+        }
+        if let Err(e) = crate::telegram::send(request).await {
+            warn!("Failed to send the report for {}: {}", self.archive_name, e);
+            return;
+        }
+        if let Some(message_id) = self.status_message {
+            bot.delete_message(self.chat_id, message_id).await.ok();
+        }
+    }
+}
+
+/// `📦 calls.zip: 2 of 3 files transcribed`
+fn summary(locale: Locale, archive_name: &str, entries: &[(&str, Outcome)]) -> String {
+    let done = entries.iter().filter(|(_, outcome)| !matches!(outcome, Outcome::Failed(_))).count();
+    i18n::tf(
+        locale,
+        "batch.summary",
+        &[("file", archive_name.to_string()), ("done", done.to_string()), ("count", entries.len().to_string())],
+    )
+}
+
+/// The report: a summary, then each file's name and transcript in archive
+/// order.
+fn report(locale: Locale, archive_name: &str, entries: &[(&str, Outcome)], skipped: usize) -> String {
+    let mut report = summary(locale, archive_name, entries);
+    if skipped > 0 {
+        report.push('\n');
+        report.push_str(&i18n::tf(
+            locale,
+            "batch.skipped",
+            &[("count", skipped.to_string()), ("max", MAX_FILES.to_string())],
+        ));
+    }
+    for (i, (name, outcome)) in entries.iter().enumerate() {
+        let body = match outcome {
+            Outcome::Transcript(text) => text.trim().to_string(),
+            Outcome::NoSpeech => i18n::t(locale, "batch.no_speech").to_string(),
+            Outcome::Failed(error) => format!("❌ {}", error),
+        };
+        report.push_str(&format!("\n\n── {}. {} ──\n{}", i + 1, name, body));
+    }
+    report.push('\n');
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_unpack_into() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("calls.zip");
+        let wav = crate::selftest::sample_wav();
+        write_zip(
+            &archive,
+            &[
+                ("day 1/call.wav", &wav),
+                ("notes.txt", b"not audio"),
+                ("../escape.wav", &wav),
+                ("__MACOSX/day 1/._call.wav", b"metadata"),
+                ("silence.wav", &[0; 2048]),
+            ],
+        );
+
+        let media_dir = dir.path().join("media");
+        let unpacked = unpack_into(&archive, Some(1024 + wav.len() as u64), |id| media_dir.join(id)).unwrap();
+        assert_eq!(unpacked.files.len(), 2);
+        assert_eq!(unpacked.files[0].name, "day 1/call.wav");
+        assert_eq!(std::fs::read(unpacked.files[0].media.path()).unwrap(), wav);
+        assert_eq!(unpacked.skipped, 3);
+
+        let unpacked = unpack_into(&archive, Some(1024), |id| media_dir.join(id)).unwrap();
+        assert_eq!(unpacked.files.len(), 0);
+        assert_eq!(unpacked.skipped, 5);
+
+        let not_zip = dir.path().join("fake.zip");
+        std::fs::write(&not_zip, b"PK but not really").unwrap();
+        assert!(matches!(unpack_into(&not_zip, None, |id| media_dir.join(id)), Err(BotError::Archive(ArchiveError::Invalid(_)))));
+    }
+
+    #[test]
+    fn test_report() {
+        let entries = [
+            ("a.mp3", Outcome::Transcript(" Hello there. ".to_string())),
+            ("b.wav", Outcome::NoSpeech),
+            ("c.ogg", Outcome::Failed("Couldn't process your audio.".to_string())),
+        ];
+        assert_eq!(
+            report(Locale::En, "calls.zip", &entries, 1),
+            "📦 calls.zip: 2 of 3 files transcribed\n\
+             Skipped in the archive: 1 (not audio or video, over the size limit or past the first 20 files)\n\n\
+             ── 1. a.mp3 ──\nHello there.\n\n\
+             ── 2. b.wav ──\n🔇 No speech detected\n\n\
+             ── 3. c.ogg ──\n❌ Couldn't process your audio.\n"
+        );
+        assert!(is_archive(None, Some("Calls.ZIP")));
+        assert!(is_archive(Some("application/zip"), None));
+        assert!(!is_archive(Some("audio/mpeg"), Some("call.mp3")));
+    }
+}
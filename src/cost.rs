@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::warn;
+use serde::{Deserialize, Serialize};
+use teloxide::types::UserId;
+use tokio::sync::RwLock;
+use crate::{BotConfig, i18n::{self, Locale}, quota::{self, QuotaData}, stt::SttProvider};
+
+pub type CostStore = Arc<RwLock<CostData>>;
+
+/// Estimated spend per provider for the current month, persisted in `data/costs.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CostData {
+    /// Accounting month in `YYYY-MM` form; totals reset when it changes.
+    pub month: String,
+    pub providers: HashMap<String, ProviderUsage>,
+    /// Providers whose budget alert has already been sent this month.
+    #[serde(default)]
+    pub alerted: HashSet<String>,
+    /// Estimated spend per user ID.
+    #[serde(default)]
+    pub users: HashMap<u64, f64>,
+}
+
+/// Heaviest users listed by `/usage` for admins.
+const TOP_USERS: usize = 10;
+
+/// Key in `CostData::alerted` for the `MONTHLY_BUDGET_USD` alert.
+const TOTAL_ALERT: &str = "total";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ProviderUsage {
+    pub requests: u64,
+    pub seconds: u64,
+    pub spend_usd: f64,
+}
+
+/// Default list prices in USD per audio minute, overridable via `PROVIDER_PRICES`.
+pub fn default_price_per_minute(provider: SttProvider) -> f64 {
+    match provider {
+        SttProvider::Whisper => 0.006,
+        SttProvider::ElevenLabs => 0.0067,
+        SttProvider::Google => 0.016,
+        SttProvider::Deepgram => 0.0043,
+        SttProvider::LocalWhisper | SttProvider::Vosk | SttProvider::Mock => 0.0,
+        SttProvider::Azure => 0.0167,
+        SttProvider::AssemblyAi => 0.0062,
+    }
+}
+
+/// Parses `name:value` pairs such as `deepgram:20,whisper:7.5`.
+pub fn parse_provider_amounts(raw: &str) -> Result<HashMap<SttProvider, f64>, String> {
+    let mut amounts = HashMap::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, value) = entry
+            .split_once(':')
+            .ok_or_else(|| format!("expected provider:amount, got '{}'", entry))?;
+        let provider = SttProvider::from_str(name.trim())
+            .ok_or_else(|| format!("unknown provider '{}'", name.trim()))?;
+        let value = value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("invalid amount '{}' for {}", value.trim(), name.trim()))?;
+        amounts.insert(provider, value);
+    }
+    Ok(amounts)
+}
+
+pub fn price_per_minute(provider: SttProvider, config: &BotConfig) -> f64 {
+    config
+        .provider_prices
+        .get(&provider)
+        .copied()
+        .unwrap_or_else(|| default_price_per_minute(provider))
+}
+
+impl CostData {
+    /// Resets totals and alerts if the stored month is not the current one.
+    /// Returns true if anything changed.
+    pub fn roll_month(&mut self, month: &str) -> bool {
+        if self.month == month {
+            return false;
+        }
+        self.month = month.to_string();
+        self.providers.clear();
+        self.alerted.clear();
+        self.users.clear();
+        true
+    }
+
+    pub fn spend(&self, provider: SttProvider) -> f64 {
+        self.providers
+            .get(provider.as_str())
+            .map(|u| u.spend_usd)
+            .unwrap_or(0.0)
+    }
+
+    pub fn total_spend(&self) -> f64 {
+        self.providers.values().map(|u| u.spend_usd).sum()
+    }
+
+    /// Whether this month's spend across all providers reached
+    /// `MONTHLY_BUDGET_USD`.
+    pub fn over_total_budget(&self, config: &BotConfig) -> bool {
+        config.monthly_budget_usd.is_some_and(|cap| self.total_spend() >= cap)
+    }
+
+    /// Over its own cap, or a paid provider once the total budget is spent.
+    /// Free (self-hosted) providers stay usable past the total budget.
+    pub fn over_budget(&self, provider: SttProvider, config: &BotConfig) -> bool {
+        self.over_provider_budget(provider, config)
+            || (self.over_total_budget(config) && price_per_minute(provider, config) > 0.0)
+    }
+
+    pub fn over_provider_budget(&self, provider: SttProvider, config: &BotConfig) -> bool {
+        config
+            .provider_budgets
+            .get(&provider)
+            .is_some_and(|&cap| self.spend(provider) >= cap)
+    }
+
+    /// Records a completed request and returns its estimated cost in USD.
+    pub fn record(&mut self, provider: SttProvider, seconds: u64, config: &BotConfig) -> f64 {
+        let cost = seconds as f64 / 60.0 * price_per_minute(provider, config);
+        let usage = self.providers.entry(provider.as_str().to_string()).or_default();
+        usage.requests += 1;
+        usage.seconds += seconds;
+        usage.spend_usd += cost;
+        cost
+    }
+
+    pub fn charge_user(&mut self, user_id: UserId, cost: f64) {
+        *self.users.entry(user_id.0).or_default() += cost;
+    }
+
+    /// Picks `preferred` if it is within budget, otherwise the first other
+    /// configured provider that still has budget left, cheapest first.
+    pub fn choose_provider(&self, preferred: SttProvider, config: &BotConfig) -> Option<SttProvider> {
+        if !self.over_budget(preferred, config) {
+            return Some(preferred);
+        }
+
+        let mut candidates: Vec<_> = SttProvider::ALL
+            .into_iter()
+            .filter(|&p| p != preferred && config.has_provider_key(p) && !self.over_budget(p, config))
+            .collect();
+        candidates.sort_by(|&a, &b| price_per_minute(a, config).total_cmp(&price_per_minute(b, config)));
+        let fallback = candidates.first().copied();
+
+        if fallback.is_none() {
+            warn!("All configured providers are over their monthly budget");
+        }
+        fallback
+    }
+
+    /// Multi-line spend summary for the admin `/status` view.
+    pub fn summary(&self, config: &BotConfig) -> String {
+        let mut lines = vec![format!("💸 Spend for {} (estimated):", self.month)];
+        let mut total_seconds = 0;
+        let mut total_spend = 0.0;
+
+        for provider in SttProvider::ALL {
+            let usage = self.providers.get(provider.as_str()).cloned().unwrap_or_default();
+            let cap = config.provider_budgets.get(&provider);
+            if usage.requests == 0 && cap.is_none() {
+                continue;
+            }
+            total_seconds += usage.seconds;
+            total_spend += usage.spend_usd;
+
+            let budget = match cap {
+                Some(&cap) => format!(", ${:.2} of ${:.2} left", (cap - usage.spend_usd).max(0.0), cap),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "• {}: ${:.2} · {:.1} min · {} req{}",
+                provider.as_str(),
+                usage.spend_usd,
+                usage.seconds as f64 / 60.0,
+                usage.requests,
+                budget
+            ));
+        }
+
+        if lines.len() == 1 {
+            lines.push("• no usage yet".to_string());
+        }
+        let budget = match config.monthly_budget_usd {
+            Some(cap) => format!(" · ${:.2} of ${:.2} left", (cap - total_spend).max(0.0), cap),
+            None => String::new(),
+        };
+        lines.push(format!(
+            "Total: ${:.2} · {:.1} min of audio{}",
+            total_spend,
+            total_seconds as f64 / 60.0,
+            budget
+        ));
+        lines.join("\n")
+    }
+
+    /// `/usage` text: the user's own month, plus every user's and each
+    /// provider's for admins.
+    pub fn usage_report(&self, quotas: &QuotaData, user_id: UserId, is_admin: bool, config: &BotConfig, locale: Locale) -> String {
+        let spend = |id: u64| self.users.get(&id).copied().unwrap_or(0.0);
+        let mut lines = vec![i18n::tf(
+            locale,
+            "usage.own",
+            &[
+                ("month", self.month.clone()),
+                ("audio", quota::format_minutes(quotas.used_seconds(user_id))),
+                ("spend", format!("{:.2}", spend(user_id.0))),
+            ],
+        )];
+        if !is_admin {
+            return lines.join("\n");
+        }
+
+        let mut users: Vec<_> = quotas.users.iter().filter(|(_, q)| q.used_seconds > 0).collect();
+        users.sort_by(|a, b| b.1.used_seconds.cmp(&a.1.used_seconds).then(a.0.cmp(b.0)));
+        let seconds: u64 = users.iter().map(|(_, q)| q.used_seconds).sum();
+        let all_users = i18n::tf(
+            locale,
+            "usage.all_users",
+            &[
+                ("audio", quota::format_minutes(seconds)),
+                ("users", users.len().to_string()),
+                ("spend", format!("{:.2}", self.users.values().sum::<f64>())),
+            ],
+        );
+        lines.push(format!("\n{}", all_users));
+        for (id, usage) in users.into_iter().take(TOP_USERS) {
+            let name = usage.username.as_ref().map(|name| format!("@{}", name)).unwrap_or_else(|| id.to_string());
+            lines.push(format!("• {}: {} · ${:.2}", name, quota::format_minutes(usage.used_seconds), spend(*id)));
+        }
+
+        lines.push(String::new());
+        lines.push(self.summary(config));
+        lines.join("\n")
+    }
+
+    /// Marks a provider as alerted; returns true the first time each month.
+    pub fn mark_alerted(&mut self, provider: SttProvider) -> bool {
+        self.alerted.insert(provider.as_str().to_string())
+    }
+
+    /// Marks the `MONTHLY_BUDGET_USD` alert as sent; true the first time
+    /// each month.
+    pub fn mark_total_alerted(&mut self) -> bool {
+        self.alerted.insert(TOTAL_ALERT.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_provider_amounts() {
+        let amounts = parse_provider_amounts("deepgram:20, whisper:7.5").unwrap();
+        assert_eq!(amounts[&SttProvider::Deepgram], 20.0);
+        assert_eq!(amounts[&SttProvider::Whisper], 7.5);
+
+        assert!(parse_provider_amounts("").unwrap().is_empty());
+        assert!(parse_provider_amounts("nope:1").is_err());
+        assert!(parse_provider_amounts("deepgram").is_err());
+        assert!(parse_provider_amounts("deepgram:abc").is_err());
+    }
+
+    #[test]
+    fn test_usage_report() {
+        let config = crate::BotConfig::for_tests();
+        let mut costs = CostData { month: "2026-03".to_string(), ..Default::default() };
+        let mut quotas = QuotaData { month: "2026-03".to_string(), ..Default::default() };
+        for (user, username, seconds) in [(1, Some("alice"), 90), (2, None, 600)] {
+            quotas.record_usage(UserId(user), username, seconds);
+            let cost = costs.record(SttProvider::Deepgram, seconds, &config);
+            costs.charge_user(UserId(user), cost);
+        }
+
+        assert_eq!(
+            costs.usage_report(&quotas, UserId(1), false, &config, Locale::En),
+            "📈 Usage for 2026-03\nYou: 1:30 of audio · $0.01 estimated"
+        );
+
+        let report = costs.usage_report(&quotas, UserId(1), true, &config, Locale::En);
+        assert!(report.contains("👥 All users: 11:30 of audio from 2 users · $0.05 estimated\n• 2: 10:00 · $0.04\n• @alice: 1:30 · $0.01"));
+        assert!(report.contains("deepgram: $0.05 · 11.5 min · 2 req"));
+    }
+
+    #[test]
+    fn test_summary_includes_remaining_budget() {
+        let config = crate::BotConfig {
+            provider_budgets: HashMap::from([(SttProvider::Deepgram, 10.0)]),
+            ..crate::BotConfig::for_tests()
+        };
+        let mut data = CostData { month: "2026-03".to_string(), ..Default::default() };
+        data.providers.insert("deepgram".to_string(), ProviderUsage { requests: 2, seconds: 120, spend_usd: 2.5 });
+
+        let summary = data.summary(&config);
+        assert!(summary.contains("deepgram: $2.50 · 2.0 min · 2 req, $7.50 of $10.00 left"));
+        assert!(summary.contains("Total: $2.50 · 2.0 min"));
+        assert!(!summary.contains("whisper"));
+    }
+
+    #[test]
+    fn test_monthly_budget_prefers_free_providers() {
+        let mut config = crate::BotConfig {
+            openai_api_key: Some("key".to_string()),
+            deepgram_api_key: Some("key".to_string()),
+            monthly_budget_usd: Some(5.0),
+            ..crate::BotConfig::for_tests()
+        };
+        let mut data = CostData { month: "2026-03".to_string(), ..Default::default() };
+        data.providers.insert("deepgram".to_string(), ProviderUsage { requests: 1, seconds: 60, spend_usd: 3.0 });
+        assert_eq!(data.choose_provider(SttProvider::Deepgram, &config), Some(SttProvider::Deepgram));
+
+        data.providers.insert("whisper".to_string(), ProviderUsage { requests: 1, seconds: 60, spend_usd: 2.0 });
+        assert!(data.over_total_budget(&config));
+        assert_eq!(data.choose_provider(SttProvider::Deepgram, &config), None);
+        assert!(data.summary(&config).contains("Total: $5.00 · 2.0 min of audio · $0.00 of $5.00 left"));
+
+        config.whisper_model_path = Some("model.bin".into());
+        assert_eq!(data.choose_provider(SttProvider::Deepgram, &config), Some(SttProvider::LocalWhisper));
+        assert!(!data.over_budget(SttProvider::LocalWhisper, &config));
+
+        assert!(data.mark_total_alerted());
+        assert!(!data.mark_total_alerted());
+        assert!(data.roll_month("2026-04"));
+        assert!(!data.over_total_budget(&config));
+    }
+
+    #[test]
+    fn test_roll_month_clears_totals() {
+        let mut data = CostData { month: "2026-01".to_string(), ..Default::default() };
+        data.providers.insert("deepgram".to_string(), ProviderUsage { requests: 1, seconds: 60, spend_usd: 1.0 });
+        data.mark_alerted(SttProvider::Deepgram);
+
+        assert!(data.roll_month("2026-02"));
+        assert_eq!(data.spend(SttProvider::Deepgram), 0.0);
+        assert!(data.mark_alerted(SttProvider::Deepgram));
+        assert!(!data.mark_alerted(SttProvider::Deepgram));
+    }
+}
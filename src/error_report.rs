@@ -0,0 +1,224 @@
+use crate::i18n::{self, Locale};
+use crate::{BotConfig, BotError};
+use tracing::warn;
+use serde_json::json;
+
+const CLIENT_NAME: &str = concat!("telegram-stt-bot/", env!("CARGO_PKG_VERSION"));
+
+/// Starts the Sentry client when `SENTRY_DSN` is set, tagging events with
+/// this build's release and `SENTRY_ENVIRONMENT`. Queued events are
+/// flushed when the guard is dropped.
+pub fn init(config: &BotConfig) -> Option<sentry::ClientInitGuard> {
+    let dsn = config.sentry_dsn.clone()?;
+    Some(sentry::init(sentry::ClientOptions {
+        dsn: Some(dsn),
+        release: sentry::release_name!(),
+        environment: config.sentry_environment.clone().map(Into::into),
+        ..Default::default()
+    }))
+}
+
+/// What was going on when a transcription failed.
+#[derive(Debug, Clone, Default)]
+pub struct FailureContext {
+    pub item_id: String,
+    pub provider: Option<&'static str>,
+    /// `private` or `group`.
+    pub chat_type: &'static str,
+    pub file_size: u64,
+    pub duration_secs: u32,
+}
+
+/// Failures the user caused or asked for; they aren't reported.
+fn is_expected(error: &BotError) -> bool {
+    matches!(
+        error,
+        BotError::QuotaExceeded(_)
+            | BotError::BudgetExhausted
+            | BotError::Cancelled
+            | BotError::SilentAudio
+            | BotError::MusicDetected
+            | BotError::TooLong(..)
+            | BotError::FileTooLarge(..)
+            | BotError::QueueFull
+            | BotError::LowDiskSpace(..)
+            | BotError::MediaNotAllowed(_)
+            | BotError::Audio(crate::audio::AudioError::UnsupportedFormat(_))
+    )
+}
+
+/// The step of processing an error came from, for grouping reports and
+/// telling the sender.
+fn stage(error: &BotError) -> &'static str {
+    match error {
+        BotError::Download(_) | BotError::Telegram(_) | BotError::DownloadFailed { .. } => "download",
+        BotError::Audio(_) => "conversion",
+        BotError::Stt(_) | BotError::Http(_) => "transcription",
+        BotError::Io(_) => "storage",
+        _ => "processing",
+    }
+}
+
+/// Whether sending the same file again might work: the failure was in
+/// the network, a provider or the bot, not the file.
+pub fn is_retryable(error: &BotError) -> bool {
+    use crate::audio::AudioError;
+    match error {
+        BotError::Stt(e) => e.is_transient() || matches!(e, crate::stt::SttError::InvalidResponse(_)),
+        BotError::Audio(e) => matches!(e, AudioError::TempFile(_) | AudioError::Io(_)),
+        BotError::DownloadFailed { source, .. } => is_retryable(source),
+        BotError::Download(_) | BotError::Telegram(_) | BotError::Http(_) | BotError::Io(_) | BotError::QueueBackend(_) => true,
+        _ => false,
+    }
+}
+
+/// The short form of a queue item's ID that error replies show and logs
+/// can be searched for.
+pub fn error_id(item_id: &str) -> &str {
+    &item_id[..item_id.len().min(8)]
+}
+
+/// `message` for the sender, followed by the stage that failed, the
+/// item's error ID and whether sending the file again could help.
+pub fn user_reply(message: &str, error: &BotError, item_id: &str, locale: Locale) -> String {
+    let stage = i18n::t(locale, &format!("error.stage.{}", stage(error))).to_string();
+    let details = i18n::tf(locale, "error.details", &[("stage", stage), ("id", error_id(item_id).to_string())]);
+    let hint = i18n::t(locale, if is_retryable(error) { "error.retry" } else { "error.no_retry" });
+    format!("{}\n\n{}\n{}", message, details, hint)
+}
+
+/// Variant name of the error, e.g. `Stt` for `Stt(Timeout)`.
+fn error_type(error: &BotError) -> String {
+    let debug = format!("{:?}", error);
+    debug.split(['(', ' ', '{']).next().unwrap_or_default().to_string()
+}
+
+fn event(error: &BotError, context: &FailureContext, event_id: &str) -> serde_json::Value {
+    json!({
+        "event_id": event_id,
+        "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "platform": "rust",
+        "level": "error",
+        "logger": "telegram_stt_bot::queue",
+        "release": CLIENT_NAME,
+        "message": { "formatted": format!("Failed to process queue item: {}", error) },
+        "exception": { "values": [{ "type": error_type(error), "value": error.to_string() }] },
+        "tags": {
+            "stage": stage(error),
+            "provider": context.provider.unwrap_or("none"),
+            "chat_type": context.chat_type,
+            "error_id": error_id(&context.item_id),
+        },
+        "extra": {
+            "item_id": context.item_id,
+            "file_size": context.file_size,
+            "duration_secs": context.duration_secs,
+        },
+    })
+}
+
+/// Reports a failed transcription to Sentry, tagged with the item,
+/// provider and chat type, and posts it to `ERROR_WEBHOOK_URL` in the
+/// background. Without a Sentry client the capture does nothing; webhook
+/// failures are only logged.
+pub fn report(error: &BotError, context: FailureContext, config: &BotConfig) {
+    if is_expected(error) {
+        return;
+    }
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("item_id", &context.item_id);
+            scope.set_tag("error_id", error_id(&context.item_id));
+            scope.set_tag("provider", context.provider.unwrap_or("none"));
+            scope.set_tag("chat_type", context.chat_type);
+            scope.set_tag("stage", stage(error));
+            scope.set_extra("file_size", context.file_size.into());
+            scope.set_extra("duration_secs", context.duration_secs.into());
+        },
+        || sentry::capture_error(error),
+    );
+
+    let Some(url) = config.error_webhook_url.clone() else {
+        return;
+    };
+    let event = event(error, &context, &uuid::Uuid::new_v4().simple().to_string());
+    tokio::spawn(async move {
+        let result = crate::http::client().post(url).json(&event).send().await.and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            warn!("Failed to report error to the error webhook: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> FailureContext {
+        FailureContext {
+            item_id: "abc-1".to_string(),
+            provider: Some("deepgram"),
+            chat_type: "private",
+            file_size: 2048,
+            duration_secs: 30,
+        }
+    }
+
+    #[test]
+    fn test_event() {
+        let error = BotError::Stt(crate::stt::SttError::Timeout);
+        let event = event(&error, &context(), "0123");
+        assert_eq!(event["exception"]["values"][0]["type"], "Stt");
+        assert_eq!(event["tags"]["stage"], "transcription");
+        assert_eq!(event["tags"]["provider"], "deepgram");
+        assert_eq!(event["extra"]["file_size"], 2048);
+    }
+
+    #[test]
+    fn test_report_to_sentry() {
+        let config = BotConfig::for_tests();
+        let events = sentry::test::with_captured_events(|| {
+            report(&BotError::Stt(crate::stt::SttError::Timeout), context(), &config);
+            report(&BotError::Cancelled, context(), &config);
+        });
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.exception.values.last().unwrap().ty, "Stt");
+        assert_eq!(event.tags["item_id"], "abc-1");
+        assert_eq!(event.tags["provider"], "deepgram");
+        assert_eq!(event.tags["chat_type"], "private");
+        assert_eq!(event.tags["stage"], "transcription");
+        assert_eq!(event.extra["file_size"], 2048);
+    }
+
+    #[test]
+    fn test_expected_errors_are_skipped() {
+        assert!(is_expected(&BotError::Cancelled));
+        assert!(is_expected(&BotError::QuotaExceeded(10)));
+        assert!(!is_expected(&BotError::Stt(crate::stt::SttError::Authentication)));
+        assert_eq!(stage(&BotError::Config("x".to_string())), "processing");
+    }
+
+    #[test]
+    fn test_user_reply() {
+        let item_id = "1a2b3c4d-5e6f-4a5b-8c7d-9e0f1a2b3c4d";
+        let unavailable = BotError::Stt(crate::stt::SttError::ServiceUnavailable);
+        assert_eq!(
+            user_reply("❌ Failed.", &unavailable, item_id, Locale::En),
+            "❌ Failed.\n\nStage: transcription · Error ID: 1a2b3c4d\n🔁 Retrying may help."
+        );
+
+        let corrupt = BotError::Audio(crate::audio::AudioError::ConversionFailed("moov atom not found".to_string()));
+        assert!(user_reply("❌", &corrupt, item_id, Locale::En).ends_with("conversion · Error ID: 1a2b3c4d\nSending the same file again won't help."));
+
+        let download = BotError::DownloadFailed {
+            id: item_id.to_string(),
+            source: Box::new(BotError::Io(std::io::ErrorKind::ConnectionReset.into())),
+        };
+        assert!(is_retryable(&download));
+        assert_eq!(stage(&download), "download");
+        assert!(!is_retryable(&BotError::Stt(crate::stt::SttError::Authentication)));
+        assert_eq!(error_id("short"), "short");
+    }
+}
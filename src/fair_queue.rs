@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use teloxide::types::UserId;
+use tokio::sync::Notify;
+
+/// Work queue that takes turns between senders, so one user sending fifty
+/// files doesn't hold up everyone else. Priority items (admins, when
+/// `ADMIN_PRIORITY` is on) are served before the rest, still taking turns
+/// among themselves.
+///
+/// The capacity is advisory: `push` always succeeds, and callers check
+/// `is_full` first to apply `QUEUE_FULL_POLICY`, counting the places they
+/// hold for files still on their way.
+pub struct FairQueue<T> {
+    lanes: Mutex<Lanes<T>>,
+    capacity: Option<usize>,
+    ready: Notify,
+    space: Notify,
+}
+
+struct Lanes<T> {
+    priority: VecDeque<Lane<T>>,
+    normal: VecDeque<Lane<T>>,
+    /// Arrival counter, to find the oldest item across lanes.
+    next_seq: u64,
+}
+
+/// One sender's items, oldest first, with their arrival numbers.
+struct Lane<T> {
+    user: UserId,
+    items: VecDeque<(u64, T)>,
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        Self::with_capacity(None)
+    }
+}
+
+impl<T> FairQueue<T> {
+    pub fn with_capacity(capacity: Option<usize>) -> Self {
+        Self {
+            lanes: Mutex::new(Lanes { priority: VecDeque::new(), normal: VecDeque::new(), next_seq: 0 }),
+            capacity,
+            ready: Notify::new(),
+            space: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, user: UserId, priority: bool, item: T) {
+        {
+            let mut lanes = self.lanes.lock().expect("queue lock poisoned");
+            let seq = lanes.next_seq;
+            lanes.next_seq += 1;
+            let lanes = if priority { &mut lanes.priority } else { &mut lanes.normal };
+            match lanes.iter_mut().find(|lane| lane.user == user) {
+                Some(lane) => lane.items.push_back((seq, item)),
+                None => lanes.push_back(Lane { user, items: VecDeque::from([(seq, item)]) }),
+            }
+        }
+        self.ready.notify_one();
+    }
+
+    pub fn len(&self) -> usize {
+        let lanes = self.lanes.lock().expect("queue lock poisoned");
+        lanes.priority.iter().chain(&lanes.normal).map(|lane| lane.items.len()).sum()
+    }
+
+    /// Whether the queue is at capacity once `held` more items arrive.
+    pub fn is_full(&self, held: usize) -> bool {
+        self.capacity.is_some_and(|capacity| self.len() + held >= capacity)
+    }
+
+    /// Waits until the worker has taken enough items to get below capacity.
+    pub async fn wait_for_space(&self) {
+        while self.is_full(0) {
+            self.space.notified().await;
+        }
+    }
+
+    /// Takes out the longest-waiting item that isn't a priority one, to
+    /// make room for a new one.
+    pub fn pop_oldest(&self) -> Option<T> {
+        let mut lanes = self.lanes.lock().expect("queue lock poisoned");
+        let index = (0..lanes.normal.len()).min_by_key(|&i| lanes.normal[i].items.front().map(|(seq, _)| *seq))?;
+        let lane = &mut lanes.normal[index];
+        let (_, item) = lane.items.pop_front()?;
+        if lane.items.is_empty() {
+            lanes.normal.remove(index);
+        }
+        Some(item)
+    }
+
+    /// Waits for the next item. Meant for a single worker.
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some(item) = self.try_pop() {
+                self.space.notify_one();
+                return item;
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let mut lanes = self.lanes.lock().expect("queue lock poisoned");
+        let lanes = if lanes.priority.is_empty() { &mut lanes.normal } else { &mut lanes.priority };
+        let mut lane = lanes.pop_front()?;
+        let item = lane.items.pop_front();
+        // The sender goes to the back of the line if they have more
+        if !lane.items.is_empty() {
+            lanes.push_back(lane);
+        }
+        item.map(|(_, item)| item)
+    }
+
+    /// `key` of every waiting item, in the order they will be served if
+    /// nothing else arrives.
+    pub fn order<K>(&self, key: impl Fn(&T) -> K) -> Vec<K> {
+        let lanes = self.lanes.lock().expect("queue lock poisoned");
+        let mut order = Vec::new();
+        for lanes in [&lanes.priority, &lanes.normal] {
+            let rounds = lanes.iter().map(|lane| lane.items.len()).max().unwrap_or(0);
+            for round in 0..rounds {
+                order.extend(lanes.iter().filter_map(|lane| lane.items.get(round)).map(|(_, item)| key(item)));
+            }
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &FairQueue<&'static str>) -> Vec<&'static str> {
+        std::iter::from_fn(|| queue.try_pop()).collect()
+    }
+
+    #[test]
+    fn test_round_robin_between_users() {
+        let queue = FairQueue::default();
+        for item in ["a1", "a2", "a3"] {
+            queue.push(UserId(1), false, item);
+        }
+        queue.push(UserId(2), false, "b1");
+        queue.push(UserId(3), false, "c1");
+        queue.push(UserId(2), false, "b2");
+
+        let expected = ["a1", "b1", "c1", "a2", "b2", "a3"];
+        assert_eq!(queue.len(), 6);
+        assert_eq!(queue.order(|item| *item), expected);
+        assert_eq!(drain(&queue), expected);
+    }
+
+    #[test]
+    fn test_priority_goes_first() {
+        let queue = FairQueue::default();
+        queue.push(UserId(1), false, "a1");
+        queue.push(UserId(1), false, "a2");
+        queue.push(UserId(9), true, "admin1");
+        queue.push(UserId(9), true, "admin2");
+
+        assert_eq!(queue.order(|item| *item), ["admin1", "admin2", "a1", "a2"]);
+        assert_eq!(drain(&queue), ["admin1", "admin2", "a1", "a2"]);
+    }
+
+    #[test]
+    fn test_capacity_and_pop_oldest() {
+        let queue = FairQueue::with_capacity(Some(3));
+        queue.push(UserId(9), true, "admin1");
+        queue.push(UserId(1), false, "a1");
+        queue.push(UserId(2), false, "b1");
+        assert!(queue.is_full(0));
+
+        // Priority items are never the ones dropped
+        assert_eq!(queue.pop_oldest(), Some("a1"));
+        assert!(!queue.is_full(0));
+        assert!(queue.is_full(1));
+        assert_eq!(queue.pop_oldest(), Some("b1"));
+        assert_eq!(queue.pop_oldest(), None);
+        assert_eq!(drain(&queue), ["admin1"]);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_space() {
+        let queue = std::sync::Arc::new(FairQueue::with_capacity(Some(1)));
+        queue.push(UserId(1), false, "a1");
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.wait_for_space().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        assert_eq!(queue.pop().await, "a1");
+        waiter.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pop_waits_for_push() {
+        let queue = std::sync::Arc::new(FairQueue::default());
+        let worker = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.pop().await }
+        });
+        tokio::task::yield_now().await;
+        queue.push(UserId(1), false, "late");
+        assert_eq!(worker.await.unwrap(), "late");
+    }
+}
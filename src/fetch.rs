@@ -0,0 +1,228 @@
+//! Media downloaded from links for /url: straight from the server, or
+//! through yt-dlp for pages that embed it.
+
+use crate::queue::MediaFile;
+use crate::{BotConfig, BotError, Result, audio, persistence};
+use tracing::{info, warn};
+use reqwest::Url;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Redirects followed, each to an allowed domain.
+const MAX_REDIRECTS: usize = 5;
+/// yt-dlp is stopped after this long.
+const YTDLP_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("Not an http(s) link")]
+    InvalidLink,
+    #[error("Domain {0} isn't in URL_ALLOWED_DOMAINS")]
+    DomainNotAllowed(String),
+    #[error("Linked file is over the {0} MB limit")]
+    TooLarge(u32),
+    #[error("Link doesn't lead to audio or video")]
+    NotMedia,
+    #[error("Download failed: {0}")]
+    Failed(String),
+}
+
+/// Whether `host` is one of `domains` or a subdomain of one.
+pub fn domain_allowed(host: &str, domains: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_lowercase();
+    domains
+        .iter()
+        .any(|domain| host == *domain || host.strip_suffix(domain.as_str()).is_some_and(|rest| rest.ends_with('.')))
+}
+
+/// Parses a /url argument, refusing anything but http(s) on an allowed domain.
+pub fn parse_link(link: &str, domains: &[String]) -> std::result::Result<Url, FetchError> {
+    let url = Url::parse(link.trim()).map_err(|_| FetchError::InvalidLink)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(FetchError::InvalidLink);
+    }
+    let host = url.host_str().ok_or(FetchError::InvalidLink)?;
+    if !domain_allowed(host, domains) {
+        return Err(FetchError::DomainNotAllowed(host.to_string()));
+    }
+    Ok(url)
+}
+
+/// A name for what the link points to: its file name, else its host.
+pub fn file_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| name.contains('.'))
+        .map(str::to_string)
+        .unwrap_or_else(|| url.host_str().unwrap_or("link").to_string())
+}
+
+fn is_media_type(content_type: &str, url: &Url) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    match essence {
+        "application/octet-stream" | "binary/octet-stream" | "" => {
+            audio::sniff::is_media_document(None, Some(&file_name(url)))
+        }
+        mime => audio::sniff::is_media_document(Some(mime), None),
+    }
+}
+
+/// What a direct download found.
+#[derive(Debug, PartialEq)]
+enum Direct {
+    Downloaded(u64),
+    /// A page or other non-media reply, for yt-dlp to try.
+    NotMedia,
+}
+
+fn client(domains: &[String]) -> Result<reqwest::Client> {
+    let domains = domains.to_vec();
+    let policy = reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if attempt.url().host_str().is_some_and(|host| domain_allowed(host, &domains)) {
+            attempt.follow()
+        } else {
+            attempt.error("redirected to a domain that isn't allowed")
+        }
+    });
+    Ok(reqwest::Client::builder().redirect(policy).connect_timeout(Duration::from_secs(10)).build()?)
+}
+
+/// Streams `url` into `file`, stopping past `max_bytes`.
+async fn fetch_direct(
+    client: &reqwest::Client,
+    url: &Url,
+    file: &mut tokio::fs::File,
+    max_bytes: u64,
+    max_mb: u32,
+) -> Result<Direct> {
+    let failed = |e: reqwest::Error| BotError::from(FetchError::Failed(e.without_url().to_string()));
+    let mut response = client.get(url.clone()).send().await.map_err(failed)?;
+    if !response.status().is_success() {
+        return Err(FetchError::Failed(format!("HTTP {}", response.status())).into());
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !is_media_type(content_type, response.url()) {
+        return Ok(Direct::NotMedia);
+    }
+    if response.content_length().is_some_and(|len| len > max_bytes) {
+        return Err(FetchError::TooLarge(max_mb).into());
+    }
+
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await.map_err(failed)? {
+        size += chunk.len() as u64;
+        if size > max_bytes {
+            return Err(FetchError::TooLarge(max_mb).into());
+        }
+        file.write_all(&chunk).await.map_err(BotError::Io)?;
+    }
+    file.flush().await.map_err(BotError::Io)?;
+    Ok(Direct::Downloaded(size))
+}
+
+/// Has yt-dlp save the best audio of the page at `url` to `path`.
+async fn fetch_with_ytdlp(ytdlp: &str, url: &Url, path: &Path, max_mb: u32) -> Result<u64> {
+    info!("Fetching {} with yt-dlp", url);
+    let mut command = tokio::process::Command::new(ytdlp);
+    command
+        .args(["--no-playlist", "--no-part", "--quiet", "--no-warnings", "--force-overwrites"])
+        .args(["-f", "bestaudio/best", "--max-filesize", &format!("{}M", max_mb), "-o"])
+        .arg(path)
+        .arg("--")
+        .arg(url.as_str())
+        .kill_on_drop(true);
+    let output = match tokio::time::timeout(YTDLP_TIMEOUT, command.output()).await {
+        Ok(output) => output.map_err(BotError::Io)?,
+        Err(_) => return Err(FetchError::Failed("yt-dlp timed out".to_string()).into()),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        warn!("yt-dlp failed for {}: {}", url, stderr.trim());
+        return Err(FetchError::NotMedia.into());
+    }
+    // --max-filesize skips the download rather than failing
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.len() > 0 => Ok(metadata.len()),
+        _ => Err(FetchError::TooLarge(max_mb).into()),
+    }
+}
+
+/// Downloads the media at `url` for queue item `id`, up to
+/// `URL_MAX_FILE_MB`. Pages that aren't media themselves go to yt-dlp when
+/// `YTDLP_PATH` is set.
+pub async fn download(url: &Url, id: &str, config: &BotConfig) -> Result<MediaFile> {
+    let max_mb = config.url_max_file_mb;
+    let max_bytes = max_mb as u64 * 1024 * 1024;
+    let (path, mut file) = persistence::create_pending_media(&config.queue_dir(), id).await?;
+    let mut media = MediaFile::new(path, 0);
+
+    let size = match fetch_direct(&client(&config.url_allowed_domains)?, url, &mut file, max_bytes, max_mb).await? {
+        Direct::Downloaded(size) => size,
+        Direct::NotMedia => match &config.ytdlp_path {
+            Some(ytdlp) => {
+                drop(file);
+                fetch_with_ytdlp(ytdlp, url, media.path(), max_mb).await?
+            }
+            None => return Err(FetchError::NotMedia.into()),
+        },
+    };
+    media.set_size(size);
+
+    if audio::sniff::detect_file(media.path()).await.map_err(BotError::Io)?.is_none() {
+        return Err(FetchError::NotMedia.into());
+    }
+    Ok(media)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+
+    #[test]
+    fn test_parse_link() {
+        let domains = vec!["example.com".to_string(), "cdn.test".to_string()];
+        assert!(parse_link("https://example.com/a.mp3", &domains).is_ok());
+        assert!(parse_link("https://media.Example.com./a.mp3", &domains).is_ok());
+        assert!(matches!(parse_link("https://badexample.com/a.mp3", &domains), Err(FetchError::DomainNotAllowed(_))));
+        assert!(matches!(parse_link("https://example.com.evil.io/a.mp3", &domains), Err(FetchError::DomainNotAllowed(_))));
+        assert!(matches!(parse_link("ftp://example.com/a.mp3", &domains), Err(FetchError::InvalidLink)));
+        assert!(matches!(parse_link("file:///etc/passwd", &domains), Err(FetchError::InvalidLink)));
+        assert!(matches!(parse_link("not a link", &domains), Err(FetchError::InvalidLink)));
+
+        assert_eq!(file_name(&Url::parse("https://example.com/talks/intro.mp3?x=1").unwrap()), "intro.mp3");
+        assert_eq!(file_name(&Url::parse("https://example.com/watch?v=1").unwrap()), "example.com");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_direct() {
+        let wav = crate::selftest::sample_wav();
+        let audio = warp::path!("a.wav").map(move || warp::reply::with_header(wav.clone(), "content-type", "audio/wav"));
+        let page = warp::path!("page").map(|| warp::reply::html("<html></html>"));
+        let away = warp::path!("away").map(|| warp::redirect::temporary(warp::http::Uri::from_static("http://localhost:1/a.wav")));
+        let (address, server) = warp::serve(audio.or(page).or(away)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let domains = vec!["127.0.0.1".to_string()];
+        let client = client(&domains).unwrap();
+        let url = |path: &str| Url::parse(&format!("http://{}/{}", address, path)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = tokio::fs::File::create(dir.path().join("media")).await.unwrap();
+
+        let size = crate::selftest::sample_wav().len() as u64;
+        assert_eq!(fetch_direct(&client, &url("a.wav"), &mut file, size, 1).await.unwrap(), Direct::Downloaded(size));
+        assert!(matches!(
+            fetch_direct(&client, &url("a.wav"), &mut file, size - 1, 1).await,
+            Err(BotError::Fetch(FetchError::TooLarge(1)))
+        ));
+        assert_eq!(fetch_direct(&client, &url("page"), &mut file, size, 1).await.unwrap(), Direct::NotMedia);
+        assert!(matches!(fetch_direct(&client, &url("away"), &mut file, size, 1).await, Err(BotError::Fetch(FetchError::Failed(_)))));
+    }
+}
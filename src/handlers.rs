@@ -1,10 +1,10 @@
-use crate::{audio, stt, BotConfig, BotError, Result, AuthorizedUsers, CurrentProvider, queue, persistence};
-use log::{error, info};
+use crate::{audio, llm, tts, reload, SharedConfig, roles::RoleChange, stt, subtitles, BotConfig, BotError, Result, UserRoles, CurrentProvider, UsageStores, queue, persistence, quota, cost, history, selftest, settings, i18n, batch, fetch, request_logger};
+use crate::i18n::Locale;
+use tracing::{Instrument, error, info, warn};
 use teloxide::{
     prelude::*,
-    types::MessageKind,
+    types::{InlineKeyboardButtonKind, InputFile, Me, MessageEntityKind, MessageId, MessageKind},
     utils::command::BotCommands,
-    net::Download,
 };
 
 #[derive(BotCommands, Clone)]
@@ -20,48 +20,170 @@ pub enum Command {
     Queue,
     #[command(description = "Show credits for a provider: /credits [deepgram|elevenlabs]")]
     Credits(String),
-    #[command(description = "Show current STT provider")]
-    Provider,
-    #[command(description = "Switch STT provider (admin only): /setprovider <whisper|elevenlabs|google|deepgram>")]
+    #[command(description = "Show the STT provider, or choose one for this chat: /provider [<name>|default]")]
+    Provider(String),
+    #[command(description = "Switch STT provider (admin only): /setprovider <whisper|elevenlabs|google|deepgram|azure|assemblyai|local-whisper|vosk>")]
     SetProvider(String),
+    #[command(description = "Run a sample clip through the whole pipeline (admin only)")]
+    SelfTest,
+    #[command(description = "Re-read API keys, provider, limits and password from the environment (admin only)")]
+    Reload,
+    #[command(description = "Show your monthly transcription quota")]
+    Quota,
+    #[command(description = "Show audio minutes and estimated cost this month; admins also see every user and provider")]
+    Usage,
+    #[command(description = "List your recent transcriptions, or re-send one in full: /history [<n>]")]
+    History(String),
+    #[command(description = "Grant quota (admin only): /grant <@user|id> <minutes> or /grant <@user|id> limit <minutes>")]
+    Grant(String),
+    #[command(description = "Ignore a user's messages (admin only): /ban <@user|id>")]
+    Ban(String),
+    #[command(description = "Lift a ban (admin only): /unban <@user|id>")]
+    Unban(String),
+    #[command(description = "Give a user access without the password (admin only): /authorize <@user|id>")]
+    Authorize(String),
+    #[command(description = "Take away a user's access (admin only): /revoke <@user|id>")]
+    Revoke(String),
+    #[command(description = "Forget your password login; send the password again to come back")]
+    Logout,
+    #[command(description = "Show usage statistics for all users (admin only)")]
+    Stats,
+    #[command(description = "Show who keeps sending the same clip (admin only)")]
+    Spam,
+    #[command(description = "Download the request log as CSV (admin only): /export [YYYY-MM]")]
+    Export(String),
+    #[command(description = "Reply to a media message (or caption one) to transcribe it: /transcribe [12:30-18:00] [phone]")]
+    Transcribe(String),
+    #[command(description = "Transcribe audio or video from a link: /url <link>")]
+    Url(String),
+    #[command(description = "Reply to a transcript or any text message to get a bullet-point summary")]
+    Summarize,
+    #[command(description = "Reply to a transcript or any text message to hear it read aloud as a voice message")]
+    Speak,
+    #[command(description = "Set the spoken language for this chat: /language <code> | /language auto")]
+    Language(String),
+    #[command(description = "Treat media in this chat as phone call recordings: /phonecall on|off")]
+    PhoneCall(String),
+    #[command(description = "Post transcripts without replying to or quoting the sender: /anonymous on|off")]
+    Anonymous(String),
+    #[command(description = "Mask profanity in transcripts: /filter on|off")]
+    Filter(String),
+    #[command(description = "Prefix transcripts with the detected language and duration: /langline on|off")]
+    LangLine(String),
+    #[command(description = "Also attach each transcript as a .json file with timestamps and confidences: /json on|off")]
+    Json(String),
+    #[command(description = "Attach .srt and .vtt subtitles to transcripts of videos: /subtitles on|off")]
+    Subtitles(String),
+    #[command(description = "Show language, duration, provider and word count under transcripts: /metadata on|off")]
+    Metadata(String),
+    #[command(description = "What the bot transcribes unprompted in this group: /groupmode all|mention|reply|default")]
+    GroupMode(String),
+    #[command(description = "Clean up audio before transcribing: /preprocess loudnorm highpass lowpass denoise | off | default")]
+    Preprocess(String),
+    #[command(description = "Limit media transcribed in this chat: /media all | /media voice videonote [noforward] | /media message <text>")]
+    Media(String),
+    #[command(description = "Language of the bot's messages in this chat: /botlanguage en | ru | auto")]
+    BotLanguage(String),
+    #[command(description = "Layout of transcripts in this chat: /template {emoji} {text} · {provider} | /template default")]
+    Template(String),
+    #[command(description = "Only post transcripts in this group, without queue and progress messages: /quiet on|off")]
+    Quiet(String),
+    #[command(description = "Hold transcripts back, or post them silently, during these hours: /quiethours 23:00-07:00 [UTC+3] [delay|silent] | off")]
+    QuietHours(String),
+    #[command(description = "Settings commands sent in a forum topic apply to that topic only: /topic | /topic reset")]
+    Topic(String),
 }
 
-async fn is_authorized(msg: &Message, config: &BotConfig, authorized_users: &AuthorizedUsers) -> bool {
+impl Command {
+    /// Commands that change per-chat settings; routed to `settings_handler`.
+    pub fn is_chat_setting(&self) -> bool {
+        matches!(
+            self,
+            Command::PhoneCall(_)
+                | Command::Language(_)
+                | Command::Anonymous(_)
+                | Command::Filter(_)
+                | Command::LangLine(_)
+                | Command::Json(_)
+                | Command::Subtitles(_)
+                | Command::Metadata(_)
+                | Command::GroupMode(_)
+                | Command::Preprocess(_)
+                | Command::Media(_)
+                | Command::BotLanguage(_)
+                | Command::Quiet(_)
+                | Command::QuietHours(_)
+                | Command::Topic(_)
+                | Command::Template(_)
+        ) || matches!(self, Command::Provider(arg) if !arg.trim().is_empty())
+    }
+}
+
+/// The channel behind a channel post, or behind its automatic forward into
+/// the channel's discussion group.
+fn channel_sender(msg: &Message) -> Option<&teloxide::types::Chat> {
+    msg.sender_chat().filter(|chat| chat.is_channel())
+}
+
+async fn is_authorized(msg: &Message, config: &BotConfig, roles: &UserRoles) -> bool {
+    // Channels can't send the password; with one set they must be listed
+    if let Some(channel) = channel_sender(msg) {
+        return config.bot_password.is_none() || config.channel_ids.contains(&channel.id);
+    }
+
     let user_id = match msg.from() {
         Some(user) => user.id,
         None => return false,
     };
-
-    // If no password is configured, allow all users
-    let Some(password) = &config.bot_password else {
+    if is_user_authorized(user_id, config, roles).await {
         return true;
+    }
+    let Some(password) = &config.bot_password else {
+        return false;
     };
 
-    // Check if user is already authorized
+    // Check if current message is the password
+    if let Some(text) = msg.text()
+        && text == password
+        && !roles.read().await.banned.contains(&user_id)
     {
-        let users = authorized_users.read().await;
-        if users.contains(&user_id) {
-            return true;
+        // Authorize the user
+        let mut roles = roles.write().await;
+        roles.authorized.insert(user_id, chrono::Utc::now());
+
+        // Save to persistent storage
+        if let Err(e) = persistence::save_authorized_users(&roles.authorized).await {
+            error!("Failed to save authorized users: {}", e);
         }
+
+        return true;
     }
 
-    // Check if current message is the password
-    if let Some(text) = msg.text() {
-        if text == password {
-            // Authorize the user
-            let mut users = authorized_users.write().await;
-            users.insert(user_id);
-
-            // Save to persistent storage
-            if let Err(e) = persistence::save_authorized_users(&users).await {
-                error!("Failed to save authorized users: {}", e);
-            }
+    false
+}
 
-            return true;
-        }
+/// Whether `user_id` may use the bot without sending the password: not
+/// banned, and authorized unless no password is configured.
+async fn is_user_authorized(user_id: UserId, config: &BotConfig, roles: &UserRoles) -> bool {
+    // Banned users are ignored even when no password is configured
+    if roles.read().await.banned.contains(&user_id) {
+        return false;
     }
 
-    false
+    // If no password is configured, allow all users
+    if config.bot_password.is_none() {
+        return true;
+    }
+
+    // Check if user is already authorized and the authorization still valid
+    let mut roles = roles.write().await;
+    if roles.expire(config.auth_ttl(), chrono::Utc::now()) {
+        info!("Expired stale authorizations, {} users remain authorized", roles.authorized.len());
+        if let Err(e) = persistence::save_authorized_users(&roles.authorized).await {
+            error!("Failed to save authorized users: {}", e);
+        }
+    }
+    roles.authorized.contains_key(&user_id)
 }
 
 fn is_admin(msg: &Message, config: &BotConfig) -> bool {
@@ -70,63 +192,132 @@ fn is_admin(msg: &Message, config: &BotConfig) -> bool {
         .unwrap_or(false)
 }
 
-fn provider_key_configured(provider: stt::SttProvider, config: &BotConfig) -> bool {
-    match provider {
-        stt::SttProvider::Whisper => config.openai_api_key.is_some(),
-        stt::SttProvider::ElevenLabs => config.elevenlabs_api_key.is_some(),
-        stt::SttProvider::Google => config.google_credentials_json.is_some(),
-        stt::SttProvider::Deepgram => config.deepgram_api_key.is_some(),
+/// Providers with credentials, as `/provider` choices: `whisper|deepgram`.
+fn configured_providers(config: &BotConfig) -> String {
+    stt::SttProvider::ALL
+        .into_iter()
+        .filter(|&p| config.has_provider_key(p))
+        .map(|p| p.as_str())
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Anyone may change settings of their private chat; in groups only bot
+/// admins and the group's own administrators can.
+async fn can_change_chat_settings(bot: &Bot, msg: &Message, config: &BotConfig) -> ResponseResult<bool> {
+    if msg.chat.is_private() || is_admin(msg, config) {
+        return Ok(true);
     }
+    let Some(user) = msg.from() else {
+        return Ok(false);
+    };
+    let member = bot.get_chat_member(msg.chat.id, user.id).await?;
+    Ok(member.is_privileged())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn command_handler(
     bot: Bot,
     msg: Message,
     cmd: Command,
-    config: BotConfig,
-    authorized_users: AuthorizedUsers,
+    shared_config: SharedConfig,
+    roles: UserRoles,
     queue_stats: queue::QueueStats,
     current_provider: CurrentProvider,
+    usage: UsageStores,
+    settings_store: settings::ChatSettingsStore,
 ) -> ResponseResult<()> {
-    if !is_authorized(&msg, &config, &authorized_users).await {
+    let config = shared_config.read().await.clone();
+    if !is_authorized(&msg, &config, &roles).await {
         return Ok(());
     }
+    let locale = message_locale(&msg, &settings::get_for(&settings_store, msg.chat.id, topic_id(&msg)).await);
     match cmd {
         Command::Help => {
-            bot.send_message(msg.chat.id, Command::descriptions().to_string())
+            chat_reply(&bot, &msg, Command::descriptions().to_string())
                 .await?;
         }
         Command::Start => {
-            let welcome_text = "🎤 Welcome to the Speech-to-Text Bot!\n\n\
-                📝 Send me:\n\
-                • Voice messages\n\
-                • Video notes (round video messages)\n\
-                • Audio files (.mp3, .m4a, .ogg, etc.)\n\
-                • Video files (I'll extract the audio)\n\n\
-                I'll transcribe the speech and send you the text!";
-
-            bot.send_message(msg.chat.id, welcome_text).await?;
+            chat_reply(&bot, &msg, i18n::t(locale, "start.welcome")).await?;
         }
         Command::Status => {
             let provider = *current_provider.read().await;
-            let status_text = format!(
-                "🤖 Bot Status: ✅ Online\n\
-                🔧 STT Provider: {}\n\
-                🧠 Model: {}\n\
-                📊 Memory usage: Low\n\
-                🚀 Ready to transcribe!",
-                provider.as_str(),
-                provider.model()
+            let stt_options = stt::SttOptions::from_config(&config);
+            let mut status_text = i18n::tf(
+                locale,
+                "status.online",
+                &[("provider", provider.as_str().to_string()), ("model", provider.model_label(&stt_options))],
             );
 
-            bot.send_message(msg.chat.id, status_text).await?;
+            if is_admin(&msg, &config) {
+                let mut costs = usage.costs.write().await;
+                costs.roll_month(&quota::current_month());
+                status_text.push_str("\n\n");
+                status_text.push_str(&costs.summary(&config));
+
+                let configured: Vec<_> = stt::SttProvider::ALL.into_iter().filter(|&p| config.has_provider_key(p)).collect();
+                status_text.push_str("\n\n");
+                status_text.push_str(i18n::t(locale, "status.models"));
+                for p in &configured {
+                    status_text.push_str(&format!("\n• {}: {}", p.as_str(), p.model_label(&stt_options)));
+                }
+                status_text.push_str("\n\n");
+                status_text.push_str(&stt::health::report(&configured));
+            }
+
+            chat_reply(&bot, &msg, status_text).await?;
         }
         Command::Queue => {
-            let queue_status = queue::get_queue_status(&queue_stats).await;
-            bot.send_message(msg.chat.id, queue_status)
+            let queue_status = queue::get_queue_status(&queue_stats, locale).await;
+            chat_reply(&bot, &msg, queue_status)
                 .parse_mode(teloxide::types::ParseMode::MarkdownV2)
                 .await?;
         }
+        Command::SelfTest => {
+            if !is_admin(&msg, &config) {
+                chat_reply(&bot, &msg, i18n::t(locale, "denied.selftest")).await?;
+                return Ok(());
+            }
+
+            let progress = chat_reply(&bot, &msg, i18n::t(locale, "status.selftest_running")).await?;
+            let provider = *current_provider.read().await;
+            let stages = selftest::run(&config, provider).await;
+            bot.edit_message_text(msg.chat.id, progress.id, selftest::report(&stages)).await?;
+        }
+        Command::Reload => {
+            if !is_admin(&msg, &config) {
+                chat_reply(&bot, &msg, i18n::t(locale, "denied.reload")).await?;
+                return Ok(());
+            }
+            let text = match reload::reload(&shared_config, &current_provider).await {
+                Ok(summary) => i18n::tf(locale, "reload.done", &[("summary", summary)]),
+                Err(e) => {
+                    error!("Configuration reload failed: {}", e);
+                    i18n::tf(locale, "reload.failed", &[("error", e.to_string())])
+                }
+            };
+            chat_reply(&bot, &msg, text).await?;
+        }
+        Command::Summarize => {
+            let Some(api_key) = &config.openai_api_key else {
+                chat_reply(&bot, &msg, i18n::t(locale, "summary.no_key")).await?;
+                return Ok(());
+            };
+
+            let text = match msg.reply_to_message() {
+                Some(replied) => message_text(&bot, replied).await,
+                None => None,
+            };
+            let Some(text) = text else {
+                chat_reply(&bot, &msg, i18n::t(locale, "summary.usage"))
+                    .reply_to_message_id(msg.id)
+                    .await?;
+                return Ok(());
+            };
+
+            let summary = llm::summarize(&text, &config.openai_base_url, api_key, &config.llm_model);
+            reply_with_llm(&bot, msg.chat.id, msg.id, locale, i18n::t(locale, "summary.progress"), i18n::t(locale, "summary.heading"), summary).await?;
+        }
         Command::Credits(arg) => {
             let name = arg.trim().to_lowercase();
             let target = if name.is_empty() {
@@ -135,9 +326,10 @@ pub async fn command_handler(
                 match stt::SttProvider::from_str(&name) {
                     Some(p) => p,
                     None => {
-                        bot.send_message(
-                            msg.chat.id,
-                            format!("❌ Unknown provider '{}'. Valid options: deepgram, elevenlabs", name),
+                        chat_reply(
+                            &bot,
+                            &msg,
+                            i18n::tf(locale, "credits.unknown_provider", &[("name", name)]),
                         ).await?;
                         return Ok(());
                     }
@@ -148,104 +340,116 @@ pub async fn command_handler(
                 stt::SttProvider::ElevenLabs => {
                     match &config.elevenlabs_api_key {
                         Some(api_key) => {
-                            match stt::elevenlabs::get_user_credits(api_key).await {
+                            match stt::elevenlabs::get_user_credits(api_key, &config.elevenlabs_base_url).await {
                                 Ok(user_info) => {
-                                    let credits_text = format!(
-                                        "💳 ElevenLabs Credits\n\
-                                        Used: {} characters\n\
-                                        Limit: {} characters\n\
-                                        Remaining: {} characters",
-                                        user_info.subscription.character_count,
-                                        user_info.subscription.character_limit,
-                                        user_info.subscription.character_limit.saturating_sub(user_info.subscription.character_count)
+                                    let subscription = &user_info.subscription;
+                                    let credits_text = i18n::tf(
+                                        locale,
+                                        "credits.elevenlabs",
+                                        &[
+                                            ("used", subscription.character_count.to_string()),
+                                            ("limit", subscription.character_limit.to_string()),
+                                            ("remaining", subscription.character_limit.saturating_sub(subscription.character_count).to_string()),
+                                        ],
                                     );
-                                    bot.send_message(msg.chat.id, credits_text).await?;
+                                    chat_reply(&bot, &msg, credits_text).await?;
                                 }
                                 Err(e) => {
-                                    bot.send_message(msg.chat.id, format!("❌ Failed to get credits: {}", e)).await?;
+                                    chat_reply(&bot, &msg, i18n::tf(locale, "credits.failed", &[("error", e.to_string())])).await?;
                                 }
                             }
                         }
                         None => {
-                            bot.send_message(msg.chat.id, "❌ ElevenLabs API key not configured").await?;
+                            chat_reply(&bot, &msg, i18n::t(locale, "credits.no_elevenlabs_key")).await?;
                         }
                     }
                 }
                 stt::SttProvider::Deepgram => {
                     match &config.deepgram_api_key {
                         Some(api_key) => {
-                            match stt::deepgram::get_balance(api_key).await {
+                            match stt::deepgram::get_balance(api_key, &config.deepgram_base_url).await {
                                 Ok(b) => {
-                                    let credits_text = format!(
-                                        "💳 Deepgram Balance\nRemaining: {:.2} {}",
-                                        b.amount,
-                                        b.units.to_uppercase()
+                                    let credits_text = i18n::tf(
+                                        locale,
+                                        "credits.deepgram",
+                                        &[("amount", format!("{:.2}", b.amount)), ("units", b.units.to_uppercase())],
                                     );
-                                    bot.send_message(msg.chat.id, credits_text).await?;
+                                    chat_reply(&bot, &msg, credits_text).await?;
                                 }
                                 Err(e) => {
-                                    bot.send_message(msg.chat.id, format!("❌ Failed to get Deepgram balance: {}", e)).await?;
+                                    chat_reply(&bot, &msg, i18n::tf(locale, "credits.deepgram_failed", &[("error", e.to_string())])).await?;
                                 }
                             }
                         }
                         None => {
-                            bot.send_message(msg.chat.id, "❌ Deepgram API key not configured").await?;
+                            chat_reply(&bot, &msg, i18n::t(locale, "credits.no_deepgram_key")).await?;
                         }
                     }
                 }
-                stt::SttProvider::Whisper | stt::SttProvider::Google => {
-                    bot.send_message(
-                        msg.chat.id,
-                        format!("ℹ️ Credits lookup is not supported for '{}'.", target.as_str()),
+                stt::SttProvider::Whisper
+                | stt::SttProvider::Google
+                | stt::SttProvider::Azure
+                | stt::SttProvider::LocalWhisper
+                | stt::SttProvider::Vosk
+                | stt::SttProvider::Mock
+                | stt::SttProvider::AssemblyAi => {
+                    chat_reply(
+                        &bot,
+                        &msg,
+                        i18n::tf(locale, "credits.unsupported", &[("provider", target.as_str().to_string())]),
                     ).await?;
                 }
             }
         }
-        Command::Provider => {
-            let provider = *current_provider.read().await;
-            let key_status = if provider_key_configured(provider, &config) {
-                "✅ API key configured"
-            } else {
-                "⚠️ API key not configured"
+        Command::Provider(_) => {
+            let chat_provider = settings::get_for(&settings_store, msg.chat.id, topic_id(&msg)).await.provider;
+            let provider = match chat_provider.filter(|&p| config.has_provider_key(p)) {
+                Some(provider) => provider,
+                None => *current_provider.read().await,
             };
-            let text = format!(
-                "🔧 Current STT provider: {}\n🧠 Model: {}\n{}",
-                provider.as_str(),
-                provider.model(),
-                key_status
+            let key_status = if config.has_provider_key(provider) { "provider.key_ok" } else { "provider.key_missing" };
+            let mut text = i18n::tf(
+                locale,
+                if chat_provider.is_some() { "provider.current_chat" } else { "provider.current" },
+                &[
+                    ("provider", provider.as_str().to_string()),
+                    ("model", provider.model_for(&stt::SttOptions::from_config(&config)).to_string()),
+                    ("key", i18n::t(locale, key_status).to_string()),
+                ],
             );
-            bot.send_message(msg.chat.id, text).await?;
+            text.push_str("\n\n");
+            text.push_str(&i18n::tf(locale, "provider.choose", &[("providers", configured_providers(&config))]));
+            chat_reply(&bot, &msg, text).await?;
         }
         Command::SetProvider(name) => {
             if !is_admin(&msg, &config) {
-                bot.send_message(msg.chat.id, "❌ Not authorized. Only admins can switch providers.").await?;
+                chat_reply(&bot, &msg, i18n::t(locale, "denied.setprovider")).await?;
                 return Ok(());
             }
 
             let name = name.trim().to_lowercase();
             if name.is_empty() {
-                bot.send_message(
-                    msg.chat.id,
-                    "Usage: /setprovider <whisper|elevenlabs|google|deepgram>",
-                ).await?;
+                chat_reply(&bot, &msg, i18n::t(locale, "setprovider.usage")).await?;
                 return Ok(());
             }
 
             let new_provider = match stt::SttProvider::from_str(&name) {
                 Some(p) => p,
                 None => {
-                    bot.send_message(
-                        msg.chat.id,
-                        format!("❌ Unknown provider '{}'. Valid options: whisper, elevenlabs, google, deepgram", name),
+                    chat_reply(
+                        &bot,
+                        &msg,
+                        i18n::tf(locale, "setprovider.unknown", &[("name", name)]),
                     ).await?;
                     return Ok(());
                 }
             };
 
-            if !provider_key_configured(new_provider, &config) {
-                bot.send_message(
-                    msg.chat.id,
-                    format!("❌ Cannot switch to '{}': API key not configured on this bot.", name),
+            if !config.has_provider_key(new_provider) {
+                chat_reply(
+                    &bot,
+                    &msg,
+                    i18n::tf(locale, "setprovider.no_key", &[("name", name)]),
                 ).await?;
                 return Ok(());
             }
@@ -254,177 +458,1955 @@ pub async fn command_handler(
 
             if let Err(e) = persistence::save_runtime_config(new_provider).await {
                 error!("Failed to persist provider switch: {}", e);
-                bot.send_message(msg.chat.id, "⚠️ Provider switched but could not be persisted. It will revert after restart.").await?;
+                chat_reply(&bot, &msg, i18n::t(locale, "setprovider.not_saved")).await?;
                 return Ok(());
             }
 
-            bot.send_message(
-                msg.chat.id,
-                format!("✅ STT provider switched to '{}'.", new_provider.as_str()),
+            chat_reply(
+                &bot,
+                &msg,
+                i18n::tf(locale, "setprovider.done", &[("provider", new_provider.as_str().to_string())]),
             ).await?;
         }
-    }
-    Ok(())
-}
-
-pub async fn audio_handler(
-    bot: Bot,
-    msg: Message,
-    config: BotConfig,
-    authorized_users: AuthorizedUsers,
-    queue_sender: queue::QueueSender,
-    queue_stats: queue::QueueStats,
-) -> ResponseResult<()> {
-    if !is_authorized(&msg, &config, &authorized_users).await {
-        return Ok(());
-    }
+        Command::Quota => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
 
-    // Download and queue the audio file
-    let queue_result = download_and_queue_audio(&bot, &msg, &queue_sender, &queue_stats).await;
+            let text = {
+                let mut quotas = usage.quotas.write().await;
+                quotas.roll_month(&quota::current_month());
 
-    match queue_result {
-        Ok(queue_position) => {
-            info!("Audio file queued successfully at position {}", queue_position);
-        }
-        Err(e) => {
-            error!("Error queueing audio: {}", e);
-            let error_msg = match e {
-                BotError::Audio(audio::AudioError::UnsupportedFormat(_)) => {
-                    "❌ Unsupported audio format. Please send voice messages, video notes, audio files (.mp3, .m4a, .ogg), or video files."
+                let used = quotas.used_seconds(user.id);
+                let granted = quotas.users.get(&user.id.0).map(|q| q.granted_minutes).unwrap_or(0);
+                let args = [("month", quotas.month.clone()), ("used", quota::format_minutes(used))];
+                match quotas.allowance_seconds(user.id, config.quota_minutes_per_month) {
+                    _ if is_admin(&msg, &config) => i18n::tf(locale, "quota.admin", &args),
+                    Some(allowance) => i18n::tf(
+                        locale,
+                        "quota.limited",
+                        &[
+                            args[0].clone(),
+                            args[1].clone(),
+                            ("limit", (allowance / 60).to_string()),
+                            ("granted", granted.to_string()),
+                            ("remaining", quota::format_minutes(allowance.saturating_sub(used))),
+                        ],
+                    ),
+                    None => i18n::tf(locale, "quota.unlimited", &args),
                 }
-                _ => "❌ An error occurred while processing your audio. Please try again."
             };
 
-            bot.send_message(msg.chat.id, error_msg)
-                .reply_to_message_id(msg.id)
-                .await?;
+            chat_reply(&bot, &msg, text).await?;
         }
-    }
+        Command::Usage => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
 
-    Ok(())
-}
+            let text = {
+                let mut quotas = usage.quotas.write().await;
+                quotas.roll_month(&quota::current_month());
+                let mut costs = usage.costs.write().await;
+                costs.roll_month(&quota::current_month());
+                costs.usage_report(&quotas, user.id, is_admin(&msg, &config), &config, locale)
+            };
 
-async fn download_and_queue_audio(
-    bot: &Bot,
-    msg: &Message,
-    queue_sender: &queue::QueueSender,
-    queue_stats: &queue::QueueStats,
-) -> Result<u64> {
-    let (file_ref, original_filename) = match &msg.kind {
-        MessageKind::Common(common) => {
-            match &common.media_kind {
-                teloxide::types::MediaKind::Voice(voice_msg) => {
-                    info!("Processing voice message: duration {}s", voice_msg.voice.duration);
-                    (&voice_msg.voice.file, "voice.ogg")
-                }
-                teloxide::types::MediaKind::Audio(audio_msg) => {
-                    info!("Processing audio file: {} ({}s)",
-                        audio_msg.audio.file_name.as_deref().unwrap_or("unknown"),
-                        audio_msg.audio.duration
-                    );
-                    let filename = audio_msg.audio.file_name.as_deref().unwrap_or("audio.mp3");
-                    (&audio_msg.audio.file, filename)
-                }
-                teloxide::types::MediaKind::Video(video_msg) => {
-                    info!("Processing video file: duration {}s", video_msg.video.duration);
-                    (&video_msg.video.file, "video.mp4")
-                }
-                teloxide::types::MediaKind::VideoNote(video_note_msg) => {
-                    info!("Processing video note: duration {}s", video_note_msg.video_note.duration);
-                    (&video_note_msg.video_note.file, "video_note.mp4")
-                }
-                teloxide::types::MediaKind::Document(doc_msg) => {
-                    info!("Processing document: {}",
-                        doc_msg.document.file_name.as_deref().unwrap_or("unknown"));
-                    let filename = doc_msg.document.file_name.as_deref().unwrap_or("document.bin");
-                    (&doc_msg.document.file, filename)
+            chat_reply(&bot, &msg, text).await?;
+        }
+        Command::History(args) => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
+            // Transcripts may come from other chats, so don't list them in groups
+            if !msg.chat.is_private() {
+                chat_reply(&bot, &msg, i18n::t(locale, "history.private")).await?;
+                return Ok(());
+            }
+            if config.history_max_entries == 0 {
+                chat_reply(&bot, &msg, i18n::t(locale, "history.disabled")).await?;
+                return Ok(());
+            }
+
+            let (text, full) = {
+                let mut history = usage.history.write().await;
+                history.prune(chrono::Utc::now(), config.history_retention());
+                let entries = history.newest_first(user.id);
+                let args = args.trim();
+                if entries.is_empty() {
+                    (i18n::t(locale, "history.empty").to_string(), false)
+                } else if args.is_empty() {
+                    (history::format_list(&entries, locale), false)
+                } else {
+                    match args.parse::<usize>().ok().and_then(|n| entries.get(n.checked_sub(1)?)) {
+                        Some(entry) => (
+                            i18n::tf(
+                                locale,
+                                "history.entry",
+                                &[
+                                    ("file", entry.filename.clone()),
+                                    ("time", entry.at.format("%Y-%m-%d %H:%M UTC").to_string()),
+                                    ("duration", quota::format_minutes(entry.duration_secs)),
+                                    ("provider", entry.provider.clone()),
+                                    ("text", entry.text.clone()),
+                                ],
+                            ),
+                            true,
+                        ),
+                        None => (i18n::tf(locale, "history.usage", &[("count", entries.len().to_string())]), false),
+                    }
                 }
-                _ => {
-                    return Err(BotError::Config("Unsupported media type".to_string()));
+            };
+
+            if full {
+                let text = queue::escape_markdown_v2(&text);
+                if let Err(e) = queue::send_long_message(&bot, msg.chat.id, &text, None, None, locale).await {
+                    error!("Failed to re-send history entry: {}", e);
                 }
+            } else {
+                chat_reply(&bot, &msg, text).await?;
             }
         }
-        _ => {
-            return Err(BotError::Config("Message is not a common type".to_string()));
-        }
-    };
+        Command::Grant(args) => {
+            if !is_admin(&msg, &config) {
+                chat_reply(&bot, &msg, i18n::t(locale, "denied.grant")).await?;
+                return Ok(());
+            }
 
-    // Download the file
-    info!("Downloading file: {}", file_ref.id);
-    let file = bot.get_file(&file_ref.id).await?;
+            let parts: Vec<&str> = args.split_whitespace().collect();
+            let (target, set_limit, minutes) = match parts.as_slice() {
+                [target, minutes] => (*target, false, minutes.parse::<u64>().ok()),
+                [target, "limit", minutes] => (*target, true, minutes.parse::<u64>().ok()),
+                _ => ("", false, None),
+            };
+            let Some(minutes) = minutes else {
+                chat_reply(&bot, &msg, i18n::t(locale, "grant.usage")).await?;
+                return Ok(());
+            };
 
-    let mut file_data = Vec::new();
-    bot.download_file(&file.path, &mut file_data).await?;
+            let mut quotas = usage.quotas.write().await;
+            quotas.roll_month(&quota::current_month());
 
-    info!("Downloaded {} bytes", file_data.len());
+            let Some(user_id) = quotas.resolve_user(target) else {
+                chat_reply(
+                    &bot,
+                    &msg,
+                    i18n::tf(locale, "user.unknown", &[("user", target.to_string())]),
+                ).await?;
+                return Ok(());
+            };
 
-    // Get user info for logging
-    let user_info = msg.from()
-        .map(|user| {
-            if let Some(username) = &user.username {
-                format!("@{}", username)
+            if set_limit {
+                quotas.set_limit(user_id, minutes);
             } else {
-                format!("{} {}", user.first_name, user.last_name.as_deref().unwrap_or(""))
+                quotas.grant_minutes(user_id, minutes);
             }
-        })
-        .unwrap_or_else(|| "Unknown".to_string());
 
-    // Extract user ID and username for detailed logging
-    let (user_id, username) = msg.from()
-        .map(|user| (user.id, user.username.clone()))
-        .unwrap_or_else(|| (teloxide::types::UserId(0), None));
+            let remaining = quotas
+                .remaining_seconds(user_id, config.quota_minutes_per_month)
+                .map(quota::format_minutes)
+                .unwrap_or_else(|| i18n::t(locale, "quota.unlimited_label").to_string());
 
-    // Get current queue size for position calculation
-    let queue_position = {
-        let mut stats = queue_stats.write().await;
-        stats.increment_queued().await;
-        stats.current_queue_size
-    };
+            if let Err(e) = persistence::save_quotas(&quotas).await {
+                error!("Failed to persist quota grant: {}", e);
+                chat_reply(&bot, &msg, i18n::t(locale, "grant.not_saved")).await?;
+                return Ok(());
+            }
 
-    // Send initial queue message
-    let processing_msg = bot
-        .send_message(
-            msg.chat.id,
-            format!("📥 Added to queue (position: {})\nFile: {}", queue_position, original_filename)
-        )
-        .await?;
+            let key = if set_limit { "grant.limit_set" } else { "grant.granted" };
+            let args = [("user", target.to_string()), ("minutes", minutes.to_string()), ("remaining", remaining)];
+            chat_reply(&bot, &msg, i18n::tf(locale, key, &args)).await?;
+        }
+        Command::Ban(target) => change_role(&bot, &msg, &config, &roles, &usage, locale, RoleChange::Ban, &target).await?,
+        Command::Unban(target) => {
+            change_role(&bot, &msg, &config, &roles, &usage, locale, RoleChange::Unban, &target).await?
+        }
+        Command::Authorize(target) => {
+            change_role(&bot, &msg, &config, &roles, &usage, locale, RoleChange::Authorize, &target).await?
+        }
+        Command::Revoke(target) => {
+            change_role(&bot, &msg, &config, &roles, &usage, locale, RoleChange::Revoke, &target).await?
+        }
+        Command::Logout => {
+            if config.bot_password.is_none() {
+                chat_reply(&bot, &msg, i18n::t(locale, "logout.no_password")).await?;
+                return Ok(());
+            }
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
 
-    // Create queue item
-    let queue_item = queue::QueueItem::new(
-        bot.clone(),
-        msg.chat.id,
-        processing_msg.id,
-        msg.id,
-        file_data,
-        original_filename.to_string(),
-        user_info,
-        user_id,
-        username,
-    );
+            let mut roles = roles.write().await;
+            if roles.authorized.remove(&user.id).is_none() {
+                chat_reply(&bot, &msg, i18n::t(locale, "logout.not_logged_in")).await?;
+                return Ok(());
+            }
+            if let Err(e) = persistence::save_authorized_users(&roles.authorized).await {
+                error!("Failed to save authorized users: {}", e);
+            }
+            chat_reply(&bot, &msg, i18n::t(locale, "logout.done")).await?;
+        }
+        Command::Stats => {
+            if !is_admin(&msg, &config) {
+                chat_reply(&bot, &msg, i18n::t(locale, "denied.stats")).await?;
+                return Ok(());
+            }
 
-    // Send to queue
-    if let Err(e) = queue_sender.send(queue_item) {
-        error!("Failed to send item to queue: {}", e);
+            let mut args = {
+                let stats = queue_stats.read().await;
+                vec![
+                    ("waiting", stats.current_queue_size.to_string()),
+                    ("processed", stats.total_processed.to_string()),
+                    ("failed", stats.total_failed.to_string()),
+                    ("skipped", stats.total_skipped.to_string()),
+                ]
+            };
+            {
+                let roles = roles.read().await;
+                args.push(("authorized", roles.authorized.len().to_string()));
+                args.push(("banned", roles.banned.len().to_string()));
+            }
+            {
+                let mut quotas = usage.quotas.write().await;
+                quotas.roll_month(&quota::current_month());
+                let active = quotas.users.values().filter(|q| q.used_seconds > 0).count();
+                let seconds: u64 = quotas.users.values().map(|q| q.used_seconds).sum();
+                args.push(("audio", quota::format_minutes(seconds)));
+                args.push(("users", active.to_string()));
+            }
+            {
+                let mut costs = usage.costs.write().await;
+                costs.roll_month(&quota::current_month());
+                args.push(("costs", costs.summary(&config)));
+            }
 
-        // Decrement queue count since we failed to queue
-        {
-            let mut stats = queue_stats.write().await;
-            stats.current_queue_size = stats.current_queue_size.saturating_sub(1);
+            chat_reply(&bot, &msg, i18n::tf(locale, "stats.report", &args)).await?;
+        }
+        Command::Spam => {
+            if !is_admin(&msg, &config) {
+                chat_reply(&bot, &msg, i18n::t(locale, "denied.spam")).await?;
+                return Ok(());
+            }
+            let report = usage.spam.read().await.report(&config);
+            chat_reply(&bot, &msg, report).await?;
+        }
+        Command::Export(arg) => {
+            if !is_admin(&msg, &config) {
+                chat_reply(&bot, &msg, i18n::t(locale, "denied.export")).await?;
+                return Ok(());
+            }
+            let month = Some(arg.trim()).filter(|month| !month.is_empty());
+            if month.is_some_and(|month| chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_err()) {
+                chat_reply(&bot, &msg, i18n::t(locale, "export.usage")).await?;
+                return Ok(());
+            }
+            let csv = match request_logger::export_csv(&config, month).await {
+                Ok(csv) => csv,
+                Err(e) => {
+                    error!("Failed to read the request log: {}", e);
+                    chat_reply(&bot, &msg, i18n::t(locale, "export.failed")).await?;
+                    return Ok(());
+                }
+            };
+            let rows = csv.lines().count() - 1;
+            let file = InputFile::memory(csv.into_bytes()).file_name(format!("requests-{}.csv", month.unwrap_or("all")));
+            let caption = i18n::tf(locale, if rows == 1 { "export.caption_one" } else { "export.caption" }, &[("count", rows.to_string())]);
+            let mut request = bot.send_document(msg.chat.id, file).caption(caption);
+            if let Some(topic) = topic_id(&msg) {
+                request = request.message_thread_id(topic);
+            }
+            request.await?;
         }
+        // Routed to `transcribe_handler`, `url_handler`, `speak_handler` and `settings_handler` by the dispatcher
+        Command::Transcribe(_)
+        | Command::Url(_)
+        | Command::Speak
+        | Command::PhoneCall(_)
+        | Command::Language(_)
+        | Command::Anonymous(_)
+        | Command::Filter(_)
+        | Command::LangLine(_)
+        | Command::Json(_)
+        | Command::Subtitles(_)
+        | Command::Metadata(_)
+        | Command::GroupMode(_)
+        | Command::Preprocess(_)
+        | Command::Media(_)
+        | Command::BotLanguage(_)
+        | Command::Quiet(_)
+        | Command::QuietHours(_)
+        | Command::Topic(_)
+        | Command::Template(_) => {}
+    }
+    Ok(())
+}
 
-        // Delete the processing message
-        bot.delete_message(msg.chat.id, processing_msg.id).await.ok();
+/// Applies an admin's `/ban`, `/unban`, `/authorize` or `/revoke`.
+#[allow(clippy::too_many_arguments)]
+async fn change_role(
+    bot: &Bot,
+    msg: &Message,
+    config: &BotConfig,
+    roles: &UserRoles,
+    usage: &UsageStores,
+    locale: Locale,
+    change: RoleChange,
+    target: &str,
+) -> ResponseResult<()> {
+    if !is_admin(msg, config) {
+        chat_reply(bot, msg, i18n::t(locale, "denied.users")).await?;
+        return Ok(());
+    }
+
+    let target = target.trim();
+    if target.is_empty() {
+        chat_reply(bot, msg, i18n::tf(locale, "role.usage", &[("command", change.command().to_string())])).await?;
+        return Ok(());
+    }
+    let Some(user_id) = usage.quotas.read().await.resolve_user(target) else {
+        chat_reply(bot, msg, i18n::tf(locale, "user.unknown", &[("user", target.to_string())])).await?;
+        return Ok(());
+    };
+    if change == RoleChange::Ban && config.admin_user_ids.contains(&user_id) {
+        chat_reply(bot, msg, i18n::t(locale, "role.admin_ban")).await?;
+        return Ok(());
+    }
 
-        return Err(BotError::Config("Queue is full or closed".to_string()));
+    let args = [("user", target.to_string()), ("state", i18n::t(locale, change.state_key()).to_string())];
+    let mut roles = roles.write().await;
+    if !change.apply(&mut roles, user_id) {
+        chat_reply(bot, msg, i18n::tf(locale, "role.unchanged", &args)).await?;
+        return Ok(());
     }
 
-    Ok(queue_position)
+    if let Err(e) = persistence::save_roles(&roles).await {
+        error!("Failed to persist role change: {}", e);
+        chat_reply(bot, msg, i18n::t(locale, "role.not_saved")).await?;
+        return Ok(());
+    }
+
+    let mut reply = i18n::tf(locale, "role.done", &args);
+    if config.bot_password.is_none() && matches!(change, RoleChange::Authorize | RoleChange::Revoke) {
+        reply.push(' ');
+        reply.push_str(i18n::t(locale, "role.no_password"));
+    }
+    chat_reply(bot, msg, reply).await?;
+    Ok(())
 }
 
-pub async fn text_handler(bot: Bot, msg: Message, config: BotConfig, authorized_users: AuthorizedUsers) -> ResponseResult<()> {
-    if !is_authorized(&msg, &config, &authorized_users).await {
+pub async fn settings_handler(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    shared_config: SharedConfig,
+    roles: UserRoles,
+    settings_store: settings::ChatSettingsStore,
+) -> ResponseResult<()> {
+    let config = shared_config.read().await.clone();
+    if !is_authorized(&msg, &config, &roles).await {
+        return Ok(());
+    }
+
+    let topic = topic_id(&msg);
+    let mut current = settings::get_for(&settings_store, msg.chat.id, topic).await;
+    let locale = message_locale(&msg, &current);
+    if !can_change_chat_settings(&bot, &msg, &config).await? {
+        chat_reply(&bot, &msg, i18n::t(locale, "denied.settings")).await?;
         return Ok(());
     }
 
+    if let Command::Topic(arg) = &cmd {
+        return topic_settings(&bot, &msg, topic, arg, locale, &settings_store).await;
+    }
+
+    let reply = match cmd {
+        Command::PhoneCall(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.phone_call = enabled;
+                i18n::tf(locale, "settings.phonecall.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.phone_call, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.phonecall.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::Language(arg) => {
+            let arg = arg.trim();
+            if arg.eq_ignore_ascii_case("auto") {
+                current.language = None;
+                i18n::t(locale, "settings.language.auto").to_string()
+            } else if let Some(code) = stt::language::code(arg) {
+                current.language = Some(code.to_string());
+                let language = format!("{} ({})", stt::language::display_name(code), code);
+                i18n::tf(locale, "settings.language.changed", &[("language", language)])
+            } else {
+                let current_language = match &current.language {
+                    Some(code) => format!("{} ({})", stt::language::display_name(code), code),
+                    None => i18n::t(locale, "settings.language.auto_label").to_string(),
+                };
+                let args = [("language", current_language), ("codes", stt::language::known_codes().join(", "))];
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.language.usage", &args)).await?;
+                return Ok(());
+            }
+        }
+        Command::Anonymous(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.anonymous = enabled;
+                i18n::tf(locale, "settings.anonymous.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.anonymous, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.anonymous.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::Filter(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.profanity_filter = enabled;
+                i18n::tf(locale, "settings.filter.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.profanity_filter, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.filter.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::Provider(arg) => {
+            let arg = arg.trim();
+            if arg.eq_ignore_ascii_case("default") {
+                current.provider = None;
+                i18n::t(locale, "settings.provider.default").to_string()
+            } else {
+                match stt::SttProvider::from_str(arg) {
+                    Some(provider) if config.has_provider_key(provider) => {
+                        current.provider = Some(provider);
+                        i18n::tf(locale, "settings.provider.changed", &[("provider", provider.as_str().to_string())])
+                    }
+                    _ => {
+                        let args = [("name", arg.to_string()), ("providers", configured_providers(&config))];
+                        chat_reply(&bot, &msg, i18n::tf(locale, "settings.provider.unknown", &args)).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Command::Template(arg) => {
+            let template = arg.trim().replace("\\n", "\n");
+            if template.eq_ignore_ascii_case("default") {
+                current.reply_template = None;
+                i18n::t(locale, "settings.template.default").to_string()
+            } else if template.is_empty() {
+                let current_template = current.reply_template.as_deref().or(config.reply_template.as_deref()).unwrap_or("default");
+                let args = [
+                    ("template", current_template.to_string()),
+                    ("placeholders", format!("{{{}}}", crate::template::REPLY_PLACEHOLDERS.join("}, {"))),
+                ];
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.template.usage", &args)).await?;
+                return Ok(());
+            } else if let Err(e) = crate::template::validate_reply(&template) {
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.template.invalid", &[("error", e)])).await?;
+                return Ok(());
+            } else {
+                current.reply_template = Some(template);
+                i18n::t(locale, "settings.template.changed").to_string()
+            }
+        }
+        Command::Quiet(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.quiet = enabled;
+                i18n::tf(locale, "settings.quiet.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.quiet, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.quiet.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::QuietHours(arg) => {
+            if arg.trim().eq_ignore_ascii_case("off") {
+                current.quiet_hours = None;
+                i18n::t(locale, "settings.quiethours.off").to_string()
+            } else if let Some(hours) = settings::QuietHours::parse(&arg) {
+                current.quiet_hours = Some(hours);
+                i18n::tf(locale, "settings.quiethours.changed", &[("hours", hours.describe(locale))])
+            } else {
+                let current_hours = current
+                    .quiet_hours
+                    .map(|hours| hours.describe(locale))
+                    .unwrap_or_else(|| settings::toggle_label(false, locale).to_string());
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.quiethours.usage", &[("hours", current_hours)])).await?;
+                return Ok(());
+            }
+        }
+        Command::LangLine(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.language_line = enabled;
+                i18n::tf(locale, "settings.langline.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.language_line, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.langline.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::Json(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.json_attachment = enabled;
+                i18n::tf(locale, "settings.json.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.json_attachment, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.json.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::Subtitles(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.subtitles = enabled;
+                i18n::tf(locale, "settings.subtitles.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.subtitles, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.subtitles.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::Metadata(arg) => match settings::parse_toggle(&arg) {
+            Some(enabled) => {
+                current.show_metadata = enabled;
+                i18n::tf(locale, "settings.metadata.changed", &[("state", settings::toggle_label(enabled, locale).to_string())])
+            }
+            None => {
+                let state = settings::toggle_label(current.show_metadata, locale).to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.metadata.usage", &[("state", state)])).await?;
+                return Ok(());
+            }
+        },
+        Command::GroupMode(arg) => {
+            let arg = arg.trim();
+            if arg.eq_ignore_ascii_case("default") {
+                current.group_mode = None;
+                i18n::tf(locale, "settings.groupmode.default", &[("mode", config.group_mode.as_str().to_string())])
+            } else if let Some(mode) = settings::GroupMode::from_str(arg) {
+                current.group_mode = Some(mode);
+                i18n::tf(locale, "settings.groupmode.changed", &[("mode", mode.as_str().to_string())])
+            } else {
+                let mode = current.group_mode.unwrap_or(config.group_mode).as_str().to_string();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.groupmode.usage", &[("mode", mode)])).await?;
+                return Ok(());
+            }
+        }
+        Command::Preprocess(arg) => {
+            let arg = arg.trim();
+            if arg.eq_ignore_ascii_case("default") {
+                current.preprocess = None;
+                i18n::tf(locale, "settings.preprocess.default", &[("filters", config.audio_preprocess.describe())])
+            } else if let Some(preprocess) = audio::Preprocess::parse(arg) {
+                current.preprocess = Some(preprocess);
+                i18n::tf(locale, "settings.preprocess.changed", &[("filters", preprocess.describe())])
+            } else {
+                let filters = current.preprocess.unwrap_or(config.audio_preprocess).describe();
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.preprocess.usage", &[("filters", filters)])).await?;
+                return Ok(());
+            }
+        }
+        Command::Media(arg) => match apply_media_setting(&mut current, &arg, locale) {
+            Some(reply) => reply,
+            None => {
+                let args = [
+                    ("policy", current.media_policy(locale)),
+                    ("kinds", settings::MediaKind::ALL.map(|k| k.as_str()).join("|")),
+                ];
+                chat_reply(&bot, &msg, i18n::tf(locale, "settings.media.usage", &args)).await?;
+                return Ok(());
+            }
+        },
+        Command::BotLanguage(arg) => {
+            let arg = arg.trim();
+            if arg.eq_ignore_ascii_case("auto") {
+                current.locale = None;
+                i18n::t(message_locale(&msg, &current), "locale.auto").to_string()
+            } else if let Some(locale) = Locale::from_code(arg) {
+                current.locale = Some(locale);
+                i18n::t(locale, "locale.set").to_string()
+            } else {
+                let locale = message_locale(&msg, &current);
+                let current_label = match current.locale {
+                    Some(locale) => locale.as_str().to_string(),
+                    None => i18n::t(locale, "locale.auto_label").to_string(),
+                };
+                chat_reply(&bot, &msg, i18n::tf(locale, "locale.usage", &[("current", current_label)])).await?;
+                return Ok(());
+            }
+        }
+        _ => return Ok(()),
+    };
+
+    let mut store = settings_store.write().await;
+    settings::put(&mut store, msg.chat.id, topic, current);
+    if let Err(e) = persistence::save_chat_settings(&store).await {
+        error!("Failed to persist chat settings: {}", e);
+        chat_reply(&bot, &msg, i18n::t(locale, "settings.not_saved")).await?;
+        return Ok(());
+    }
+
+    chat_reply(&bot, &msg, reply).await?;
+    Ok(())
+}
+
+/// `/topic`: whether a forum topic has settings of its own, and `/topic
+/// reset` to have it follow the chat's again.
+async fn topic_settings(
+    bot: &Bot,
+    msg: &Message,
+    topic: Option<i32>,
+    arg: &str,
+    locale: Locale,
+    settings_store: &settings::ChatSettingsStore,
+) -> ResponseResult<()> {
+    let Some(topic) = topic else {
+        chat_reply(bot, msg, i18n::t(locale, "topic.outside")).await?;
+        return Ok(());
+    };
+
+    let reply = if arg.trim().eq_ignore_ascii_case("reset") {
+        let mut store = settings_store.write().await;
+        let removed = store.get_mut(&msg.chat.id).and_then(|chat| chat.topics.remove(&topic)).is_some();
+        if removed && let Err(e) = persistence::save_chat_settings(&store).await {
+            error!("Failed to persist chat settings: {}", e);
+            chat_reply(bot, msg, i18n::t(locale, "settings.not_saved")).await?;
+            return Ok(());
+        }
+        "topic.reset"
+    } else if settings_store.read().await.get(&msg.chat.id).is_some_and(|chat| chat.topics.contains_key(&topic)) {
+        "topic.own"
+    } else {
+        "topic.inherited"
+    };
+    chat_reply(bot, msg, i18n::t(locale, reply)).await?;
+    Ok(())
+}
+
+/// Applies a `/media` argument to `settings`; returns the confirmation, or
+/// None if the argument isn't valid.
+fn apply_media_setting(settings: &mut settings::ChatSettings, arg: &str, locale: Locale) -> Option<String> {
+    let arg = arg.trim();
+    if let Some(text) = arg.strip_prefix("message") {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        settings.media_refusal = (!text.eq_ignore_ascii_case("default")).then(|| text.to_string());
+        return Some(i18n::t(locale, "settings.media.refusal").to_string());
+    }
+
+    let mut kinds = Vec::new();
+    let mut block_forwarded = false;
+    let mut all = false;
+    for token in arg.split_whitespace() {
+        match token.to_lowercase().as_str() {
+            "all" => all = true,
+            "noforward" | "noforwards" => block_forwarded = true,
+            other => {
+                let kind = settings::MediaKind::from_str(other)?;
+                if !kinds.contains(&kind) {
+                    kinds.push(kind);
+                }
+            }
+        }
+    }
+    if kinds.is_empty() && !all {
+        return None;
+    }
+
+    settings.allowed_media = (!all).then_some(kinds);
+    settings.block_forwarded = block_forwarded;
+    Some(i18n::tf(locale, "settings.media.changed", &[("policy", settings.media_policy(locale))]))
+}
+
+/// Posts a progress message under `reply_to` and replaces it with the LLM's
+/// answer. Answers too long for one message are re-sent in parts.
+async fn reply_with_llm(
+    bot: &Bot,
+    chat_id: ChatId,
+    reply_to: MessageId,
+    locale: Locale,
+    progress_text: &str,
+    heading: &str,
+    request: impl std::future::Future<Output = std::result::Result<String, llm::LlmError>>,
+) -> ResponseResult<()> {
+    const MAX_LENGTH: usize = 4000;
+
+    let progress = bot.send_message(chat_id, progress_text)
+        .reply_to_message_id(reply_to)
+        .await?;
+
+    let answer = match request.await {
+        Ok(answer) => answer,
+        Err(e) => {
+            error!("LLM request failed: {}", e);
+            bot.edit_message_text(chat_id, progress.id, i18n::t(locale, "llm.failed")).await?;
+            return Ok(());
+        }
+    };
+
+    let reply = format!("{}:\n\n{}", heading, answer);
+    if reply.len() <= MAX_LENGTH {
+        bot.edit_message_text(chat_id, progress.id, reply).await?;
+        return Ok(());
+    }
+
+    bot.delete_message(chat_id, progress.id).await.ok();
+    let escaped = queue::escape_markdown_v2(&reply);
+    if let Err(e) = queue::send_long_message(bot, chat_id, &escaped, Some(reply_to), None, locale).await {
+        error!("Failed to send LLM answer: {}", e);
+    }
+    Ok(())
+}
+
+/// Text to summarize from a message: an attached `.txt` transcript, else
+/// the message text or caption.
+async fn message_text(bot: &Bot, msg: &Message) -> Option<String> {
+    // Large enough for hours of speech, small enough to send in one request
+    const MAX_DOCUMENT_BYTES: u32 = 512 * 1024;
+
+    if let Some(document) = msg.document()
+        && document.file.size <= MAX_DOCUMENT_BYTES
+        && (document.mime_type.as_ref().is_some_and(|m| m.essence_str() == "text/plain")
+            || document.file_name.as_deref().is_some_and(|name| name.ends_with(".txt")))
+    {
+        let mut data = Vec::new();
+        let downloaded = match bot.get_file(&document.file.id).await {
+            Ok(file) => queue::fetch_telegram_file(bot, &file.path, &mut data).await,
+            Err(e) => Err(e.into()),
+        };
+        match downloaded {
+            Ok(()) => return Some(String::from_utf8_lossy(&data).into_owned()),
+            Err(e) => error!("Failed to download text document: {}", e),
+        }
+    }
+
+    msg.text()
+        .or_else(|| msg.caption())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+pub fn has_transcribable_media(msg: &Message) -> bool {
+    msg.voice().is_some()
+        || msg.audio().is_some()
+        || msg.video().is_some()
+        || msg.video_note().is_some()
+        || msg.document().is_some()
+        || msg.animation().is_some()
+}
+
+/// The file behind a message's transcribable media.
+fn media_file(msg: &Message) -> Option<&teloxide::types::FileMeta> {
+    msg.voice()
+        .map(|voice| &voice.file)
+        .or_else(|| msg.audio().map(|audio| &audio.file))
+        .or_else(|| msg.video().map(|video| &video.file))
+        .or_else(|| msg.video_note().map(|video_note| &video_note.file))
+        .or_else(|| msg.document().map(|document| &document.file))
+        .or_else(|| msg.animation().map(|animation| &animation.file))
+}
+
+/// Replies to `msg`, or just posts in the chat when it is anonymous.
+fn reply_unless_anonymous(
+    bot: &Bot,
+    msg: &Message,
+    chat_settings: &settings::ChatSettings,
+    text: String,
+) -> <Bot as Requester>::SendMessage {
+    let request = chat_reply(bot, msg, text);
+    if chat_settings.anonymous {
+        request
+    } else {
+        request.reply_to_message_id(msg.id)
+    }
+}
+
+/// Language for replies to `msg`: the chat's /botlanguage, else the sender's.
+fn message_locale(msg: &Message, chat_settings: &settings::ChatSettings) -> Locale {
+    Locale::resolve(chat_settings.locale, msg.from().and_then(|user| user.language_code.as_deref()))
+}
+
+fn queue_error_text(e: &BotError, locale: Locale) -> String {
+    match e {
+        BotError::Audio(audio::AudioError::UnsupportedFormat(_)) => i18n::t(locale, "error.unsupported_file").to_string(),
+        BotError::MediaNotAllowed(refusal) => refusal.clone(),
+        BotError::DownloadFailed { id, .. } => crate::error_report::user_reply(i18n::t(locale, "error.generic"), e, id, locale),
+        BotError::QueueFull => i18n::t(locale, "error.queue_full").to_string(),
+        BotError::LowDiskSpace(..) => i18n::t(locale, "error.low_disk").to_string(),
+        BotError::Fetch(fetch::FetchError::InvalidLink) => i18n::t(locale, "error.url_invalid").to_string(),
+        BotError::Fetch(fetch::FetchError::DomainNotAllowed(domain)) => {
+            i18n::tf(locale, "error.url_domain", &[("domain", domain.clone())])
+        }
+        BotError::Fetch(fetch::FetchError::TooLarge(max_mb)) => {
+            i18n::tf(locale, "error.url_too_large", &[("max", max_mb.to_string())])
+        }
+        BotError::Fetch(fetch::FetchError::NotMedia) => i18n::t(locale, "error.url_not_media").to_string(),
+        BotError::Fetch(fetch::FetchError::Failed(_)) => i18n::t(locale, "error.url_failed").to_string(),
+        BotError::Archive(batch::ArchiveError::Invalid(_)) => i18n::t(locale, "error.archive_invalid").to_string(),
+        BotError::Archive(batch::ArchiveError::Empty) => i18n::t(locale, "error.archive_empty").to_string(),
+        BotError::Archive(batch::ArchiveError::TooLarge(max_mb)) => {
+            i18n::tf(locale, "error.archive_too_large", &[("max", max_mb.to_string())])
+        }
+        BotError::BudgetExhausted => i18n::t(locale, "error.budget_exhausted").to_string(),
+        BotError::FileTooLarge(size_mb, max_mb) => {
+            i18n::tf(locale, "error.file_too_large", &[("size", size_mb.to_string()), ("max", max_mb.to_string())])
+        }
+        BotError::TooLong(secs, max_secs) => i18n::tf(
+            locale,
+            "error.too_long",
+            &[
+                ("duration", quota::format_minutes(*secs as u64)),
+                ("max", quota::format_minutes(*max_secs as u64)),
+            ],
+        ),
+        BotError::QuotaExceeded(remaining) => {
+            i18n::tf(locale, "error.quota_exceeded", &[("remaining", quota::format_minutes(*remaining))])
+        }
+        _ => i18n::t(locale, "error.generic").to_string(),
+    }
+}
+
+/// Applies `/transcribe` arguments (a time range and/or `phone`); false if
+/// any of them isn't valid.
+fn apply_transcribe_args(options: &mut queue::ProcessingOptions, args: &str) -> bool {
+    for token in args.split_whitespace() {
+        if token.eq_ignore_ascii_case("phone") {
+            options.phone_call = true;
+        } else if let Some(range) = audio::TimeRange::parse(token) {
+            options.time_range = Some(range);
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn transcribe_handler(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    shared_config: SharedConfig,
+    roles: UserRoles,
+    queue_sender: queue::QueueSender,
+    queue_stats: queue::QueueStats,
+    usage: UsageStores,
+    settings_store: settings::ChatSettingsStore,
+) -> ResponseResult<()> {
+    let config = shared_config.read().await.clone();
+    if !is_authorized(&msg, &config, &roles).await {
+        return Ok(());
+    }
+
+    let chat_settings = settings::get_for(&settings_store, msg.chat.id, topic_id(&msg)).await;
+    let locale = message_locale(&msg, &chat_settings);
+    let Some(media_msg) = msg.reply_to_message().filter(|m| has_transcribable_media(m)) else {
+        chat_reply(&bot, &msg, i18n::t(locale, "transcribe.usage")).reply_to_message_id(msg.id).await?;
+        return Ok(());
+    };
+
+    let mut options = queue::ProcessingOptions::for_chat(&chat_settings);
+    if !apply_transcribe_args(&mut options, &args) {
+        chat_reply(&bot, &msg, i18n::t(locale, "transcribe.usage")).reply_to_message_id(msg.id).await?;
+        return Ok(());
+    }
+
+    match download_and_queue_audio(&bot, &msg, media_msg, options, &chat_settings, &config, &queue_sender, &queue_stats, &usage).await {
+        Ok(queue_position) => {
+            info!("Requested transcription queued successfully at position {}", queue_position);
+        }
+        Err(e) => {
+            error!("Error queueing requested transcription: {}", e);
+            reply_unless_anonymous(&bot, &msg, &chat_settings, queue_error_text(&e, locale)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles /url: downloads media from a link on an allowed domain and
+/// queues it like a file sent to the chat.
+#[allow(clippy::too_many_arguments)]
+pub async fn url_handler(
+    bot: Bot,
+    msg: Message,
+    link: String,
+    shared_config: SharedConfig,
+    roles: UserRoles,
+    queue_sender: queue::QueueSender,
+    queue_stats: queue::QueueStats,
+    usage: UsageStores,
+    settings_store: settings::ChatSettingsStore,
+) -> ResponseResult<()> {
+    let config = shared_config.read().await.clone();
+    if !is_authorized(&msg, &config, &roles).await {
+        return Ok(());
+    }
+
+    let chat_settings = settings::get_for(&settings_store, msg.chat.id, topic_id(&msg)).await;
+    let locale = message_locale(&msg, &chat_settings);
+    if config.url_allowed_domains.is_empty() {
+        reply_unless_anonymous(&bot, &msg, &chat_settings, i18n::t(locale, "error.url_disabled").to_string()).await?;
+        return Ok(());
+    }
+    if link.trim().is_empty() {
+        reply_unless_anonymous(&bot, &msg, &chat_settings, i18n::t(locale, "url.usage").to_string()).await?;
+        return Ok(());
+    }
+
+    let options = queue::ProcessingOptions::for_chat(&chat_settings);
+    match download_and_queue_link(&bot, &msg, &link, options, &chat_settings, &config, &queue_sender, &queue_stats, &usage).await {
+        Ok(queue_position) => {
+            info!("Linked media queued successfully at position {}", queue_position);
+        }
+        Err(e) => {
+            error!("Error queueing linked media: {}", e);
+            reply_unless_anonymous(&bot, &msg, &chat_settings, queue_error_text(&e, locale)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles /speak: reads the replied transcript or text message aloud.
+pub async fn speak_handler(
+    bot: Bot,
+    msg: Message,
+    shared_config: SharedConfig,
+    roles: UserRoles,
+    transcripts: queue::TranscriptCache,
+    settings_store: settings::ChatSettingsStore,
+) -> ResponseResult<()> {
+    let config = shared_config.read().await.clone();
+    if !is_authorized(&msg, &config, &roles).await {
+        return Ok(());
+    }
+    let locale = message_locale(&msg, &settings::get_for(&settings_store, msg.chat.id, topic_id(&msg)).await);
+    let Some(provider) = config.tts_provider else {
+        chat_reply(&bot, &msg, i18n::t(locale, "speak.no_key")).await?;
+        return Ok(());
+    };
+
+    let Some(replied) = msg.reply_to_message() else {
+        chat_reply(&bot, &msg, i18n::t(locale, "speak.usage"))
+            .reply_to_message_id(msg.id)
+            .await?;
+        return Ok(());
+    };
+    // A transcript still behind its buttons is read without the heading and footer
+    let cached = match transcript_item_id(replied) {
+        Some(item_id) => transcripts.read().await.get(&item_id).map(|c| c.transcription.text.clone()),
+        None => None,
+    };
+    let text = match cached {
+        Some(text) => Some(text),
+        None => message_text(&bot, replied).await,
+    };
+    let Some(text) = text.filter(|text| !text.trim().is_empty()) else {
+        chat_reply(&bot, &msg, i18n::t(locale, "speak.usage"))
+            .reply_to_message_id(msg.id)
+            .await?;
+        return Ok(());
+    };
+
+    let (text, truncated) = tts::truncate(text.trim(), tts::MAX_CHARS);
+    bot.send_chat_action(msg.chat.id, teloxide::types::ChatAction::RecordVoice).await.ok();
+    match tts::synthesize(text, provider, &config).await {
+        Ok(speech) => {
+            let mut request = bot
+                .send_voice(msg.chat.id, InputFile::memory(speech.data).file_name(speech.file_name))
+                .reply_to_message_id(replied.id);
+            if truncated {
+                request = request.caption(i18n::tf(locale, "speak.truncated", &[("count", text.chars().count().to_string())]));
+            }
+            request.await?;
+        }
+        Err(e) => {
+            error!("Speech synthesis with {} failed: {}", provider.as_str(), e);
+            chat_reply(&bot, &msg, i18n::t(locale, "speak.failed"))
+                .reply_to_message_id(msg.id)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Item ID behind the buttons of a delivered transcript, e.g. `summarize:<id>`.
+fn transcript_item_id(msg: &Message) -> Option<String> {
+    msg.reply_markup()?
+        .inline_keyboard
+        .iter()
+        .flatten()
+        .find_map(|button| match &button.kind {
+            InlineKeyboardButtonKind::CallbackData(data) => data.split_once(':').map(|(_, id)| id.to_string()),
+            _ => None,
+        })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn audio_handler(
+    bot: Bot,
+    msg: Message,
+    shared_config: SharedConfig,
+    roles: UserRoles,
+    queue_sender: queue::QueueSender,
+    queue_stats: queue::QueueStats,
+    usage: UsageStores,
+    settings_store: settings::ChatSettingsStore,
+    me: Me,
+) -> ResponseResult<()> {
+    let config = shared_config.read().await.clone();
+    if !is_authorized(&msg, &config, &roles).await {
+        return Ok(());
+    }
+
+    // A `/transcribe` caption is an explicit request in any group mode
+    let caption_args = msg
+        .caption()
+        .and_then(|caption| Command::parse(caption, me.username()).ok())
+        .and_then(|cmd| match cmd {
+            Command::Transcribe(args) => Some(args),
+            _ => None,
+        });
+
+    // Other files shared in groups are none of the bot's business
+    if let Some(document) = msg.document()
+        && !msg.chat.is_private()
+        && caption_args.is_none()
+        && !audio::sniff::is_media_document(
+            document.mime_type.as_ref().map(|m| m.essence_str()),
+            document.file_name.as_deref(),
+        )
+    {
+        return Ok(());
+    }
+
+    // Most GIFs are silent, so groups only get them transcribed on request
+    if msg.animation().is_some() && !msg.chat.is_private() && caption_args.is_none() && !mentions_bot(&msg, &me) {
+        return Ok(());
+    }
+
+    let chat_settings = settings::get_for(&settings_store, msg.chat.id, topic_id(&msg)).await;
+    if !msg.chat.is_private() && caption_args.is_none() {
+        let wanted = match chat_settings.group_mode.unwrap_or(config.group_mode) {
+            settings::GroupMode::All => true,
+            settings::GroupMode::Mention => mentions_bot(&msg, &me),
+            settings::GroupMode::Reply => false,
+        };
+        if !wanted {
+            return Ok(());
+        }
+    }
+    let mut options = queue::ProcessingOptions::for_chat(&chat_settings);
+    if let Some(args) = caption_args
+        && !apply_transcribe_args(&mut options, &args)
+    {
+        let usage = i18n::t(message_locale(&msg, &chat_settings), "transcribe.usage");
+        chat_reply(&bot, &msg, usage).reply_to_message_id(msg.id).await?;
+        return Ok(());
+    }
+
+    // A file sent twice in a row is answered by the first one's transcript
+    let duplicate = match media_file(&msg) {
+        Some(file) => {
+            let stats = queue_stats.read().await;
+            stats.duplicate_of(msg.chat.id, &file.unique_id).map(|recent| (recent.item_id.clone(), recent.done))
+        }
+        None => None,
+    };
+    if let Some((item_id, done)) = duplicate {
+        info!("Message {} repeats the file of queue item {}, not queueing it again", msg.id, item_id);
+        let key = if done { "queue.duplicate_done" } else { "queue.duplicate_pending" };
+        chat_reply(&bot, &msg, i18n::t(message_locale(&msg, &chat_settings), key)).reply_to_message_id(msg.id).await?;
+        return Ok(());
+    }
+
+    // Download and queue the audio file, or each one in a zip archive
+    let queue_result = match msg.document() {
+        Some(document)
+            if batch::is_archive(document.mime_type.as_ref().map(|m| m.essence_str()), document.file_name.as_deref()) =>
+        {
+            download_and_queue_archive(&bot, &msg, document, options, &chat_settings, &config, &queue_sender, &queue_stats, &usage)
+                .await
+        }
+        _ => {
+            download_and_queue_audio(&bot, &msg, &msg, options, &chat_settings, &config, &queue_sender, &queue_stats, &usage)
+                .await
+        }
+    };
+
+    match queue_result {
+        Ok(queue_position) => {
+            info!("Audio file queued successfully at position {}", queue_position);
+        }
+        Err(e) => {
+            error!("Error queueing audio: {}", e);
+            reply_unless_anonymous(&bot, &msg, &chat_settings, queue_error_text(&e, message_locale(&msg, &chat_settings))).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns a download of `size` bytes away while disk space is short, and
+/// alerts the admins.
+async fn check_disk_space(bot: &Bot, config: &BotConfig, size: u64) -> Result<()> {
+    let result = crate::janitor::check_disk_space(config, size);
+    if let Err(BotError::LowDiskSpace(free_mb, min_mb)) = result {
+        warn!("Refusing a {} KB download: {} MB of disk space free", size / 1024, free_mb);
+        crate::alerts::send(bot, config, crate::alerts::Alert::LowDiskSpace(free_mb, min_mb)).await;
+    }
+    result
+}
+
+/// Downloads the media in `media_msg` and queues it on behalf of the sender of `msg`.
+#[allow(clippy::too_many_arguments)]
+async fn download_and_queue_audio(
+    bot: &Bot,
+    msg: &Message,
+    media_msg: &Message,
+    mut options: queue::ProcessingOptions,
+    chat_settings: &settings::ChatSettings,
+    config: &BotConfig,
+    queue_sender: &queue::QueueSender,
+    queue_stats: &queue::QueueStats,
+    usage: &UsageStores,
+) -> Result<u64> {
+    use settings::MediaKind;
+
+    let (file_ref, original_filename, media_duration, kind) = match &media_msg.kind {
+        MessageKind::Common(common) => {
+            match &common.media_kind {
+                teloxide::types::MediaKind::Voice(voice_msg) => {
+                    info!("Processing voice message: duration {}s", voice_msg.voice.duration);
+                    (&voice_msg.voice.file, "voice.ogg", voice_msg.voice.duration, MediaKind::Voice)
+                }
+                teloxide::types::MediaKind::Audio(audio_msg) => {
+                    info!("Processing audio file: {} ({}s)",
+                        audio_msg.audio.file_name.as_deref().unwrap_or("unknown"),
+                        audio_msg.audio.duration
+                    );
+                    let filename = audio_msg.audio.file_name.as_deref().unwrap_or("audio.mp3");
+                    (&audio_msg.audio.file, filename, audio_msg.audio.duration, MediaKind::Audio)
+                }
+                teloxide::types::MediaKind::Video(video_msg) => {
+                    info!("Processing video file: duration {}s", video_msg.video.duration);
+                    (&video_msg.video.file, "video.mp4", video_msg.video.duration, MediaKind::Video)
+                }
+                teloxide::types::MediaKind::VideoNote(video_note_msg) => {
+                    info!("Processing video note: duration {}s", video_note_msg.video_note.duration);
+                    (&video_note_msg.video_note.file, "video_note.mp4", video_note_msg.video_note.duration, MediaKind::VideoNote)
+                }
+                teloxide::types::MediaKind::Document(doc_msg) => {
+                    info!("Processing document: {}",
+                        doc_msg.document.file_name.as_deref().unwrap_or("unknown"));
+                    let mime_type = doc_msg.document.mime_type.as_ref().map(|m| m.essence_str());
+                    if !audio::sniff::is_media_document(mime_type, doc_msg.document.file_name.as_deref()) {
+                        return Err(audio::AudioError::UnsupportedFormat(format!(
+                            "document {} ({})",
+                            doc_msg.document.file_name.as_deref().unwrap_or("unnamed"),
+                            mime_type.unwrap_or("no MIME type")
+                        )).into());
+                    }
+                    let filename = doc_msg.document.file_name.as_deref().unwrap_or("document.bin");
+                    (&doc_msg.document.file, filename, 0, MediaKind::Document)
+                }
+                teloxide::types::MediaKind::Animation(animation_msg) => {
+                    info!("Processing animation: duration {}s", animation_msg.animation.duration);
+                    let filename = animation_msg.animation.file_name.as_deref().unwrap_or("animation.mp4");
+                    (&animation_msg.animation.file, filename, animation_msg.animation.duration, MediaKind::Animation)
+                }
+                _ => {
+                    return Err(BotError::Config("Unsupported media type".to_string()));
+                }
+            }
+        }
+        _ => {
+            return Err(BotError::Config("Message is not a common type".to_string()));
+        }
+    };
+
+    check_media_allowed(msg, media_msg, kind, chat_settings)?;
+
+    // Subtitles only make sense for something with a picture
+    options.subtitles &= matches!(kind, MediaKind::Video | MediaKind::VideoNote);
+
+    // Only the requested slice counts towards quota and cost
+    let duration_secs = match options.time_range {
+        Some(range) if media_duration > 0 => range.duration_secs().min(media_duration.saturating_sub(range.start_secs)),
+        Some(range) => range.duration_secs(),
+        None => media_duration,
+    };
+
+    // Decline what is too big or too long using Telegram's metadata, before
+    // spending bandwidth on the download
+    if let Some(max_mb) = config.max_file_size_mb
+        && file_ref.size > max_mb.saturating_mul(1024 * 1024)
+    {
+        return Err(BotError::FileTooLarge(file_ref.size.div_ceil(1024 * 1024), max_mb));
+    }
+    if let Some(max_secs) = config.max_duration_secs
+        && duration_secs > max_secs
+    {
+        return Err(BotError::TooLong(duration_secs, max_secs));
+    }
+
+    check_quota_and_budget(msg, duration_secs, config, usage).await?;
+    check_disk_space(bot, config, file_ref.size as u64).await?;
+
+    // Apply the queue-full policy before spending bandwidth on the download
+    options.priority = config.admin_priority && is_admin(msg, config);
+    options.locale = message_locale(msg, chat_settings);
+    options.topic = topic_id(msg);
+    // Private chats always see their queue position
+    options.quiet &= !msg.chat.is_private();
+    let admission = queue::admit(bot, msg.chat.id, &options, queue_sender, queue_stats, config).await?;
+
+    // Download the file straight to disk; it stays there until processed
+    info!("Downloading file: {}", file_ref.id);
+    let item_id = uuid::Uuid::new_v4().to_string();
+    let download = async {
+        let file = bot.get_file(&file_ref.id).await?;
+        queue::download_media(bot, &file.path, &item_id, &config.queue_dir()).await
+    };
+    let media = match download.instrument(crate::logging::item_span(&item_id)).await {
+        Ok(media) => media,
+        Err(e) => {
+            error!("Failed to download item {} (error ID {}): {:?}", item_id, crate::error_report::error_id(&item_id), e);
+            crate::alerts::on_failure(bot, config, &e, None).await;
+            return Err(BotError::DownloadFailed { id: item_id, source: Box::new(e) });
+        }
+    };
+
+    info!("Downloaded {} bytes to {}", media.size(), media.path().display());
+
+    // Documents are only named like media; check what they really contain
+    if kind == MediaKind::Document {
+        match audio::sniff::detect_file(media.path()).await.map_err(BotError::Io)? {
+            Some(container) => info!("Document looks like {}", container),
+            None => {
+                return Err(audio::AudioError::UnsupportedFormat(format!(
+                    "{} doesn't contain audio or video",
+                    original_filename
+                )).into());
+            }
+        }
+    }
+
+    let (user_info, user_id, username) = sender_info(msg);
+
+    // Get current queue size for position calculation
+    let queue_position = {
+        let mut stats = queue_stats.write().await;
+        stats.increment_queued().await;
+        stats.remember_file(msg.chat.id, &file_ref.unique_id, &item_id);
+        stats.current_queue_size
+    };
+
+    // Send initial queue message, threaded under the media since it turns
+    // into the transcript
+    let processing_msg_id = if options.quiet {
+        None
+    } else {
+        let mut request = chat_reply(
+            bot,
+            msg,
+            i18n::tf(
+                options.locale,
+                "queue.added",
+                &[("position", queue_position.to_string()), ("file", original_filename.to_string())],
+            ),
+        )
+        .reply_markup(queue::cancel_keyboard(&item_id, options.locale));
+        if !options.anonymous {
+            request = request.reply_to_message_id(media_msg.id);
+        }
+        match request.await {
+            Ok(sent) => Some(sent.id),
+            Err(e) => {
+                queue_stats.write().await.unqueue(&item_id);
+                return Err(e.into());
+            }
+        }
+    };
+
+    // Create queue item; the transcript threads under the media, not under
+    // a /transcribe request
+    let mut queue_item = queue::QueueItem::new(
+        item_id,
+        bot.clone(),
+        msg.chat.id,
+        processing_msg_id,
+        media_msg.id,
+        media,
+        original_filename.to_string(),
+        user_info,
+        user_id,
+        username,
+        duration_secs,
+    );
+    queue_item.options = options;
+
+    // Send to queue
+    queue::enqueue_admitted(queue_sender, queue_stats, queue_item, admission).await?;
+
+    Ok(queue_position)
+}
+
+/// Downloads a zip archive and queues each media file in it. The files
+/// are answered together with one report document.
+#[allow(clippy::too_many_arguments)]
+async fn download_and_queue_archive(
+    bot: &Bot,
+    msg: &Message,
+    document: &teloxide::types::Document,
+    mut options: queue::ProcessingOptions,
+    chat_settings: &settings::ChatSettings,
+    config: &BotConfig,
+    queue_sender: &queue::QueueSender,
+    queue_stats: &queue::QueueStats,
+    usage: &UsageStores,
+) -> Result<u64> {
+    let archive_name = document.file_name.as_deref().unwrap_or("archive.zip");
+    info!("Processing archive: {}", archive_name);
+    check_media_allowed(msg, msg, settings::MediaKind::Document, chat_settings)?;
+
+    if let Some(max_mb) = config.max_file_size_mb
+        && document.file.size > max_mb.saturating_mul(1024 * 1024)
+    {
+        return Err(BotError::FileTooLarge(document.file.size.div_ceil(1024 * 1024), max_mb));
+    }
+    check_quota_and_budget(msg, 0, config, usage).await?;
+    // Unpacked, the files take up about as much again
+    check_disk_space(bot, config, document.file.size as u64 * 2).await?;
+
+    // The files report back once, so they get no queue messages of their
+    // own, and the sender chose them, so no music check either
+    options.priority = config.admin_priority && is_admin(msg, config);
+    options.locale = message_locale(msg, chat_settings);
+    options.topic = topic_id(msg);
+    options.quiet = true;
+    options.skip_music_check = true;
+    options.subtitles = false;
+    options.json_attachment = false;
+    let admission = queue::admit(bot, msg.chat.id, &options, queue_sender, queue_stats, config).await?;
+
+    let archive_id = uuid::Uuid::new_v4().to_string();
+    let download = async {
+        let file = bot.get_file(&document.file.id).await?;
+        queue::download_media(bot, &file.path, &archive_id, &config.queue_dir()).await
+    };
+    let archive = match download.instrument(crate::logging::item_span(&archive_id)).await {
+        Ok(archive) => archive,
+        Err(e) => {
+            error!("Failed to download archive {} (error ID {}): {:?}", archive_id, crate::error_report::error_id(&archive_id), e);
+            crate::alerts::on_failure(bot, config, &e, None).await;
+            return Err(BotError::DownloadFailed { id: archive_id, source: Box::new(e) });
+        }
+    };
+    let max_file_bytes = config.max_file_size_mb.map(|mb| mb as u64 * 1024 * 1024);
+    let unpacked = batch::unpack(archive.path(), max_file_bytes, &config.queue_dir()).await?;
+    drop(archive);
+    info!("Unpacked {} media files from {}, skipped {} entries", unpacked.files.len(), archive_name, unpacked.skipped);
+
+    let reply_to = (!options.anonymous).then_some(msg.id);
+    let mut request = chat_reply(
+        bot,
+        msg,
+        i18n::tf(
+            options.locale,
+            "batch.queued",
+            &[("count", unpacked.files.len().to_string()), ("file", archive_name.to_string())],
+        ),
+    );
+    if let Some(reply_to) = reply_to {
+        request = request.reply_to_message_id(reply_to);
+    }
+    let status_message = request.await?.id;
+
+    let names = unpacked.files.iter().map(|file| file.name.clone()).collect();
+    let batch = std::sync::Arc::new(batch::Batch::new(
+        archive_name.to_string(),
+        msg.chat.id,
+        reply_to,
+        Some(status_message),
+        options.locale,
+        names,
+        unpacked.skipped,
+    ).in_topic(options.topic));
+    let (user_info, user_id, username) = sender_info(msg);
+
+    let mut queue_position = 0;
+    for (index, file) in unpacked.files.into_iter().enumerate() {
+        {
+            let mut stats = queue_stats.write().await;
+            stats.increment_queued().await;
+            if index == 0 {
+                stats.remember_file(msg.chat.id, &document.file.unique_id, &file.id);
+            }
+            queue_position = stats.current_queue_size;
+        }
+
+        let mut queue_item = queue::QueueItem::new(
+            file.id,
+            bot.clone(),
+            msg.chat.id,
+            None,
+            msg.id,
+            file.media,
+            file.name,
+            user_info.clone(),
+            user_id,
+            username.clone(),
+            0,
+        );
+        queue_item.options = options;
+        let slot = batch::Slot { batch: batch.clone(), index };
+        queue_item.batch = Some(slot.clone());
+        if let Err(e) = queue::enqueue_admitted(queue_sender, queue_stats, queue_item, admission.next_file()).await {
+            error!("Failed to queue file {} of archive {}: {}", index + 1, archive_name, e);
+            slot.finish(bot, batch::Outcome::Failed(queue_error_text(&e, options.locale))).await;
+        }
+    }
+
+    Ok(queue_position)
+}
+
+/// Downloads the media behind `link` and queues it on behalf of the sender
+/// of `msg`.
+#[allow(clippy::too_many_arguments)]
+async fn download_and_queue_link(
+    bot: &Bot,
+    msg: &Message,
+    link: &str,
+    mut options: queue::ProcessingOptions,
+    chat_settings: &settings::ChatSettings,
+    config: &BotConfig,
+    queue_sender: &queue::QueueSender,
+    queue_stats: &queue::QueueStats,
+    usage: &UsageStores,
+) -> Result<u64> {
+    let url = fetch::parse_link(link, &config.url_allowed_domains)?;
+    let original_filename = fetch::file_name(&url);
+    info!("Processing link: {}", url);
+    check_quota_and_budget(msg, 0, config, usage).await?;
+    check_disk_space(bot, config, config.url_max_file_mb as u64 * 1024 * 1024).await?;
+
+    options.priority = config.admin_priority && is_admin(msg, config);
+    options.locale = message_locale(msg, chat_settings);
+    options.topic = topic_id(msg);
+    options.quiet &= !msg.chat.is_private();
+    let admission = queue::admit(bot, msg.chat.id, &options, queue_sender, queue_stats, config).await?;
+
+    // Links can take a while, so the queue message comes first
+    let item_id = uuid::Uuid::new_v4().to_string();
+    let processing_msg_id = if options.quiet {
+        None
+    } else {
+        let mut request = chat_reply(
+            bot,
+            msg,
+            i18n::tf(options.locale, "queue.downloading", &[("file", original_filename.clone())]),
+        );
+        if !options.anonymous {
+            request = request.reply_to_message_id(msg.id);
+        }
+        Some(request.await?.id)
+    };
+
+    // Links say nothing about their length, so the quota and length
+    // checks wait for the file
+    let download = async {
+        let media = fetch::download(&url, &item_id, config).await?;
+        info!("Downloaded {} bytes to {}", media.size(), media.path().display());
+        let duration_secs = match audio::analyze::probe_duration(media.path(), config.ffmpeg_timeout()).await {
+            Ok(secs) => secs.round() as u32,
+            Err(e) => {
+                // The worker decodes items without a duration and checks them then
+                warn!("Failed to probe the duration of {}: {}", item_id, e);
+                0
+            }
+        };
+        if let Some(max_secs) = config.max_duration_secs
+            && duration_secs > max_secs
+        {
+            return Err(BotError::TooLong(duration_secs, max_secs));
+        }
+        check_quota_and_budget(msg, duration_secs, config, usage).await?;
+        Ok((media, duration_secs))
+    };
+    let (media, duration_secs) = match download.instrument(crate::logging::item_span(&item_id)).await {
+        Ok(downloaded) => downloaded,
+        Err(e) => {
+            if let Some(message_id) = processing_msg_id {
+                bot.delete_message(msg.chat.id, message_id).await.ok();
+            }
+            return Err(e);
+        }
+    };
+
+    let (user_info, user_id, username) = sender_info(msg);
+    let queue_position = {
+        let mut stats = queue_stats.write().await;
+        stats.increment_queued().await;
+        stats.current_queue_size
+    };
+    if let Some(message_id) = processing_msg_id
+        && let Err(e) = bot
+            .edit_message_text(
+                msg.chat.id,
+                message_id,
+                i18n::tf(
+                    options.locale,
+                    "queue.added",
+                    &[("position", queue_position.to_string()), ("file", original_filename.clone())],
+                ),
+            )
+            .reply_markup(queue::cancel_keyboard(&item_id, options.locale))
+            .await
+    {
+        warn!("Failed to update the queue message for {}: {}", item_id, e);
+    }
+
+    let mut queue_item = queue::QueueItem::new(
+        item_id,
+        bot.clone(),
+        msg.chat.id,
+        processing_msg_id,
+        msg.id,
+        media,
+        original_filename,
+        user_info,
+        user_id,
+        username,
+        duration_secs,
+    );
+    queue_item.options = options;
+    queue::enqueue_admitted(queue_sender, queue_stats, queue_item, admission).await?;
+
+    Ok(queue_position)
+}
+
+/// Refuses media the chat's allowlist doesn't take.
+fn check_media_allowed(
+    msg: &Message,
+    media_msg: &Message,
+    kind: settings::MediaKind,
+    chat_settings: &settings::ChatSettings,
+) -> Result<()> {
+    if chat_settings.allows_media(kind, media_msg.forward_date().is_some()) {
+        return Ok(());
+    }
+    info!("Refusing {} media in chat {} ({})", kind.as_str(), msg.chat.id, chat_settings.media_policy(Locale::En));
+    let refusal = chat_settings.media_refusal.clone().unwrap_or_else(|| {
+        let locale = message_locale(msg, chat_settings);
+        i18n::tf(locale, "media.refused", &[("policy", chat_settings.media_policy(locale))])
+    });
+    Err(BotError::MediaNotAllowed(refusal))
+}
+
+/// Enforces the monthly quota and budget before spending bandwidth on a
+/// download. Channel posts have no user to charge.
+async fn check_quota_and_budget(msg: &Message, duration_secs: u32, config: &BotConfig, usage: &UsageStores) -> Result<()> {
+    let user = msg.from().filter(|_| channel_sender(msg).is_none());
+    check_user_quota_and_budget(user, duration_secs, config, usage).await
+}
+
+/// Enforces `user`'s monthly quota, unless they're an admin, and the
+/// budget; choose_provider falls back across all configured providers.
+async fn check_user_quota_and_budget(
+    user: Option<&teloxide::types::User>,
+    duration_secs: u32,
+    config: &BotConfig,
+    usage: &UsageStores,
+) -> Result<()> {
+    if let Some(user) = user
+        && !config.admin_user_ids.contains(&user.id)
+    {
+        let mut quotas = usage.quotas.write().await;
+        quotas.roll_month(&quota::current_month());
+        if let Some(username) = &user.username {
+            quotas.remember_username(user.id, username);
+        }
+        if let Some(remaining) = quotas.remaining_seconds(user.id, config.quota_minutes_per_month)
+            && (remaining == 0 || remaining < duration_secs as u64)
+        {
+            return Err(BotError::QuotaExceeded(remaining));
+        }
+    }
+
+    let mut costs = usage.costs.write().await;
+    costs.roll_month(&quota::current_month());
+    let provider = costs
+        .choose_provider(config.stt_provider, config)
+        .ok_or(BotError::BudgetExhausted)?;
+    info!(
+        "Estimated cost for {}s via {}: ${:.4}",
+        duration_secs,
+        provider.as_str(),
+        duration_secs as f64 / 60.0 * cost::price_per_minute(provider, config)
+    );
+    Ok(())
+}
+
+/// The forum topic `msg` was posted in, if any besides General.
+fn topic_id(msg: &Message) -> Option<i32> {
+    match &msg.kind {
+        MessageKind::Common(common) if common.is_topic_message => msg.thread_id,
+        _ => None,
+    }
+}
+
+/// Starts a message in the chat of `msg`, in the same forum topic.
+fn chat_reply(bot: &Bot, msg: &Message, text: impl Into<String>) -> <Bot as Requester>::SendMessage {
+    let request = bot.send_message(msg.chat.id, text);
+    match topic_id(msg) {
+        Some(topic) => request.message_thread_id(topic),
+        None => request,
+    }
+}
+
+/// Who sent `msg`, for logs, and their user ID and username.
+fn sender_info(msg: &Message) -> (String, teloxide::types::UserId, Option<String>) {
+    let user_info = match channel_sender(msg) {
+        Some(channel) => format!("channel {}", channel.title().unwrap_or("untitled")),
+        None => msg.from()
+            .map(|user| {
+                if let Some(username) = &user.username {
+                    format!("@{}", username)
+                } else {
+                    format!("{} {}", user.first_name, user.last_name.as_deref().unwrap_or(""))
+                }
+            })
+            .unwrap_or_else(|| "Unknown".to_string()),
+    };
+    let (user_id, username) = msg.from()
+        .map(|user| (user.id, user.username.clone()))
+        .unwrap_or_else(|| (teloxide::types::UserId(0), None));
+    (user_info, user_id, username)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn text_handler(
+    bot: Bot,
+    msg: Message,
+    shared_config: SharedConfig,
+    roles: UserRoles,
+    queue_sender: queue::QueueSender,
+    queue_stats: queue::QueueStats,
+    usage: UsageStores,
+    settings_store: settings::ChatSettingsStore,
+    me: Me,
+) -> ResponseResult<()> {
+    let config = shared_config.read().await.clone();
+    if !is_authorized(&msg, &config, &roles).await {
+        return Ok(());
+    }
+
+    // In mention mode, replying to media with a mention of the bot asks
+    // for a transcript
+    if msg.chat.is_private() || !mentions_bot(&msg, &me) {
+        return Ok(());
+    }
+    let Some(media_msg) = msg.reply_to_message().filter(|m| has_transcribable_media(m)) else {
+        return Ok(());
+    };
+    let chat_settings = settings::get_for(&settings_store, msg.chat.id, topic_id(&msg)).await;
+    if chat_settings.group_mode.unwrap_or(config.group_mode) != settings::GroupMode::Mention {
+        return Ok(());
+    }
+
+    let options = queue::ProcessingOptions::for_chat(&chat_settings);
+    match download_and_queue_audio(&bot, &msg, media_msg, options, &chat_settings, &config, &queue_sender, &queue_stats, &usage).await {
+        Ok(queue_position) => {
+            info!("Mentioned media queued successfully at position {}", queue_position);
+        }
+        Err(e) => {
+            error!("Error queueing mentioned media: {}", e);
+            reply_unless_anonymous(&bot, &msg, &chat_settings, queue_error_text(&e, message_locale(&msg, &chat_settings))).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the text or caption mentions the bot by @username or as a
+/// text mention.
+fn mentions_bot(msg: &Message, me: &Me) -> bool {
+    let username = me.user.username.as_deref();
+    msg.parse_entities()
+        .into_iter()
+        .chain(msg.parse_caption_entities())
+        .flatten()
+        .any(|entity| match entity.kind() {
+            MessageEntityKind::Mention => {
+                username.is_some_and(|name| entity.text().trim_start_matches('@').eq_ignore_ascii_case(name))
+            }
+            MessageEntityKind::TextMention { user } => user.id == me.user.id,
+            _ => false,
+        })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    shared_config: SharedConfig,
+    roles: UserRoles,
+    queue_sender: queue::QueueSender,
+    queue_stats: queue::QueueStats,
+    usage: UsageStores,
+    parked_items: queue::ParkedItems,
+    transcripts: queue::TranscriptCache,
+) -> ResponseResult<()> {
+    let config = shared_config.read().await.clone();
+    let Some(data) = q.data.as_deref() else {
+        return Ok(());
+    };
+
+    // Answers pop up for whoever tapped, so they follow that user's language
+    let locale = Locale::resolve(None, q.from.language_code.as_deref());
+
+    // Buttons stay on old messages; banned or expired users can't use them
+    if !is_user_authorized(q.from.id, &config, &roles).await {
+        bot.answer_callback_query(q.id).text(i18n::t(locale, "callback.unauthorized")).await?;
+        return Ok(());
+    }
+
+    if let Some(item_id) = data.strip_prefix("cancel:") {
+        return cancel_item(&bot, &q, item_id, locale, &config, &queue_sender, &queue_stats).await;
+    }
+
+    if let Some((action, item_id)) = data.split_once(':')
+        && !matches!(action, "music" | "retry")
+    {
+        return transcript_action(&bot, &q, action, item_id, locale, &config, &queue_sender, &queue_stats, &usage, &transcripts).await;
+    }
+
+    // "Transcribe anyway" under a music notice, or Retry under an error
+    if let Some((action, item_id)) = data.split_once(':') {
+        let parked = parked_items.write().await.remove(item_id);
+        let Some(parked) = parked else {
+            bot.answer_callback_query(q.id).text(i18n::t(locale, "callback.file_gone")).await?;
+            return Ok(());
+        };
+
+        if parked.item.user_id != q.from.id {
+            queue::park_item(&parked_items, parked.item).await;
+            bot.answer_callback_query(q.id).text(i18n::t(locale, "callback.sender_only")).await?;
+            return Ok(());
+        }
+
+        // The quota and budget may have run out since the file was sent
+        if let Err(e) = check_user_quota_and_budget(Some(&q.from), parked.item.duration_secs, &config, &usage).await {
+            let text = queue_error_text(&e, locale);
+            queue::park_item(&parked_items, parked.item).await;
+            bot.answer_callback_query(q.id).text(text).show_alert(true).await?;
+            return Ok(());
+        }
+
+        let mut item = parked.item;
+        if action == "music" {
+            item.options.skip_music_check = true;
+        }
+
+        requeue(&bot, item, &queue_sender, &queue_stats).await?;
+
+        bot.answer_callback_query(q.id).await?;
+
+        // Drop the button so it can't be triggered twice
+        if let Some(message) = &q.message {
+            bot.edit_message_reply_markup(message.chat.id, message.id).await.ok();
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles the Cancel button under queue and processing messages.
+async fn cancel_item(
+    bot: &Bot,
+    q: &CallbackQuery,
+    item_id: &str,
+    locale: Locale,
+    config: &BotConfig,
+    queue_sender: &queue::QueueSender,
+    queue_stats: &queue::QueueStats,
+) -> ResponseResult<()> {
+    let is_admin = config.admin_user_ids.contains(&q.from.id);
+    let outcome = queue_stats.write().await.cancel(item_id, q.from.id, is_admin);
+
+    let answer = match outcome {
+        queue::CancelOutcome::Dequeued { original_filename } => {
+            // Don't let a restart, or another instance, bring it back
+            persistence::remove_pending_item(&persistence::pending_media_path(&config.queue_dir(), item_id)).await;
+            queue_sender.remove(item_id).await;
+            if let Some(message) = &q.message {
+                let text = i18n::tf(locale, "queue.cancelled", &[("file", original_filename)]);
+                bot.edit_message_text(message.chat.id, message.id, text).await.ok();
+            }
+            "callback.cancelled"
+        }
+        queue::CancelOutcome::Stopping => "callback.stopping",
+        queue::CancelOutcome::NotAllowed => "callback.cancel_not_allowed",
+        queue::CancelOutcome::NotFound => "callback.cancel_not_found",
+    };
+
+    bot.answer_callback_query(q.id.clone()).text(i18n::t(locale, answer)).await?;
+    Ok(())
+}
+
+/// Handles the buttons under a delivered transcript.
+#[allow(clippy::too_many_arguments)]
+async fn transcript_action(
+    bot: &Bot,
+    q: &CallbackQuery,
+    action: &str,
+    item_id: &str,
+    locale: Locale,
+    config: &BotConfig,
+    queue_sender: &queue::QueueSender,
+    queue_stats: &queue::QueueStats,
+    usage: &UsageStores,
+    transcripts: &queue::TranscriptCache,
+) -> ResponseResult<()> {
+    let cached = transcripts
+        .read()
+        .await
+        .get(item_id)
+        .map(|c| (c.item.clone(), c.transcription.clone(), c.provider));
+    let (Some((item, transcription, provider)), Some(message)) = (cached, &q.message) else {
+        bot.answer_callback_query(q.id.clone()).text(i18n::t(locale, "callback.transcript_gone")).await?;
+        return Ok(());
+    };
+
+    match action {
+        "summarize" | "translate" => {
+            let Some(api_key) = &config.openai_api_key else {
+                bot.answer_callback_query(q.id.clone()).text(i18n::t(locale, "callback.llm_disabled")).await?;
+                return Ok(());
+            };
+            bot.answer_callback_query(q.id.clone()).await?;
+
+            // The answer is posted in the chat, in the language the item was queued with
+            let chat_locale = item.options.locale;
+            let text = &transcription.text;
+            if action == "summarize" {
+                let summary = llm::summarize(text, &config.openai_base_url, api_key, &config.llm_model);
+                let (progress, heading) = (i18n::t(chat_locale, "summary.progress"), i18n::t(chat_locale, "summary.heading"));
+                reply_with_llm(bot, message.chat.id, message.id, chat_locale, progress, heading, summary).await?;
+            } else {
+                let target = llm::translation_target(q.from.language_code.as_deref(), transcription.language.as_deref());
+                let heading = i18n::tf(chat_locale, "translate.heading", &[("language", stt::language::display_name(target).to_string())]);
+                let translation = llm::translate(text, target, &config.openai_base_url, api_key, &config.llm_model);
+                let progress = i18n::t(chat_locale, "translate.progress");
+                reply_with_llm(bot, message.chat.id, message.id, chat_locale, progress, &heading, translation).await?;
+            }
+        }
+        "timestamps" => {
+            let Some(lines) = subtitles::timestamped_lines(&transcription) else {
+                bot.answer_callback_query(q.id.clone()).text(i18n::t(locale, "callback.no_timestamps")).await?;
+                return Ok(());
+            };
+            bot.answer_callback_query(q.id.clone()).await?;
+
+            let heading = queue::escape_markdown_v2(i18n::t(item.options.locale, "transcript.timestamps"));
+            let text = format!("🕒 *{}*\n\n{}", heading, queue::escape_markdown_v2(&lines));
+            if let Err(e) = queue::send_long_message(bot, message.chat.id, &text, Some(message.id), None, item.options.locale).await {
+                error!("Failed to send timestamps for item {}: {}", item_id, e);
+            }
+        }
+        "rerun" => {
+            if item.user_id != q.from.id {
+                bot.answer_callback_query(q.id.clone()).text(i18n::t(locale, "callback.rerun_sender_only")).await?;
+                return Ok(());
+            }
+            let Some(other) = queue::rerun_provider(provider, config) else {
+                bot.answer_callback_query(q.id.clone()).text(i18n::t(locale, "callback.no_other_provider")).await?;
+                return Ok(());
+            };
+            if let Err(e) = check_user_quota_and_budget(Some(&q.from), item.duration_secs, config, usage).await {
+                bot.answer_callback_query(q.id.clone())
+                    .text(queue_error_text(&e, locale))
+                    .show_alert(true)
+                    .await?;
+                return Ok(());
+            }
+
+            let mut item = item;
+            item.options.provider = Some(other);
+            item.options.skip_music_check = true;
+
+            requeue(bot, item, queue_sender, queue_stats).await?;
+            bot.answer_callback_query(q.id.clone())
+                .text(i18n::tf(locale, "callback.rerunning", &[("provider", other.as_str().to_string())]))
+                .await?;
+        }
+        _ => {
+            bot.answer_callback_query(q.id.clone()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Puts an item back on the queue with a fresh position message.
+async fn requeue(
+    bot: &Bot,
+    mut item: queue::QueueItem,
+    queue_sender: &queue::QueueSender,
+    queue_stats: &queue::QueueStats,
+) -> ResponseResult<()> {
+    let queue_position = {
+        let mut stats = queue_stats.write().await;
+        stats.increment_queued().await;
+        stats.current_queue_size
+    };
+
+    item.message_id = if item.options.quiet {
+        None
+    } else {
+        let mut request = bot
+            .send_message(
+                item.chat_id,
+                i18n::tf(
+                    item.options.locale,
+                    "queue.added",
+                    &[("position", queue_position.to_string()), ("file", item.original_filename.clone())],
+                ),
+            )
+            .reply_markup(queue::cancel_keyboard(&item.id, item.options.locale));
+        if let Some(topic) = item.options.topic {
+            request = request.message_thread_id(topic);
+        }
+        if let Some(reply_to) = item.reply_target() {
+            request = request.reply_to_message_id(reply_to);
+        }
+        match request.await {
+            Ok(sent) => Some(sent.id),
+            Err(e) => {
+                queue_stats.write().await.unqueue(&item.id);
+                return Err(e);
+            }
+        }
+    };
+
+    let (chat_id, message_id, locale) = (item.chat_id, item.message_id, item.options.locale);
+    if let Err(e) = queue::enqueue(queue_sender, queue_stats, item).await {
+        error!("Failed to queue the file again: {}", e);
+        let notice = i18n::t(locale, "queue.requeue_failed");
+        match message_id {
+            Some(message_id) => bot.edit_message_text(chat_id, message_id, notice).await.map(|_| ())?,
+            None => bot.send_message(chat_id, notice).await.map(|_| ())?,
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::UserId;
+use tokio::sync::RwLock;
+use crate::i18n::{self, Locale};
+
+pub type HistoryStore = Arc<RwLock<HistoryData>>;
+
+/// Each user's recent transcripts, persisted in `data/history.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HistoryData {
+    /// Oldest first.
+    pub users: HashMap<u64, Vec<HistoryEntry>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    pub at: DateTime<Utc>,
+    pub duration_secs: u64,
+    pub filename: String,
+    pub provider: String,
+    pub text: String,
+}
+
+/// Longest first-line preview in the `/history` list.
+const PREVIEW_CHARS: usize = 60;
+
+impl HistoryData {
+    /// Adds an entry, dropping the user's oldest ones past `max_entries`.
+    pub fn record(&mut self, user_id: UserId, entry: HistoryEntry, max_entries: usize) {
+        let entries = self.users.entry(user_id.0).or_default();
+        entries.push(entry);
+        let excess = entries.len().saturating_sub(max_entries);
+        entries.drain(..excess);
+    }
+
+    /// Drops entries older than `retention`. Returns true if anything was
+    /// removed.
+    pub fn prune(&mut self, now: DateTime<Utc>, retention: Option<chrono::Duration>) -> bool {
+        let Some(retention) = retention else {
+            return false;
+        };
+        let mut changed = false;
+        self.users.retain(|_, entries| {
+            let before = entries.len();
+            entries.retain(|entry| now - entry.at < retention);
+            changed |= entries.len() != before;
+            !entries.is_empty()
+        });
+        changed
+    }
+
+    /// A user's entries, newest first.
+    pub fn newest_first(&self, user_id: UserId) -> Vec<&HistoryEntry> {
+        self.users.get(&user_id.0).map(|entries| entries.iter().rev().collect()).unwrap_or_default()
+    }
+}
+
+/// Text of the `/history` list; numbers match `/history <n>`.
+pub fn format_list(entries: &[&HistoryEntry], locale: Locale) -> String {
+    let mut lines = vec![i18n::tf(locale, "history.list", &[("count", entries.len().to_string())])];
+    for (i, entry) in entries.iter().enumerate() {
+        lines.push(format!(
+            "\n{}. {} · {} · {}\n{}",
+            i + 1,
+            entry.at.format("%Y-%m-%d %H:%M UTC"),
+            crate::quota::format_minutes(entry.duration_secs),
+            entry.filename,
+            preview(&entry.text)
+        ));
+    }
+    lines.push(format!("\n{}", i18n::t(locale, "history.list_hint")));
+    lines.join("\n")
+}
+
+/// First line of the transcript, shortened to fit a list.
+fn preview(text: &str) -> String {
+    let line = text.lines().find(|line| !line.trim().is_empty()).unwrap_or("").trim();
+    if line.chars().count() > PREVIEW_CHARS {
+        format!("{}…", line.chars().take(PREVIEW_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(at: DateTime<Utc>, text: &str) -> HistoryEntry {
+        HistoryEntry { at, duration_secs: 83, filename: "voice.ogg".to_string(), provider: "deepgram".to_string(), text: text.to_string() }
+    }
+
+    #[test]
+    fn test_record_and_prune() {
+        let now = Utc::now();
+        let user = UserId(1);
+        let mut data = HistoryData::default();
+        for i in 0..4 {
+            data.record(user, entry(now - chrono::Duration::days(10 - i), &format!("text {}", i)), 3);
+        }
+        let texts: Vec<_> = data.newest_first(user).iter().map(|e| e.text.clone()).collect();
+        assert_eq!(texts, ["text 3", "text 2", "text 1"]);
+
+        assert!(!data.prune(now, None));
+        assert!(data.prune(now, Some(chrono::Duration::days(8))));
+        assert_eq!(data.newest_first(user).len(), 1);
+        assert!(data.prune(now, Some(chrono::Duration::days(1))));
+        assert!(data.users.is_empty());
+    }
+
+    #[test]
+    fn test_format_list() {
+        let at = DateTime::parse_from_rfc3339("2026-03-01T14:02:00Z").unwrap().with_timezone(&Utc);
+        let long = "word ".repeat(20);
+        let (first, second) = (entry(at, "\nHello there\nsecond line"), entry(at, &long));
+        assert_eq!(
+            format_list(&[&first, &second], Locale::En),
+            format!(
+                "📜 Your last 2 transcriptions, newest first:\n\n\
+                 1. 2026-03-01 14:02 UTC · 1:23 · voice.ogg\nHello there\n\n\
+                 2. 2026-03-01 14:02 UTC · 1:23 · voice.ogg\n{}…\n\n\
+                 Send /history <n> to get one again in full.",
+                &long[..60]
+            )
+        );
+    }
+}
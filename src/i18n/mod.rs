@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Language of the bot's own messages, set per chat with /botlanguage.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Ru,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Self::En, Self::Ru];
+
+    /// Accepts plain and regional codes, e.g. `ru` or Telegram's `ru-RU`.
+    pub fn from_code(code: &str) -> Option<Self> {
+        let code = code.trim().to_lowercase();
+        match code.split(['-', '_']).next().unwrap_or_default() {
+            "en" => Some(Self::En),
+            "ru" => Some(Self::Ru),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Ru => "ru",
+        }
+    }
+
+    /// The chat's setting if it has one, else the sender's Telegram
+    /// language when the bot speaks it, else English.
+    pub fn resolve(setting: Option<Locale>, sender_language: Option<&str>) -> Self {
+        setting.or_else(|| sender_language.and_then(Self::from_code)).unwrap_or_default()
+    }
+
+    fn bundle_source(&self) -> &'static str {
+        match self {
+            Self::En => include_str!("en.json"),
+            Self::Ru => include_str!("ru.json"),
+        }
+    }
+}
+
+type Bundle = HashMap<String, String>;
+
+static BUNDLES: OnceLock<HashMap<Locale, Bundle>> = OnceLock::new();
+
+fn bundles() -> &'static HashMap<Locale, Bundle> {
+    BUNDLES.get_or_init(|| {
+        Locale::ALL
+            .into_iter()
+            .map(|locale| {
+                let bundle = serde_json::from_str(locale.bundle_source())
+                    .unwrap_or_else(|e| panic!("invalid {} message bundle: {}", locale.as_str(), e));
+                (locale, bundle)
+            })
+            .collect()
+    })
+}
+
+/// The message `key` in `locale`, falling back to English, then to the key.
+pub fn t(locale: Locale, key: &str) -> &str {
+    let bundles = bundles();
+    bundles[&locale]
+        .get(key)
+        .or_else(|| bundles[&Locale::En].get(key))
+        .map_or(key, String::as_str)
+}
+
+/// `t` with `{name}` placeholders filled from `args`.
+pub fn tf(locale: Locale, key: &str, args: &[(&str, String)]) -> String {
+    crate::template::render(t(locale, key), args, str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholders(text: &str) -> Vec<String> {
+        let mut names: Vec<String> = text.split('{').skip(1).filter_map(|rest| rest.split_once('}')).map(|(name, _)| name.to_string()).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    #[test]
+    fn test_bundles_match_english() {
+        let english = &bundles()[&Locale::En];
+        for locale in Locale::ALL {
+            let bundle = &bundles()[&locale];
+            for (key, text) in english {
+                let translated = bundle.get(key).unwrap_or_else(|| panic!("{} bundle lacks {}", locale.as_str(), key));
+                assert_eq!(placeholders(translated), placeholders(text), "{} {}", locale.as_str(), key);
+            }
+            for key in bundle.keys() {
+                assert!(english.contains_key(key), "{} bundle has {}, which en.json lacks", locale.as_str(), key);
+            }
+        }
+    }
+
+    fn source_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                source_files(&path, files);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                files.push(path);
+            }
+        }
+    }
+
+    /// Every quoted `namespace.name` in the code whose namespace the bundles
+    /// use must be an English key, so a typo can't show users the raw key.
+    #[test]
+    fn test_code_keys_exist() {
+        let english = &bundles()[&Locale::En];
+        let namespaces: std::collections::HashSet<_> = english.keys().filter_map(|key| key.split_once('.')).map(|(ns, _)| ns).collect();
+        let mut files = Vec::new();
+        source_files(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut files);
+
+        let mut missing = Vec::new();
+        for file in files {
+            let source = std::fs::read_to_string(&file).unwrap();
+            for quoted in source.split('"') {
+                let is_key = quoted.contains('.') && quoted.chars().all(|c| c.is_ascii_lowercase() || c == '_' || c == '.');
+                if is_key
+                    && quoted.split_once('.').is_some_and(|(ns, _)| namespaces.contains(ns))
+                    && !english.contains_key(quoted)
+                {
+                    missing.push(format!("{}: {}", file.display(), quoted));
+                }
+            }
+        }
+        assert!(missing.is_empty(), "keys missing from en.json: {:?}", missing);
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(t(Locale::Ru, "result.cancelled"), "❌ Распознавание отменено.");
+        assert_eq!(t(Locale::En, "no.such.key"), "no.such.key");
+        assert_eq!(
+            tf(Locale::En, "queue.added", &[("position", "2".to_string()), ("file", "voice.ogg".to_string())]),
+            "📥 Added to queue (position: 2)\nFile: voice.ogg"
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(Locale::from_code("ru-RU"), Some(Locale::Ru));
+        assert_eq!(Locale::from_code("de"), None);
+        assert_eq!(Locale::resolve(None, Some("ru")), Locale::Ru);
+        assert_eq!(Locale::resolve(Some(Locale::En), Some("ru")), Locale::En);
+        assert_eq!(Locale::resolve(None, Some("de")), Locale::En);
+    }
+}
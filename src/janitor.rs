@@ -0,0 +1,212 @@
+//! Disk housekeeping: orphaned media and temp files are swept away
+//! periodically, and new files are turned away while the disk is nearly full.
+
+use crate::queue::MediaFile;
+use crate::{BotConfig, BotError, Result};
+use tracing::{info, warn};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Temp files and directories the bot creates start with this, so the
+/// janitor can tell them from other programs' files.
+const TEMP_PREFIX: &str = "stt-bot-";
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// Files untouched for this long are no longer being written or converted.
+const ORPHAN_AGE: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// A temp file for converted or uploaded audio, removed when dropped.
+pub fn temp_file() -> std::io::Result<tempfile::NamedTempFile> {
+    tempfile::Builder::new().prefix(TEMP_PREFIX).tempfile()
+}
+
+/// A temp directory, removed with its contents when dropped.
+pub fn temp_dir() -> std::io::Result<tempfile::TempDir> {
+    tempfile::Builder::new().prefix(TEMP_PREFIX).tempdir()
+}
+
+/// Free space, in bytes, on the filesystem holding `path`; None where it
+/// can't be told.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `path` is NUL-terminated and `stat` is a plain C struct
+    // statvfs fills in
+    let stat = unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        stat
+    };
+    // The field types vary between platforms
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Refuses a download of `size` bytes when it would leave less than
+/// `MIN_FREE_DISK_MB` free where media is stored or converted.
+pub fn check_disk_space(config: &BotConfig, size: u64) -> Result<()> {
+    let Some(min_mb) = config.min_free_disk_mb else {
+        return Ok(());
+    };
+    let queue_dir = config.queue_dir();
+    std::fs::create_dir_all(&queue_dir).map_err(BotError::Io)?;
+    let free = [queue_dir.as_path(), &std::env::temp_dir()]
+        .iter()
+        .filter_map(|dir| free_space(dir))
+        .min();
+    match free {
+        Some(free) if free.saturating_sub(size) < min_mb.saturating_mul(1024 * 1024) => {
+            Err(BotError::LowDiskSpace(free / (1024 * 1024), min_mb))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// What a sweep removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Sweep {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+fn is_stale(metadata: &std::fs::Metadata, now: SystemTime) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| now.duration_since(modified).ok())
+        .is_some_and(|age| age >= ORPHAN_AGE)
+}
+
+/// Removes stale media in `pending_dir` that no queued item or live
+/// `MediaFile` refers to, and stale temp files of the bot's in `temp_dir`.
+pub async fn sweep(pending_dir: Option<&Path>, temp_dir: &Path, now: SystemTime) -> Sweep {
+    let mut swept = Sweep::default();
+
+    if let Some(pending_dir) = pending_dir
+        && let Ok(mut entries) = tokio::fs::read_dir(pending_dir).await
+    {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "bin")
+                || path.with_extension("json").exists()
+                || MediaFile::is_live(&path)
+            {
+                continue;
+            }
+            if let Ok(metadata) = entry.metadata().await
+                && is_stale(&metadata, now)
+                && tokio::fs::remove_file(&path).await.is_ok()
+            {
+                swept.files += 1;
+                swept.bytes += metadata.len();
+            }
+        }
+    }
+
+    if let Ok(mut entries) = tokio::fs::read_dir(temp_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if !entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if !is_stale(&metadata, now) {
+                continue;
+            }
+            let removed = if metadata.is_dir() {
+                tokio::fs::remove_dir_all(entry.path()).await
+            } else {
+                tokio::fs::remove_file(entry.path()).await
+            };
+            match removed {
+                Ok(()) => {
+                    swept.files += 1;
+                    swept.bytes += metadata.len();
+                }
+                Err(e) => warn!("Failed to remove {}: {}", entry.path().display(), e),
+            }
+        }
+    }
+    swept
+}
+
+/// Sweeps every `SWEEP_INTERVAL`. Queued media in `queue_dir` is only swept
+/// with the local queue; with a shared one, other instances own files there
+/// too.
+pub async fn start(queue_dir: PathBuf, local_queue: bool) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pending_dir = local_queue.then_some(queue_dir.as_path());
+        let swept = sweep(pending_dir, &std::env::temp_dir(), SystemTime::now()).await;
+        if swept.files > 0 {
+            info!("Janitor removed {} orphaned files ({} KB)", swept.files, swept.bytes / 1024);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backdate(path: &Path) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - ORPHAN_AGE - Duration::from_secs(60)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sweep_removes_only_orphans() {
+        let pending = tempfile::tempdir().unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let write = |path: std::path::PathBuf, stale: bool| {
+            std::fs::write(&path, b"data").unwrap();
+            if stale {
+                backdate(&path);
+            }
+            path
+        };
+
+        let orphan = write(pending.path().join("orphan.bin"), true);
+        let queued = write(pending.path().join("queued.bin"), true);
+        write(pending.path().join("queued.json"), true);
+        let downloading = write(pending.path().join("downloading.bin"), false);
+        let held = MediaFile::new(write(pending.path().join("held.bin"), true), 4);
+        let old_temp = write(temp.path().join(format!("{}old", TEMP_PREFIX)), true);
+        let new_temp = write(temp.path().join(format!("{}new", TEMP_PREFIX)), false);
+        let foreign = write(temp.path().join("someone-elses"), true);
+
+        let swept = sweep(Some(pending.path()), temp.path(), SystemTime::now()).await;
+        assert_eq!(swept, Sweep { files: 2, bytes: 8 });
+        assert!(!orphan.exists() && !old_temp.exists());
+        assert!(queued.exists() && downloading.exists() && held.path().exists());
+        assert!(new_temp.exists() && foreign.exists());
+
+        // Without the local queue, media is left to whoever owns it
+        drop(held);
+        let stale = write(pending.path().join("other.bin"), true);
+        assert_eq!(sweep(None, temp.path(), SystemTime::now()).await, Sweep::default());
+        assert!(stale.exists());
+    }
+
+    #[test]
+    fn test_disk_space_guard() {
+        assert!(free_space(&std::env::temp_dir()).is_some_and(|free| free > 0));
+        assert!(check_disk_space(&BotConfig::for_tests(), u64::MAX).is_ok());
+        let data = tempfile::tempdir().unwrap();
+        let config = BotConfig {
+            min_free_disk_mb: Some(u64::MAX / (1024 * 1024)),
+            data_dir: data.path().to_path_buf(),
+            ..BotConfig::for_tests()
+        };
+        assert!(matches!(check_disk_space(&config, 0), Err(BotError::LowDiskSpace(_, _))));
+    }
+}
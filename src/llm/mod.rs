@@ -0,0 +1,66 @@
+pub mod openai;
+
+use crate::stt::language;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LlmError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("API error: {0}")]
+    Api(String),
+    #[error("Invalid response format: {0}")]
+    InvalidResponse(String),
+    #[error("Authentication failed")]
+    Authentication,
+    #[error("Rate limit exceeded")]
+    RateLimit,
+}
+
+const SUMMARY_PROMPT: &str = "You summarize transcripts of voice messages and recordings. \
+Reply with a short bullet-point summary of the key points, one point per line starting with \"• \". \
+Write in the same language as the transcript. Do not add an introduction or a conclusion.";
+
+/// Summarizes a transcript as bullet points.
+pub async fn summarize(transcript: &str, base_url: &str, api_key: &str, model: &str) -> Result<String, LlmError> {
+    openai::complete(base_url, api_key, model, SUMMARY_PROMPT, transcript).await
+}
+
+/// Translates a transcript into the language with the given ISO 639-1 code.
+pub async fn translate(
+    transcript: &str,
+    target: &str,
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+) -> Result<String, LlmError> {
+    let prompt = format!(
+        "You translate transcripts of voice messages and recordings into {}. \
+Reply with the translation only, keeping the speaker labels and line breaks of the original.",
+        language::display_name(target)
+    );
+    openai::complete(base_url, api_key, model, &prompt, transcript).await
+}
+
+/// Language to translate into: the reader's Telegram language, or English
+/// when the transcript is already in it.
+pub fn translation_target(reader_language: Option<&str>, spoken_language: Option<&str>) -> &'static str {
+    let preferred = reader_language.and_then(language::code).unwrap_or("en");
+    if spoken_language.and_then(language::code) == Some(preferred) {
+        "en"
+    } else {
+        preferred
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translation_target() {
+        assert_eq!(translation_target(Some("de"), Some("russian")), "de");
+        assert_eq!(translation_target(Some("ru"), Some("ru-RU")), "en");
+        assert_eq!(translation_target(None, Some("es")), "en");
+    }
+}
@@ -0,0 +1,116 @@
+use super::LlmError;
+use tracing::{debug, info};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: [ChatMessage<'a>; 2],
+    temperature: f32,
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatReply,
+}
+
+#[derive(Deserialize)]
+struct ChatReply {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    message: String,
+}
+
+/// Runs one system + user turn through the chat completions API under
+/// `base_url` and returns the reply text.
+pub async fn complete(base_url: &str, api_key: &str, model: &str, system: &str, user: &str) -> Result<String, LlmError> {
+    info!("Starting completion model={} chars={}", model, user.len());
+
+    let request = ChatRequest {
+        model,
+        messages: [
+            ChatMessage { role: "system", content: system },
+            ChatMessage { role: "user", content: user },
+        ],
+        temperature: 0.2,
+    };
+
+    let response = crate::http::client()
+        .post(format!("{}/chat/completions", base_url))
+        .bearer_auth(api_key)
+        .json(&request)
+        .send()
+        .await?;
+
+    let status = response.status();
+    debug!("Chat completions response status: {}", status);
+    let body = response.text().await?;
+
+    if !status.is_success() {
+        return Err(match status.as_u16() {
+            401 => LlmError::Authentication,
+            429 => LlmError::RateLimit,
+            _ => match serde_json::from_str::<ErrorResponse>(&body) {
+                Ok(error) => LlmError::Api(error.error.message),
+                Err(_) => LlmError::Api(format!("HTTP {}: {}", status, body)),
+            },
+        });
+    }
+
+    let reply = parse_reply(&body)?;
+    info!("Completion finished model={} chars={}", model, reply.len());
+    Ok(reply)
+}
+
+fn parse_reply(body: &str) -> Result<String, LlmError> {
+    let response: ChatResponse = serde_json::from_str(body)
+        .map_err(|e| LlmError::InvalidResponse(format!("Failed to parse chat completion: {}", e)))?;
+
+    response
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.message.content)
+        .map(|content| content.trim().to_string())
+        .filter(|content| !content.is_empty())
+        .ok_or_else(|| LlmError::InvalidResponse("Chat completion has no content".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reply() {
+        let body = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "choices": [
+                {"index": 0, "message": {"role": "assistant", "content": "\n• First point\n• Second point\n"}, "finish_reason": "stop"}
+            ]
+        }"#;
+        assert_eq!(parse_reply(body).unwrap(), "• First point\n• Second point");
+
+        let empty = r#"{"choices": [{"index": 0, "message": {"role": "assistant", "content": null}}]}"#;
+        assert!(matches!(parse_reply(empty), Err(LlmError::InvalidResponse(_))));
+    }
+}
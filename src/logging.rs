@@ -0,0 +1,98 @@
+use tracing::warn;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// The span for work on queue item `item_id`. Lines written inside it carry
+/// the id, so one request can be followed from download through conversion
+/// to STT.
+pub fn item_span(item_id: &str) -> tracing::Span {
+    tracing::info_span!("item", item_id = %item_id)
+}
+
+/// A subscriber writing text, colored if `ansi`, or one JSON object per
+/// line to `writer`.
+fn subscriber<W>(json: bool, ansi: bool, filter: EnvFilter, writer: W) -> Box<dyn tracing::Subscriber + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_ansi(ansi && !json).with_writer(writer);
+    if json {
+        Box::new(builder.json().flatten_event(true).with_current_span(true).with_span_list(false).finish())
+    } else {
+        Box::new(builder.finish())
+    }
+}
+
+/// Sets up logging from `RUST_LOG` (default `info`) and `LOG_FORMAT`:
+/// `text` (default) or `json`. Lines from dependencies still on the `log`
+/// crate are picked up too.
+pub fn init() {
+    let format = std::env::var("LOG_FORMAT").unwrap_or_default();
+    let json = format.trim().eq_ignore_ascii_case("json");
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let ansi = std::io::IsTerminal::is_terminal(&std::io::stderr());
+    subscriber(json, ansi, filter, std::io::stderr).init();
+
+    if !json && !format.trim().is_empty() && !format.trim().eq_ignore_ascii_case("text") {
+        warn!("Unknown LOG_FORMAT {:?}, using text", format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines_carry_item_id() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = subscriber(true, false, EnvFilter::new("info"), move || writer.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            item_span("abc-1").in_scope(|| tracing::info!("Converted \"a\""));
+            tracing::warn!("outside");
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "Converted \"a\"");
+        assert_eq!(lines[0]["span"]["item_id"], "abc-1");
+        assert!(lines[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+        assert!(lines[1].get("span").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_item_span_follows_futures() {
+        use tracing::Instrument;
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = subscriber(false, false, EnvFilter::new("info"), move || writer.clone());
+        let _default = tracing::subscriber::set_default(subscriber);
+        async {
+            tokio::task::yield_now().await;
+            tracing::info!("transcribed");
+        }
+        .instrument(item_span("abc-2"))
+        .await;
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("item{item_id=abc-2}"), "{}", output);
+    }
+}
@@ -4,13 +4,43 @@ mod audio;
 mod queue;
 mod persistence;
 mod request_logger;
+mod quota;
+mod cost;
+mod settings;
+mod metrics;
+mod selftest;
+mod subtitles;
+mod llm;
+mod roles;
+mod fair_queue;
+mod history;
+mod text;
+mod http;
+mod logging;
+mod error_report;
+mod alerts;
+mod template;
+mod i18n;
+mod telegram;
+mod tts;
+mod reload;
+mod readiness;
+mod api;
+mod queue_backend;
+mod storage;
+mod batch;
+mod fetch;
+mod spam;
+mod janitor;
+#[cfg(test)]
+mod e2e;
 
 use dotenvy::dotenv;
-use log::{error, info};
+use tracing::{error, info};
 use std::env;
 use std::sync::Arc;
-use std::collections::HashSet;
-use tokio::sync::{RwLock, mpsc};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::RwLock;
 use teloxide::{prelude::*, Bot, types::UserId};
 use thiserror::Error;
 use warp::Filter;
@@ -29,99 +59,1218 @@ pub enum BotError {
     Io(#[from] std::io::Error),
     #[error("Download error: {0}")]
     Download(#[from] teloxide::DownloadError),
+    /// Fetching the file of queue item `id` failed; the sender is given
+    /// the item's error ID.
+    #[error("Download of item {id} failed: {source}")]
+    DownloadFailed { id: String, source: Box<BotError> },
     #[error("Configuration error: {0}")]
     Config(String),
+    #[error("File is {0} MB, over the {1} MB limit")]
+    FileTooLarge(u32, u32),
+    #[error("Media is {0}s long, over the {1}s limit")]
+    TooLong(u32, u32),
+    #[error("Monthly quota exceeded ({0}s remaining)")]
+    QuotaExceeded(u64),
+    #[error("Monthly budget exhausted for all configured providers")]
+    BudgetExhausted,
+    #[error("Audio looks like music")]
+    MusicDetected,
+    #[error("Audio is effectively silent")]
+    SilentAudio,
+    #[error("Same clip transcribed too often recently")]
+    RepeatedClip,
+    #[error("Cancelled by the user")]
+    Cancelled,
+    #[error("Queue is full")]
+    QueueFull,
+    #[error("Only {0} MB of disk space free, under the {1} MB minimum")]
+    LowDiskSpace(u64, u64),
+    #[error("Queue backend error: {0}")]
+    QueueBackend(String),
+    #[error("Database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Media type not allowed in this chat")]
+    MediaNotAllowed(String),
+    #[error("Archive error: {0}")]
+    Archive(#[from] batch::ArchiveError),
+    #[error("Link error: {0}")]
+    Fetch(#[from] fetch::FetchError),
 }
 
 pub type Result<T> = std::result::Result<T, BotError>;
 
-pub type AuthorizedUsers = Arc<RwLock<HashSet<UserId>>>;
+const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_DEEPGRAM_BASE_URL: &str = "https://api.deepgram.com/v1";
+const DEFAULT_ELEVENLABS_BASE_URL: &str = "https://api.elevenlabs.io/v1";
+const DEFAULT_GOOGLE_SPEECH_BASE_URL: &str = "https://speech.googleapis.com/v1";
+const DEFAULT_ASSEMBLYAI_BASE_URL: &str = "https://api.assemblyai.com/v2";
+const DEFAULT_VOSK_MODEL: &str = "vosk-model-small-en-us-0.15";
+const DEFAULT_CONFIDENCE_WARNING_THRESHOLD: f32 = 0.5;
+/// Largest file a self-hosted Bot API server lets bots download.
+const LOCAL_BOT_API_MAX_FILE_MB: u32 = 2000;
+
+pub type UserRoles = Arc<RwLock<roles::Roles>>;
 pub type CurrentProvider = Arc<RwLock<stt::SttProvider>>;
+/// The configuration handlers and the queue worker read; swapped by /reload.
+pub type SharedConfig = Arc<RwLock<BotConfig>>;
+
+/// Monthly usage accounting and transcript history, bundled so handlers
+/// stay within dptree's parameter limit.
+#[derive(Clone)]
+pub struct UsageStores {
+    pub quotas: quota::QuotaStore,
+    pub costs: cost::CostStore,
+    pub history: history::HistoryStore,
+    pub spam: spam::SpamStore,
+}
 
 #[derive(Clone)]
 pub struct BotConfig {
     pub telegram_token: String,
     pub stt_provider: stt::SttProvider,
+    /// Transcripts needing more messages than this are sent as a `.txt`
+    /// file instead; None always splits into messages.
+    pub max_message_parts: Option<usize>,
+    /// Chunks of over-long media transcribed at the same time.
+    pub chunk_concurrency: usize,
+    /// Providers tried in order after `stt_provider` when it is rate
+    /// limited, unavailable or times out.
+    pub stt_fallbacks: Vec<stt::SttProvider>,
+    /// Two providers every item is transcribed with at once, for accuracy
+    /// over cost; None uses `stt_provider` alone.
+    pub consensus_providers: Option<[stt::SttProvider; 2]>,
+    pub consensus_strategy: stt::consensus::ConsensusStrategy,
+    /// Failures in a row that take a provider out of rotation; None never does.
+    pub circuit_breaker_failures: Option<u32>,
+    /// How long a provider stays out of rotation before it is tried again.
+    pub circuit_breaker_cooldown_secs: u64,
+    /// Provider requests taking longer than this fail with a timeout; None
+    /// lets them run.
+    pub stt_timeout_secs: Option<u64>,
+    /// Proxy for outbound requests to providers, the LLM and the
+    /// Pushgateway, and for Telegram unless `telegram_proxy_url` is set.
+    pub proxy_url: Option<String>,
+    /// Proxy for Telegram only, e.g. where api.telegram.org is blocked.
+    pub telegram_proxy_url: Option<String>,
+    /// A self-hosted Bot API server to use instead of api.telegram.org.
+    pub telegram_api_url: Option<reqwest::Url>,
+    /// Failed transcriptions are reported to this Sentry project.
+    pub sentry_dsn: Option<sentry::types::Dsn>,
+    /// Sentry's environment tag, e.g. `staging`.
+    pub sentry_environment: Option<String>,
+    /// Failed transcriptions are posted here as JSON.
+    pub error_webhook_url: Option<reqwest::Url>,
+    /// Bearer token for `POST /api/transcribe`; the API is off without one.
+    pub api_token: Option<String>,
     pub elevenlabs_api_key: Option<String>,
     pub openai_api_key: Option<String>,
+    /// Root of the OpenAI-compatible API (OpenAI, Groq, Together or a
+    /// self-hosted server) used for Whisper and the LLM features.
+    pub openai_base_url: String,
+    /// API roots of the hosted providers, for proxies, regional endpoints
+    /// and test servers.
+    pub deepgram_base_url: String,
+    pub elevenlabs_base_url: String,
+    pub google_speech_base_url: String,
+    pub assemblyai_base_url: String,
+    /// Host for both Azure endpoints in place of the region's own, e.g. a
+    /// custom domain.
+    pub azure_speech_base_url: Option<String>,
+    /// Model name sent to the Whisper endpoint.
+    pub whisper_model: String,
+    /// ElevenLabs speech-to-text model.
+    pub elevenlabs_model: &'static str,
+    /// Google recognition model; `default` leaves the choice to Google.
+    pub google_model: &'static str,
+    pub google_use_enhanced: bool,
+    /// OpenAI chat model used for /summarize.
+    pub llm_model: String,
+    /// Reads transcripts back for /speak; None when neither ElevenLabs nor
+    /// OpenAI is configured.
+    pub tts_provider: Option<tts::TtsProvider>,
+    /// Voice for /speak: an OpenAI voice name or an ElevenLabs voice ID.
+    pub tts_voice: Option<String>,
     pub google_credentials_json: Option<String>,
     pub deepgram_api_key: Option<String>,
+    pub assemblyai_api_key: Option<String>,
+    /// Asks AssemblyAI to tell speakers apart and label their turns.
+    pub assemblyai_speaker_labels: bool,
+    /// Asks AssemblyAI for chapters with summaries, for the JSON attachment.
+    pub assemblyai_auto_chapters: bool,
+    /// ggml model file for the local whisper.cpp provider.
+    pub whisper_model_path: Option<String>,
+    /// whisper.cpp CLI executable name or path.
+    pub whisper_cpp_bin: String,
+    /// Where Vosk models are kept and downloaded to.
+    pub vosk_model_dir: String,
+    /// Vosk model name, as listed on alphacephei.com/vosk/models.
+    pub vosk_model: String,
+    /// Delay before each answer of the `mock` provider.
+    pub mock_stt_latency_ms: u64,
+    /// Share of `mock` requests that fail, 0.0-1.0.
+    pub mock_stt_failure_rate: f64,
+    /// Whether `mock` transcripts describe the audio they were sent.
+    pub mock_stt_echo: bool,
+    pub azure_speech_key: Option<String>,
+    /// Azure region of the Speech resource, e.g. `westeurope`.
+    pub azure_speech_region: Option<String>,
     pub bot_password: Option<String>,
+    /// Channels whose posts are transcribed even with a password set; a
+    /// channel can't log in.
+    pub channel_ids: HashSet<ChatId>,
+    /// What the bot transcribes unprompted in groups, unless a group
+    /// overrides it with /groupmode.
+    pub group_mode: settings::GroupMode,
+    /// Password logins expire after this many hours; None keeps them forever.
+    pub auth_ttl_hours: Option<u64>,
     pub admin_user_ids: HashSet<UserId>,
+    /// Chat that gets operational alerts: bad provider keys, failing
+    /// conversions, a full queue or disk, and budget caps.
+    pub admin_chat_id: Option<ChatId>,
+    /// The same alert is sent at most once per this many minutes.
+    pub alert_interval_minutes: u64,
+    /// Admins' files skip ahead of everyone else's in the queue.
+    pub admin_priority: bool,
+    /// Most files allowed to wait in the queue; None is unlimited.
+    pub queue_capacity: Option<usize>,
+    /// What happens to new files when the queue is at capacity.
+    pub queue_full_policy: queue::QueueFullPolicy,
+    /// Where queued files wait: in this process or in Redis, shared with
+    /// other instances.
+    pub queue_backend: queue_backend::BackendConfig,
+    /// A shared queue hands a file to another worker if its worker goes
+    /// this long without renewing its lease.
+    pub queue_visibility_timeout_secs: u64,
+    /// Only works the shared queue; another instance talks to Telegram.
+    pub queue_worker_only: bool,
+    /// Where users, chat settings, quotas, history and spend are kept.
+    pub storage_backend: storage::StorageConfig,
+    /// Root of everything the bot writes: queued media under `queue/`, and
+    /// by default the JSON state, request logs and Vosk models.
+    pub data_dir: std::path::PathBuf,
+    pub quota_minutes_per_month: Option<u64>,
+    /// Transcripts kept per user for /history; 0 keeps none.
+    pub history_max_entries: usize,
+    /// History entries are dropped after this many days; None keeps them
+    /// until newer ones push them out.
+    pub history_retention_days: Option<u64>,
+    /// Larger files are declined before downloading; the public Bot API
+    /// can't fetch more than 20 MB anyway, a local server up to 2000 MB.
+    pub max_file_size_mb: Option<u32>,
+    /// Longer media (or requested ranges) are declined before downloading.
+    pub max_duration_secs: Option<u32>,
+    /// Domains /url may download from, subdomains included; empty turns
+    /// /url off.
+    pub url_allowed_domains: Vec<String>,
+    pub url_max_file_mb: u32,
+    /// yt-dlp binary for links to pages rather than files.
+    pub ytdlp_path: Option<String>,
+    /// Times the same clip is transcribed within `spam_window_minutes`
+    /// before further copies are refused; None turns throttling off.
+    pub spam_repeat_limit: Option<u32>,
+    pub spam_window_minutes: u64,
+    /// Downloads that would leave less than this much disk space free, in
+    /// MB, are refused; None turns the check off.
+    pub min_free_disk_mb: Option<u64>,
+    /// Where the request log (`requests.jsonl`) and its rotated files live.
+    pub request_log_dir: std::path::PathBuf,
+    /// The request log is rotated past this size, and daily; None rotates
+    /// daily only.
+    pub request_log_max_mb: Option<u64>,
+    /// Rotated request logs kept; older ones are deleted.
+    pub request_log_keep: usize,
+    pub provider_prices: HashMap<stt::SttProvider, f64>,
+    pub provider_budgets: HashMap<stt::SttProvider, f64>,
+    /// Cap on the month's estimated spend across all providers; past it only
+    /// free providers are used.
+    pub monthly_budget_usd: Option<f64>,
+    pub music_detection: bool,
+    pub stt_alternatives: u8,
+    pub low_confidence_threshold: Option<f32>,
+    /// Transcripts scored below this overall get a warning line.
+    pub confidence_warning_threshold: Option<f32>,
+    pub auto_gain_threshold_db: Option<f32>,
+    /// Cleanup filters applied before transcription unless a chat overrides them.
+    pub audio_preprocess: audio::Preprocess,
+    /// Audio quieter than this (dBFS) isn't sent to the provider; None
+    /// transcribes everything.
+    pub silence_threshold_db: Option<f32>,
+    pub channel_split: bool,
+    /// Cleanup applied to transcripts after STT.
+    pub text_formatting: text::Formatting,
+    /// Segmented transcription: media at least this long is extracted and
+    /// transcribed in segments of `segment_secs`.
+    pub segmented_min_secs: Option<u32>,
+    pub segment_secs: u32,
+    /// Shows the transcript so far in the processing message while media
+    /// is transcribed segment by segment.
+    pub live_transcript: bool,
+    /// ffmpeg runs longer than this are killed; None lets them run.
+    pub ffmpeg_timeout_secs: Option<u64>,
+    /// Replies with the converted audio and ffmpeg's log, for diagnosing
+    /// conversion failures.
+    pub debug_audio: audio::DebugAudio,
+    /// Appended to every transcript; see `queue::render_footer` for placeholders.
+    pub transcript_footer: Option<String>,
+    /// Replaces the `📝 Transcription:` heading and text of replies; see
+    /// `template::REPLY_PLACEHOLDERS`.
+    pub reply_template: Option<String>,
+    pub pushgateway_url: Option<String>,
+    pub pushgateway_job: String,
+    pub pushgateway_interval_secs: u64,
+}
+
+/// An HTTP(S) proxy URL from `name`, or None if it's unset. SOCKS URLs and
+/// ones reqwest can't parse are refused up front, so a typo fails at
+/// startup rather than on the first request.
+fn proxy_from_env(name: &str) -> Result<Option<String>> {
+    match env::var(name) {
+        Ok(v) if !v.trim().is_empty() => {
+            let url = v.trim();
+            if url.starts_with("socks") {
+                return Err(BotError::Config(format!(
+                    "Invalid {}: SOCKS proxies aren't supported by this build, use an HTTP(S) proxy: {}",
+                    name, url
+                )));
+            }
+            reqwest::Proxy::all(url).map_err(|e| BotError::Config(format!("Invalid {}: {}", name, e)))?;
+            Ok(Some(url.to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads a boolean env var, accepting 1/0, true/false, on/off, yes/no.
+fn env_flag(name: &str, default: bool) -> Result<bool> {
+    match env::var(name) {
+        Ok(v) => match v.trim().to_lowercase().as_str() {
+            "" => Ok(default),
+            "1" | "true" | "on" | "yes" => Ok(true),
+            "0" | "false" | "off" | "no" => Ok(false),
+            _ => Err(BotError::Config(format!("Invalid {}: {}", name, v))),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+/// An API root from `name` without its trailing slash, or `default`.
+fn base_url_from_env(name: &str, default: &str) -> String {
+    env::var(name)
+        .ok()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| default.to_string())
 }
 
 impl BotConfig {
     pub fn from_env() -> Result<Self> {
         let telegram_token = env::var("TELEGRAM_BOT_TOKEN")
             .map_err(|_| BotError::Config("TELEGRAM_BOT_TOKEN not set".to_string()))?;
+        let data_dir = std::path::PathBuf::from(
+            env::var("DATA_DIR").ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).unwrap_or_else(|| "data".to_string()),
+        );
 
+        // An ordered failover chain: `whisper,elevenlabs,google`
         let stt_provider_str = env::var("STT_PROVIDER").unwrap_or_else(|_| "deepgram".to_string());
-        let stt_provider = stt::SttProvider::from_str(&stt_provider_str)
-            .ok_or_else(|| BotError::Config(format!("Invalid STT_PROVIDER: {}", stt_provider_str)))?;
+        let mut stt_chain = Vec::new();
+        for name in stt_provider_str.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let provider = stt::SttProvider::from_str(name)
+                .ok_or_else(|| BotError::Config(format!("Invalid STT_PROVIDER: {}", name)))?;
+            if !stt_chain.contains(&provider) {
+                stt_chain.push(provider);
+            }
+        }
+        if stt_chain.is_empty() {
+            return Err(BotError::Config(format!("Invalid STT_PROVIDER: {}", stt_provider_str)));
+        }
+        let stt_provider = stt_chain.remove(0);
+        let stt_fallbacks = stt_chain;
+
+        // `deepgram,whisper`: exactly two different providers
+        let consensus_providers = match env::var("CONSENSUS_PROVIDERS") {
+            Ok(v) if !v.trim().is_empty() => {
+                let providers: Vec<_> = v.split(',').map(|name| stt::SttProvider::from_str(name.trim())).collect();
+                match providers.as_slice() {
+                    [Some(first), Some(second)] if first != second => Some([*first, *second]),
+                    _ => {
+                        return Err(BotError::Config(format!(
+                            "Invalid CONSENSUS_PROVIDERS (two different providers, e.g. deepgram,whisper): {}",
+                            v
+                        )));
+                    }
+                }
+            }
+            _ => None,
+        };
+        let circuit_breaker_failures = match env::var("CIRCUIT_BREAKER_FAILURES") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u32>() {
+                Ok(0) => None,
+                Ok(n) => Some(n),
+                Err(_) => return Err(BotError::Config(format!("Invalid CIRCUIT_BREAKER_FAILURES: {}", v))),
+            },
+            _ => Some(5),
+        };
+        let circuit_breaker_cooldown_secs = match env::var("CIRCUIT_BREAKER_COOLDOWN_SECS") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse::<u64>()
+                .map_err(|_| BotError::Config(format!("Invalid CIRCUIT_BREAKER_COOLDOWN_SECS: {}", v)))?,
+            _ => 60,
+        };
+
+        let stt_timeout_secs = match env::var("STT_TIMEOUT_SECONDS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(secs) => Some(secs),
+                Err(_) => return Err(BotError::Config(format!("Invalid STT_TIMEOUT_SECONDS: {}", v))),
+            },
+            _ => Some(stt::DEFAULT_TIMEOUT_SECS),
+        };
+
+        let proxy_url = proxy_from_env("HTTPS_PROXY")?;
+        let telegram_proxy_url = proxy_from_env("TELEGRAM_PROXY")?;
+        let telegram_api_url = match env::var("TELEGRAM_API_URL") {
+            Ok(v) if !v.trim().is_empty() => Some(
+                reqwest::Url::parse(v.trim())
+                    .map_err(|e| BotError::Config(format!("Invalid TELEGRAM_API_URL: {}", e)))?,
+            ),
+            _ => None,
+        };
+
+        let sentry_dsn = match env::var("SENTRY_DSN") {
+            Ok(v) if !v.trim().is_empty() => Some(
+                v.trim()
+                    .parse::<sentry::types::Dsn>()
+                    .map_err(|e| BotError::Config(format!("Invalid SENTRY_DSN, expected https://<key>@<host>/<project>: {}", e)))?,
+            ),
+            _ => None,
+        };
+        let sentry_environment = env::var("SENTRY_ENVIRONMENT").ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let error_webhook_url = match env::var("ERROR_WEBHOOK_URL") {
+            Ok(v) if !v.trim().is_empty() => Some(
+                reqwest::Url::parse(v.trim())
+                    .map_err(|e| BotError::Config(format!("Invalid ERROR_WEBHOOK_URL: {}", e)))?,
+            ),
+            _ => None,
+        };
+
+        let api_token = env::var("API_TOKEN")
+            .ok()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty());
+
+        let consensus_strategy = match env::var("CONSENSUS_STRATEGY") {
+            Ok(v) if !v.trim().is_empty() => stt::consensus::ConsensusStrategy::from_str(&v)
+                .ok_or_else(|| BotError::Config(format!("Invalid CONSENSUS_STRATEGY (confidence or merge): {}", v)))?,
+            _ => stt::consensus::ConsensusStrategy::default(),
+        };
 
         let elevenlabs_api_key = env::var("ELEVENLABS_API_KEY").ok();
         let openai_api_key = env::var("OPENAI_API_KEY").ok();
+        let openai_base_url = base_url_from_env("OPENAI_BASE_URL", DEFAULT_OPENAI_BASE_URL);
+        let deepgram_base_url = base_url_from_env("DEEPGRAM_BASE_URL", DEFAULT_DEEPGRAM_BASE_URL);
+        let elevenlabs_base_url = base_url_from_env("ELEVENLABS_BASE_URL", DEFAULT_ELEVENLABS_BASE_URL);
+        let google_speech_base_url = base_url_from_env("GOOGLE_SPEECH_BASE_URL", DEFAULT_GOOGLE_SPEECH_BASE_URL);
+        let assemblyai_base_url = base_url_from_env("ASSEMBLYAI_BASE_URL", DEFAULT_ASSEMBLYAI_BASE_URL);
+        let azure_speech_base_url = Some(base_url_from_env("AZURE_SPEECH_BASE_URL", "")).filter(|url| !url.is_empty());
+        let whisper_model = match env::var("WHISPER_MODEL") {
+            Ok(v) if !v.trim().is_empty() => v.trim().to_string(),
+            _ => "whisper-1".to_string(),
+        };
+        // Other OpenAI-compatible servers name their models as they like
+        if openai_base_url == DEFAULT_OPENAI_BASE_URL && !stt::whisper::OPENAI_MODELS.contains(&whisper_model.as_str()) {
+            return Err(BotError::Config(format!(
+                "Invalid WHISPER_MODEL for OpenAI ({}): {}",
+                stt::whisper::OPENAI_MODELS.join(", "),
+                whisper_model
+            )));
+        }
+        let elevenlabs_model = match env::var("ELEVENLABS_MODEL_ID") {
+            Ok(v) if !v.trim().is_empty() => stt::elevenlabs::MODELS
+                .into_iter()
+                .find(|model| *model == v.trim())
+                .ok_or_else(|| BotError::Config(format!(
+                    "Invalid ELEVENLABS_MODEL_ID ({}): {}",
+                    stt::elevenlabs::MODELS.join(", "),
+                    v
+                )))?,
+            _ => stt::SttProvider::ElevenLabs.model(),
+        };
+        let google_model = match env::var("GOOGLE_MODEL") {
+            Ok(v) if !v.trim().is_empty() => stt::google::MODELS
+                .into_iter()
+                .find(|model| *model == v.trim())
+                .ok_or_else(|| BotError::Config(format!(
+                    "Invalid GOOGLE_MODEL ({}): {}",
+                    stt::google::MODELS.join(", "),
+                    v
+                )))?,
+            _ => stt::SttProvider::Google.model(),
+        };
+        let google_use_enhanced = env_flag("GOOGLE_USE_ENHANCED", false)?;
+        if google_use_enhanced && !stt::google::ENHANCED_MODELS.contains(&google_model) {
+            return Err(BotError::Config(format!(
+                "GOOGLE_USE_ENHANCED needs GOOGLE_MODEL set to one of: {}",
+                stt::google::ENHANCED_MODELS.join(", ")
+            )));
+        }
+        let llm_model = env::var("LLM_MODEL")
+            .ok()
+            .map(|model| model.trim().to_string())
+            .filter(|model| !model.is_empty())
+            .unwrap_or_else(|| "gpt-4o-mini".to_string());
         let google_credentials_json = env::var("GOOGLE_CREDENTIALS_JSON").ok();
         let deepgram_api_key = env::var("DEEPGRAM_API_KEY").ok();
+        let assemblyai_api_key = env::var("ASSEMBLYAI_API_KEY").ok();
+        let assemblyai_speaker_labels = env_flag("ASSEMBLYAI_SPEAKER_LABELS", false)?;
+        let assemblyai_auto_chapters = env_flag("ASSEMBLYAI_AUTO_CHAPTERS", false)?;
+        let whisper_model_path = env::var("WHISPER_MODEL_PATH")
+            .ok()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+        let whisper_cpp_bin = env::var("WHISPER_CPP_BIN")
+            .ok()
+            .map(|bin| bin.trim().to_string())
+            .filter(|bin| !bin.is_empty())
+            .unwrap_or_else(|| "whisper-cli".to_string());
+        let vosk_model_dir = env::var("VOSK_MODEL_DIR")
+            .ok()
+            .map(|dir| dir.trim().to_string())
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| data_dir.join("vosk").display().to_string());
+        let vosk_model = match env::var("VOSK_MODEL") {
+            Ok(v) if !v.trim().is_empty() => v.trim().to_string(),
+            _ => DEFAULT_VOSK_MODEL.to_string(),
+        };
+        let mock_stt_latency_ms = match env::var("MOCK_STT_LATENCY_MS") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse::<u64>()
+                .map_err(|_| BotError::Config(format!("Invalid MOCK_STT_LATENCY_MS: {}", v)))?,
+            _ => 0,
+        };
+        let mock_stt_failure_rate = match env::var("MOCK_STT_FAILURE_RATE") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => rate,
+                _ => return Err(BotError::Config(format!("Invalid MOCK_STT_FAILURE_RATE (0.0-1.0): {}", v))),
+            },
+            _ => 0.0,
+        };
+        let mock_stt_echo = env_flag("MOCK_STT_ECHO", false)?;
+        let azure_speech_key = env::var("AZURE_SPEECH_KEY").ok();
+        let azure_speech_region = env::var("AZURE_SPEECH_REGION")
+            .ok()
+            .map(|region| region.trim().to_lowercase())
+            .filter(|region| !region.is_empty());
         let bot_password = env::var("BOT_PASSWORD").ok();
+        let channel_ids = match env::var("CHANNEL_IDS") {
+            Ok(v) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse::<i64>().map(ChatId).map_err(|_| BotError::Config(format!("Invalid CHANNEL_IDS entry: {}", s))))
+                .collect::<Result<HashSet<_>>>()?,
+            Err(_) => HashSet::new(),
+        };
+        let group_mode = match env::var("GROUP_MODE") {
+            Ok(v) if !v.trim().is_empty() => settings::GroupMode::from_str(&v)
+                .ok_or_else(|| BotError::Config(format!("Invalid GROUP_MODE: {} (expected all, mention or reply)", v)))?,
+            _ => settings::GroupMode::All,
+        };
+        let auth_ttl_hours = match env::var("AUTH_TTL_HOURS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(hours) => Some(hours),
+                Err(_) => return Err(BotError::Config(format!("Invalid AUTH_TTL_HOURS: {}", v))),
+            },
+            _ => None,
+        };
+
+        let admin_user_ids = env::var("ADMIN_USER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<u64>().map(UserId).map_err(|_| BotError::Config(format!("Invalid ADMIN_USER_IDS entry: {}", s))))
+            .collect::<Result<HashSet<_>>>()?;
+        let admin_priority = env_flag("ADMIN_PRIORITY", true)?;
+        let admin_chat_id = match env::var("ADMIN_CHAT_ID") {
+            Ok(v) if !v.trim().is_empty() => Some(ChatId(
+                v.trim().parse::<i64>().map_err(|_| BotError::Config(format!("Invalid ADMIN_CHAT_ID: {}", v)))?,
+            )),
+            _ => None,
+        };
+        let alert_interval_minutes = match env::var("ALERT_INTERVAL_MINUTES") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse::<u64>()
+                .map_err(|_| BotError::Config(format!("Invalid ALERT_INTERVAL_MINUTES: {}", v)))?,
+            _ => 30,
+        };
+
+        let queue_capacity = match env::var("QUEUE_CAPACITY") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<usize>() {
+                Ok(0) => None,
+                Ok(capacity) => Some(capacity),
+                Err(_) => return Err(BotError::Config(format!("Invalid QUEUE_CAPACITY: {}", v))),
+            },
+            _ => Some(100),
+        };
+        let queue_full_policy = match env::var("QUEUE_FULL_POLICY") {
+            Ok(v) if !v.trim().is_empty() => queue::QueueFullPolicy::from_str(&v).ok_or_else(|| {
+                BotError::Config(format!("Invalid QUEUE_FULL_POLICY: {} (expected reject, drop-oldest or defer)", v))
+            })?,
+            _ => queue::QueueFullPolicy::Reject,
+        };
+        let redis_url = match env::var("REDIS_URL") {
+            Ok(v) if !v.trim().is_empty() => Some(
+                queue_backend::redis::RedisUrl::parse(&v)
+                    .map_err(|e| BotError::Config(format!("Invalid REDIS_URL: {}", e)))?,
+            ),
+            _ => None,
+        };
+        let require_redis = |setting: &str| {
+            redis_url.clone().ok_or_else(|| BotError::Config(format!("{}=redis needs REDIS_URL", setting)))
+        };
+        let queue_backend = match env::var("QUEUE_BACKEND") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().to_lowercase().as_str() {
+                "local" => queue_backend::BackendConfig::Local,
+                "redis" => queue_backend::BackendConfig::Redis(require_redis("QUEUE_BACKEND")?),
+                _ => return Err(BotError::Config(format!("Invalid QUEUE_BACKEND: {} (expected local or redis)", v))),
+            },
+            _ => queue_backend::BackendConfig::Local,
+        };
+        let storage_backend = match env::var("STORAGE_BACKEND") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().to_lowercase().as_str() {
+                "json" => storage::StorageConfig::Json(data_dir.clone()),
+                "redis" => storage::StorageConfig::Redis(require_redis("STORAGE_BACKEND")?),
+                "sqlite" => storage::StorageConfig::Sqlite(match env::var("SQLITE_PATH") {
+                    Ok(path) if !path.trim().is_empty() => std::path::PathBuf::from(path.trim()),
+                    _ => data_dir.join("state.sqlite3"),
+                }),
+                _ => return Err(BotError::Config(format!("Invalid STORAGE_BACKEND: {} (expected json, sqlite or redis)", v))),
+            },
+            _ => storage::StorageConfig::Json(data_dir.clone()),
+        };
+        let queue_visibility_timeout_secs = match env::var("QUEUE_VISIBILITY_TIMEOUT_SECS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(secs) if secs >= 3 => secs,
+                _ => return Err(BotError::Config(format!("Invalid QUEUE_VISIBILITY_TIMEOUT_SECS: {} (at least 3)", v))),
+            },
+            _ => 300,
+        };
+        let queue_worker_only = env_flag("QUEUE_WORKER_ONLY", false)?;
+        if queue_worker_only && queue_backend == queue_backend::BackendConfig::Local {
+            return Err(BotError::Config("QUEUE_WORKER_ONLY needs QUEUE_BACKEND=redis".to_string()));
+        }
+
+        let quota_minutes_per_month = match env::var("QUOTA_MINUTES_PER_MONTH") {
+            Ok(v) if !v.trim().is_empty() => Some(v.trim().parse::<u64>().map_err(|_| {
+                BotError::Config(format!("Invalid QUOTA_MINUTES_PER_MONTH: {}", v))
+            })?),
+            _ => None,
+        };
+
+        let history_max_entries = match env::var("HISTORY_MAX_ENTRIES") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse::<usize>().map_err(|_| {
+                BotError::Config(format!("Invalid HISTORY_MAX_ENTRIES: {}", v))
+            })?,
+            _ => 20,
+        };
+        let history_retention_days = match env::var("HISTORY_RETENTION_DAYS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(days) => Some(days),
+                Err(_) => return Err(BotError::Config(format!("Invalid HISTORY_RETENTION_DAYS: {}", v))),
+            },
+            _ => Some(30),
+        };
+
+        let max_file_size_mb = match env::var("MAX_FILE_SIZE_MB") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u32>() {
+                Ok(0) => None,
+                Ok(mb) => Some(mb),
+                Err(_) => return Err(BotError::Config(format!("Invalid MAX_FILE_SIZE_MB: {}", v))),
+            },
+            // A local Bot API server serves files up to 2000 MB
+            _ if telegram_api_url.is_some() => Some(LOCAL_BOT_API_MAX_FILE_MB),
+            _ => Some(20),
+        };
 
-        let admin_user_ids: HashSet<UserId> = env::var("ADMIN_USER_IDS")
+        let url_allowed_domains: Vec<String> = env::var("URL_ALLOWED_DOMAINS")
             .unwrap_or_default()
             .split(',')
-            .filter_map(|s| s.trim().parse::<u64>().ok())
-            .map(UserId)
+            .map(|domain| domain.trim().trim_start_matches("*.").trim_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
             .collect();
+        let url_max_file_mb = match env::var("URL_MAX_FILE_MB") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u32>() {
+                Ok(mb) if mb > 0 => mb,
+                _ => return Err(BotError::Config(format!("Invalid URL_MAX_FILE_MB: {}", v))),
+            },
+            _ => 100,
+        };
+        let ytdlp_path = env::var("YTDLP_PATH").ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
 
-        // Validate that required API keys are present for selected provider
-        match stt_provider {
-            stt::SttProvider::Whisper if openai_api_key.is_none() => {
-                return Err(BotError::Config("OPENAI_API_KEY required for Whisper".to_string()));
-            }
-            stt::SttProvider::ElevenLabs if elevenlabs_api_key.is_none() => {
-                return Err(BotError::Config("ELEVENLABS_API_KEY required for ElevenLabs".to_string()));
-            }
-            stt::SttProvider::Google if google_credentials_json.is_none() => {
-                return Err(BotError::Config("GOOGLE_CREDENTIALS_JSON required for Google".to_string()));
+        let spam_repeat_limit = match env::var("SPAM_REPEAT_LIMIT") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u32>() {
+                Ok(0) => None,
+                Ok(limit) => Some(limit),
+                Err(_) => return Err(BotError::Config(format!("Invalid SPAM_REPEAT_LIMIT: {}", v))),
+            },
+            _ => None,
+        };
+        let request_log_dir = match env::var("REQUEST_LOG_DIR") {
+            Ok(v) if !v.trim().is_empty() => std::path::PathBuf::from(v.trim()),
+            _ => data_dir.join("logs"),
+        };
+        let request_log_max_mb = match env::var("REQUEST_LOG_MAX_MB") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(mb) => Some(mb),
+                Err(_) => return Err(BotError::Config(format!("Invalid REQUEST_LOG_MAX_MB: {}", v))),
+            },
+            _ => Some(10),
+        };
+        let request_log_keep = match env::var("REQUEST_LOG_KEEP") {
+            Ok(v) if !v.trim().is_empty() => v.trim().parse::<usize>().map_err(|_| {
+                BotError::Config(format!("Invalid REQUEST_LOG_KEEP: {}", v))
+            })?,
+            _ => 30,
+        };
+        let spam_window_minutes = match env::var("SPAM_WINDOW_MINUTES") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(minutes) if minutes > 0 => minutes,
+                _ => return Err(BotError::Config(format!("Invalid SPAM_WINDOW_MINUTES: {}", v))),
+            },
+            _ => 60,
+        };
+        let min_free_disk_mb = match env::var("MIN_FREE_DISK_MB") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(mb) => Some(mb),
+                Err(_) => return Err(BotError::Config(format!("Invalid MIN_FREE_DISK_MB: {}", v))),
+            },
+            _ => Some(200),
+        };
+
+        let max_duration_secs = match env::var("MAX_DURATION_SECONDS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u32>() {
+                Ok(0) => None,
+                Ok(secs) => Some(secs),
+                Err(_) => return Err(BotError::Config(format!("Invalid MAX_DURATION_SECONDS: {}", v))),
+            },
+            _ => None,
+        };
+
+        let provider_prices = cost::parse_provider_amounts(&env::var("PROVIDER_PRICES").unwrap_or_default())
+            .map_err(|e| BotError::Config(format!("Invalid PROVIDER_PRICES: {}", e)))?;
+        let provider_budgets = cost::parse_provider_amounts(&env::var("PROVIDER_BUDGETS").unwrap_or_default())
+            .map_err(|e| BotError::Config(format!("Invalid PROVIDER_BUDGETS: {}", e)))?;
+        let monthly_budget_usd = match env::var("MONTHLY_BUDGET_USD") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<f64>() {
+                Ok(cap) if cap >= 0.0 => Some(cap),
+                _ => return Err(BotError::Config(format!("Invalid MONTHLY_BUDGET_USD: {}", v))),
+            },
+            _ => None,
+        };
+
+        let music_detection = env_flag("MUSIC_DETECTION", true)?;
+
+        let stt_alternatives = match env::var("STT_ALTERNATIVES") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u8>() {
+                Ok(n @ 1..=5) => n,
+                _ => return Err(BotError::Config(format!("Invalid STT_ALTERNATIVES (1-5): {}", v))),
+            },
+            _ => 1,
+        };
+
+        let low_confidence_threshold = match env::var("LOW_CONFIDENCE_THRESHOLD") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<f32>() {
+                Ok(t) if (0.0..=1.0).contains(&t) => Some(t),
+                _ => return Err(BotError::Config(format!("Invalid LOW_CONFIDENCE_THRESHOLD (0.0-1.0): {}", v))),
+            },
+            _ => None,
+        };
+
+        let confidence_warning_threshold = match env::var("CONFIDENCE_WARNING_THRESHOLD") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<f32>() {
+                Ok(0.0) => None,
+                Ok(t) if (0.0..=1.0).contains(&t) => Some(t),
+                _ => return Err(BotError::Config(format!("Invalid CONFIDENCE_WARNING_THRESHOLD (0.0-1.0): {}", v))),
+            },
+            _ => Some(DEFAULT_CONFIDENCE_WARNING_THRESHOLD),
+        };
+
+        let auto_gain_threshold_db = if env_flag("AUTO_GAIN", false)? {
+            match env::var("AUTO_GAIN_THRESHOLD_DB") {
+                Ok(v) if !v.trim().is_empty() => Some(v.trim().parse::<f32>().map_err(|_| {
+                    BotError::Config(format!("Invalid AUTO_GAIN_THRESHOLD_DB: {}", v))
+                })?),
+                _ => Some(-30.0),
             }
-            stt::SttProvider::Deepgram if deepgram_api_key.is_none() => {
-                return Err(BotError::Config("DEEPGRAM_API_KEY required for Deepgram".to_string()));
+        } else {
+            None
+        };
+
+        let audio_preprocess = match env::var("AUDIO_PREPROCESS") {
+            Ok(v) if !v.trim().is_empty() => audio::Preprocess::parse(&v).ok_or_else(|| {
+                BotError::Config(format!(
+                    "Invalid AUDIO_PREPROCESS: {} (expected off or a list of {})",
+                    v,
+                    audio::Preprocess::FILTERS.join(", ")
+                ))
+            })?,
+            _ => audio::Preprocess::default(),
+        };
+
+        let silence_threshold_db = if env_flag("SILENCE_SKIP", true)? {
+            match env::var("SILENCE_THRESHOLD_DB") {
+                Ok(v) if !v.trim().is_empty() => Some(v.trim().parse::<f32>().map_err(|_| {
+                    BotError::Config(format!("Invalid SILENCE_THRESHOLD_DB: {}", v))
+                })?),
+                _ => Some(audio::analyze::DEFAULT_SILENCE_THRESHOLD_DB),
             }
-            _ => {}
+        } else {
+            None
+        };
+
+        let channel_split = env_flag("CHANNEL_SPLIT", true)?;
+
+        let text_formatting = text::Formatting {
+            capitalize: env_flag("TEXT_CAPITALIZE", true)?,
+            remove_fillers: env_flag("TEXT_REMOVE_FILLERS", false)?,
+            normalize_numbers: env_flag("TEXT_NORMALIZE_NUMBERS", false)?,
+            paragraph_pause_secs: match env::var("TEXT_PARAGRAPH_PAUSE_SECS") {
+                Ok(v) if !v.trim().is_empty() => match v.trim().parse::<f32>() {
+                    Ok(0.0) => None,
+                    Ok(secs) if secs > 0.0 => Some(secs),
+                    _ => return Err(BotError::Config(format!("Invalid TEXT_PARAGRAPH_PAUSE_SECS: {}", v))),
+                },
+                _ => None,
+            },
+        };
+
+        let segmented_min_secs = match env::var("SEGMENTED_MIN_SECS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u32>() {
+                Ok(0) => None,
+                Ok(secs) => Some(secs),
+                Err(_) => return Err(BotError::Config(format!("Invalid SEGMENTED_MIN_SECS: {}", v))),
+            },
+            _ => Some(900),
+        };
+
+        let segment_secs = match env::var("SEGMENT_SECS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u32>() {
+                Ok(secs) if secs >= 10 => secs,
+                _ => return Err(BotError::Config(format!("Invalid SEGMENT_SECS (10 or more): {}", v))),
+            },
+            _ => 300,
+        };
+
+        let live_transcript = env_flag("LIVE_TRANSCRIPT", true)?;
+
+        let ffmpeg_timeout_secs = match env::var("FFMPEG_TIMEOUT_SECS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(secs) => Some(secs),
+                Err(_) => return Err(BotError::Config(format!("Invalid FFMPEG_TIMEOUT_SECS: {}", v))),
+            },
+            _ => Some(300),
+        };
+
+        let debug_audio = match env::var("DEBUG_AUDIO") {
+            Ok(v) => audio::DebugAudio::from_str(&v)
+                .ok_or_else(|| BotError::Config(format!("Invalid DEBUG_AUDIO (on, off or only): {}", v)))?,
+            Err(_) => audio::DebugAudio::Off,
+        };
+
+        let max_message_parts = match env::var("MAX_MESSAGE_PARTS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<usize>() {
+                Ok(0) => None,
+                Ok(n) => Some(n),
+                Err(_) => return Err(BotError::Config(format!("Invalid MAX_MESSAGE_PARTS: {}", v))),
+            },
+            _ => Some(3),
+        };
+
+        let chunk_concurrency = match env::var("CHUNK_CONCURRENCY") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(BotError::Config(format!("Invalid CHUNK_CONCURRENCY: {}", v))),
+            },
+            _ => 3,
+        };
+
+        let transcript_footer = env::var("TRANSCRIPT_FOOTER")
+            .ok()
+            .map(|footer| footer.trim().replace("\\n", "\n"))
+            .filter(|footer| !footer.is_empty());
+        let reply_template = env::var("REPLY_TEMPLATE")
+            .ok()
+            .map(|template| template.trim().replace("\\n", "\n"))
+            .filter(|template| !template.is_empty());
+        if let Some(template) = &reply_template {
+            template::validate_reply(template).map_err(|e| BotError::Config(format!("Invalid REPLY_TEMPLATE: {}", e)))?;
         }
 
-        Ok(BotConfig {
+        let pushgateway_url = env::var("PUSHGATEWAY_URL")
+            .ok()
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        let tts_provider = match env::var("TTS_PROVIDER") {
+            Ok(v) if !v.trim().is_empty() => {
+                let provider = tts::TtsProvider::from_str(&v)
+                    .ok_or_else(|| BotError::Config(format!("Invalid TTS_PROVIDER (elevenlabs or openai): {}", v)))?;
+                let has_key = match provider {
+                    tts::TtsProvider::ElevenLabs => elevenlabs_api_key.is_some(),
+                    tts::TtsProvider::OpenAi => openai_api_key.is_some(),
+                };
+                if !has_key {
+                    return Err(BotError::Config(format!(
+                        "TTS_PROVIDER={} needs {}",
+                        provider.as_str(),
+                        if provider == tts::TtsProvider::OpenAi { "OPENAI_API_KEY" } else { "ELEVENLABS_API_KEY" }
+                    )));
+                }
+                Some(provider)
+            }
+            // Whichever key is configured, ElevenLabs first
+            _ if elevenlabs_api_key.is_some() => Some(tts::TtsProvider::ElevenLabs),
+            _ if openai_api_key.is_some() => Some(tts::TtsProvider::OpenAi),
+            _ => None,
+        };
+        let tts_voice = env::var("TTS_VOICE")
+            .ok()
+            .map(|voice| voice.trim().to_string())
+            .filter(|voice| !voice.is_empty());
+        let pushgateway_job = env::var("PUSHGATEWAY_JOB")
+            .ok()
+            .map(|job| job.trim().to_string())
+            .filter(|job| !job.is_empty())
+            .unwrap_or_else(|| "tg_stt_bot".to_string());
+        let pushgateway_interval_secs = match env::var("PUSHGATEWAY_INTERVAL_SECS") {
+            Ok(v) if !v.trim().is_empty() => match v.trim().parse::<u64>() {
+                Ok(secs) if secs > 0 => secs,
+                _ => return Err(BotError::Config(format!("Invalid PUSHGATEWAY_INTERVAL_SECS: {}", v))),
+            },
+            _ => 60,
+        };
+
+        let config = BotConfig {
             telegram_token,
             stt_provider,
+            stt_fallbacks,
+            consensus_providers,
+            consensus_strategy,
+            circuit_breaker_failures,
+            circuit_breaker_cooldown_secs,
+            stt_timeout_secs,
+            proxy_url,
+            telegram_proxy_url,
+            telegram_api_url,
+            sentry_dsn,
+            sentry_environment,
+            error_webhook_url,
+            api_token,
+            chunk_concurrency,
+            max_message_parts,
             elevenlabs_api_key,
             openai_api_key,
+            openai_base_url,
+            deepgram_base_url,
+            elevenlabs_base_url,
+            google_speech_base_url,
+            assemblyai_base_url,
+            azure_speech_base_url,
+            whisper_model,
+            elevenlabs_model,
+            google_model,
+            google_use_enhanced,
+            llm_model,
+            tts_provider,
+            tts_voice,
             google_credentials_json,
             deepgram_api_key,
+            assemblyai_api_key,
+            assemblyai_speaker_labels,
+            assemblyai_auto_chapters,
+            whisper_model_path,
+            whisper_cpp_bin,
+            vosk_model_dir,
+            vosk_model,
+            mock_stt_latency_ms,
+            mock_stt_failure_rate,
+            mock_stt_echo,
+            azure_speech_key,
+            azure_speech_region,
             bot_password,
+            channel_ids,
+            group_mode,
+            auth_ttl_hours,
             admin_user_ids,
-        })
+            admin_chat_id,
+            alert_interval_minutes,
+            admin_priority,
+            queue_capacity,
+            queue_full_policy,
+            queue_backend,
+            queue_visibility_timeout_secs,
+            queue_worker_only,
+            storage_backend,
+            data_dir,
+            quota_minutes_per_month,
+            history_max_entries,
+            history_retention_days,
+            max_file_size_mb,
+            url_allowed_domains,
+            url_max_file_mb,
+            ytdlp_path,
+            spam_repeat_limit,
+            spam_window_minutes,
+            min_free_disk_mb,
+            request_log_dir,
+            request_log_max_mb,
+            request_log_keep,
+            max_duration_secs,
+            provider_prices,
+            provider_budgets,
+            monthly_budget_usd,
+            music_detection,
+            stt_alternatives,
+            low_confidence_threshold,
+            confidence_warning_threshold,
+            auto_gain_threshold_db,
+            audio_preprocess,
+            silence_threshold_db,
+            channel_split,
+            text_formatting,
+            segmented_min_secs,
+            segment_secs,
+            live_transcript,
+            ffmpeg_timeout_secs,
+            debug_audio,
+            transcript_footer,
+            reply_template,
+            pushgateway_url,
+            pushgateway_job,
+            pushgateway_interval_secs,
+        };
+
+        // The selected provider needs its credentials
+        stt::engine::engine(config.stt_provider).check_config(&config).map_err(BotError::Config)?;
+        if let Some(missing) = config.stt_fallbacks.iter().find(|&&p| !config.has_provider_key(p)) {
+            return Err(BotError::Config(format!(
+                "Fallback provider {} in STT_PROVIDER has no credentials configured",
+                missing.as_str()
+            )));
+        }
+        if let Some(missing) = config.consensus_providers.iter().flatten().find(|&&p| !config.has_provider_key(p)) {
+            return Err(BotError::Config(format!(
+                "Provider {} in CONSENSUS_PROVIDERS has no credentials configured",
+                missing.as_str()
+            )));
+        }
+
+        Ok(config)
+    }
+
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        BotConfig {
+            telegram_token: String::new(),
+            stt_provider: stt::SttProvider::Deepgram,
+            stt_fallbacks: Vec::new(),
+            consensus_providers: None,
+            consensus_strategy: stt::consensus::ConsensusStrategy::default(),
+            circuit_breaker_failures: Some(5),
+            circuit_breaker_cooldown_secs: 60,
+            stt_timeout_secs: Some(stt::DEFAULT_TIMEOUT_SECS),
+            proxy_url: None,
+            telegram_proxy_url: None,
+            telegram_api_url: None,
+            sentry_dsn: None,
+            sentry_environment: None,
+            error_webhook_url: None,
+            api_token: None,
+            chunk_concurrency: 3,
+            max_message_parts: Some(3),
+            elevenlabs_api_key: None,
+            openai_api_key: None,
+            openai_base_url: DEFAULT_OPENAI_BASE_URL.to_string(),
+            deepgram_base_url: DEFAULT_DEEPGRAM_BASE_URL.to_string(),
+            elevenlabs_base_url: DEFAULT_ELEVENLABS_BASE_URL.to_string(),
+            google_speech_base_url: DEFAULT_GOOGLE_SPEECH_BASE_URL.to_string(),
+            assemblyai_base_url: DEFAULT_ASSEMBLYAI_BASE_URL.to_string(),
+            azure_speech_base_url: None,
+            whisper_model: "whisper-1".to_string(),
+            elevenlabs_model: "scribe_v1_experimental",
+            google_model: "default",
+            google_use_enhanced: false,
+            llm_model: "gpt-4o-mini".to_string(),
+            tts_provider: None,
+            tts_voice: None,
+            google_credentials_json: None,
+            deepgram_api_key: None,
+            assemblyai_api_key: None,
+            assemblyai_speaker_labels: false,
+            assemblyai_auto_chapters: false,
+            whisper_model_path: None,
+            whisper_cpp_bin: "whisper-cli".to_string(),
+            vosk_model_dir: "data/vosk".to_string(),
+            vosk_model: DEFAULT_VOSK_MODEL.to_string(),
+            mock_stt_latency_ms: 0,
+            mock_stt_failure_rate: 0.0,
+            mock_stt_echo: false,
+            azure_speech_key: None,
+            azure_speech_region: None,
+            bot_password: None,
+            channel_ids: HashSet::new(),
+            group_mode: settings::GroupMode::All,
+            auth_ttl_hours: None,
+            admin_user_ids: HashSet::new(),
+            admin_chat_id: None,
+            alert_interval_minutes: 30,
+            admin_priority: true,
+            queue_capacity: Some(100),
+            queue_full_policy: queue::QueueFullPolicy::Reject,
+            queue_backend: queue_backend::BackendConfig::Local,
+            queue_visibility_timeout_secs: 300,
+            queue_worker_only: false,
+            storage_backend: storage::StorageConfig::Json(std::path::PathBuf::from("data")),
+            data_dir: std::path::PathBuf::from("data"),
+            quota_minutes_per_month: None,
+            history_max_entries: 20,
+            history_retention_days: Some(30),
+            max_file_size_mb: Some(20),
+            url_allowed_domains: Vec::new(),
+            url_max_file_mb: 100,
+            ytdlp_path: None,
+            spam_repeat_limit: None,
+            spam_window_minutes: 60,
+            min_free_disk_mb: None,
+            request_log_dir: std::path::PathBuf::from("data/logs"),
+            request_log_max_mb: Some(10),
+            request_log_keep: 30,
+            max_duration_secs: None,
+            provider_prices: HashMap::new(),
+            provider_budgets: HashMap::new(),
+            monthly_budget_usd: None,
+            music_detection: false,
+            stt_alternatives: 1,
+            low_confidence_threshold: None,
+            confidence_warning_threshold: None,
+            auto_gain_threshold_db: None,
+            audio_preprocess: audio::Preprocess::default(),
+            silence_threshold_db: Some(audio::analyze::DEFAULT_SILENCE_THRESHOLD_DB),
+            channel_split: false,
+            text_formatting: text::Formatting::default(),
+            segmented_min_secs: None,
+            segment_secs: 300,
+            live_transcript: false,
+            ffmpeg_timeout_secs: Some(300),
+            debug_audio: audio::DebugAudio::Off,
+            transcript_footer: None,
+            reply_template: None,
+            pushgateway_url: None,
+            pushgateway_job: "tg_stt_bot".to_string(),
+            pushgateway_interval_secs: 60,
+        }
+    }
+
+    /// Queued media (`<id>.bin`) and its metadata (`<id>.json`), removed
+    /// once the item has been handled.
+    pub fn queue_dir(&self) -> std::path::PathBuf {
+        self.data_dir.join("queue")
+    }
+
+    pub fn auth_ttl(&self) -> Option<chrono::Duration> {
+        self.auth_ttl_hours.map(|hours| chrono::Duration::hours(hours as i64))
+    }
+
+    pub fn history_retention(&self) -> Option<chrono::Duration> {
+        self.history_retention_days.map(|days| chrono::Duration::days(days as i64))
+    }
+
+    pub fn ffmpeg_timeout(&self) -> Option<std::time::Duration> {
+        self.ffmpeg_timeout_secs.map(std::time::Duration::from_secs)
+    }
+
+    pub fn has_provider_key(&self, provider: stt::SttProvider) -> bool {
+        stt::engine::engine(provider).check_config(self).is_ok()
     }
 }
 
+/// Routes updates to the handlers; the dispatcher supplies `Bot`, `Me` and
+/// the update, `main` the shared state.
+fn schema() -> teloxide::dispatching::UpdateHandler<teloxide::RequestError> {
+    dptree::entry()
+        .branch(
+            Update::filter_message()
+                .filter_command::<handlers::Command>()
+                .branch(
+                    dptree::case![handlers::Command::Transcribe(args)]
+                        .endpoint(handlers::transcribe_handler),
+                )
+                .branch(dptree::case![handlers::Command::Speak].endpoint(handlers::speak_handler))
+                .branch(dptree::case![handlers::Command::Url(link)].endpoint(handlers::url_handler))
+                .branch(
+                    dptree::filter(|cmd: handlers::Command| cmd.is_chat_setting())
+                        .endpoint(handlers::settings_handler),
+                )
+                .branch(dptree::endpoint(handlers::command_handler)),
+        )
+        .branch(
+            Update::filter_message()
+                .chain(dptree::filter(|msg: Message| handlers::has_transcribable_media(&msg)))
+                .endpoint(handlers::audio_handler),
+        )
+        .branch(
+            Update::filter_channel_post()
+                .chain(dptree::filter(|msg: Message| handlers::has_transcribable_media(&msg)))
+                .endpoint(handlers::audio_handler),
+        )
+        .branch(
+            Update::filter_message()
+                .endpoint(handlers::text_handler),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .endpoint(handlers::callback_handler),
+        )
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logger
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
-    // Load environment variables
+    // Load environment variables first so .env can set RUST_LOG and LOG_FORMAT
     dotenv().ok();
 
+    // Initialize logger
+    logging::init();
+
     info!("Starting Telegram STT Bot");
 
     // Load configuration
     let config = BotConfig::from_env()?;
+    http::init(config.stt_timeout_secs.map(std::time::Duration::from_secs), config.proxy_url.as_deref())?;
     info!("Using STT provider (env): {:?}", config.stt_provider);
+    let _sentry = error_report::init(&config);
+
+    // Create bot instance; it keeps its own client for Telegram's long polling
+    // and file downloads
+    let bot = match config.telegram_proxy_url.as_ref().or(config.proxy_url.as_ref()) {
+        Some(proxy_url) => {
+            info!("Connecting to Telegram through proxy {}", http::redact_proxy(proxy_url));
+            Bot::with_client(&config.telegram_token, http::telegram_client(proxy_url)?)
+        }
+        None => Bot::new(&config.telegram_token),
+    };
+    let bot = match &config.telegram_api_url {
+        Some(url) => {
+            info!("Using Bot API server at {}", url);
+            bot.set_api_url(url.clone())
+        }
+        None => bot,
+    };
 
-    // Create bot instance
-    let bot = Bot::new(&config.telegram_token);
+    persistence::init_storage(&config.storage_backend).await?;
 
-    // Load authorized users from persistent storage
-    let initial_users = persistence::load_authorized_users().await?;
-    let authorized_users: AuthorizedUsers = Arc::new(RwLock::new(initial_users));
+    // Load authorized and banned users from persistent storage
+    let roles: UserRoles = Arc::new(RwLock::new(persistence::load_roles().await?));
 
     // Determine active provider: persisted runtime config overrides env
     let initial_provider = match persistence::load_runtime_config().await? {
@@ -133,49 +1282,130 @@ async fn main() -> Result<()> {
     };
     let current_provider: CurrentProvider = Arc::new(RwLock::new(initial_provider));
 
+    // Load per-user quotas, resetting usage if a new month has started
+    let mut quotas = persistence::load_quotas().await?;
+    if quotas.roll_month(&quota::current_month()) {
+        persistence::save_quotas(&quotas).await?;
+    }
+    let quotas: quota::QuotaStore = Arc::new(RwLock::new(quotas));
+
+    // Load this month's estimated provider spend
+    let mut costs = persistence::load_costs().await?;
+    if costs.roll_month(&quota::current_month()) {
+        persistence::save_costs(&costs).await?;
+    }
+    let costs: cost::CostStore = Arc::new(RwLock::new(costs));
+
+    // Load transcript history, dropping entries past their retention
+    let mut history = persistence::load_history().await?;
+    if history.prune(chrono::Utc::now(), config.history_retention()) {
+        persistence::save_history(&history).await?;
+    }
+    let history: history::HistoryStore = Arc::new(RwLock::new(history));
+    let usage = UsageStores { quotas, costs, history, spam: Default::default() };
+
+    let chat_settings = persistence::load_chat_settings().await?;
+    let settings_store: settings::ChatSettingsStore = Arc::new(RwLock::new(chat_settings));
+
     // Create queue system
-    let (queue_sender, queue_receiver) = mpsc::unbounded_channel();
+    let queue_sender: queue::QueueSender = match &config.queue_backend {
+        queue_backend::BackendConfig::Local => Arc::new(fair_queue::FairQueue::with_capacity(config.queue_capacity)),
+        queue_backend::BackendConfig::Redis(url) => Arc::new(
+            queue_backend::redis::RedisQueue::connect(
+                url.clone(),
+                bot.clone(),
+                config.queue_capacity,
+                std::time::Duration::from_secs(config.queue_visibility_timeout_secs),
+                config.queue_dir(),
+            )
+            .await?,
+        ),
+    };
     let queue_stats = Arc::new(RwLock::new(queue::QueueStatistics::default()));
+    let parked_items: queue::ParkedItems = Arc::new(RwLock::new(HashMap::new()));
+    let transcripts: queue::TranscriptCache = Arc::new(RwLock::new(HashMap::new()));
+
+    let shared_config: SharedConfig = Arc::new(RwLock::new(config.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload::on_sighup(shared_config.clone(), current_provider.clone()));
 
     // Start queue processor in background
-    let config_clone = config.clone();
+    let config_clone = shared_config.clone();
     let stats_clone = queue_stats.clone();
     let provider_clone = current_provider.clone();
-    tokio::spawn(async move {
-        queue::start_queue_processor(queue_receiver, config_clone, stats_clone, provider_clone).await;
+    let usage_clone = usage.clone();
+    let parked_clone = parked_items.clone();
+    let transcripts_clone = transcripts.clone();
+    let settings_clone = settings_store.clone();
+    let queue_clone = queue_sender.clone();
+    let worker = tokio::spawn(async move {
+        queue::start_queue_processor(
+            queue_clone,
+            config_clone,
+            stats_clone,
+            provider_clone,
+            usage_clone,
+            parked_clone,
+            transcripts_clone,
+            settings_clone,
+        ).await;
     });
 
-    // Set up dispatcher
-    let handler = dptree::entry()
-        .branch(
-            Update::filter_message()
-                .filter_command::<handlers::Command>()
-                .endpoint(handlers::command_handler),
-        )
-        .branch(
-            Update::filter_message()
-                .chain(dptree::filter(|msg: Message| {
-                    msg.voice().is_some() || msg.audio().is_some() || msg.video().is_some() || msg.video_note().is_some()
-                }))
-                .endpoint(handlers::audio_handler),
-        )
-        .branch(
-            Update::filter_message()
-                .endpoint(handlers::text_handler),
-        );
+    // Pick up work that was still queued when the bot last stopped; a
+    // shared queue keeps its own
+    if queue_sender.is_local() {
+        queue::resume_pending(&bot, &queue_sender, &queue_stats, &config.queue_dir()).await;
+    }
+    queue::resume_held(&bot, &shared_config, &transcripts).await;
+
+    tokio::spawn(queue::start_progress_updater(queue_sender.clone(), queue_stats.clone()));
+    tokio::spawn(janitor::start(config.queue_dir(), queue_sender.is_local()));
+
 
     info!("Bot started. Listening for messages...");
 
-    // Start health check server
+    // Start health check server; /health is the old name of /live
     let health_route = warp::path("health")
+        .or(warp::path("live"))
+        .unify()
         .and(warp::get())
         .map(|| warp::reply::with_status("OK", warp::http::StatusCode::OK));
 
+    let probe = readiness::Probe {
+        bot: bot.clone(),
+        config: shared_config.clone(),
+        current_provider: current_provider.clone(),
+        worker: Arc::new(worker),
+    };
+    let ready_route = warp::path("ready")
+        .and(warp::get())
+        .then(move || {
+            let probe = probe.clone();
+            async move {
+                let (body, ready) = readiness::report(&readiness::run(&probe).await);
+                let status = if ready { warp::http::StatusCode::OK } else { warp::http::StatusCode::SERVICE_UNAVAILABLE };
+                warp::reply::with_status(warp::reply::json(&body), status)
+            }
+        });
+
+    let metrics_stats = queue_stats.clone();
+    let metrics_costs = usage.costs.clone();
     let metrics_route = warp::path("metrics")
         .and(warp::get())
-        .map(|| "# Telegram STT Bot Metrics\n# (Add your metrics here)\n");
+        .then(move || {
+            let stats = metrics_stats.clone();
+            let costs = metrics_costs.clone();
+            async move { metrics::render(&*stats.read().await, &*costs.read().await) }
+        });
+
+    let api_state = api::ApiState {
+        config: shared_config.clone(),
+        current_provider: current_provider.clone(),
+        costs: usage.costs.clone(),
+    };
+    let api_route = api::routes(api_state, config.max_file_size_mb);
 
-    let routes = health_route.or(metrics_route);
+    let routes = health_route.or(ready_route).or(metrics_route).or(api_route);
 
     // Start health check server in background
     tokio::spawn(async move {
@@ -186,8 +1416,19 @@ async fn main() -> Result<()> {
 
     info!("Health check server started on port 8091");
 
-    Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![config, authorized_users, queue_sender, queue_stats, current_provider])
+    if config.pushgateway_url.is_some() {
+        tokio::spawn(metrics::start_pusher(config.clone(), queue_stats.clone(), usage.costs.clone()));
+    }
+
+    if config.queue_worker_only {
+        info!("Worker-only instance: processing the shared queue, not polling Telegram");
+        tokio::signal::ctrl_c().await?;
+        info!("Bot stopped");
+        return Ok(());
+    }
+
+    Dispatcher::builder(bot, schema())
+        .dependencies(dptree::deps![shared_config, roles, queue_sender, queue_stats, current_provider, usage, parked_items, settings_store, transcripts])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
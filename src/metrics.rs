@@ -0,0 +1,130 @@
+use crate::{BotConfig, cost, queue, stt, stt::SttProvider};
+use tracing::{debug, info, warn};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Metric name, help text and how to read it from a provider's usage.
+type ProviderMetric = (&'static str, &'static str, fn(&cost::ProviderUsage) -> f64);
+
+/// Metric name, type, help text and how to read it from a provider's health.
+type HealthMetric = (&'static str, &'static str, &'static str, fn(&stt::health::ProviderHealth) -> f64);
+
+/// Renders current counters in the Prometheus text exposition format.
+pub fn render(stats: &queue::QueueStatistics, costs: &cost::CostData) -> String {
+    let mut out = String::new();
+
+    let counters = [
+        ("stt_bot_items_queued_total", "Items added to the queue", stats.total_queued),
+        ("stt_bot_items_processed_total", "Items transcribed successfully", stats.total_processed),
+        ("stt_bot_items_failed_total", "Items that failed to process", stats.total_failed),
+        ("stt_bot_items_skipped_total", "Items skipped before transcription (silence, music)", stats.total_skipped),
+        ("stt_bot_items_cancelled_total", "Items cancelled by their sender or an admin", stats.total_cancelled),
+        ("stt_bot_items_rejected_total", "Items turned away or dropped because the queue was full", stats.total_rejected),
+    ];
+    for (name, help, value) in counters {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP stt_bot_queue_size Items waiting or in progress\n# TYPE stt_bot_queue_size gauge\nstt_bot_queue_size {}",
+        stats.current_queue_size
+    );
+
+    let per_provider: [ProviderMetric; 3] = [
+        ("stt_bot_provider_requests", "Transcription requests this month", |u| u.requests as f64),
+        ("stt_bot_provider_audio_seconds", "Audio seconds transcribed this month", |u| u.seconds as f64),
+        ("stt_bot_provider_spend_usd", "Estimated spend this month in USD", |u| u.spend_usd),
+    ];
+    for (name, help, value) in per_provider {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+        for provider in SttProvider::ALL {
+            let usage = costs.providers.get(provider.as_str()).cloned().unwrap_or_default();
+            let _ = writeln!(out, "{}{{provider=\"{}\"}} {}", name, provider.as_str(), value(&usage));
+        }
+    }
+
+    let health_metrics: [HealthMetric; 3] = [
+        ("stt_bot_provider_up", "gauge", "1 unless the provider's circuit breaker is open", |h| {
+            if h.is_open(std::time::Instant::now()) { 0.0 } else { 1.0 }
+        }),
+        ("stt_bot_provider_consecutive_failures", "gauge", "Failed requests in a row", |h| h.consecutive_failures as f64),
+        ("stt_bot_provider_circuit_trips_total", "counter", "Times the circuit breaker opened since startup", |h| h.trips as f64),
+    ];
+    for (name, kind, help, value) in health_metrics {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        for provider in SttProvider::ALL {
+            let _ = writeln!(out, "{}{{provider=\"{}\"}} {}", name, provider.as_str(), value(&stt::health::get(provider)));
+        }
+    }
+
+    out
+}
+
+/// Periodically pushes metrics to a Prometheus Pushgateway, for deployments
+/// where `/metrics` can't be scraped.
+pub async fn start_pusher(config: BotConfig, stats: queue::QueueStats, costs: cost::CostStore) {
+    let Some(base_url) = config.pushgateway_url.clone() else {
+        return;
+    };
+
+    let url = format!(
+        "{}/metrics/job/{}",
+        base_url.trim_end_matches('/'),
+        config.pushgateway_job
+    );
+    info!("Pushing metrics to {} every {}s", url, config.pushgateway_interval_secs);
+
+    let client = crate::http::client();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.pushgateway_interval_secs));
+    loop {
+        interval.tick().await;
+
+        let body = {
+            let stats = stats.read().await;
+            let costs = costs.read().await;
+            render(&stats, &costs)
+        };
+
+        // PUT replaces every metric of the job, so stale series don't linger
+        match client
+            .put(&url)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(body)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => debug!("Pushed metrics to Pushgateway"),
+            Ok(response) => warn!("Pushgateway rejected metrics: HTTP {}", response.status()),
+            Err(e) => warn!("Failed to push metrics: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let stats = queue::QueueStatistics {
+            total_queued: 5,
+            total_processed: 3,
+            current_queue_size: 1,
+            ..Default::default()
+        };
+        let mut costs = cost::CostData::default();
+        costs.providers.insert(
+            "deepgram".to_string(),
+            cost::ProviderUsage { requests: 3, seconds: 90, spend_usd: 0.25 },
+        );
+
+        let text = render(&stats, &costs);
+        assert!(text.contains("# TYPE stt_bot_items_queued_total counter\nstt_bot_items_queued_total 5\n"));
+        assert!(text.contains("stt_bot_queue_size 1\n"));
+        assert!(text.contains("stt_bot_provider_spend_usd{provider=\"deepgram\"} 0.25\n"));
+        assert!(text.contains("stt_bot_provider_requests{provider=\"whisper\"} 0\n"));
+        assert!(text.contains("# TYPE stt_bot_provider_up gauge\n"));
+        assert!(text.contains("stt_bot_provider_circuit_trips_total{provider=\"whisper\"} "));
+    }
+}
@@ -1,94 +1,141 @@
-use std::collections::HashSet;
-use std::path::Path;
-use log::{info, warn, error};
-use serde::{Deserialize, Serialize};
-use teloxide::types::UserId;
-use crate::{BotError, Result, stt::SttProvider};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{info, warn, error};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use crate::storage::{JsonFiles, RedisStorage, SqliteStorage, Storage, StorageConfig};
+use teloxide::types::{ChatId, MessageId, UserId};
+use crate::{BotError, Result, cost::CostData, history::HistoryData, queue::ProcessingOptions, quota::QuotaData, roles::Roles, settings::ChatSettings, stt::SttProvider};
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct AuthorizedUsersData {
     pub users: HashSet<u64>,
+    /// When each user was authorized, for `AUTH_TTL_HOURS`. Missing for
+    /// files written before authorizations could expire.
+    #[serde(default)]
+    pub authorized_at: HashMap<u64, DateTime<Utc>>,
 }
 
-const USERS_FILE: &str = "data/authorized_users.json";
-const RUNTIME_CONFIG_FILE: &str = "data/runtime_config.json";
+/// Documents in the configured `Storage`; with JSON files, each is
+/// `<DATA_DIR>/<name>.json`.
+const USERS_DOCUMENT: &str = "authorized_users";
+const ROLES_DOCUMENT: &str = "roles";
+const RUNTIME_CONFIG_DOCUMENT: &str = "runtime_config";
+const QUOTAS_DOCUMENT: &str = "quotas";
+const COSTS_DOCUMENT: &str = "costs";
+const HISTORY_DOCUMENT: &str = "history";
+const CHAT_SETTINGS_DOCUMENT: &str = "chat_settings";
 
 impl AuthorizedUsersData {
-    pub fn from_user_ids(user_ids: &HashSet<UserId>) -> Self {
+    pub fn from_users(users: &HashMap<UserId, DateTime<Utc>>) -> Self {
         Self {
-            users: user_ids.iter().map(|id| id.0).collect(),
+            users: users.keys().map(|id| id.0).collect(),
+            authorized_at: users.iter().map(|(id, &at)| (id.0, at)).collect(),
         }
     }
 
-    pub fn to_user_ids(&self) -> HashSet<UserId> {
-        self.users.iter().map(|&id| UserId(id)).collect()
+    /// Users without a recorded time count as authorized at `now`.
+    pub fn to_users(&self, now: DateTime<Utc>) -> HashMap<UserId, DateTime<Utc>> {
+        self.users
+            .iter()
+            .map(|&id| (UserId(id), self.authorized_at.get(&id).copied().unwrap_or(now)))
+            .collect()
     }
 }
 
-pub async fn load_authorized_users() -> Result<HashSet<UserId>> {
-    // Create data directory if it doesn't exist
-    if let Some(parent) = Path::new(USERS_FILE).parent() {
-        if !parent.exists() {
-            tokio::fs::create_dir_all(parent).await.map_err(BotError::Io)?;
-            info!("Created data directory: {}", parent.display());
-        }
-    }
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
 
-    if !Path::new(USERS_FILE).exists() {
-        info!("No authorized users file found, starting with empty list");
-        return Ok(HashSet::new());
+/// Sets where the state documents are kept, per `STORAGE_BACKEND`. Until
+/// then, and without a call, they are JSON files under `data/`.
+pub async fn init_storage(config: &StorageConfig) -> Result<()> {
+    let storage: Box<dyn Storage> = match config {
+        StorageConfig::Json(dir) => Box::new(JsonFiles::new(dir)),
+        StorageConfig::Redis(url) => Box::new(RedisStorage::connect(url.clone()).await?),
+        StorageConfig::Sqlite(path) => Box::new(SqliteStorage::open(path.clone()).await?),
+    };
+    if STORAGE.set(storage).is_err() {
+        warn!("Storage was already set up, keeping it");
     }
+    Ok(())
+}
 
-    match tokio::fs::read_to_string(USERS_FILE).await {
-        Ok(contents) => {
-            match serde_json::from_str::<AuthorizedUsersData>(&contents) {
-                Ok(data) => {
-                    let user_ids = data.to_user_ids();
-                    info!("Loaded {} authorized users from {}", user_ids.len(), USERS_FILE);
-                    Ok(user_ids)
-                }
-                Err(e) => {
-                    warn!("Failed to parse authorized users file: {}, starting with empty list", e);
-                    Ok(HashSet::new())
-                }
-            }
-        }
+fn storage() -> &'static dyn Storage {
+    STORAGE.get_or_init(|| Box::new(JsonFiles::new("data"))).as_ref()
+}
+
+/// Loads and parses the document `name`. None if it was never saved or
+/// doesn't parse, which is logged; the caller starts from a default. A
+/// store that can't be read fails instead, so the state isn't replaced by
+/// defaults on the next save.
+async fn load_document<T: DeserializeOwned>(name: &str) -> Result<Option<T>> {
+    let Some(contents) = storage().load(name).await? else {
+        return Ok(None);
+    };
+    match serde_json::from_str(&contents) {
+        Ok(data) => Ok(Some(data)),
         Err(e) => {
-            warn!("Failed to read authorized users file: {}, starting with empty list", e);
-            Ok(HashSet::new())
+            warn!("Failed to parse {}: {}, using defaults", storage().location(name), e);
+            Ok(None)
         }
     }
 }
 
-pub async fn save_authorized_users(user_ids: &HashSet<UserId>) -> Result<()> {
-    // Create data directory if it doesn't exist
-    if let Some(parent) = Path::new(USERS_FILE).parent() {
-        if !parent.exists() {
-            tokio::fs::create_dir_all(parent).await.map_err(BotError::Io)?;
-            info!("Created data directory: {}", parent.display());
+async fn save_document<T: Serialize + ?Sized>(name: &str, data: &T) -> Result<()> {
+    let json_content = serde_json::to_string_pretty(data)
+        .map_err(|e| BotError::Config(format!("JSON serialization error: {}", e)))?;
+    storage().save(name, json_content).await.inspect_err(|e| {
+        error!("Failed to write {}: {}", storage().location(name), e);
+    })
+}
+
+pub async fn load_authorized_users() -> Result<HashMap<UserId, DateTime<Utc>>> {
+    match load_document::<AuthorizedUsersData>(USERS_DOCUMENT).await? {
+        Some(data) => {
+            let user_ids = data.to_users(Utc::now());
+            info!("Loaded {} authorized users from {}", user_ids.len(), storage().location(USERS_DOCUMENT));
+            Ok(user_ids)
+        }
+        None => {
+            info!("No authorized users saved, starting with empty list");
+            Ok(HashMap::new())
         }
     }
+}
 
-    let data = AuthorizedUsersData::from_user_ids(user_ids);
+pub async fn save_authorized_users(user_ids: &HashMap<UserId, DateTime<Utc>>) -> Result<()> {
+    save_document(USERS_DOCUMENT, &AuthorizedUsersData::from_users(user_ids)).await?;
+    info!("Saved {} authorized users to {}", user_ids.len(), storage().location(USERS_DOCUMENT));
+    Ok(())
+}
 
-    match serde_json::to_string_pretty(&data) {
-        Ok(json_content) => {
-            match tokio::fs::write(USERS_FILE, json_content).await {
-                Ok(_) => {
-                    info!("Saved {} authorized users to {}", user_ids.len(), USERS_FILE);
-                    Ok(())
-                }
-                Err(e) => {
-                    error!("Failed to write authorized users file: {}", e);
-                    Err(BotError::Io(e))
-                }
-            }
-        }
-        Err(e) => {
-            error!("Failed to serialize authorized users: {}", e);
-            Err(BotError::Config(format!("JSON serialization error: {}", e)))
-        }
+/// Roles other than authorization, which keeps its own document.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct RolesData {
+    #[serde(default)]
+    banned: HashSet<u64>,
+}
+
+pub async fn load_roles() -> Result<Roles> {
+    let authorized = load_authorized_users().await?;
+    let data = load_document::<RolesData>(ROLES_DOCUMENT).await?.unwrap_or_default();
+    if !data.banned.is_empty() {
+        info!("Loaded {} banned users from {}", data.banned.len(), storage().location(ROLES_DOCUMENT));
     }
+
+    Ok(Roles {
+        authorized,
+        banned: data.banned.into_iter().map(UserId).collect(),
+    })
+}
+
+pub async fn save_roles(roles: &Roles) -> Result<()> {
+    save_authorized_users(&roles.authorized).await?;
+
+    let data = RolesData {
+        banned: roles.banned.iter().map(|id| id.0).collect(),
+    };
+    save_document(ROLES_DOCUMENT, &data).await
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -97,81 +144,312 @@ struct RuntimeConfigData {
 }
 
 pub async fn load_runtime_config() -> Result<Option<SttProvider>> {
-    if !Path::new(RUNTIME_CONFIG_FILE).exists() {
+    let Some(data) = load_document::<RuntimeConfigData>(RUNTIME_CONFIG_DOCUMENT).await? else {
         return Ok(None);
-    }
-
-    match tokio::fs::read_to_string(RUNTIME_CONFIG_FILE).await {
-        Ok(contents) => {
-            match serde_json::from_str::<RuntimeConfigData>(&contents) {
-                Ok(data) => {
-                    match SttProvider::from_str(&data.stt_provider) {
-                        Some(provider) => {
-                            info!("Loaded runtime config: provider={}", data.stt_provider);
-                            Ok(Some(provider))
-                        }
-                        None => {
-                            warn!("Unknown provider '{}' in runtime config, ignoring", data.stt_provider);
-                            Ok(None)
-                        }
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to parse runtime config: {}, ignoring", e);
-                    Ok(None)
-                }
-            }
+    };
+    match SttProvider::from_str(&data.stt_provider) {
+        Some(provider) => {
+            info!("Loaded runtime config: provider={}", data.stt_provider);
+            Ok(Some(provider))
         }
-        Err(e) => {
-            warn!("Failed to read runtime config: {}, ignoring", e);
+        None => {
+            warn!("Unknown provider '{}' in runtime config, ignoring", data.stt_provider);
             Ok(None)
         }
     }
 }
 
 pub async fn save_runtime_config(provider: SttProvider) -> Result<()> {
-    if let Some(parent) = Path::new(RUNTIME_CONFIG_FILE).parent() {
-        if !parent.exists() {
-            tokio::fs::create_dir_all(parent).await.map_err(BotError::Io)?;
-        }
-    }
-
     let data = RuntimeConfigData {
         stt_provider: provider.as_str().to_string(),
     };
+    save_document(RUNTIME_CONFIG_DOCUMENT, &data).await?;
+    info!("Saved runtime config: provider={}", provider.as_str());
+    Ok(())
+}
+
+pub async fn load_quotas() -> Result<QuotaData> {
+    let Some(data) = load_document::<QuotaData>(QUOTAS_DOCUMENT).await? else {
+        return Ok(QuotaData::default());
+    };
+    info!("Loaded quotas for {} users from {}", data.users.len(), storage().location(QUOTAS_DOCUMENT));
+    Ok(data)
+}
+
+pub async fn save_quotas(data: &QuotaData) -> Result<()> {
+    save_document(QUOTAS_DOCUMENT, data).await
+}
+
+pub async fn load_history() -> Result<HistoryData> {
+    let Some(data) = load_document::<HistoryData>(HISTORY_DOCUMENT).await? else {
+        return Ok(HistoryData::default());
+    };
+    info!("Loaded transcript history for {} users from {}", data.users.len(), storage().location(HISTORY_DOCUMENT));
+    Ok(data)
+}
+
+pub async fn save_history(data: &HistoryData) -> Result<()> {
+    save_document(HISTORY_DOCUMENT, data).await
+}
+
+pub async fn load_costs() -> Result<CostData> {
+    let Some(data) = load_document::<CostData>(COSTS_DOCUMENT).await? else {
+        return Ok(CostData::default());
+    };
+    info!("Loaded cost tracking for {} from {}", data.month, storage().location(COSTS_DOCUMENT));
+    Ok(data)
+}
+
+pub async fn save_costs(data: &CostData) -> Result<()> {
+    save_document(COSTS_DOCUMENT, data).await
+}
+
+pub async fn load_chat_settings() -> Result<HashMap<ChatId, ChatSettings>> {
+    let Some(data) = load_document::<HashMap<i64, ChatSettings>>(CHAT_SETTINGS_DOCUMENT).await? else {
+        return Ok(HashMap::new());
+    };
+    info!("Loaded settings for {} chats from {}", data.len(), storage().location(CHAT_SETTINGS_DOCUMENT));
+    Ok(data.into_iter().map(|(id, settings)| (ChatId(id), settings)).collect())
+}
+
+pub async fn save_chat_settings(settings: &HashMap<ChatId, ChatSettings>) -> Result<()> {
+    let data: HashMap<i64, &ChatSettings> = settings.iter().map(|(id, s)| (id.0, s)).collect();
+    save_document(CHAT_SETTINGS_DOCUMENT, &data).await
+}
+
+/// A queue item as stored on disk while it waits to be processed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingItemData {
+    pub id: String,
+    pub chat_id: ChatId,
+    #[serde(default)]
+    pub message_id: Option<MessageId>,
+    pub reply_to_message_id: MessageId,
+    pub original_filename: String,
+    pub user_info: String,
+    pub user_id: UserId,
+    pub username: Option<String>,
+    pub duration_secs: u32,
+    pub options: ProcessingOptions,
+    /// `options.language`, which isn't serialized with the rest.
+    pub language: Option<String>,
+    pub queued_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A finished transcript held back for a chat's quiet hours. It's saved
+/// before its item is acknowledged, so a restart doesn't lose it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HeldDeliveryData {
+    pub item: PendingItemData,
+    pub transcription: crate::stt::Transcription,
+    pub provider: SttProvider,
+    /// The rendered reply, and the provider line that heads it.
+    pub response: String,
+    pub via: String,
+    pub media_secs: u64,
+    pub deliver_at: DateTime<Utc>,
+}
+
+/// Where held transcripts are kept, under the queue directory `dir`.
+fn held_dir(dir: &Path) -> PathBuf {
+    dir.join("held")
+}
+
+fn held_delivery_path(dir: &Path, id: &str) -> PathBuf {
+    held_dir(dir).join(format!("{}.json", id))
+}
 
-    match serde_json::to_string_pretty(&data) {
-        Ok(json_content) => {
-            tokio::fs::write(RUNTIME_CONFIG_FILE, json_content)
-                .await
-                .map_err(|e| {
-                    error!("Failed to write runtime config: {}", e);
-                    BotError::Io(e)
-                })?;
-            info!("Saved runtime config: provider={}", provider.as_str());
-            Ok(())
+pub async fn save_held_delivery(dir: &Path, held: &HeldDeliveryData) -> Result<()> {
+    tokio::fs::create_dir_all(held_dir(dir)).await.map_err(BotError::Io)?;
+    let json_content = serde_json::to_string_pretty(held)
+        .map_err(|e| BotError::Config(format!("JSON serialization error: {}", e)))?;
+    tokio::fs::write(held_delivery_path(dir, &held.item.id), json_content).await.map_err(BotError::Io)
+}
+
+pub async fn remove_held_delivery(dir: &Path, id: &str) {
+    let path = held_delivery_path(dir, id);
+    if let Err(e) = tokio::fs::remove_file(&path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
+
+/// Transcripts still held when the bot last stopped, with the size of
+/// their media.
+pub async fn load_held_deliveries(dir: &Path) -> Result<Vec<(HeldDeliveryData, u64)>> {
+    if !held_dir(dir).exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut held = Vec::new();
+    let mut entries = tokio::fs::read_dir(held_dir(dir)).await.map_err(BotError::Io)?;
+    while let Some(entry) = entries.next_entry().await.map_err(BotError::Io)? {
+        let path = entry.path();
+        let parsed = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_str::<HeldDeliveryData>(&contents).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(data) => {
+                let size = tokio::fs::metadata(pending_media_path(dir, &data.item.id)).await.map_or(0, |m| m.len());
+                held.push((data, size));
+            }
+            Err(e) => {
+                warn!("Failed to load held transcript {}: {}, dropping it", path.display(), e);
+                tokio::fs::remove_file(&path).await.ok();
+            }
         }
-        Err(e) => {
-            error!("Failed to serialize runtime config: {}", e);
-            Err(BotError::Config(format!("JSON serialization error: {}", e)))
+    }
+    Ok(held)
+}
+
+/// Where a queued item's media lives in the queue directory `dir` until
+/// the item is done with it.
+pub fn pending_media_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.bin", id))
+}
+
+/// Creates the file an item's media is downloaded into, next to where its
+/// metadata will be saved.
+pub async fn create_pending_media(dir: &Path, id: &str) -> Result<(PathBuf, tokio::fs::File)> {
+    tokio::fs::create_dir_all(dir).await.map_err(BotError::Io)?;
+    let path = pending_media_path(dir, id);
+    let file = tokio::fs::File::create(&path).await.map_err(BotError::Io)?;
+    Ok((path, file))
+}
+
+/// Stores a queued item's metadata next to its media, which is already on
+/// disk at `media`.
+pub async fn save_pending_item(media: &Path, item: &PendingItemData) -> Result<()> {
+    let json_content = serde_json::to_string_pretty(item)
+        .map_err(|e| BotError::Config(format!("JSON serialization error: {}", e)))?;
+
+    tokio::fs::write(media.with_extension("json"), json_content).await.map_err(BotError::Io)
+}
+
+/// Drops the item with its media at `media` from the on-disk queue. The
+/// media is left alone; it goes away once nothing in memory holds it any
+/// more.
+pub async fn remove_pending_item(media: &Path) {
+    let path = media.with_extension("json");
+    if let Err(e) = tokio::fs::remove_file(&path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
+
+/// Items that were queued but not finished before the last shutdown, oldest
+/// first, with the size of their media. Media no item refers to, e.g. kept
+/// for a finished transcript's buttons, is deleted.
+pub async fn load_pending_items(dir: &Path) -> Result<Vec<(PendingItemData, u64)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut items = Vec::new();
+    let mut media = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await.map_err(BotError::Io)?;
+    while let Some(entry) = entries.next_entry().await.map_err(BotError::Io)? {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "bin") {
+            media.push(path);
+            continue;
+        }
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+
+        let item = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => match serde_json::from_str::<PendingItemData>(&contents) {
+                Ok(item) => item,
+                Err(e) => {
+                    warn!("Failed to parse pending item {}: {}, dropping it", path.display(), e);
+                    tokio::fs::remove_file(&path).await.ok();
+                    continue;
+                }
+            },
+            Err(e) => {
+                warn!("Failed to read pending item {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        match tokio::fs::metadata(pending_media_path(dir, &item.id)).await {
+            Ok(metadata) => items.push((item, metadata.len())),
+            Err(e) => {
+                warn!("Media for pending item {} is missing: {}, dropping it", item.id, e);
+                remove_pending_item(&pending_media_path(dir, &item.id)).await;
+            }
+        }
+    }
+
+    // Media of a held transcript stays for its buttons
+    for path in media {
+        let held = path.file_stem().is_some_and(|id| held_delivery_path(dir, &id.to_string_lossy()).exists());
+        if !held && !items.iter().any(|(item, _)| pending_media_path(dir, &item.id) == path) {
+            tokio::fs::remove_file(&path).await.ok();
         }
     }
+
+    items.sort_by_key(|(item, _)| item.queued_at);
+    info!("Loaded {} pending queue items from {}", items.len(), dir.display());
+    Ok(items)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
 
     #[test]
     fn test_authorized_users_data_conversion() {
-        let mut user_ids = HashSet::new();
-        user_ids.insert(UserId(123456789));
-        user_ids.insert(UserId(987654321));
+        let now = Utc::now();
+        let mut user_ids = HashMap::new();
+        user_ids.insert(UserId(123456789), now - chrono::Duration::hours(5));
+        user_ids.insert(UserId(987654321), now);
 
-        let data = AuthorizedUsersData::from_user_ids(&user_ids);
-        let converted_back = data.to_user_ids();
+        let data = AuthorizedUsersData::from_users(&user_ids);
+        let converted_back = data.to_users(now);
 
         assert_eq!(user_ids, converted_back);
     }
+
+    #[test]
+    fn test_authorized_users_without_times() {
+        let data: AuthorizedUsersData = serde_json::from_str(r#"{"users": [42]}"#).unwrap();
+        let now = Utc::now();
+
+        assert_eq!(data.to_users(now), HashMap::from([(UserId(42), now)]));
+    }
+
+    #[tokio::test]
+    async fn test_held_delivery_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let item = crate::queue::QueueItem::for_tests("held").pending_data();
+        let media = pending_media_path(dir.path(), &item.id);
+        tokio::fs::write(&media, b"audio").await.unwrap();
+        let held = HeldDeliveryData {
+            item,
+            transcription: crate::stt::Transcription::from_text("Good night."),
+            provider: SttProvider::Deepgram,
+            response: "🎙 Good night\\.".to_string(),
+            via: "via Deepgram".to_string(),
+            media_secs: 30,
+            deliver_at: Utc::now(),
+        };
+        save_held_delivery(dir.path(), &held).await.unwrap();
+
+        // Its media isn't taken for an orphan, and it loads back as saved
+        assert!(load_pending_items(dir.path()).await.unwrap().is_empty());
+        assert!(media.exists());
+        let loaded = load_held_deliveries(dir.path()).await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        let (data, size) = &loaded[0];
+        assert_eq!((data.item.id.as_str(), data.transcription.text.as_str(), data.provider), ("held", "Good night.", SttProvider::Deepgram));
+        assert_eq!((data.response.as_str(), data.media_secs, *size), (held.response.as_str(), 30, 5));
+
+        remove_held_delivery(dir.path(), "held").await;
+        assert!(load_held_deliveries(dir.path()).await.unwrap().is_empty());
+    }
 }
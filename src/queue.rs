@@ -1,329 +1,3360 @@
-use crate::{BotConfig, CurrentProvider, Result, BotError, request_logger, stt::SttProvider};
-use log::{info, error, warn};
-use std::sync::Arc;
-use teloxide::{prelude::*, types::MessageId};
-use tokio::sync::{mpsc, RwLock};
-use uuid::Uuid;
+use crate::{BotConfig, CurrentProvider, Result, BotError, UsageStores, history, i18n, persistence, quota, request_logger, stt::SttProvider};
+use crate::i18n::Locale;
+use tracing::{Instrument, info, error, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use teloxide::{net::Download, prelude::*, types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, MessageId}};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Notify, RwLock};
+
+/// Paths of every `MediaFile` alive in this process, so the janitor leaves
+/// them alone.
+static LIVE_MEDIA: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+/// Downloaded media on disk. The file is deleted when the last item
+/// holding it is dropped, which also covers parked and cached copies.
+#[derive(Debug)]
+pub struct MediaFile {
+    path: PathBuf,
+    size: u64,
+    /// Set once another instance owns the file, via a shared queue.
+    kept: AtomicBool,
+}
+
+impl MediaFile {
+    pub fn new(path: PathBuf, size: u64) -> Self {
+        LIVE_MEDIA.lock().expect("live media lock poisoned").get_or_insert_with(HashSet::new).insert(path.clone());
+        Self { path, size, kept: AtomicBool::new(false) }
+    }
+
+    /// Whether a `MediaFile` in this process still refers to `path`.
+    pub fn is_live(path: &Path) -> bool {
+        LIVE_MEDIA.lock().expect("live media lock poisoned").as_ref().is_some_and(|live| live.contains(path))
+    }
+
+    /// Leaves the file on disk when dropped.
+    pub fn keep_on_disk(&self) {
+        self.kept.store(true, Ordering::Relaxed);
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.size = size;
+    }
+}
+
+impl Drop for MediaFile {
+    fn drop(&mut self) {
+        if let Some(live) = LIVE_MEDIA.lock().expect("live media lock poisoned").as_mut() {
+            live.remove(&self.path);
+        }
+        if self.kept.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(e) = std::fs::remove_file(&self.path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Streams a Telegram file to disk for the item with this id. A partial
+/// file is removed if the download fails.
+pub async fn download_media(bot: &Bot, telegram_path: &str, id: &str, queue_dir: &Path) -> Result<MediaFile> {
+    let (path, mut file) = persistence::create_pending_media(queue_dir, id).await?;
+    let mut media = MediaFile::new(path, 0);
+
+    fetch_telegram_file(bot, telegram_path, &mut file).await?;
+    file.flush().await.map_err(BotError::Io)?;
+    media.size = file.metadata().await.map_err(BotError::Io)?.len();
+
+    Ok(media)
+}
+
+/// Copies a Telegram file into `destination`. A local Bot API server
+/// running with `--local` hands out absolute paths on its own disk instead
+/// of download paths; those are read directly, so its working directory has
+/// to be mounted at the same path here.
+pub async fn fetch_telegram_file(
+    bot: &Bot,
+    telegram_path: &str,
+    destination: &mut (dyn tokio::io::AsyncWrite + Unpin + Send),
+) -> Result<()> {
+    if Path::new(telegram_path).is_absolute() {
+        let mut source = tokio::fs::File::open(telegram_path).await.map_err(|e| {
+            warn!("Can't read {} from the local Bot API server; is its working directory mounted here?", telegram_path);
+            BotError::Io(e)
+        })?;
+        tokio::io::copy(&mut source, destination).await.map_err(BotError::Io)?;
+        return Ok(());
+    }
+    bot.download_file(telegram_path, destination).await?;
+    Ok(())
+}
 
 #[derive(Clone)]
 pub struct QueueItem {
     pub id: String,
     pub bot: Bot,
     pub chat_id: ChatId,
-    pub message_id: MessageId,
+    /// The queue and progress message; None in /quiet groups.
+    pub message_id: Option<MessageId>,
     pub reply_to_message_id: MessageId,
-    pub file_data: Vec<u8>,
+    pub media: Arc<MediaFile>,
     pub original_filename: String,
     pub user_info: String,
     pub user_id: teloxide::types::UserId,
     pub username: Option<String>,
+    /// Duration reported by Telegram, used for quota accounting.
+    pub duration_secs: u32,
+    pub options: ProcessingOptions,
+    /// Set for files from a zip archive, which are answered together. Not
+    /// kept across a restart; those files are then answered one by one.
+    pub batch: Option<crate::batch::Slot>,
+}
+
+/// Per-request options that change how a queued item is processed.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ProcessingOptions {
+    /// Only transcribe this slice of the source media.
+    pub time_range: Option<crate::audio::TimeRange>,
+    /// Set when the user overrode the music detector for this item.
+    pub skip_music_check: bool,
+    /// Telephone preset: 8 kHz audio and phone-call models.
+    pub phone_call: bool,
+    /// Append a processing metadata line to the reply.
+    pub show_metadata: bool,
+    /// Prefix the transcript with language and duration.
+    pub language_line: bool,
+    /// Attach the full result as a `.json` file.
+    pub json_attachment: bool,
+    /// Attach subtitle files; only set for videos and video notes.
+    pub subtitles: bool,
+    /// Post results without replying to the sender's message.
+    pub anonymous: bool,
+    /// Mask profanity, through the provider where it supports it and the
+    /// local wordlist everywhere.
+    pub profanity_filter: bool,
+    /// Preferred transcription language (ISO 639-1).
+    /// Stored separately in `PendingItemData`; serde can't produce a
+    /// `&'static str`.
+    #[serde(skip)]
+    pub language: Option<&'static str>,
+    /// Transcribe with this provider instead of the current one; set by
+    /// the chat's /provider choice and when re-running a transcript.
+    pub provider: Option<SttProvider>,
+    /// The chat's cleanup filters, if it overrides `AUDIO_PREPROCESS`.
+    pub preprocess: Option<crate::audio::Preprocess>,
+    /// Served before other senders' items; set for admins when
+    /// `ADMIN_PRIORITY` is on.
+    pub priority: bool,
+    /// Language of the bot's messages about this item.
+    pub locale: crate::i18n::Locale,
+    /// Skip the queue and progress message; set in /quiet groups.
+    pub quiet: bool,
+    /// Post the transcript without a notification; set during the chat's
+    /// silent /quiethours.
+    #[serde(skip)]
+    pub silent: bool,
+    /// Forum topic the media was posted in; replies go there too.
+    pub topic: Option<i32>,
+}
+
+impl ProcessingOptions {
+    /// Defaults taken from a chat's settings.
+    pub fn for_chat(settings: &crate::settings::ChatSettings) -> Self {
+        Self {
+            phone_call: settings.phone_call,
+            show_metadata: settings.show_metadata,
+            language_line: settings.language_line,
+            json_attachment: settings.json_attachment,
+            subtitles: settings.subtitles,
+            anonymous: settings.anonymous,
+            profanity_filter: settings.profanity_filter,
+            language: settings.language.as_deref().and_then(crate::stt::language::code),
+            preprocess: settings.preprocess,
+            locale: settings.locale.unwrap_or_default(),
+            quiet: settings.quiet,
+            provider: settings.provider,
+            ..Default::default()
+        }
+    }
+
+    pub fn stt_options<'a>(&self, config: &'a BotConfig) -> crate::stt::SttOptions<'a> {
+        crate::stt::SttOptions {
+            phone_call: self.phone_call,
+            language: self.language,
+            profanity_filter: self.profanity_filter,
+            ..crate::stt::SttOptions::from_config(config)
+        }
+    }
 }
 
 impl QueueItem {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        id: String,
         bot: Bot,
         chat_id: ChatId,
-        message_id: MessageId,
+        message_id: Option<MessageId>,
         reply_to_message_id: MessageId,
-        file_data: Vec<u8>,
+        media: MediaFile,
         original_filename: String,
         user_info: String,
         user_id: teloxide::types::UserId,
         username: Option<String>,
+        duration_secs: u32,
     ) -> Self {
         Self {
-            id: Uuid::new_v4().to_string(),
+            id,
             bot,
             chat_id,
             message_id,
             reply_to_message_id,
-            file_data,
+            media: Arc::new(media),
             original_filename,
             user_info,
             user_id,
             username,
+            duration_secs,
+            options: ProcessingOptions::default(),
+            batch: None,
         }
     }
-}
 
-pub type QueueSender = mpsc::UnboundedSender<QueueItem>;
-pub type QueueReceiver = mpsc::UnboundedReceiver<QueueItem>;
-pub type QueueStats = Arc<RwLock<QueueStatistics>>;
+    /// The item without its media, for the on-disk queue.
+    pub fn pending_data(&self) -> persistence::PendingItemData {
+        persistence::PendingItemData {
+            id: self.id.clone(),
+            chat_id: self.chat_id,
+            message_id: self.message_id,
+            reply_to_message_id: self.reply_to_message_id,
+            original_filename: self.original_filename.clone(),
+            user_info: self.user_info.clone(),
+            user_id: self.user_id,
+            username: self.username.clone(),
+            duration_secs: self.duration_secs,
+            options: self.options,
+            language: self.options.language.map(str::to_string),
+            queued_at: chrono::Utc::now(),
+        }
+    }
 
-#[derive(Default)]
-pub struct QueueStatistics {
-    pub total_queued: u64,
-    pub total_processed: u64,
-    pub total_failed: u64,
-    pub current_queue_size: u64,
-    pub processing_item_id: Option<String>,
-}
+    pub fn from_pending(bot: Bot, data: persistence::PendingItemData, file_size: u64, queue_dir: &Path) -> Self {
+        let options = ProcessingOptions {
+            language: data.language.as_deref().and_then(crate::stt::language::code),
+            ..data.options
+        };
+        let media = MediaFile::new(persistence::pending_media_path(queue_dir, &data.id), file_size);
+        Self {
+            id: data.id,
+            bot,
+            chat_id: data.chat_id,
+            message_id: data.message_id,
+            reply_to_message_id: data.reply_to_message_id,
+            media: Arc::new(media),
+            original_filename: data.original_filename,
+            user_info: data.user_info,
+            user_id: data.user_id,
+            username: data.username,
+            duration_secs: data.duration_secs,
+            options,
+            batch: None,
+        }
+    }
 
-impl QueueStatistics {
-    pub async fn increment_queued(&mut self) {
-        self.total_queued += 1;
-        self.current_queue_size += 1;
+    /// Message to reply to, or None in anonymous chats.
+    pub fn reply_target(&self) -> Option<MessageId> {
+        (!self.options.anonymous).then_some(self.reply_to_message_id)
     }
 
-    pub async fn increment_processed(&mut self) {
-        self.total_processed += 1;
-        self.current_queue_size = self.current_queue_size.saturating_sub(1);
-        self.processing_item_id = None;
+    /// Removes the queue and progress message, if there is one.
+    async fn delete_status_message(&self) {
+        if let Some(message_id) = self.message_id {
+            self.bot.delete_message(self.chat_id, message_id).await.ok();
+        }
     }
 
-    pub async fn increment_failed(&mut self) {
-        self.total_failed += 1;
-        self.current_queue_size = self.current_queue_size.saturating_sub(1);
-        self.processing_item_id = None;
+    /// Starts a message in the item's chat, replying to the source message
+    /// unless the chat is anonymous.
+    fn reply(&self, text: impl Into<String>) -> <Bot as Requester>::SendMessage {
+        let mut request = self.bot.send_message(self.chat_id, text).disable_notification(self.options.silent);
+        if let Some(topic) = self.options.topic {
+            request = request.message_thread_id(topic);
+        }
+        match self.reply_target() {
+            Some(id) => request.reply_to_message_id(id),
+            None => request,
+        }
     }
 
-    pub async fn set_processing(&mut self, item_id: String) {
-        self.processing_item_id = Some(item_id);
+    /// A 30-second voice message from user 42 in chat 1, for tests.
+    #[cfg(test)]
+    pub fn for_tests(id: &str) -> Self {
+        Self::new(
+            id.to_string(),
+            Bot::new("token"),
+            ChatId(1),
+            Some(MessageId(2)),
+            MessageId(1),
+            MediaFile::new(PathBuf::from(id), 0),
+            "voice.ogg".to_string(),
+            "@user".to_string(),
+            teloxide::types::UserId(42),
+            None,
+            30,
+        )
     }
 }
 
-pub async fn start_queue_processor(
-    mut receiver: QueueReceiver,
-    config: BotConfig,
-    stats: QueueStats,
-    current_provider: CurrentProvider,
-) {
-    info!("Starting queue processor worker");
+/// The processing queue, shared by the handlers and the worker; with
+/// `QUEUE_BACKEND=redis`, also with other instances.
+pub type QueueSender = Arc<dyn crate::queue_backend::QueueBackend>;
+pub type QueueStats = Arc<RwLock<QueueStatistics>>;
+pub type ParkedItems = Arc<RwLock<HashMap<String, ParkedItem>>>;
+pub type TranscriptCache = Arc<RwLock<HashMap<String, CachedTranscript>>>;
 
-    while let Some(item) = receiver.recv().await {
-        info!(
-            "Processing queue item {} for user {} (file: {}, size: {} bytes)",
-            item.id, item.user_info, item.original_filename, item.file_data.len()
-        );
+/// What happens to a new file when `QUEUE_CAPACITY` files are already
+/// waiting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueFullPolicy {
+    /// Turn the file away and ask the sender to try later.
+    Reject,
+    /// Drop the longest-waiting file, telling its sender, to fit the new one.
+    DropOldest,
+    /// Hold the new file back until there is room.
+    Defer,
+}
 
-        // Update stats
-        {
-            let mut stats_guard = stats.write().await;
-            stats_guard.set_processing(item.id.clone()).await;
+impl QueueFullPolicy {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "reject" => Some(Self::Reject),
+            "drop-oldest" | "drop_oldest" => Some(Self::DropOldest),
+            "defer" | "wait" => Some(Self::Defer),
+            _ => None,
         }
+    }
+}
 
-        // Update the processing message
-        if let Err(e) = item.bot
-            .edit_message_text(
-                item.chat_id,
-                item.message_id,
-                format!("🎵 Processing audio... (Queue position: processing)\nFile: {}", item.original_filename)
-            )
-            .await
-        {
-            warn!("Failed to update processing message: {}", e);
+/// How often a deferred file looks again when the queue has room but
+/// other files hold it.
+const RESERVED_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A queue place held for a file until it's pushed, so files downloading
+/// at the same time can't overshoot `QUEUE_CAPACITY`. Dropping it gives
+/// the place back.
+pub struct Reservation {
+    reserved: Arc<AtomicUsize>,
+}
+
+impl Reservation {
+    /// Holds a place, returning how many others were already held.
+    fn hold(reserved: &Arc<AtomicUsize>) -> (Self, usize) {
+        let held = reserved.fetch_add(1, Ordering::SeqCst);
+        (Self { reserved: reserved.clone() }, held)
+    }
+
+    /// Holds a place once the queue has room for it.
+    async fn wait(queue: &QueueSender, reserved: &Arc<AtomicUsize>) -> Self {
+        loop {
+            let (reservation, held) = Self::hold(reserved);
+            if !queue.is_full(held).await {
+                return reservation;
+            }
+            drop(reservation);
+            if queue.is_full(0).await {
+                queue.wait_for_space().await;
+            } else {
+                tokio::time::sleep(RESERVED_POLL_INTERVAL).await;
+            }
         }
+    }
+}
 
-        // Process the audio
-        let result = process_audio_item(&item, &config, &current_provider).await;
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.reserved.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-        // Delete the processing message
-        item.bot.delete_message(item.chat_id, item.message_id).await.ok();
+/// The "waiting for room" message of a deferred file, taken down when the
+/// file gets its place or is given up.
+struct DeferredNotice {
+    bot: Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+}
 
-        // Send result
-        match result {
-            Ok((transcription, provider)) => {
-                info!("Successfully processed queue item {}", item.id);
+impl Drop for DeferredNotice {
+    fn drop(&mut self) {
+        let (bot, chat_id, message_id) = (self.bot.clone(), self.chat_id, self.message_id);
+        tokio::spawn(async move {
+            bot.delete_message(chat_id, message_id).await.ok();
+        });
+    }
+}
 
-                let via = format!(
-                    "_via {} · {}_",
-                    escape_markdown_v2(provider.as_str()),
-                    escape_markdown_v2(provider.model())
-                );
+/// What `admit` let a file in with: a held queue place, or under
+/// `QueueFullPolicy::Defer` a notice and a wait for room. Dropping it
+/// without queueing the file, e.g. after a failed download, gives the
+/// place back and takes the notice down.
+pub struct Admission {
+    /// Only held, never read.
+    _reservation: Option<Reservation>,
+    notice: Option<Arc<DeferredNotice>>,
+}
 
-                let response = if transcription.trim().is_empty() {
-                    format!(
-                        "{}\n\n🔇 No speech detected in the audio\\. The audio might be too quiet or contain no spoken words\\.",
-                        via
-                    )
-                } else {
-                    format!(
-                        "{}\n\n📝 *Transcription:*\n\n{}",
-                        via,
-                        escape_markdown_v2(&transcription)
-                    )
-                };
+impl Admission {
+    fn deferred(&self) -> bool {
+        self.notice.is_some()
+    }
 
-                if let Err(e) = send_long_message(&item.bot, item.chat_id, &response, item.reply_to_message_id).await {
-                    error!("Failed to send transcription for item {}: {}", item.id, e);
-                }
+    /// Admission for another file from the same archive. It holds no place
+    /// of its own; the archive's is kept until all its files are queued.
+    pub fn next_file(&self) -> Admission {
+        Admission { _reservation: None, notice: self.notice.clone() }
+    }
+}
 
-                // Update stats
+/// Applies `QUEUE_FULL_POLICY` before a new file is downloaded. Priority
+/// files always get in. A file let in holds its place while it downloads.
+pub async fn admit(
+    bot: &Bot,
+    chat_id: ChatId,
+    options: &ProcessingOptions,
+    queue: &QueueSender,
+    stats: &QueueStats,
+    config: &BotConfig,
+) -> Result<Admission> {
+    let reserved = stats.read().await.reserved.clone();
+    let (reservation, held) = Reservation::hold(&reserved);
+    if options.priority || !queue.is_full(held).await {
+        return Ok(Admission { _reservation: Some(reservation), notice: None });
+    }
+
+    match config.queue_full_policy {
+        QueueFullPolicy::Reject => {
+            stats.write().await.total_rejected += 1;
+            crate::alerts::send(bot, config, crate::alerts::Alert::QueueFull).await;
+            Err(BotError::QueueFull)
+        }
+        QueueFullPolicy::DropOldest => {
+            if let Some(dropped) = queue.pop_oldest().await {
+                warn!("Queue full, dropping item {} of user {}", dropped.id, dropped.user_info);
+                crate::alerts::send(bot, config, crate::alerts::Alert::QueueFull).await;
                 {
-                    let mut stats_guard = stats.write().await;
-                    stats_guard.increment_processed().await;
+                    let mut stats = stats.write().await;
+                    stats.remove_waiting(&dropped.id);
+                    stats.current_queue_size = stats.current_queue_size.saturating_sub(1);
+                    stats.total_rejected += 1;
                 }
+                persistence::remove_pending_item(dropped.media.path()).await;
+                let notice = i18n::tf(
+                    dropped.options.locale,
+                    "queue.dropped",
+                    &[("file", dropped.original_filename.clone())],
+                );
+                tell_unqueued(&dropped, notice).await;
             }
-            Err(e) => {
-                error!("Failed to process queue item {}: {}", item.id, e);
+            Ok(Admission { _reservation: Some(reservation), notice: None })
+        }
+        QueueFullPolicy::Defer => {
+            drop(reservation);
+            let mut request = bot.send_message(chat_id, i18n::t(options.locale, "queue.deferred"));
+            if let Some(topic) = options.topic {
+                request = request.message_thread_id(topic);
+            }
+            let notice = request.await?;
+            let notice = DeferredNotice { bot: bot.clone(), chat_id, message_id: notice.id };
+            Ok(Admission { _reservation: None, notice: Some(Arc::new(notice)) })
+        }
+    }
+}
 
-                let error_msg = match e {
-                    BotError::Audio(crate::audio::AudioError::UnsupportedFormat(_)) => {
-                        "❌ Unsupported audio format. Please send voice messages, video notes, audio files (.mp3, .m4a, .ogg), or video files."
-                    }
-                    BotError::Audio(crate::audio::AudioError::ConversionFailed(_)) => {
-                        "❌ Failed to process audio. The file might be corrupted or in an unsupported format."
-                    }
-                    BotError::Stt(_) => {
-                        "❌ Speech-to-text service is temporarily unavailable. Please try again later."
-                    }
-                    _ => "❌ An error occurred while processing your audio. Please try again."
-                };
+/// Tells the sender an item is out of the queue, in its batch report or in
+/// place of its queue message.
+async fn tell_unqueued(item: &QueueItem, notice: String) {
+    if let Some(slot) = &item.batch {
+        slot.finish(&item.bot, crate::batch::Outcome::Failed(notice)).await;
+        return;
+    }
+    let sent = match item.message_id {
+        Some(message_id) => item.bot.edit_message_text(item.chat_id, message_id, notice).await,
+        None => item.reply(notice).await,
+    };
+    if let Err(e) = sent {
+        warn!("Failed to tell the sender of item {} it left the queue: {}", item.id, e);
+    }
+}
 
-                if let Err(e) = item.bot
-                    .send_message(item.chat_id, error_msg)
-                    .reply_to_message_id(item.reply_to_message_id)
-                    .await
-                {
-                    error!("Failed to send error message for item {}: {}", item.id, e);
-                }
+/// Queues an item `admit` let in, giving its held place back once it's
+/// pushed. A deferred one waits for room in a background task, so the
+/// update handler isn't held up meanwhile.
+pub async fn enqueue_admitted(
+    sender: &QueueSender,
+    stats: &QueueStats,
+    item: QueueItem,
+    admission: Admission,
+) -> Result<()> {
+    if !admission.deferred() {
+        return enqueue(sender, stats, item).await;
+    }
+    let reserved = stats.read().await.reserved.clone();
+    let (sender, stats) = (sender.clone(), stats.clone());
+    tokio::spawn(async move {
+        let _reservation = Reservation::wait(&sender, &reserved).await;
+        drop(admission);
+        if let Err(e) = enqueue(&sender, &stats, item.clone()).await {
+            error!("Failed to queue deferred item {}: {}", item.id, e);
+            tell_unqueued(&item, i18n::t(item.options.locale, error_key(&e)).to_string()).await;
+        }
+    });
+    Ok(())
+}
 
-                // Update stats
-                {
-                    let mut stats_guard = stats.write().await;
-                    stats_guard.increment_failed().await;
-                }
-            }
+/// Saves the item to the on-disk queue so it survives a restart, then hands
+/// it to the worker. A failed save is logged and the item still queued; a
+/// shared queue that can't be reached fails it.
+pub async fn enqueue(sender: &QueueSender, stats: &QueueStats, item: QueueItem) -> Result<()> {
+    if sender.is_local()
+        && let Err(e) = persistence::save_pending_item(item.media.path(), &item.pending_data()).await
+    {
+        warn!("Failed to persist queue item {}, it won't survive a restart: {}", item.id, e);
+    }
+    push_waiting(sender, stats, item).await
+}
+
+/// Queues the item, then lists it as waiting. The statistics aren't locked
+/// during the push, which may wait on a shared queue's server.
+async fn push_waiting(sender: &QueueSender, stats: &QueueStats, item: QueueItem) -> Result<()> {
+    let waiting = WaitingItem::new(&item);
+    if let Err(e) = sender.push(item).await {
+        stats.write().await.unqueue(&waiting.id);
+        return Err(e);
+    }
+    stats.write().await.add_waiting(waiting);
+    Ok(())
+}
+
+/// Re-queues items left over from before a restart and tells their senders
+/// the file is still being processed.
+pub async fn resume_pending(bot: &Bot, sender: &QueueSender, stats: &QueueStats, queue_dir: &Path) {
+    let pending = match persistence::load_pending_items(queue_dir).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Failed to load pending queue items: {}", e);
+            return;
+        }
+    };
+
+    for (data, file_size) in pending {
+        let item = QueueItem::from_pending(bot.clone(), data, file_size, queue_dir);
+        let queue_position = {
+            let mut stats = stats.write().await;
+            stats.increment_queued().await;
+            stats.current_queue_size
+        };
+
+        if let Some(message_id) = item.message_id
+            && let Err(e) = bot
+                .edit_message_text(
+                    item.chat_id,
+                    message_id,
+                    i18n::tf(
+                        item.options.locale,
+                        "queue.resumed",
+                        &[("position", queue_position.to_string()), ("file", item.original_filename.clone())],
+                    ),
+                )
+                .reply_markup(cancel_keyboard(&item.id, item.options.locale))
+                .await
+        {
+            warn!("Failed to update resumed item {}: {}", item.id, e);
+        }
+
+        info!("Resuming queue item {} for user {}", item.id, item.user_info);
+        if let Err(e) = push_waiting(sender, stats, item).await {
+            error!("Failed to resume queue item: {}", e);
         }
     }
+}
+
+/// How long skipped and failed items are kept around for a "transcribe
+/// anyway" override or a retry.
+const PARKED_ITEM_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_PARKED_ITEMS: usize = 20;
 
-    warn!("Queue processor stopped - receiver closed");
+/// An item that was skipped before transcription, or failed with a
+/// retryable error, but can still be re-queued.
+pub struct ParkedItem {
+    pub item: QueueItem,
+    pub parked_at: Instant,
 }
 
-async fn process_audio_item(
-    item: &QueueItem,
+pub async fn park_item(parked: &ParkedItems, item: QueueItem) {
+    let mut parked = parked.write().await;
+    make_room(&mut parked, PARKED_ITEM_TTL, MAX_PARKED_ITEMS, |p| p.parked_at);
+    parked.insert(item.id.clone(), ParkedItem { item, parked_at: Instant::now() });
+}
+
+/// How long finished transcripts stay available to the reply buttons.
+const CACHED_TRANSCRIPT_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_CACHED_TRANSCRIPTS: usize = 20;
+
+/// A delivered transcript, kept for the buttons under it. The item is kept
+/// too so the file can be re-run with another provider.
+pub struct CachedTranscript {
+    pub item: QueueItem,
+    pub transcription: crate::stt::Transcription,
+    pub provider: SttProvider,
+    pub cached_at: Instant,
+}
+
+pub async fn cache_transcript(
+    cache: &TranscriptCache,
+    item: QueueItem,
+    transcription: crate::stt::Transcription,
+    provider: SttProvider,
+) {
+    let mut cache = cache.write().await;
+    make_room(&mut cache, CACHED_TRANSCRIPT_TTL, MAX_CACHED_TRANSCRIPTS, |c| c.cached_at);
+    cache.insert(
+        item.id.clone(),
+        CachedTranscript { item, transcription, provider, cached_at: Instant::now() },
+    );
+}
+
+/// Drops expired entries, then the oldest ones until one more fits.
+fn make_room<T>(entries: &mut HashMap<String, T>, ttl: Duration, max: usize, stored_at: impl Fn(&T) -> Instant) {
+    entries.retain(|_, entry| stored_at(entry).elapsed() < ttl);
+
+    while entries.len() >= max {
+        let Some(oldest) = entries
+            .iter()
+            .min_by_key(|(_, entry)| stored_at(entry))
+            .map(|(id, _)| id.clone())
+        else {
+            break;
+        };
+        entries.remove(&oldest);
+    }
+}
+
+/// Provider offered by the "Re-run" button: the next configured one in
+/// failover order, then any other with credentials.
+pub fn rerun_provider(used: SttProvider, config: &BotConfig) -> Option<SttProvider> {
+    std::iter::once(config.stt_provider)
+        .chain(config.stt_fallbacks.iter().copied())
+        .chain(SttProvider::ALL)
+        .find(|&p| p != used && config.has_provider_key(p))
+}
+
+/// Buttons under a delivered transcript; None when no action applies.
+fn transcript_keyboard(
+    item_id: &str,
+    transcription: &crate::stt::Transcription,
+    provider: SttProvider,
     config: &BotConfig,
-    current_provider: &CurrentProvider,
-) -> Result<(String, SttProvider)> {
-    use crate::{audio, stt};
+    locale: Locale,
+) -> Option<InlineKeyboardMarkup> {
+    if transcription.text.trim().is_empty() {
+        return None;
+    }
 
-    let provider = *current_provider.read().await;
+    let mut first = Vec::new();
+    if config.openai_api_key.is_some() {
+        first.push(InlineKeyboardButton::callback(i18n::t(locale, "button.summarize"), format!("summarize:{}", item_id)));
+        first.push(InlineKeyboardButton::callback(i18n::t(locale, "button.translate"), format!("translate:{}", item_id)));
+    }
 
-    // Log transcription request for ElevenLabs
-    if matches!(provider, SttProvider::ElevenLabs) {
-        if let Err(e) = request_logger::log_transcription_request(
-            item.user_id,
-            item.username.as_deref(),
-            item.file_data.len(),
-        ).await {
-            error!("Failed to log transcription request: {}", e);
+    let mut second = Vec::new();
+    if !transcription.segments.is_empty() {
+        second.push(InlineKeyboardButton::callback(i18n::t(locale, "button.timestamps"), format!("timestamps:{}", item_id)));
+    }
+    if let Some(other) = rerun_provider(provider, config) {
+        second.push(InlineKeyboardButton::callback(
+            i18n::tf(locale, "button.rerun", &[("provider", other.as_str().to_string())]),
+            format!("rerun:{}", item_id),
+        ));
+    }
+
+    let rows: Vec<_> = [first, second].into_iter().filter(|row| !row.is_empty()).collect();
+    (!rows.is_empty()).then(|| InlineKeyboardMarkup::new(rows))
+}
+
+/// How often queued items' position messages are refreshed.
+const PROGRESS_REFRESH_INTERVAL: Duration = Duration::from_secs(20);
+
+/// An item is listed right after its push returns, so one started without
+/// being listed for this long was never going to be.
+const UNLISTED_EXPIRY: Duration = Duration::from_secs(60);
+
+/// Recent processing times kept for ETAs.
+const ETA_SAMPLE_SIZE: usize = 10;
+
+/// An item waiting for the worker, with the message showing its position.
+pub struct WaitingItem {
+    pub id: String,
+    bot: Bot,
+    chat_id: ChatId,
+    message_id: Option<MessageId>,
+    user_id: teloxide::types::UserId,
+    original_filename: String,
+    locale: crate::i18n::Locale,
+    /// What the message was last edited to, to skip no-op edits.
+    shown: Option<String>,
+    listed_at: Instant,
+}
+
+impl WaitingItem {
+    fn new(item: &QueueItem) -> Self {
+        Self {
+            id: item.id.clone(),
+            bot: item.bot.clone(),
+            chat_id: item.chat_id,
+            message_id: item.message_id,
+            user_id: item.user_id,
+            original_filename: item.original_filename.clone(),
+            locale: item.options.locale,
+            shown: None,
+            listed_at: Instant::now(),
         }
     }
+}
 
-    // Convert audio to the format required by the STT provider
-    let converted_audio = audio::convert_for_stt(&item.file_data, &item.original_filename, provider).await?;
+/// A queue message that needs editing to show a new position.
+struct PositionUpdate {
+    bot: Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    item_id: String,
+    locale: Locale,
+    text: String,
+}
 
-    // Transcribe using the current provider
-    let transcription = stt::transcribe(&converted_audio, provider, config).await?;
+/// Result of pressing Cancel under a queue message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// Taken out of the queue before it started.
+    Dequeued { original_filename: String },
+    /// Already running; the worker has been told to stop.
+    Stopping,
+    NotAllowed,
+    NotFound,
+}
 
-    Ok((transcription, provider))
+/// The Cancel button under queue and processing messages.
+pub fn cancel_keyboard(item_id: &str, locale: Locale) -> InlineKeyboardMarkup {
+    let button = InlineKeyboardButton::callback(i18n::t(locale, "button.cancel"), format!("cancel:{}", item_id));
+    InlineKeyboardMarkup::new(vec![vec![button]])
 }
 
-fn escape_markdown_v2(text: &str) -> String {
-    text.chars()
-        .map(|c| match c {
-            '_' | '*' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '=' | '|' | '{' | '}' | '.' | '!' => {
-                format!("\\{}", c)
-            }
-            _ => c.to_string(),
-        })
-        .collect()
+#[derive(Default)]
+pub struct QueueStatistics {
+    pub total_queued: u64,
+    pub total_processed: u64,
+    pub total_failed: u64,
+    pub total_skipped: u64,
+    pub total_cancelled: u64,
+    /// Files turned away or dropped because the queue was full.
+    pub total_rejected: u64,
+    pub current_queue_size: u64,
+    /// Queue places held for files `admit` let in that aren't pushed yet.
+    pub reserved: Arc<AtomicUsize>,
+    pub processing_item_id: Option<String>,
+    pub processing_user_id: Option<teloxide::types::UserId>,
+    /// Wakes the worker to abandon the running item.
+    pub processing_cancel: Option<Arc<Notify>>,
+    /// Cancelled while waiting; dropped when the worker reaches them.
+    pub cancelled: HashSet<String>,
+    /// Items handed to the worker but not started yet, sorted into serving
+    /// order on each refresh.
+    pub waiting: VecDeque<WaitingItem>,
+    /// Items the worker started before they were listed as waiting, and
+    /// when; they aren't listed when their turn comes. Another instance's
+    /// items are never listed, so entries expire.
+    pub started_unlisted: HashMap<String, Instant>,
+    /// How long the last few items took, newest last.
+    pub recent_durations: VecDeque<Duration>,
+    /// Files queued within `DUPLICATE_WINDOW`, by chat and Telegram's
+    /// unique file ID.
+    pub recent_files: HashMap<String, RecentFile>,
 }
 
-async fn send_long_message(bot: &Bot, chat_id: ChatId, text: &str, reply_to: MessageId) -> Result<()> {
-    const MAX_LENGTH: usize = 4000; // Leave some buffer below 4096 limit
+/// How long a file sent again in the same chat is taken for a duplicate.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(60);
+const MAX_RECENT_FILES: usize = 100;
 
-    if text.len() <= MAX_LENGTH {
-        bot.send_message(chat_id, text)
-            .parse_mode(teloxide::types::ParseMode::MarkdownV2)
-            .reply_to_message_id(reply_to)
-            .await?;
-        return Ok(());
+/// A recently queued file and the item handling it. Files whose item
+/// failed, was skipped or cancelled are forgotten so they can be sent again.
+pub struct RecentFile {
+    pub item_id: String,
+    pub queued_at: Instant,
+    /// Whether the item was transcribed; until then it's still queued.
+    pub done: bool,
+}
+
+fn recent_file_key(chat_id: ChatId, file_unique_id: &str) -> String {
+    format!("{}:{}", chat_id, file_unique_id)
+}
+
+impl QueueStatistics {
+    pub fn add_waiting(&mut self, mut waiting: WaitingItem) {
+        if self.started_unlisted.remove(&waiting.id).is_some() {
+            return;
+        }
+        waiting.listed_at = Instant::now();
+        self.waiting.push_back(waiting);
     }
 
-    // Split the message into chunks
-    let mut chunks = Vec::new();
-    let mut current_chunk = String::new();
-
-    // Split by lines first to avoid breaking mid-sentence
-    for line in text.lines() {
-        if current_chunk.len() + line.len() + 1 > MAX_LENGTH {
-            if !current_chunk.is_empty() {
-                chunks.push(current_chunk.clone());
-                current_chunk.clear();
-            }
-
-            // If a single line is too long, split it by words
-            if line.len() > MAX_LENGTH {
-                for word in line.split_whitespace() {
-                    if current_chunk.len() + word.len() + 1 > MAX_LENGTH {
-                        if !current_chunk.is_empty() {
-                            chunks.push(current_chunk.clone());
-                            current_chunk.clear();
-                        }
-                    }
-                    if !current_chunk.is_empty() {
-                        current_chunk.push(' ');
-                    }
-                    current_chunk.push_str(word);
-                }
-            } else {
-                current_chunk = line.to_string();
+    /// Cancels an item for `user_id`, who must be its sender unless they
+    /// are an admin.
+    pub fn cancel(&mut self, item_id: &str, user_id: teloxide::types::UserId, is_admin: bool) -> CancelOutcome {
+        if let Some(index) = self.waiting.iter().position(|waiting| waiting.id == item_id) {
+            if self.waiting[index].user_id != user_id && !is_admin {
+                return CancelOutcome::NotAllowed;
             }
-        } else {
-            if !current_chunk.is_empty() {
-                current_chunk.push('\n');
+            let waiting = self.waiting.remove(index).expect("index is in range");
+            self.forget_file(&waiting.id);
+            self.cancelled.insert(waiting.id);
+            self.total_cancelled += 1;
+            self.current_queue_size = self.current_queue_size.saturating_sub(1);
+            return CancelOutcome::Dequeued { original_filename: waiting.original_filename };
+        }
+
+        if self.processing_item_id.as_deref() == Some(item_id) {
+            if self.processing_user_id != Some(user_id) && !is_admin {
+                return CancelOutcome::NotAllowed;
             }
-            current_chunk.push_str(line);
+            if let Some(cancel) = &self.processing_cancel {
+                cancel.notify_one();
+            }
+            return CancelOutcome::Stopping;
         }
+
+        CancelOutcome::NotFound
     }
 
-    if !current_chunk.is_empty() {
-        chunks.push(current_chunk);
+    /// True, once, for an item cancelled while it was waiting.
+    pub fn take_cancelled(&mut self, item_id: &str) -> bool {
+        self.cancelled.remove(item_id)
     }
 
-    // Send each chunk
-    for (i, chunk) in chunks.iter().enumerate() {
-        let message_text = if chunks.len() > 1 {
-            format!("{}\n\n*\\(Part {} of {}\\)*", chunk, i + 1, chunks.len())
-        } else {
-            chunk.clone()
-        };
+    pub fn remove_waiting(&mut self, item_id: &str) {
+        self.waiting.retain(|waiting| waiting.id != item_id);
+    }
 
-        let mut request = bot.send_message(chat_id, message_text)
-            .parse_mode(teloxide::types::ParseMode::MarkdownV2);
+    /// Forgets waiting items another instance's worker took from a shared
+    /// queue, counting only this instance's items from then on. Items
+    /// listed after `order` was read can't be in it yet.
+    fn forget_taken(&mut self, order: &[String], read_at: Instant) {
+        self.waiting.retain(|waiting| waiting.listed_at >= read_at || order.contains(&waiting.id));
+        self.current_queue_size = self.waiting.len() as u64 + self.processing_item_id.is_some() as u64;
+    }
 
-        // Only reply to original message for the first chunk
-        if i == 0 {
-            request = request.reply_to_message_id(reply_to);
+    pub fn record_duration(&mut self, elapsed: Duration) {
+        if self.recent_durations.len() == ETA_SAMPLE_SIZE {
+            self.recent_durations.pop_front();
         }
-
-        request.await?;
+        self.recent_durations.push_back(elapsed);
     }
 
-    Ok(())
-}
+    pub fn average_duration(&self) -> Option<Duration> {
+        let count = self.recent_durations.len() as u32;
+        (count > 0).then(|| self.recent_durations.iter().sum::<Duration>() / count)
+    }
 
-pub async fn get_queue_status(stats: &QueueStats) -> String {
-    let stats_guard = stats.read().await;
+    /// Position messages whose text changed since they were last edited.
+    /// `order` is the queue's serving order by item id.
+    fn stale_position_messages(&mut self, order: &[String]) -> Vec<PositionUpdate> {
+        let rank = |id: &str| order.iter().position(|queued| queued == id).unwrap_or(order.len());
+        self.waiting.make_contiguous().sort_by_key(|waiting| rank(&waiting.id));
 
-    let processing_info = if let Some(ref item_id) = stats_guard.processing_item_id {
-        format!("Currently processing: {}", &item_id[..8])
-    } else {
-        "Idle".to_string()
-    };
+        let average = self.average_duration();
+        let busy = self.processing_item_id.is_some() as u32;
 
-    format!(
-        "🔄 *Queue Status:*\n\
-        📊 Current queue size: {}\n\
-        ⚙️ Status: {}\n\
-        ✅ Total processed: {}\n\
-        ❌ Total failed: {}\n\
-        📥 Total queued: {}",
-        stats_guard.current_queue_size,
-        processing_info,
-        stats_guard.total_processed,
-        stats_guard.total_failed,
-        stats_guard.total_queued
-    )
+        let mut updates = Vec::new();
+        for (index, waiting) in self.waiting.iter_mut().enumerate() {
+            // The item being processed counts as ahead, half done on average
+            let ahead = index as u32 + busy;
+            let eta = average.map(|avg| avg * ahead - avg * busy / 2);
+            let Some(message_id) = waiting.message_id else {
+                continue;
+            };
+            let text = position_text(waiting.locale, ahead as u64 + 1, &waiting.original_filename, eta);
+            if waiting.shown.as_ref() != Some(&text) {
+                waiting.shown = Some(text.clone());
+                updates.push(PositionUpdate {
+                    bot: waiting.bot.clone(),
+                    chat_id: waiting.chat_id,
+                    message_id,
+                    item_id: waiting.id.clone(),
+                    locale: waiting.locale,
+                    text,
+                });
+            }
+        }
+        updates
+    }
+
+    /// The item already handling this file if it was queued in the chat
+    /// within `DUPLICATE_WINDOW`, e.g. a voice note forwarded twice.
+    pub fn duplicate_of(&self, chat_id: ChatId, file_unique_id: &str) -> Option<&RecentFile> {
+        self.recent_files
+            .get(&recent_file_key(chat_id, file_unique_id))
+            .filter(|recent| recent.queued_at.elapsed() < DUPLICATE_WINDOW)
+    }
+
+    pub fn remember_file(&mut self, chat_id: ChatId, file_unique_id: &str, item_id: &str) {
+        make_room(&mut self.recent_files, DUPLICATE_WINDOW, MAX_RECENT_FILES, |recent| recent.queued_at);
+        self.recent_files.insert(
+            recent_file_key(chat_id, file_unique_id),
+            RecentFile { item_id: item_id.to_string(), queued_at: Instant::now(), done: false },
+        );
+    }
+
+    fn forget_file(&mut self, item_id: &str) {
+        self.recent_files.retain(|_, recent| recent.item_id != item_id);
+    }
+
+    /// Marks the running item's file as transcribed, or forgets it if the
+    /// item ended any other way.
+    fn settle_file(&mut self, succeeded: bool) {
+        let Some(item_id) = self.processing_item_id.clone() else {
+            return;
+        };
+        if succeeded {
+            self.recent_files.values_mut().filter(|recent| recent.item_id == item_id).for_each(|recent| recent.done = true);
+        } else {
+            self.forget_file(&item_id);
+        }
+    }
+
+    pub async fn increment_queued(&mut self) {
+        self.total_queued += 1;
+        self.current_queue_size += 1;
+    }
+
+    /// Takes back `increment_queued` and `remember_file` for an item that
+    /// never made it into the queue.
+    pub fn unqueue(&mut self, item_id: &str) {
+        self.total_queued = self.total_queued.saturating_sub(1);
+        self.current_queue_size = self.current_queue_size.saturating_sub(1);
+        self.forget_file(item_id);
+    }
+
+    pub async fn increment_processed(&mut self) {
+        self.total_processed += 1;
+        self.settle_file(true);
+        self.finish_processing();
+    }
+
+    pub async fn increment_failed(&mut self) {
+        self.total_failed += 1;
+        self.settle_file(false);
+        self.finish_processing();
+    }
+
+    pub async fn increment_skipped(&mut self) {
+        self.total_skipped += 1;
+        self.settle_file(false);
+        self.finish_processing();
+    }
+
+    pub async fn increment_cancelled(&mut self) {
+        self.total_cancelled += 1;
+        self.settle_file(false);
+        self.finish_processing();
+    }
+
+    fn finish_processing(&mut self) {
+        self.current_queue_size = self.current_queue_size.saturating_sub(1);
+        self.processing_item_id = None;
+        self.processing_user_id = None;
+        self.processing_cancel = None;
+    }
+
+    /// Marks the item as running. The returned handle fires if it gets
+    /// cancelled.
+    pub async fn set_processing(&mut self, item: &QueueItem) -> Arc<Notify> {
+        if !self.waiting.iter().any(|waiting| waiting.id == item.id) {
+            self.started_unlisted.retain(|_, started| started.elapsed() < UNLISTED_EXPIRY);
+            self.started_unlisted.insert(item.id.clone(), Instant::now());
+        }
+        self.remove_waiting(&item.id);
+        let cancel = Arc::new(Notify::new());
+        self.processing_item_id = Some(item.id.clone());
+        self.processing_user_id = Some(item.user_id);
+        self.processing_cancel = Some(cancel.clone());
+        cancel
+    }
+}
+
+/// `📥 In queue (position: 3)` with the file name and, once a few items
+/// have been timed, a rough wait.
+fn position_text(locale: Locale, position: u64, original_filename: &str, eta: Option<Duration>) -> String {
+    let mut text = i18n::tf(
+        locale,
+        "queue.position",
+        &[("position", position.to_string()), ("file", original_filename.to_string())],
+    );
+    if let Some(eta) = eta {
+        let minutes = (eta.as_secs() + 30) / 60;
+        text.push('\n');
+        if minutes == 0 {
+            text.push_str(i18n::t(locale, "queue.eta_soon"));
+        } else {
+            text.push_str(&i18n::tf(locale, "queue.eta_minutes", &[("minutes", minutes.to_string())]));
+        }
+    }
+    text
+}
+
+/// Keeps the "Added to queue" messages of waiting items current as the
+/// queue moves.
+pub async fn start_progress_updater(queue: QueueSender, stats: QueueStats) {
+    let mut interval = tokio::time::interval(PROGRESS_REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        // Read without the statistics locked; a shared queue may be slow
+        let read_at = Instant::now();
+        let order = match queue.order().await {
+            Ok(order) => order,
+            Err(e) => {
+                warn!("Failed to read the queue order: {}", e);
+                continue;
+            }
+        };
+        let updates = {
+            let mut stats = stats.write().await;
+            if !queue.is_local() {
+                stats.forget_taken(&order, read_at);
+            }
+            stats.stale_position_messages(&order)
+        };
+        for update in updates {
+            if let Err(e) = update
+                .bot
+                .edit_message_text(update.chat_id, update.message_id, update.text)
+                .reply_markup(cancel_keyboard(&update.item_id, update.locale))
+                .await
+            {
+                warn!("Failed to refresh queue position message: {}", e);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn start_queue_processor(
+    queue: QueueSender,
+    shared_config: crate::SharedConfig,
+    stats: QueueStats,
+    current_provider: CurrentProvider,
+    usage: UsageStores,
+    parked_items: ParkedItems,
+    transcripts: TranscriptCache,
+    settings_store: crate::settings::ChatSettingsStore,
+) {
+    info!("Starting queue processor worker");
+
+    loop {
+        let item = queue.pop().await;
+        // Picks up a /reload between items
+        let config = shared_config.read().await.clone();
+        if stats.write().await.take_cancelled(&item.id) {
+            info!("Dropping queue item {}, cancelled while waiting", item.id);
+            persistence::remove_pending_item(item.media.path()).await;
+            queue.ack(&item.id).await;
+            if let Some(slot) = &item.batch {
+                let cancelled = i18n::t(item.options.locale, "result.cancelled").to_string();
+                slot.finish(&item.bot, crate::batch::Outcome::Failed(cancelled)).await;
+            }
+            continue;
+        }
+
+        info!(
+            "Processing queue item {} for user {} (file: {}, size: {} bytes)",
+            item.id, item.user_info, item.original_filename, item.media.size()
+        );
+
+        // Update stats
+        let cancel = {
+            let mut stats_guard = stats.write().await;
+            stats_guard.set_processing(&item).await
+        };
+
+        // Update the processing message
+        if let Some(message_id) = item.message_id
+            && let Err(e) = item.bot
+                .edit_message_text(
+                    item.chat_id,
+                    message_id,
+                    i18n::tf(item.options.locale, "queue.processing", &[("file", item.original_filename.clone())])
+                )
+                .reply_markup(cancel_keyboard(&item.id, item.options.locale))
+                .await
+        {
+            warn!("Failed to update processing message: {}", e);
+        }
+
+        // Show how the file converts, before or instead of transcribing it
+        if config.debug_audio != crate::audio::DebugAudio::Off {
+            send_audio_debug(&item, &config, &current_provider).await;
+            if config.debug_audio == crate::audio::DebugAudio::Only {
+                persistence::remove_pending_item(item.media.path()).await;
+                queue.ack(&item.id).await;
+                item.delete_status_message().await;
+                stats.write().await.increment_skipped().await;
+                if let Some(slot) = &item.batch {
+                    slot.finish(&item.bot, crate::batch::Outcome::Failed("DEBUG_AUDIO=only".to_string())).await;
+                }
+                continue;
+            }
+        }
+
+        // Process the audio. Dropping the future on cancel kills ffmpeg and
+        // abandons provider requests.
+        let started = Instant::now();
+        let result = tokio::select! {
+            result = process_audio_item(&item, &config, &current_provider, &usage, &settings_store) => result,
+            _ = cancel.notified() => Err(BotError::Cancelled),
+        };
+        let latency = started.elapsed();
+        if result.is_ok() {
+            stats.write().await.record_duration(latency);
+        }
+        if let Err(e) = request_logger::append(&request_log_entry(&item, &result, latency), &config).await {
+            error!("Failed to log transcription request: {}", e);
+        }
+
+        // Handled either way; skipped items are only kept in memory. Others
+        // are acknowledged once their result is out, or saved to be sent
+        // after quiet hours.
+        let (item_id, media_path) = (item.id.clone(), item.media.path().to_path_buf());
+        if let Some(slot) = item.batch.clone() {
+            persistence::remove_pending_item(&media_path).await;
+            queue.ack(&item_id).await;
+            finish_batch_item(&item, &slot, result, &config, &current_provider, &usage, &settings_store, &stats).await;
+            continue;
+        }
+
+        // Delete the processing message; a transcript that fits in one
+        // message replaces it instead
+        if result.is_err() {
+            item.delete_status_message().await;
+        }
+
+        // Send result
+        match result {
+            Ok(ProcessedItem { mut transcription, provider, media_secs, billed_secs }) => {
+                info!("Successfully processed queue item {} via {}", item.id, provider.as_str());
+                crate::alerts::on_success();
+                crate::text::apply(&mut transcription, &config.text_formatting);
+                if item.options.profanity_filter {
+                    crate::text::redact::mask_transcription(&mut transcription);
+                }
+
+                let locale = item.options.locale;
+                let model = provider.model_for(&item.options.stt_options(&config)).to_string();
+                let via = i18n::tf(locale, "transcript.via", &[("provider", provider.as_str().to_string()), ("model", model)]);
+                let via = format!("_{}_", escape_markdown_v2(&via));
+
+                let mut response = if transcription.text.trim().is_empty() {
+                    format!("{}\n\n{}", via, escape_markdown_v2(i18n::t(locale, "result.no_speech")))
+                } else {
+                    let body = render_transcript_body(&transcription, &config);
+                    let header = if item.options.language_line {
+                        format!("{}\n\n", escape_markdown_v2(&language_line(&transcription, media_secs)))
+                    } else {
+                        String::new()
+                    };
+                    let language = transcription.language.as_deref().or(item.options.language);
+                    let chat_template = crate::settings::get_for(&settings_store, item.chat_id, item.options.topic).await.reply_template;
+                    let content = match chat_template.as_ref().or(config.reply_template.as_ref()) {
+                        Some(template) => render_reply(template, &body, &transcription, provider, &config, &item.options, media_secs),
+                        None => format!("{}\n\n{}", transcription_heading(language, locale), body),
+                    };
+                    format!("{}\n\n{}{}", via, header, content)
+                };
+
+                if let Some(warning) = low_confidence_warning(&transcription, &config, locale) {
+                    response.push_str(&format!("\n\n{}", escape_markdown_v2(&warning)));
+                }
+
+                if item.options.show_metadata {
+                    response.push_str(&format!(
+                        "\n\n_{}_",
+                        escape_markdown_v2(&metadata_line(&transcription, provider, media_secs, locale))
+                    ));
+                }
+
+                if !transcription.alternatives.is_empty() {
+                    response.push_str(&format!("\n\n🔀 *{}*", escape_markdown_v2(i18n::t(locale, "transcript.alternatives"))));
+                    for (i, alternative) in transcription.alternatives.iter().enumerate() {
+                        response.push_str(&format!("\n{}\\. {}", i + 2, escape_markdown_v2(alternative)));
+                    }
+                }
+
+                if let Some(template) = &config.transcript_footer {
+                    let footer = render_footer(template, &transcription, provider, &config, &item.options, media_secs);
+                    response.push_str(&format!("\n\n{}", escape_markdown_v2(&footer)));
+                }
+
+                record_quota_usage(&item, media_secs, &usage.quotas).await;
+                record_cost(&item, provider, billed_secs, &config, &usage.costs, &settings_store).await;
+                record_history(&item, &transcription, provider, media_secs, &config, &usage.history).await;
+
+                // Update stats
+                {
+                    let mut stats_guard = stats.write().await;
+                    stats_guard.increment_processed().await;
+                }
+
+                let quiet_hours = crate::settings::get_for(&settings_store, item.chat_id, item.options.topic).await.quiet_hours;
+                match quiet_hours.and_then(|hours| Some((hours, hours.remaining(chrono::Utc::now())?))) {
+                    Some((hours, wait)) if hours.mode == crate::settings::QuietMode::Delay => {
+                        info!("Holding the transcript of item {} for {}s, quiet hours in chat {}", item.id, wait.as_secs(), item.chat_id);
+                        if let Some(message_id) = item.message_id {
+                            let notice = i18n::tf(item.options.locale, "queue.held", &[("time", hours.end_label())]);
+                            if let Err(e) = crate::telegram::send(item.bot.edit_message_text(item.chat_id, message_id, notice)).await {
+                                warn!("Failed to show the quiet hours notice for item {}: {}", item.id, e);
+                            }
+                        }
+                        let held = persistence::HeldDeliveryData {
+                            item: item.pending_data(),
+                            transcription,
+                            provider,
+                            response,
+                            via,
+                            media_secs,
+                            deliver_at: chrono::Utc::now() + wait,
+                        };
+                        if let Err(e) = persistence::save_held_delivery(&config.queue_dir(), &held).await {
+                            warn!("Failed to save the held transcript of item {}, it won't survive a restart: {}", item.id, e);
+                        }
+                        tokio::spawn(deliver_held(item, held, config.clone(), transcripts.clone()));
+                    }
+                    quiet => {
+                        let mut item = item;
+                        item.options.silent = quiet.is_some();
+                        deliver_transcript(&item, &transcription, provider, &response, &via, &config, media_secs).await;
+                        cache_transcript(&transcripts, item, transcription, provider).await;
+                    }
+                }
+            }
+            Err(BotError::SilentAudio) => {
+                info!("Queue item {} is effectively silent, skipping provider call", item.id);
+
+                if let Err(e) = crate::telegram::send(item.reply(i18n::t(item.options.locale, "result.no_speech"))).await {
+                    error!("Failed to send silence notice for item {}: {}", item.id, e);
+                }
+
+                {
+                    let mut stats_guard = stats.write().await;
+                    stats_guard.increment_skipped().await;
+                }
+            }
+            Err(BotError::RepeatedClip) => {
+                if let Err(e) = crate::telegram::send(item.reply(i18n::t(item.options.locale, "result.repeated_clip"))).await {
+                    error!("Failed to send repeated clip notice for item {}: {}", item.id, e);
+                }
+
+                {
+                    let mut stats_guard = stats.write().await;
+                    stats_guard.increment_skipped().await;
+                }
+            }
+            Err(BotError::MusicDetected) => {
+                info!("Queue item {} looks like music, skipping transcription", item.id);
+
+                let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                    InlineKeyboardButton::callback(i18n::t(item.options.locale, "result.music_override"), format!("music:{}", item.id)),
+                ]]);
+
+                if let Err(e) =
+                    crate::telegram::send(item.reply(i18n::t(item.options.locale, "result.music")).reply_markup(keyboard)).await
+                {
+                    error!("Failed to send music notice for item {}: {}", item.id, e);
+                }
+
+                {
+                    let mut stats_guard = stats.write().await;
+                    stats_guard.increment_skipped().await;
+                }
+
+                park_item(&parked_items, item).await;
+            }
+            Err(BotError::Cancelled) => {
+                info!("Queue item {} was cancelled while processing", item.id);
+
+                if let Err(e) = crate::telegram::send(item.reply(i18n::t(item.options.locale, "result.cancelled"))).await {
+                    error!("Failed to send cancel notice for item {}: {}", item.id, e);
+                }
+
+                {
+                    let mut stats_guard = stats.write().await;
+                    stats_guard.increment_cancelled().await;
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to process queue item {} (error ID {}): {:?}",
+                    item.id,
+                    crate::error_report::error_id(&item.id),
+                    e
+                );
+                let provider = match item.options.provider {
+                    Some(provider) => provider,
+                    None => *current_provider.read().await,
+                };
+                crate::error_report::report(
+                    &e,
+                    crate::error_report::FailureContext {
+                        item_id: item.id.clone(),
+                        provider: Some(provider.as_str()),
+                        chat_type: if item.chat_id.is_user() { "private" } else { "group" },
+                        file_size: item.media.size(),
+                        duration_secs: item.duration_secs,
+                    },
+                    &config,
+                );
+                crate::alerts::on_failure(&item.bot, &config, &e, Some(provider)).await;
+
+                let text = crate::error_report::user_reply(
+                    i18n::t(item.options.locale, error_key(&e)),
+                    &e,
+                    &item.id,
+                    item.options.locale,
+                );
+                // The file is kept so a transient failure can be retried without re-uploading
+                let retryable = crate::error_report::is_retryable(&e);
+                let mut request = item.reply(text);
+                if retryable {
+                    request = request.reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                        InlineKeyboardButton::callback(i18n::t(item.options.locale, "result.retry"), format!("retry:{}", item.id)),
+                    ]]));
+                }
+                if let Err(e) = crate::telegram::send(request).await {
+                    error!("Failed to send error message for item {}: {}", item.id, e);
+                }
+
+                // Update stats
+                {
+                    let mut stats_guard = stats.write().await;
+                    stats_guard.increment_failed().await;
+                }
+
+                if retryable {
+                    park_item(&parked_items, item).await;
+                }
+            }
+        }
+
+        persistence::remove_pending_item(&media_path).await;
+        queue.ack(&item_id).await;
+    }
+}
+
+/// Posts a transcript held for quiet hours once they're over, then drops
+/// its saved copy.
+async fn deliver_held(item: QueueItem, held: persistence::HeldDeliveryData, config: BotConfig, transcripts: TranscriptCache) {
+    let wait = (held.deliver_at - chrono::Utc::now()).to_std().unwrap_or_default();
+    tokio::time::sleep(wait).await;
+    deliver_transcript(&item, &held.transcription, held.provider, &held.response, &held.via, &config, held.media_secs).await;
+    persistence::remove_held_delivery(&config.queue_dir(), &item.id).await;
+    cache_transcript(&transcripts, item, held.transcription, held.provider).await;
+}
+
+/// Schedules the transcripts that were held for quiet hours when the bot
+/// last stopped.
+pub async fn resume_held(bot: &Bot, shared_config: &crate::SharedConfig, transcripts: &TranscriptCache) {
+    let config = shared_config.read().await.clone();
+    let queue_dir = config.queue_dir();
+    let held = match persistence::load_held_deliveries(&queue_dir).await {
+        Ok(held) => held,
+        Err(e) => {
+            error!("Failed to load held transcripts: {}", e);
+            return;
+        }
+    };
+
+    for (data, media_size) in held {
+        let item = QueueItem::from_pending(bot.clone(), data.item.clone(), media_size, &queue_dir);
+        info!("Holding the transcript of item {} until {}", item.id, data.deliver_at);
+        tokio::spawn(deliver_held(item, data, config.clone(), transcripts.clone()));
+    }
+}
+
+/// Posts a finished transcript with its buttons and any JSON or subtitle
+/// attachments the chat asked for.
+async fn deliver_transcript(
+    item: &QueueItem,
+    transcription: &crate::stt::Transcription,
+    provider: SttProvider,
+    response: &str,
+    via: &str,
+    config: &BotConfig,
+    media_secs: u64,
+) {
+    let keyboard = transcript_keyboard(&item.id, transcription, provider, config, item.options.locale);
+
+    // Past a few parts a file is easier to read than a wall of messages
+    let as_file = config.max_message_parts.is_some_and(|max| split_message(response).len() > max);
+    let sent = if as_file {
+        item.delete_status_message().await;
+        send_transcript_file(item, transcription, via, keyboard).await
+    } else if split_message(response).len() == 1 {
+        deliver_in_place(item, response, keyboard).await
+    } else {
+        item.delete_status_message().await;
+        send_item_message(item, response, keyboard).await
+    };
+    if let Err(e) = sent {
+        error!("Failed to send transcription for item {}: {}", item.id, e);
+    }
+
+    if item.options.json_attachment
+        && let Err(e) = send_json_attachment(item, transcription, provider, config, media_secs).await
+    {
+        error!("Failed to send JSON attachment for item {}: {}", item.id, e);
+    }
+
+    if item.options.subtitles {
+        send_subtitles(item, transcription).await;
+    }
+}
+
+/// What the request log records about processing `item`.
+fn request_log_entry(item: &QueueItem, result: &Result<ProcessedItem>, latency: Duration) -> request_logger::RequestLogEntry {
+    let outcome = match result {
+        Ok(processed) if processed.transcription.text.trim().is_empty() => "no_speech",
+        Ok(_) => "transcribed",
+        Err(BotError::SilentAudio) => "silent",
+        Err(BotError::MusicDetected) => "music",
+        Err(BotError::RepeatedClip) => "repeated",
+        Err(BotError::Cancelled) => "cancelled",
+        Err(BotError::QuotaExceeded(_)) => "quota_exceeded",
+        Err(BotError::BudgetExhausted) => "budget_exhausted",
+        Err(_) => "failed",
+    };
+    let mut entry = request_logger::RequestLogEntry::new(item, outcome, latency);
+    match result {
+        Ok(processed) => {
+            entry.provider = Some(processed.provider.as_str().to_string());
+            entry.duration_secs = Some(processed.media_secs);
+        }
+        Err(e) if outcome == "failed" => entry.error = Some(e.to_string()),
+        Err(_) => {}
+    }
+    entry
+}
+
+/// Message for the sender when processing an item failed with `e`.
+fn error_key(e: &BotError) -> &'static str {
+    match e {
+        BotError::Audio(crate::audio::AudioError::UnsupportedFormat(_)) => "error.unsupported_format",
+        BotError::Audio(crate::audio::AudioError::ConversionFailed(_) | crate::audio::AudioError::Timeout(_)) => {
+            "error.conversion_failed"
+        }
+        BotError::Stt(crate::stt::SttError::Timeout) => "error.stt_timeout",
+        BotError::Stt(_) => "error.stt_unavailable",
+        BotError::QuotaExceeded(_) => "error.quota_exceeded_late",
+        BotError::TooLong(..) => "error.too_long_late",
+        BotError::BudgetExhausted => "error.budget_exhausted_notified",
+        BotError::RepeatedClip => "result.repeated_clip",
+        _ => "error.generic",
+    }
+}
+
+/// Accounts for a file from an archive like any other item, but hands its
+/// result to the batch report instead of replying.
+#[allow(clippy::too_many_arguments)]
+async fn finish_batch_item(
+    item: &QueueItem,
+    slot: &crate::batch::Slot,
+    result: Result<ProcessedItem>,
+    config: &BotConfig,
+    current_provider: &CurrentProvider,
+    usage: &UsageStores,
+    settings_store: &crate::settings::ChatSettingsStore,
+    stats: &QueueStats,
+) {
+    use crate::batch::Outcome;
+
+    let outcome = match result {
+        Ok(ProcessedItem { mut transcription, provider, media_secs, billed_secs }) => {
+            info!("Successfully processed queue item {} via {}", item.id, provider.as_str());
+            crate::alerts::on_success();
+            crate::text::apply(&mut transcription, &config.text_formatting);
+            if item.options.profanity_filter {
+                crate::text::redact::mask_transcription(&mut transcription);
+            }
+
+            record_quota_usage(item, media_secs, &usage.quotas).await;
+            record_cost(item, provider, billed_secs, config, &usage.costs, settings_store).await;
+            record_history(item, &transcription, provider, media_secs, config, &usage.history).await;
+            stats.write().await.increment_processed().await;
+
+            if transcription.text.trim().is_empty() {
+                Outcome::NoSpeech
+            } else {
+                Outcome::Transcript(transcription.text)
+            }
+        }
+        Err(BotError::SilentAudio | BotError::MusicDetected) => {
+            stats.write().await.increment_skipped().await;
+            Outcome::NoSpeech
+        }
+        Err(BotError::Cancelled) => {
+            stats.write().await.increment_cancelled().await;
+            Outcome::Failed(i18n::t(item.options.locale, "result.cancelled").to_string())
+        }
+        Err(e) => {
+            error!(
+                "Failed to process queue item {} (error ID {}): {:?}",
+                item.id,
+                crate::error_report::error_id(&item.id),
+                e
+            );
+            let provider = match item.options.provider {
+                Some(provider) => provider,
+                None => *current_provider.read().await,
+            };
+            crate::error_report::report(
+                &e,
+                crate::error_report::FailureContext {
+                    item_id: item.id.clone(),
+                    provider: Some(provider.as_str()),
+                    chat_type: if item.chat_id.is_user() { "private" } else { "group" },
+                    file_size: item.media.size(),
+                    duration_secs: item.duration_secs,
+                },
+                config,
+            );
+            crate::alerts::on_failure(&item.bot, config, &e, Some(provider)).await;
+            stats.write().await.increment_failed().await;
+            let locale = item.options.locale;
+            Outcome::Failed(crate::error_report::user_reply(i18n::t(locale, error_key(&e)), &e, &item.id, locale))
+        }
+    };
+    slot.finish(&item.bot, outcome).await;
+}
+
+/// Keeps the transcript for the sender's /history.
+async fn record_history(
+    item: &QueueItem,
+    transcription: &crate::stt::Transcription,
+    provider: SttProvider,
+    media_secs: u64,
+    config: &BotConfig,
+    history_store: &history::HistoryStore,
+) {
+    if config.history_max_entries == 0 || transcription.text.trim().is_empty() {
+        return;
+    }
+
+    let entry = history::HistoryEntry {
+        at: chrono::Utc::now(),
+        duration_secs: media_secs,
+        filename: item.original_filename.clone(),
+        provider: provider.as_str().to_string(),
+        text: transcription.text.clone(),
+    };
+    let mut history = history_store.write().await;
+    history.prune(entry.at, config.history_retention());
+    history.record(item.user_id, entry, config.history_max_entries);
+
+    if let Err(e) = persistence::save_history(&history).await {
+        error!("Failed to save history: {}", e);
+    }
+}
+
+async fn record_quota_usage(item: &QueueItem, media_secs: u64, quota_store: &quota::QuotaStore) {
+    let mut quotas = quota_store.write().await;
+    quotas.roll_month(&quota::current_month());
+    quotas.record_usage(item.user_id, item.username.as_deref(), media_secs);
+
+    if let Err(e) = persistence::save_quotas(&quotas).await {
+        error!("Failed to save quota usage for item {}: {}", item.id, e);
+    }
+}
+
+async fn record_cost(
+    item: &QueueItem,
+    provider: SttProvider,
+    billed_secs: u64,
+    config: &BotConfig,
+    cost_store: &crate::cost::CostStore,
+    settings_store: &crate::settings::ChatSettingsStore,
+) {
+    let crossed_cap = {
+        let mut costs = cost_store.write().await;
+        costs.roll_month(&quota::current_month());
+        let was_over = costs.over_budget(provider, config);
+        let estimate = costs.record(provider, billed_secs, config);
+        costs.charge_user(item.user_id, estimate);
+        info!("Estimated cost for item {}: ${:.4} via {}", item.id, estimate, provider.as_str());
+
+        if let Err(e) = persistence::save_costs(&costs).await {
+            error!("Failed to save cost tracking for item {}: {}", item.id, e);
+        }
+        !was_over && costs.over_budget(provider, config)
+    };
+
+    if crossed_cap {
+        let fallback = cost_store.read().await.choose_provider(provider, config);
+        alert_budget_exceeded(&item.bot, config, cost_store, settings_store, provider, fallback).await;
+    }
+}
+
+/// Tells every admin and `ADMIN_CHAT_ID` that a provider hit its monthly
+/// cap, or that `MONTHLY_BUDGET_USD` is spent, once each per month. Each
+/// chat gets it in its /botlanguage.
+async fn alert_budget_exceeded(
+    bot: &Bot,
+    config: &BotConfig,
+    cost_store: &crate::cost::CostStore,
+    settings_store: &crate::settings::ChatSettingsStore,
+    provider: SttProvider,
+    fallback: Option<SttProvider>,
+) {
+    let (key, mut args) = {
+        let mut costs = cost_store.write().await;
+        // Past MONTHLY_BUDGET_USD every paid provider is over, so that's
+        // reported once rather than per provider
+        let total = !costs.over_provider_budget(provider, config) && costs.over_total_budget(config);
+        let first = if total { costs.mark_total_alerted() } else { costs.mark_alerted(provider) };
+        if !first {
+            return;
+        }
+        if let Err(e) = persistence::save_costs(&costs).await {
+            error!("Failed to save budget alert state: {}", e);
+        }
+
+        if total {
+            let spent = format!("{:.2}", costs.total_spend());
+            let budget = format!("{:.2}", config.monthly_budget_usd.unwrap_or_default());
+            ("budget.total", vec![("spent", spent), ("budget", budget)])
+        } else {
+            let cap = config.provider_budgets.get(&provider).copied().unwrap_or_default();
+            let args = vec![
+                ("provider", provider.as_str().to_string()),
+                ("spent", format!("{:.2}", costs.spend(provider))),
+                ("budget", format!("{:.2}", cap)),
+            ];
+            ("budget.provider", args)
+        }
+    };
+    let action = match fallback {
+        Some(p) if p != provider => "budget.fallback",
+        Some(_) => "budget.continuing",
+        None => "budget.paused",
+    };
+    args.push(("fallback", fallback.map(|p| p.as_str().to_string()).unwrap_or_default()));
+    let text = |locale: Locale| format!("{} {}", i18n::tf(locale, key, &args), i18n::tf(locale, action, &args));
+
+    warn!("{}", text(Locale::En));
+    let admin_chats = config.admin_user_ids.iter().map(|admin| ChatId(admin.0 as i64)).chain(config.admin_chat_id);
+    for chat_id in admin_chats {
+        let locale = crate::settings::get_for(settings_store, chat_id, None).await.locale.unwrap_or_default();
+        if let Err(e) = bot.send_message(chat_id, text(locale)).await {
+            error!("Failed to send budget alert to admin chat {}: {}", chat_id, e);
+        }
+    }
+}
+
+/// Live transcript edits are at least this far apart, to stay under
+/// Telegram's edit limits.
+const LIVE_EDIT_INTERVAL: Duration = Duration::from_secs(3);
+/// How much of the transcript so far a live edit shows, from its end.
+const LIVE_PREVIEW_CHARS: usize = 3000;
+
+/// The processing message of media transcribed segment by segment: how far
+/// along it is and the end of the text so far.
+fn live_transcript_text(locale: Locale, original_filename: &str, percent: u64, parts: &[crate::stt::Transcription]) -> String {
+    let text = parts.iter().map(|part| part.text.trim()).filter(|text| !text.is_empty()).collect::<Vec<_>>().join(" ");
+    let chars = text.chars().count();
+    let preview = if chars > LIVE_PREVIEW_CHARS {
+        format!("…{}", text.chars().skip(chars - LIVE_PREVIEW_CHARS).collect::<String>())
+    } else {
+        text
+    };
+    i18n::tf(
+        locale,
+        "queue.live",
+        &[("percent", percent.to_string()), ("file", original_filename.to_string()), ("text", preview)],
+    )
+}
+
+/// Outcome of a successful transcription.
+struct ProcessedItem {
+    transcription: crate::stt::Transcription,
+    provider: SttProvider,
+    /// Length of the transcribed media, counted against the user's quota.
+    media_secs: u64,
+    /// Audio seconds sent to the provider; more than the media length when
+    /// channels are transcribed separately.
+    billed_secs: u64,
+}
+
+/// Splits media that is too long for `provider` at pauses and transcribes
+/// up to `CHUNK_CONCURRENCY` chunks at once, joining them in order.
+async fn transcribe_chunked(
+    item: &QueueItem,
+    config: &BotConfig,
+    mut chain: Vec<SttProvider>,
+    provider: SttProvider,
+    conversion: crate::audio::ConversionOptions,
+    media_secs: u64,
+    max_secs: u32,
+) -> Result<ProcessedItem> {
+    use crate::{audio, stt};
+
+    let silences = audio::chunk::detect_silences(item.media.path(), conversion.time_range, conversion.timeout)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Silence detection failed for item {}, cutting at fixed lengths: {}", item.id, e);
+            Vec::new()
+        });
+    let cuts = audio::chunk::plan_cuts(&silences, media_secs as f32, max_secs);
+    info!("Transcribing item {} in {} chunks of up to {}s", item.id, cuts.len() + 1, max_secs);
+
+    let mut chunks = audio::segment::start_segmented_extraction(
+        item.media.path(),
+        &item.original_filename,
+        provider,
+        conversion,
+        audio::segment::SegmentPlan::At(cuts.clone()),
+    )?;
+
+    chain.retain(|&p| audio::same_output_format(provider, p, &conversion));
+    let options = item.options;
+    let limit = Arc::new(tokio::sync::Semaphore::new(config.chunk_concurrency));
+    let mut tasks = tokio::task::JoinSet::new();
+
+    let mut index: usize = 0;
+    while let Some(chunk) = chunks.next_segment().await? {
+        let permit = limit.clone().acquire_owned().await.expect("semaphore is never closed");
+        let (config, chain) = (config.clone(), chain.clone());
+        tasks.spawn(async move {
+            let _permit = permit;
+            let silent = audio::analyze::samples_from_converted(&chunk)
+                .map(|samples| audio::analyze::compute_stats(&samples, chunk.sample_rate))
+                .is_some_and(|stats| is_silent(&stats, &config));
+            let result = if silent {
+                Ok((stt::Transcription::default(), None))
+            } else {
+                stt::transcribe_with_failover(&chain, &config, &options.stt_options(&config), |_| async {
+                    Ok::<_, stt::SttError>(chunk.clone())
+                })
+                .await
+                .map(|(transcription, used)| (transcription, Some(used)))
+            };
+            (index, result)
+        });
+        index += 1;
+    }
+
+    let mut parts = Vec::new();
+    let mut used_counts: HashMap<SttProvider, usize> = HashMap::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (index, result) = joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        let (transcription, used) = result?;
+        if let Some(used) = used {
+            *used_counts.entry(used).or_default() += 1;
+        }
+        // The first chunk starts at 0, every other one at the cut before it
+        let offset = index.checked_sub(1).map_or(0.0, |i| cuts.get(i).copied().unwrap_or_default());
+        parts.push((index, offset, transcription));
+    }
+    parts.sort_by_key(|(index, _, _)| *index);
+
+    // Bill the provider that handled most chunks
+    let used = used_counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map_or(provider, |(used, _)| used);
+
+    Ok(ProcessedItem {
+        transcription: stt::Transcription::concat_at(
+            parts.into_iter().map(|(_, offset, transcription)| (offset, transcription)).collect(),
+        ),
+        provider: used,
+        media_secs,
+        billed_secs: media_secs,
+    })
+}
+
+/// `provider` followed by the configured fallbacks that have credentials
+/// and budget left.
+/// Providers whose circuit breaker is open go last, and when none of the
+/// configured ones is healthy any other provider with credentials stands in.
+fn failover_chain(provider: SttProvider, config: &BotConfig, costs: &crate::cost::CostData) -> Vec<SttProvider> {
+    use crate::stt::health;
+
+    let usable = |p: SttProvider| config.has_provider_key(p) && !costs.over_budget(p, config);
+    let mut chain: Vec<_> = std::iter::once(provider)
+        .chain(config.stt_fallbacks.iter().copied().filter(|&p| p != provider && usable(p)))
+        .collect();
+
+    if !chain.iter().copied().any(health::is_available) {
+        let standins: Vec<_> = SttProvider::ALL
+            .into_iter()
+            .filter(|&p| !chain.contains(&p) && usable(p) && health::is_available(p))
+            .collect();
+        chain.extend(standins);
+    }
+    chain.sort_by_key(|&p| !health::is_available(p));
+    if chain[0] != provider {
+        info!("Provider {} is out of rotation, routing to {}", provider.as_str(), chain[0].as_str());
+    }
+    chain
+}
+
+/// Once a provider has failed over, later pieces of the same item start
+/// with the one that worked.
+fn stick_to(chain: &mut Vec<SttProvider>, used: SttProvider) {
+    if let Some(pos) = chain.iter().position(|&p| p == used) {
+        chain.drain(..pos);
+    }
+}
+
+/// Length of the clip sent for language detection.
+const LANGUAGE_DETECTION_SECS: u32 = 30;
+
+/// Language for a provider that needs to be told it, found by sending the
+/// first seconds of the item to a configured provider that detects it. None
+/// when it isn't needed, is already set or couldn't be detected.
+async fn detect_language(
+    item: &QueueItem,
+    provider: SttProvider,
+    config: &BotConfig,
+    usage: &UsageStores,
+    settings_store: &crate::settings::ChatSettingsStore,
+    conversion: crate::audio::ConversionOptions,
+) -> Option<&'static str> {
+    use crate::{audio, stt};
+
+    if item.options.language.is_some() || !provider.needs_language() {
+        return None;
+    }
+    let detector = {
+        let costs = usage.costs.read().await;
+        SttProvider::ALL
+            .into_iter()
+            .find(|&p| p.detects_language() && config.has_provider_key(p) && !costs.over_budget(p, config))
+    };
+    let Some(detector) = detector else {
+        info!("No provider available to detect the language of item {}, {} will assume its default", item.id, provider.as_str());
+        return None;
+    };
+
+    let start = conversion.time_range.map_or(0, |range| range.start_secs);
+    let end = conversion.time_range.map_or(u32::MAX, |range| range.end_secs).min(start + LANGUAGE_DETECTION_SECS);
+    let clip = audio::ConversionOptions {
+        time_range: Some(audio::TimeRange { start_secs: start, end_secs: end }),
+        channel: None,
+        ..conversion
+    };
+    let result = match audio::convert_for_stt(item.media.path(), &item.original_filename, detector, clip).await {
+        Ok(audio) => stt::transcribe(&audio, detector, config, &stt::SttOptions::from_config(config)).await,
+        Err(e) => {
+            warn!("Failed to prepare the language detection clip for item {}: {}", item.id, e);
+            return None;
+        }
+    };
+
+    // Billed like any other call: saved, and alerting if it crosses a cap
+    let billed_secs = (end - start).min(item.duration_secs.max(1)) as u64;
+    record_cost(item, detector, billed_secs, config, &usage.costs, settings_store).await;
+
+    let language = match result {
+        Ok(transcription) => transcription.language.as_deref().and_then(stt::language::code),
+        Err(e) => {
+            warn!("Language detection for item {} via {} failed: {}", item.id, detector.as_str(), e);
+            None
+        }
+    };
+    info!("Detected language {:?} for item {} via {}", language, item.id, detector.as_str());
+    language
+}
+
+#[tracing::instrument(name = "item", skip_all, fields(item_id = %item.id))]
+async fn process_audio_item(
+    item: &QueueItem,
+    config: &BotConfig,
+    current_provider: &CurrentProvider,
+    usage: &UsageStores,
+    settings_store: &crate::settings::ChatSettingsStore,
+) -> Result<ProcessedItem> {
+    use crate::{audio, stt};
+
+    let cost_store = &usage.costs;
+
+    // A chat's choice may have lost its key in a restart since
+    let preferred = match item.options.provider.filter(|&p| config.has_provider_key(p)) {
+        Some(provider) => provider,
+        None => *current_provider.read().await,
+    };
+
+    // Respect monthly budget caps, falling back to another provider if needed
+    let chosen = {
+        let mut costs = cost_store.write().await;
+        costs.roll_month(&quota::current_month());
+        costs.choose_provider(preferred, config)
+    };
+    if chosen != Some(preferred) {
+        alert_budget_exceeded(&item.bot, config, cost_store, settings_store, preferred, chosen).await;
+    }
+    let provider = chosen.ok_or(BotError::BudgetExhausted)?;
+    let mut chain = failover_chain(provider, config, &*cost_store.read().await);
+    let provider = chain[0];
+
+    // Probe the source once: music detection, automatic gain, call
+    // splitting and repeated-clip throttling all need it
+    let wants_music_check = config.music_detection && !item.options.skip_music_check;
+    let wants_spam_check = config.spam_repeat_limit.is_some() && !config.admin_user_ids.contains(&item.user_id);
+    let mut source_stats = None;
+    let mut split_channels = false;
+    // Documents and links come without a duration, so they're always
+    // decoded to check their length against the limits
+    let wants_duration_check = item.duration_secs == 0;
+    if wants_music_check
+        || wants_spam_check
+        || wants_duration_check
+        || config.auto_gain_threshold_db.is_some()
+        || config.channel_split
+    {
+        match probe_source(item, config).await {
+            Ok((stats, separate_speakers)) => {
+                source_stats = Some(stats);
+                split_channels = separate_speakers;
+            }
+            Err(e) => warn!("Content analysis failed for item {}, transcribing anyway: {}", item.id, e),
+        }
+    }
+
+    // Telegram's duration comes from the sending client; trust the decoded
+    // length when the two disagree
+    let mut media_secs = item.duration_secs as u64;
+    if let Some(stats) = &source_stats {
+        let (secs, mismatch) = reconcile_duration(item.duration_secs, stats.duration_secs);
+        if mismatch {
+            warn!(
+                "Duration mismatch for item {} from {}: claimed {}s, decoded {}s",
+                item.id, item.user_info, item.duration_secs, secs
+            );
+        }
+        media_secs = secs as u64;
+        recheck_duration(item, secs, config, &mut *usage.quotas.write().await)?;
+    }
+
+    // The same clip sent over and over is only transcribed a few times
+    if wants_spam_check
+        && let Some(stats) = &source_stats
+        && !usage.spam.write().await.admit(stats.fingerprint, item, config, chrono::Utc::now())
+    {
+        warn!("Refusing item {} from {}: clip {:016x} was transcribed too often recently", item.id, item.user_info, stats.fingerprint);
+        return Err(BotError::RepeatedClip);
+    }
+
+    // Don't pay to transcribe a forwarded song
+    if wants_music_check
+        && let Some(stats) = &source_stats
+        && audio::analyze::classify(stats) == audio::analyze::ContentClass::Music
+    {
+        return Err(BotError::MusicDetected);
+    }
+
+    // Whisper-quiet recordings come back as empty transcripts unless boosted
+    let auto_gain = match (config.auto_gain_threshold_db, &source_stats) {
+        (Some(threshold), Some(stats)) => {
+            stats.rms_db < threshold && !is_silent(stats, config)
+        }
+        _ => false,
+    };
+    if auto_gain {
+        info!("Applying automatic gain to quiet item {}", item.id);
+    }
+
+    let conversion = audio::ConversionOptions {
+        time_range: item.options.time_range,
+        telephone: item.options.phone_call,
+        auto_gain,
+        channel: None,
+        timeout: config.ffmpeg_timeout(),
+        preprocess: item.options.preprocess.unwrap_or(config.audio_preprocess),
+    };
+
+    // Providers that default to English get the language from a short
+    // detection pass unless the chat set one
+    let with_language;
+    let item = match detect_language(item, provider, config, usage, settings_store, conversion).await {
+        Some(language) => {
+            with_language = QueueItem {
+                options: ProcessingOptions { language: Some(language), ..item.options },
+                ..item.clone()
+            };
+            &with_language
+        }
+        None => item,
+    };
+
+    // Call recordings: transcribe each party's channel on its own and
+    // stitch the turns back together
+    if split_channels {
+        info!("Item {} has one speaker per channel, transcribing channels separately", item.id);
+        let stt_options = stt::SttOptions {
+            word_timestamps: true,
+            ..item.options.stt_options(config)
+        };
+
+        let mut channels = Vec::new();
+        for channel in 0..stt::channels::CHANNEL_LABELS.len() as u8 {
+            let channel_conversion = audio::ConversionOptions { channel: Some(channel), ..conversion };
+            let (transcription, used) = stt::transcribe_with_failover(&chain, config, &stt_options, |p| async move {
+                Ok::<_, BotError>(
+                    audio::convert_for_stt(item.media.path(), &item.original_filename, p, channel_conversion).await?,
+                )
+            }).await?;
+            stick_to(&mut chain, used);
+            channels.push(transcription);
+        }
+
+        return Ok(ProcessedItem {
+            transcription: stt::Transcription {
+                language: channels.iter().find_map(|c| c.language.clone()),
+                words: stt::channels::merge_words(&channels),
+                segments: stt::channels::merge_segments(&channels),
+                ..stt::Transcription::from_text(stt::channels::interleave(&channels))
+            },
+            provider: chain[0],
+            media_secs,
+            billed_secs: media_secs * channels.len() as u64,
+        });
+    }
+
+    // Segmented transcription of long media: start on the early segments
+    // while ffmpeg is still extracting the later ones
+    if config.segmented_min_secs.is_some_and(|min| item.duration_secs >= min) {
+        if source_stats.is_some_and(|stats| is_silent(&stats, config)) {
+            return Err(BotError::SilentAudio);
+        }
+
+        let segment_secs = provider
+            .max_chunk_secs()
+            .map_or(config.segment_secs, |max| config.segment_secs.min(max));
+        let mut segments = audio::segment::start_segmented_extraction(
+            item.media.path(),
+            &item.original_filename,
+            provider,
+            conversion,
+            audio::segment::SegmentPlan::Every(segment_secs),
+        )?;
+
+        // Segments are already encoded for `provider`; only fail over to
+        // providers that take the same format
+        chain.retain(|&p| audio::same_output_format(provider, p, &conversion));
+
+        let stt_options = item.options.stt_options(config);
+        let mut parts = Vec::new();
+        let mut last_edit: Option<Instant> = None;
+        while let Some(segment) = segments.next_segment().await? {
+            let silent = audio::analyze::samples_from_converted(&segment)
+                .map(|samples| audio::analyze::compute_stats(&samples, segment.sample_rate))
+                .is_some_and(|stats| is_silent(&stats, config));
+            if silent {
+                info!("Skipping silent segment {} of item {}", parts.len() + 1, item.id);
+                parts.push(stt::Transcription::default());
+                continue;
+            }
+            let (transcription, used) = stt::transcribe_with_failover(&chain, config, &stt_options, |_| async {
+                Ok::<_, BotError>(segment.clone())
+            }).await?;
+            stick_to(&mut chain, used);
+            parts.push(transcription);
+
+            if config.live_transcript
+                && let Some(message_id) = item.message_id
+                && last_edit.is_none_or(|at| at.elapsed() >= LIVE_EDIT_INTERVAL)
+            {
+                let done = parts.len() as u64 * segment_secs as u64;
+                let percent = (done * 100 / media_secs.max(1)).min(99);
+                let text = live_transcript_text(item.options.locale, &item.original_filename, percent, &parts);
+                if let Err(e) = item
+                    .bot
+                    .edit_message_text(item.chat_id, message_id, text)
+                    .reply_markup(cancel_keyboard(&item.id, item.options.locale))
+                    .await
+                {
+                    warn!("Failed to show the transcript so far of item {}: {}", item.id, e);
+                }
+                last_edit = Some(Instant::now());
+            }
+        }
+        info!("Transcribed item {} in {} segments", item.id, parts.len());
+
+        return Ok(ProcessedItem {
+            transcription: stt::Transcription::concat(parts, segment_secs as f32),
+            provider: chain[0],
+            media_secs,
+            billed_secs: media_secs,
+        });
+    }
+
+    // Longer than the provider takes in one request: cut at pauses and
+    // transcribe the chunks in parallel
+    if let Some(max_secs) = provider.max_chunk_secs()
+        && media_secs > max_secs as u64
+    {
+        if source_stats.is_some_and(|stats| is_silent(&stats, config)) {
+            return Err(BotError::SilentAudio);
+        }
+        return transcribe_chunked(item, config, chain, provider, conversion, media_secs, max_secs).await;
+    }
+
+    // Convert audio to the format required by the STT provider
+    let converted_audio = audio::convert_for_stt(
+        item.media.path(),
+        &item.original_filename,
+        provider,
+        conversion,
+    ).await?;
+
+    // Skip the paid API call for pocket recordings and other silent clips.
+    // Boosted audio is judged by its original level so amplified noise
+    // doesn't pass as speech.
+    let stats = if auto_gain {
+        source_stats
+    } else {
+        audio::analyze::samples_from_converted(&converted_audio)
+            .map(|samples| audio::analyze::compute_stats(&samples, converted_audio.sample_rate))
+            .or(source_stats)
+    };
+    if stats.is_some_and(|stats| is_silent(&stats, config)) {
+        return Err(BotError::SilentAudio);
+    }
+
+    if let Some(pair) = consensus_pair(item, config, &*cost_store.read().await) {
+        return transcribe_consensus(item, config, usage, settings_store, pair, provider, &converted_audio, conversion, media_secs).await;
+    }
+
+    // Transcribe using the current provider, failing over along the chain
+    let (transcription, used) = stt::transcribe_with_failover(
+        &chain,
+        config,
+        &item.options.stt_options(config),
+        |p| {
+            let converted_audio = &converted_audio;
+            async move {
+                if audio::same_output_format(provider, p, &conversion) {
+                    return Ok::<_, BotError>(converted_audio.clone());
+                }
+                Ok(audio::convert_for_stt(item.media.path(), &item.original_filename, p, conversion).await?)
+            }
+        },
+    ).await?;
+
+    Ok(ProcessedItem {
+        transcription,
+        provider: used,
+        media_secs,
+        billed_secs: media_secs,
+    })
+}
+
+/// Both `CONSENSUS_PROVIDERS`, unless the item asked for a particular
+/// provider (a re-run) or one of them is over its budget.
+fn consensus_pair(item: &QueueItem, config: &BotConfig, costs: &crate::cost::CostData) -> Option<[SttProvider; 2]> {
+    let pair = config.consensus_providers?;
+    if item.options.provider.is_some()
+        || pair.iter().any(|&p| costs.over_budget(p, config) || !crate::stt::health::is_available(p))
+    {
+        return None;
+    }
+    Some(pair)
+}
+
+/// Transcribes with both consensus providers at once and combines the
+/// results. If one fails the other's result is used as is. The provider the
+/// result is based on is reported and billed by the caller; the other one
+/// is billed here.
+#[allow(clippy::too_many_arguments)]
+async fn transcribe_consensus(
+    item: &QueueItem,
+    config: &BotConfig,
+    usage: &UsageStores,
+    settings_store: &crate::settings::ChatSettingsStore,
+    pair: [SttProvider; 2],
+    converted_for: SttProvider,
+    converted_audio: &crate::audio::ConvertedAudio,
+    conversion: crate::audio::ConversionOptions,
+    media_secs: u64,
+) -> Result<ProcessedItem> {
+    use crate::{audio, stt};
+
+    let stt_options = item.options.stt_options(config);
+    let run = |p: SttProvider| {
+        let stt_options = &stt_options;
+        async move {
+            let converted = if audio::same_output_format(converted_for, p, &conversion) {
+                converted_audio.clone()
+            } else {
+                audio::convert_for_stt(item.media.path(), &item.original_filename, p, conversion).await?
+            };
+            Ok::<_, BotError>(stt::transcribe(&converted, p, config, stt_options).await?)
+        }
+    };
+    let (first, second) = tokio::join!(run(pair[0]), run(pair[1]));
+
+    let (transcription, provider) = match (first, second) {
+        (Ok(first), Ok(second)) => {
+            let (combined, second_is_base) = stt::consensus::combine(first, second, config.consensus_strategy);
+            let (base, other) = if second_is_base { (pair[1], pair[0]) } else { (pair[0], pair[1]) };
+            info!("Consensus for item {}: based on {}, checked against {}", item.id, base.as_str(), other.as_str());
+            record_cost(item, other, media_secs, config, &usage.costs, settings_store).await;
+            (combined, base)
+        }
+        (Ok(transcription), Err(e)) => {
+            warn!("Consensus provider {} failed for item {}, using {} alone: {}", pair[1].as_str(), item.id, pair[0].as_str(), e);
+            (transcription, pair[0])
+        }
+        (Err(e), Ok(transcription)) => {
+            warn!("Consensus provider {} failed for item {}, using {} alone: {}", pair[0].as_str(), item.id, pair[1].as_str(), e);
+            (transcription, pair[1])
+        }
+        (Err(e), Err(_)) => return Err(e),
+    };
+
+    Ok(ProcessedItem { transcription, provider, media_secs, billed_secs: media_secs })
+}
+
+/// Claimed and decoded durations further apart than this (and more than
+/// half the claim) point at a corrupt or doctored file.
+const DURATION_MISMATCH_MIN_SECS: u32 = 10;
+
+/// Returns the duration to account for and whether the claimed duration
+/// was wildly off. Small differences keep the claim, which the user already
+/// saw checked against their quota; a missing claim (0) takes the decoded one.
+fn reconcile_duration(claimed_secs: u32, decoded_secs: f32) -> (u32, bool) {
+    let decoded = decoded_secs.round() as u32;
+    if claimed_secs == 0 {
+        return (decoded, false);
+    }
+
+    let diff = claimed_secs.abs_diff(decoded);
+    if diff > DURATION_MISMATCH_MIN_SECS && diff * 2 > claimed_secs {
+        (decoded, true)
+    } else {
+        (claimed_secs, false)
+    }
+}
+
+/// Redoes the pre-download length and quota checks when the decoded length
+/// is longer than the one they passed with; a claim of 0 passed with none.
+fn recheck_duration(item: &QueueItem, decoded_secs: u32, config: &BotConfig, quotas: &mut quota::QuotaData) -> Result<()> {
+    if decoded_secs <= item.duration_secs {
+        return Ok(());
+    }
+    if let Some(max_secs) = config.max_duration_secs
+        && decoded_secs > max_secs
+    {
+        return Err(BotError::TooLong(decoded_secs, max_secs));
+    }
+    if config.admin_user_ids.contains(&item.user_id) {
+        return Ok(());
+    }
+    quotas.roll_month(&quota::current_month());
+    match quotas.remaining_seconds(item.user_id, config.quota_minutes_per_month) {
+        Some(remaining) if remaining < decoded_secs as u64 => Err(BotError::QuotaExceeded(remaining)),
+        _ => Ok(()),
+    }
+}
+
+/// Whether to skip the provider call for audio with these stats.
+fn is_silent(stats: &crate::audio::analyze::AudioStats, config: &BotConfig) -> bool {
+    config
+        .silence_threshold_db
+        .is_some_and(|threshold| crate::audio::analyze::is_effectively_silent(stats, threshold))
+}
+
+/// Decodes the source for analysis. Returns its overall stats and whether
+/// its channels carry separate speakers.
+async fn probe_source(item: &QueueItem, config: &BotConfig) -> Result<(crate::audio::analyze::AudioStats, bool)> {
+    use crate::audio::analyze::{self, ANALYSIS_SAMPLE_RATE};
+
+    let time_range = item.options.time_range;
+    if !config.channel_split {
+        let samples = analyze::decode_for_analysis(item.media.path(), time_range, config.ffmpeg_timeout()).await?;
+        return Ok((analyze::compute_stats(&samples, ANALYSIS_SAMPLE_RATE), false));
+    }
+
+    let (left, right) =
+        analyze::decode_stereo_for_analysis(item.media.path(), time_range, config.ffmpeg_timeout()).await?;
+    let separate_speakers = analyze::has_separate_speakers(&left, &right, ANALYSIS_SAMPLE_RATE);
+    let mono = analyze::downmix(&left, &right);
+    Ok((analyze::compute_stats(&mono, ANALYSIS_SAMPLE_RATE), separate_speakers))
+}
+
+/// One-line summary such as `ℹ️ en · 2:41 · deepgram · 312 words`.
+fn metadata_line(transcription: &crate::stt::Transcription, provider: SttProvider, media_secs: u64, locale: Locale) -> String {
+    let words = transcription.text.split_whitespace().count();
+    let words_key = if words == 1 { "transcript.words_one" } else { "transcript.words" };
+    let mut line = format!(
+        "ℹ️ {} · {} · {} · {}",
+        transcription.language.as_deref().unwrap_or(i18n::t(locale, "transcript.unknown_language")).to_lowercase(),
+        format_duration(media_secs),
+        provider.as_str(),
+        i18n::tf(locale, words_key, &[("count", words.to_string())])
+    );
+    if let Some(confidence) = transcription.confidence() {
+        let percent = format!("{:.0}", confidence * 100.0);
+        line.push_str(" · ");
+        line.push_str(&i18n::tf(locale, "transcript.confidence", &[("percent", percent)]));
+    }
+    line
+}
+
+/// Shown under transcripts the provider scored below
+/// `CONFIDENCE_WARNING_THRESHOLD` overall.
+fn low_confidence_warning(transcription: &crate::stt::Transcription, config: &BotConfig, locale: Locale) -> Option<String> {
+    let threshold = config.confidence_warning_threshold?;
+    let confidence = transcription.confidence().filter(|&c| c < threshold)?;
+    Some(i18n::tf(locale, "transcript.low_confidence", &[("percent", format!("{:.0}", confidence * 100.0))]))
+}
+
+/// Machine-readable transcript sent as a `.json` attachment.
+#[derive(serde::Serialize)]
+struct TranscriptExport<'a> {
+    provider: &'static str,
+    model: &'a str,
+    duration_secs: u64,
+    #[serde(flatten)]
+    transcription: &'a crate::stt::Transcription,
+}
+
+async fn send_json_attachment(
+    item: &QueueItem,
+    transcription: &crate::stt::Transcription,
+    provider: SttProvider,
+    config: &BotConfig,
+    media_secs: u64,
+) -> Result<()> {
+    let export = TranscriptExport {
+        provider: provider.as_str(),
+        model: provider.model_for(&item.options.stt_options(config)),
+        duration_secs: media_secs,
+        transcription,
+    };
+    let json = serde_json::to_vec_pretty(&export)
+        .map_err(|e| BotError::Config(format!("Failed to serialize transcript: {}", e)))?;
+
+    send_document(item, json, format!("transcript-{}.json", item.id)).await
+}
+
+/// Attaches the transcript as `.srt` and `.vtt` when the provider returned
+/// segment timing.
+async fn send_subtitles(item: &QueueItem, transcription: &crate::stt::Transcription) {
+    use crate::subtitles::{self, SubtitleFormat};
+
+    for format in SubtitleFormat::ALL {
+        let Some(text) = subtitles::render(transcription, format) else {
+            info!("No segment timing for item {}, skipping subtitles", item.id);
+            return;
+        };
+        let name = format!("transcript-{}.{}", item.id, format.extension());
+        if let Err(e) = send_document(item, text.into_bytes(), name).await {
+            error!("Failed to send {} subtitles for item {}: {}", format.extension(), item.id, e);
+        }
+    }
+}
+
+/// The `DEBUG_AUDIO` report: what the file was converted to for
+/// `provider`, or why it couldn't be.
+fn audio_debug_text(original_filename: &str, provider: SttProvider, debug: &crate::audio::DebugConversion) -> String {
+    let mut text = format!(
+        "🔬 Audio debug: {}\nProvider: {}\nConverter: {}, {:.1}s",
+        original_filename,
+        provider.as_str(),
+        debug.converter,
+        debug.elapsed.as_secs_f64()
+    );
+    match &debug.result {
+        Ok(converted) => {
+            text.push_str(&format!(
+                "\nOutput: {}, {}, {} Hz, {} ch, {} bytes",
+                converted.format,
+                debug.codec,
+                converted.sample_rate,
+                converted.channels,
+                converted.data.len()
+            ));
+            if let Some(samples) = crate::audio::analyze::samples_from_converted(converted) {
+                text.push_str(&format!(", {:.1}s", samples.len() as f64 / converted.sample_rate as f64));
+            }
+        }
+        Err(e) => text.push_str(&format!("\n❌ {}", e)),
+    }
+    if !debug.log.is_empty() {
+        text.push_str("\nffmpeg's log is attached.");
+    }
+    text
+}
+
+/// Converts the item as it would be for transcription and sends the
+/// report, the converted audio and ffmpeg's log. Content analysis is
+/// skipped, so automatic gain isn't applied.
+async fn send_audio_debug(item: &QueueItem, config: &BotConfig, current_provider: &CurrentProvider) {
+    let provider = match item.options.provider.filter(|&p| config.has_provider_key(p)) {
+        Some(provider) => provider,
+        None => *current_provider.read().await,
+    };
+    let conversion = crate::audio::ConversionOptions {
+        time_range: item.options.time_range,
+        telephone: item.options.phone_call,
+        timeout: config.ffmpeg_timeout(),
+        preprocess: item.options.preprocess.unwrap_or(config.audio_preprocess),
+        ..Default::default()
+    };
+    let debug = crate::audio::convert_for_debug(item.media.path(), &item.original_filename, provider, conversion)
+        .instrument(crate::logging::item_span(&item.id))
+        .await;
+
+    if let Err(e) = crate::telegram::send(item.reply(audio_debug_text(&item.original_filename, provider, &debug))).await {
+        error!("Failed to send audio debug report for item {}: {}", item.id, e);
+    }
+    if let Ok(converted) = &debug.result {
+        // Raw PCM gets a WAV header so it plays
+        let (data, extension) = match crate::audio::analyze::samples_from_converted(converted) {
+            Some(samples) if converted.format == "pcm" => {
+                (crate::audio::decode::wav_bytes(&samples, converted.sample_rate, 1), "wav")
+            }
+            _ => (converted.data.clone(), converted.format.as_str()),
+        };
+        if let Err(e) = send_document(item, data, format!("converted-{}.{}", item.id, extension)).await {
+            error!("Failed to send converted audio for item {}: {}", item.id, e);
+        }
+    }
+    if !debug.log.is_empty()
+        && let Err(e) = send_document(item, debug.log.into_bytes(), format!("ffmpeg-{}.log", item.id)).await
+    {
+        error!("Failed to send ffmpeg log for item {}: {}", item.id, e);
+    }
+}
+
+async fn send_document(item: &QueueItem, data: Vec<u8>, file_name: String) -> Result<()> {
+    let file = InputFile::memory(data).file_name(file_name);
+    let mut request = item.bot.send_document(item.chat_id, file).disable_notification(item.options.silent);
+    if let Some(topic) = item.options.topic {
+        request = request.message_thread_id(topic);
+    }
+    if let Some(reply_to) = item.reply_target() {
+        request = request.reply_to_message_id(reply_to);
+    }
+    crate::telegram::send(request).await?;
+    Ok(())
+}
+
+/// `📝 *Transcription \(Russian\):*` in MarkdownV2, without the language
+/// when it isn't known.
+fn transcription_heading(language: Option<&str>, locale: Locale) -> String {
+    format!("📝 *{}:*", escape_markdown_v2(&heading_text(language, locale)))
+}
+
+/// `Transcription (Russian)`, or just `Transcription`.
+fn heading_text(language: Option<&str>, locale: Locale) -> String {
+    match language {
+        Some(language) => {
+            let language = crate::stt::language::display_name(language).to_string();
+            i18n::tf(locale, "transcript.heading_language", &[("language", language)])
+        }
+        None => i18n::t(locale, "transcript.heading").to_string(),
+    }
+}
+
+/// `🗣 Russian · 2:41`, or just the duration when no language was detected.
+fn language_line(transcription: &crate::stt::Transcription, media_secs: u64) -> String {
+    match &transcription.language {
+        Some(language) => format!(
+            "🗣 {} · {}",
+            crate::stt::language::display_name(language),
+            format_duration(media_secs)
+        ),
+        None => format!("🗣 {}", format_duration(media_secs)),
+    }
+}
+
+/// Plain-text values of the reply and footer placeholders, all but `{text}`.
+fn template_values(
+    transcription: &crate::stt::Transcription,
+    provider: SttProvider,
+    config: &BotConfig,
+    options: &ProcessingOptions,
+    media_secs: u64,
+) -> Vec<(&'static str, String)> {
+    let language = transcription.language.as_deref().or(options.language);
+    vec![
+        ("emoji", "📝".to_string()),
+        ("heading", heading_text(language, options.locale)),
+        ("provider", provider.as_str().to_string()),
+        ("model", provider.model_for(&options.stt_options(config)).to_string()),
+        ("duration", format_duration(media_secs)),
+        ("seconds", media_secs.to_string()),
+        ("language", transcription.language.as_deref().unwrap_or("unknown").to_string()),
+        ("words", transcription.text.split_whitespace().count().to_string()),
+        ("confidence", transcription.confidence().map(|c| format!("{:.0}%", c * 100.0)).unwrap_or_default()),
+    ]
+}
+
+/// Renders `REPLY_TEMPLATE` as MarkdownV2: `{text}` is the rendered
+/// transcript `body`, everything else is escaped.
+fn render_reply(
+    template: &str,
+    body: &str,
+    transcription: &crate::stt::Transcription,
+    provider: SttProvider,
+    config: &BotConfig,
+    options: &ProcessingOptions,
+    media_secs: u64,
+) -> String {
+    let mut values: Vec<_> = template_values(transcription, provider, config, options, media_secs)
+        .into_iter()
+        .map(|(name, value)| (name, escape_markdown_v2(&value)))
+        .collect();
+    values.push(("text", body.to_string()));
+    crate::template::render(template, &values, escape_markdown_v2)
+}
+
+/// Fills the placeholders of `REPLY_TEMPLATE` other than `{text}` in the
+/// configured footer.
+fn render_footer(
+    template: &str,
+    transcription: &crate::stt::Transcription,
+    provider: SttProvider,
+    config: &BotConfig,
+    options: &ProcessingOptions,
+    media_secs: u64,
+) -> String {
+    let values = template_values(transcription, provider, config, options, media_secs);
+    crate::template::render(template, &values, str::to_string)
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour up.
+fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+    } else {
+        quota::format_minutes(secs)
+    }
+}
+
+pub fn escape_markdown_v2(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' | '_' | '*' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '=' | '|' | '{' | '}' | '.' | '!' => {
+                format!("\\{}", c)
+            }
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+/// MarkdownV2 transcript text, with uncertain words marked if configured.
+pub fn render_transcript_body(transcription: &crate::stt::Transcription, config: &BotConfig) -> String {
+    match config.low_confidence_threshold {
+        Some(threshold) => render_with_confidence(transcription, threshold),
+        None => escape_markdown_v2(&transcription.text),
+    }
+}
+
+/// Renders the transcript as MarkdownV2, marking words the provider was
+/// unsure about as `_word?_`. Falls back to the plain text when no word
+/// confidences are available.
+fn render_with_confidence(transcription: &crate::stt::Transcription, threshold: f32) -> String {
+    let is_low = |w: &crate::stt::Word| w.confidence.is_some_and(|c| c < threshold);
+    if !transcription.words.iter().any(is_low) {
+        return escape_markdown_v2(&transcription.text);
+    }
+
+    // Words from call recordings carry speakers; keep their turns readable
+    let mut rendered = String::new();
+    let mut speaker = None;
+    for w in &transcription.words {
+        if w.speaker.is_some() && w.speaker != speaker {
+            if !rendered.is_empty() {
+                rendered.push('\n');
+            }
+            rendered.push_str(&format!("{}: ", escape_markdown_v2(w.speaker.as_deref().unwrap_or_default())));
+            speaker = w.speaker.clone();
+        } else if !rendered.is_empty() {
+            rendered.push(' ');
+        }
+
+        if is_low(w) {
+            rendered.push_str(&format!("_{}?_", escape_markdown_v2(&w.text)));
+        } else {
+            rendered.push_str(&escape_markdown_v2(&w.text));
+        }
+    }
+    rendered
+}
+
+/// Uploads the transcript as a `.txt` document, captioned with the start of
+/// the text.
+async fn send_transcript_file(
+    item: &QueueItem,
+    transcription: &crate::stt::Transcription,
+    via: &str,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> Result<()> {
+    // Captions are capped at 1024 characters, and escaping can double the preview
+    const PREVIEW_CHARS: usize = 300;
+
+    let text = transcription.text.trim();
+    let preview: String = text.chars().take(PREVIEW_CHARS).collect();
+    let attached = i18n::tf(item.options.locale, "transcript.attached", &[("count", text.split_whitespace().count().to_string())]);
+    let caption = format!("{}\n\n📝 {}…\n\n📄 _{}_", via, escape_markdown_v2(preview.trim_end()), escape_markdown_v2(&attached));
+
+    let file = InputFile::memory(text.as_bytes().to_vec()).file_name(format!("transcript-{}.txt", item.id));
+    let mut request = item.bot.send_document(item.chat_id, file)
+        .caption(caption)
+        .disable_notification(item.options.silent)
+        .parse_mode(teloxide::types::ParseMode::MarkdownV2);
+    if let Some(topic) = item.options.topic {
+        request = request.message_thread_id(topic);
+    }
+    if let Some(reply_to) = item.reply_target() {
+        request = request.reply_to_message_id(reply_to);
+    }
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    crate::telegram::send(request).await?;
+    Ok(())
+}
+
+/// Telegram allows 4096 UTF-16 code units per message; leave room for the
+/// part label.
+const MAX_MESSAGE_LENGTH: usize = 4000;
+
+/// Splits MarkdownV2 text into messages under Telegram's length limit.
+/// Splits prefer line and then word boundaries outside of `*bold*`,
+/// `_italic_` and code spans, and never fall between a backslash and the
+/// character it escapes.
+fn split_message(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while rest.encode_utf16().count() > MAX_MESSAGE_LENGTH {
+        let at = split_point(rest);
+        let chunk = rest[..at].trim_end();
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_string());
+        }
+        rest = rest[at..].trim_start();
+    }
+    if !rest.is_empty() || chunks.is_empty() {
+        chunks.push(rest.to_string());
+    }
+    chunks
+}
+
+/// Byte offset to end the first message of `text` at.
+fn split_point(text: &str) -> usize {
+    let mut length = 0;
+    let mut escaped = false;
+    let mut open: Vec<char> = Vec::new();
+    let (mut line_break, mut space, mut outside_entity, mut anywhere) = (None, None, None, None);
+
+    for (i, c) in text.char_indices() {
+        length += c.len_utf16();
+        if length > MAX_MESSAGE_LENGTH {
+            break;
+        }
+        let end = i + c.len_utf8();
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+            continue;
+        } else if matches!(c, '*' | '_' | '~' | '`') {
+            match open.iter().position(|&o| o == c) {
+                Some(pos) => {
+                    open.remove(pos);
+                }
+                None => open.push(c),
+            }
+        }
+        anywhere = Some(end);
+        if open.is_empty() {
+            match c {
+                '\n' => line_break = Some(i),
+                ' ' => space = Some(i),
+                _ => {}
+            }
+            outside_entity = Some(end);
+        }
+    }
+
+    line_break
+        .or(space)
+        .filter(|&at| at > 0)
+        .or(outside_entity)
+        .or(anywhere)
+        .unwrap_or_else(|| text.chars().next().map_or(text.len(), char::len_utf8))
+}
+
+/// Removes MarkdownV2 markup, for resending text Telegram couldn't parse.
+fn strip_markdown_v2(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => plain.extend(chars.next()),
+            '*' | '_' | '~' | '`' | '|' => {}
+            _ => plain.push(c),
+        }
+    }
+    plain
+}
+
+/// Edits the item's "Processing…" message into the transcript, which saves
+/// a notification in busy groups. If the edit fails the message is deleted
+/// and the transcript sent as a new one.
+async fn deliver_in_place(item: &QueueItem, text: &str, keyboard: Option<InlineKeyboardMarkup>) -> Result<()> {
+    let Some(message_id) = item.message_id else {
+        return send_item_message(item, text, keyboard).await;
+    };
+    let mut request = item
+        .bot
+        .edit_message_text(item.chat_id, message_id, text)
+        .parse_mode(teloxide::types::ParseMode::MarkdownV2);
+    if let Some(keyboard) = &keyboard {
+        request = request.reply_markup(keyboard.clone());
+    }
+    match crate::telegram::send(request).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            warn!("Failed to edit the processing message of item {} into its transcript: {}", item.id, e);
+            item.delete_status_message().await;
+            send_item_message(item, text, keyboard).await
+        }
+    }
+}
+
+/// [`send_long_message`] in reply to the item's source message.
+async fn send_item_message(item: &QueueItem, text: &str, keyboard: Option<InlineKeyboardMarkup>) -> Result<()> {
+    let (topic, reply_to) = (item.options.topic, item.reply_target());
+    send_parts(&item.bot, item.chat_id, topic, text, reply_to, keyboard, item.options.silent, item.options.locale).await
+}
+
+/// Sends MarkdownV2 text as numbered parts when it is too long for one
+/// message. The keyboard goes under the last part. Parts Telegram can't
+/// parse are resent as plain text.
+pub async fn send_long_message(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    reply_to: Option<MessageId>,
+    keyboard: Option<InlineKeyboardMarkup>,
+    locale: Locale,
+) -> Result<()> {
+    send_parts(bot, chat_id, None, text, reply_to, keyboard, false, locale).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_parts(
+    bot: &Bot,
+    chat_id: ChatId,
+    topic: Option<i32>,
+    text: &str,
+    reply_to: Option<MessageId>,
+    keyboard: Option<InlineKeyboardMarkup>,
+    silent: bool,
+    locale: Locale,
+) -> Result<()> {
+    let chunks = split_message(text);
+
+    // Send each chunk, spaced out to stay under the chat's flood limit
+    for (i, chunk) in chunks.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(crate::telegram::part_interval(chat_id)).await;
+        }
+        let message_text = if chunks.len() > 1 {
+            let part = i18n::tf(locale, "transcript.part", &[("part", (i + 1).to_string()), ("count", chunks.len().to_string())]);
+            format!("{}\n\n*{}*", chunk, escape_markdown_v2(&part))
+        } else {
+            chunk.clone()
+        };
+
+        let request = |text: String| {
+            let mut request = bot.send_message(chat_id, text).disable_notification(silent);
+            if let Some(topic) = topic {
+                request = request.message_thread_id(topic);
+            }
+            // Only reply to original message for the first chunk
+            if i == 0
+                && let Some(reply_to) = reply_to
+            {
+                request = request.reply_to_message_id(reply_to);
+            }
+            if i + 1 == chunks.len()
+                && let Some(keyboard) = &keyboard
+            {
+                request = request.reply_markup(keyboard.clone());
+            }
+            request
+        };
+
+        let markdown = request(message_text.clone()).parse_mode(teloxide::types::ParseMode::MarkdownV2);
+        match crate::telegram::send(markdown).await {
+            Err(e) if crate::telegram::is_markup_error(&e) => {
+                warn!("Telegram couldn't parse part {} of a message to chat {} ({}), sending it as plain text", i + 1, chat_id, e);
+                crate::telegram::send(request(strip_markdown_v2(&message_text))).await?;
+            }
+            result => {
+                result?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn get_queue_status(stats: &QueueStats, locale: Locale) -> String {
+    let stats_guard = stats.read().await;
+
+    let processing_info = if let Some(ref item_id) = stats_guard.processing_item_id {
+        i18n::tf(locale, "queue_status.processing", &[("id", item_id[..8].to_string())])
+    } else {
+        i18n::t(locale, "queue_status.idle").to_string()
+    };
+
+    let mut status = i18n::tf(
+        locale,
+        "queue_status.summary",
+        &[
+            ("size", stats_guard.current_queue_size.to_string()),
+            ("status", processing_info),
+            ("processed", stats_guard.total_processed.to_string()),
+            ("failed", stats_guard.total_failed.to_string()),
+            ("skipped", stats_guard.total_skipped.to_string()),
+            ("queued", stats_guard.total_queued.to_string()),
+        ],
+    );
+    if let Some(average) = stats_guard.average_duration() {
+        status.push('\n');
+        status.push_str(&i18n::tf(locale, "queue_status.average", &[("seconds", average.as_secs().to_string())]));
+    }
+    if stats_guard.total_rejected > 0 {
+        status.push('\n');
+        status.push_str(&i18n::tf(locale, "queue_status.rejected", &[("count", stats_guard.total_rejected.to_string())]));
+    }
+    format!("🔄 *{}*\n{}", escape_markdown_v2(i18n::t(locale, "queue_status.heading")), escape_markdown_v2(&status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stt::{Transcription, Word};
+
+    fn word(text: &str, confidence: f32) -> Word {
+        Word { text: text.to_string(), confidence: Some(confidence), start_secs: None, speaker: None }
+    }
+
+    #[test]
+    fn test_render_with_confidence_marks_uncertain_words() {
+        let transcription = Transcription {
+            text: "Meet at 5.".to_string(),
+            words: vec![word("Meet", 0.98), word("at", 0.4), word("5.", 0.95)],
+            ..Default::default()
+        };
+
+        assert_eq!(render_with_confidence(&transcription, 0.6), "Meet _at?_ 5\\.");
+        assert_eq!(render_with_confidence(&transcription, 0.3), "Meet at 5\\.");
+    }
+
+    #[test]
+    fn test_render_with_confidence_without_words() {
+        let transcription = Transcription::from_text("a-b");
+        assert_eq!(render_with_confidence(&transcription, 0.9), "a\\-b");
+    }
+
+    #[test]
+    fn test_render_with_confidence_keeps_speakers() {
+        let speaker = |w: Word, s: &str| Word { speaker: Some(s.to_string()), ..w };
+        let transcription = Transcription {
+            words: vec![
+                speaker(word("Hi", 0.9), "Caller"),
+                speaker(word("there", 0.2), "Caller"),
+                speaker(word("Hello", 0.9), "Callee"),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(render_with_confidence(&transcription, 0.6), "Caller: Hi _there?_\nCallee: Hello");
+    }
+
+    #[test]
+    fn test_export_json() {
+        let transcription = Transcription {
+            language: Some("en".to_string()),
+            words: vec![word("Hi", 0.9)],
+            ..Transcription::from_text("Hi")
+        };
+        let export = TranscriptExport {
+            provider: "deepgram",
+            model: "nova-3",
+            duration_secs: 3,
+            transcription: &transcription,
+        };
+
+        let json: serde_json::Value = serde_json::to_value(&export).unwrap();
+        assert_eq!(json["provider"], "deepgram");
+        assert_eq!(json["text"], "Hi");
+        assert_eq!(json["language"], "en");
+        assert_eq!(json["words"][0]["confidence"], serde_json::json!(0.9f32));
+        assert!(json["words"][0].get("speaker").is_none());
+    }
+
+    #[test]
+    fn test_split_message() {
+        assert_eq!(split_message("short"), ["short"]);
+
+        let line = "word ".repeat(199);
+        let text = vec![line.trim_end(); 30].join("\n");
+        let parts = split_message(&text);
+        assert_eq!(parts.len(), 8);
+        assert!(parts.iter().all(|p| p.len() <= 4000));
+        assert_eq!(parts.join("\n").split_whitespace().count(), 30 * 199);
+    }
+
+    #[test]
+    fn test_split_message_keeps_markup_whole() {
+        // Cyrillic is two bytes but one code unit; the limit is in code units
+        let text = "слово ".repeat(1000);
+        let parts = split_message(text.trim_end());
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].len() > 4000);
+
+        // A bold span crossing the limit moves to the next part whole
+        let text = format!("{} *{}*", "a".repeat(3990), "b ".repeat(20).trim_end());
+        let parts = split_message(&text);
+        assert_eq!(parts, ["a".repeat(3990), format!("*{}*", "b ".repeat(20).trim_end())]);
+
+        // Unbroken escaped text is cut between escapes, not inside one
+        let text = "\\.".repeat(2500);
+        let parts = split_message(&text);
+        assert!(parts.iter().all(|p| p.len() % 2 == 0 && p.starts_with('\\')));
+        assert_eq!(parts.concat(), text);
+    }
+
+    #[test]
+    fn test_strip_markdown_v2() {
+        let markdown = format!("*Transcription:*\n\n_maybe?_ {}", escape_markdown_v2("1.5 (approx) \\ done!"));
+        assert_eq!(strip_markdown_v2(&markdown), "Transcription:\n\nmaybe? 1.5 (approx) \\ done!");
+    }
+
+    #[test]
+    fn test_failover_chain() {
+        let config = BotConfig {
+            stt_fallbacks: vec![SttProvider::Whisper, SttProvider::Deepgram, SttProvider::Google],
+            openai_api_key: Some("key".to_string()),
+            deepgram_api_key: Some("key".to_string()),
+            provider_budgets: HashMap::from([(SttProvider::Whisper, 0.0)]),
+            ..BotConfig::for_tests()
+        };
+
+        // Whisper is over budget, Google has no credentials
+        let mut chain = failover_chain(SttProvider::ElevenLabs, &config, &crate::cost::CostData::default());
+        assert_eq!(chain, [SttProvider::ElevenLabs, SttProvider::Deepgram]);
+
+        stick_to(&mut chain, SttProvider::Deepgram);
+        assert_eq!(chain, [SttProvider::Deepgram]);
+    }
+
+    #[test]
+    fn test_pending_data_round_trip() {
+        let mut item = QueueItem::new(
+            "pending-round-trip".to_string(),
+            Bot::new("token"),
+            ChatId(-100),
+            Some(MessageId(7)),
+            MessageId(5),
+            MediaFile::new(PathBuf::from("voice.ogg"), 3),
+            "voice.ogg".to_string(),
+            "@user".to_string(),
+            teloxide::types::UserId(42),
+            Some("user".to_string()),
+            30,
+        );
+        item.options.language = Some("ru");
+        item.options.provider = Some(SttProvider::LocalWhisper);
+        item.options.time_range = Some(crate::audio::TimeRange { start_secs: 5, end_secs: 20 });
+
+        let json = serde_json::to_string(&item.pending_data()).unwrap();
+        let restored = QueueItem::from_pending(Bot::new("token"), serde_json::from_str(&json).unwrap(), 3, Path::new("queue"));
+
+        assert_eq!(restored.id, item.id);
+        assert_eq!(restored.media.path(), persistence::pending_media_path(Path::new("queue"), "pending-round-trip"));
+        assert_eq!(restored.media.size(), 3);
+        assert_eq!(restored.message_id, Some(MessageId(7)));
+        assert_eq!(restored.reply_to_message_id, MessageId(5));
+        assert_eq!(restored.user_id, item.user_id);
+        assert_eq!(restored.options.language, Some("ru"));
+        assert_eq!(restored.options.provider, Some(SttProvider::LocalWhisper));
+        assert_eq!(restored.options.time_range, item.options.time_range);
+    }
+
+    #[test]
+    fn test_position_messages() {
+        let item = QueueItem::for_tests;
+        let order = Vec::new();
+        let texts = |stats: &mut QueueStatistics| -> Vec<String> {
+            stats.stale_position_messages(&order).into_iter().map(|update| update.text).collect()
+        };
+
+        let mut stats = QueueStatistics { processing_item_id: Some("current".to_string()), ..Default::default() };
+        stats.add_waiting(WaitingItem::new(&item("a")));
+        stats.add_waiting(WaitingItem::new(&item("b")));
+        assert_eq!(
+            texts(&mut stats),
+            ["📥 In queue (position: 2)\nFile: voice.ogg", "📥 In queue (position: 3)\nFile: voice.ogg"]
+        );
+        // Unchanged messages aren't edited again
+        assert!(texts(&mut stats).is_empty());
+
+        stats.record_duration(Duration::from_secs(100));
+        stats.record_duration(Duration::from_secs(140));
+        assert_eq!(
+            texts(&mut stats),
+            [
+                "📥 In queue (position: 2)\nFile: voice.ogg\n⏱ Starting in about 1 min",
+                "📥 In queue (position: 3)\nFile: voice.ogg\n⏱ Starting in about 3 min",
+            ]
+        );
+
+        // "a" starts, "b" moves up
+        stats.remove_waiting("a");
+        stats.processing_item_id = Some("a".to_string());
+        assert_eq!(texts(&mut stats), ["📥 In queue (position: 2)\nFile: voice.ogg\n⏱ Starting in about 1 min"]);
+    }
+
+    #[tokio::test]
+    async fn test_listing_after_push() {
+        let mut stats = QueueStatistics::default();
+
+        // The worker got to the item before it was listed
+        stats.set_processing(&QueueItem::for_tests("fast")).await;
+        stats.add_waiting(WaitingItem::new(&QueueItem::for_tests("fast")));
+        assert!(stats.waiting.is_empty());
+
+        // Items listed after the order was read aren't taken for gone
+        stats.add_waiting(WaitingItem::new(&QueueItem::for_tests("old")));
+        let read_at = Instant::now();
+        stats.add_waiting(WaitingItem::new(&QueueItem::for_tests("new")));
+        stats.forget_taken(&[], read_at);
+        assert_eq!(stats.waiting.iter().map(|waiting| waiting.id.as_str()).collect::<Vec<_>>(), ["new"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let (sender, other) = (teloxide::types::UserId(42), teloxide::types::UserId(7));
+        let mut stats = QueueStatistics::default();
+        for id in ["a", "b"] {
+            stats.increment_queued().await;
+            stats.add_waiting(WaitingItem::new(&QueueItem::for_tests(id)));
+        }
+
+        assert_eq!(stats.cancel("b", other, false), CancelOutcome::NotAllowed);
+        assert_eq!(
+            stats.cancel("b", sender, false),
+            CancelOutcome::Dequeued { original_filename: "voice.ogg".to_string() }
+        );
+        assert_eq!(stats.current_queue_size, 1);
+        assert!(stats.take_cancelled("b"));
+        assert!(!stats.take_cancelled("b"));
+
+        // Admins may stop anyone's running item
+        let cancel = stats.set_processing(&QueueItem::for_tests("a")).await;
+        assert_eq!(stats.cancel("a", other, true), CancelOutcome::Stopping);
+        cancel.notified().await;
+
+        stats.increment_cancelled().await;
+        assert_eq!((stats.current_queue_size, stats.total_cancelled), (0, 2));
+        assert_eq!(stats.cancel("a", sender, false), CancelOutcome::NotFound);
+    }
+
+    #[test]
+    fn test_live_transcript_text() {
+        let parts = [
+            crate::stt::Transcription::from_text("Hello there. "),
+            crate::stt::Transcription::default(),
+            crate::stt::Transcription::from_text("General Kenobi."),
+        ];
+        assert_eq!(
+            live_transcript_text(Locale::En, "talk.mp3", 40, &parts),
+            "✍️ Transcribing... 40%\nFile: talk.mp3\n\nHello there. General Kenobi."
+        );
+
+        let long = [crate::stt::Transcription::from_text("a".repeat(LIVE_PREVIEW_CHARS + 10))];
+        let text = live_transcript_text(Locale::En, "talk.mp3", 99, &long);
+        assert!(text.ends_with(&format!("…{}", "a".repeat(LIVE_PREVIEW_CHARS))));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_of() {
+        let mut stats = QueueStatistics::default();
+        stats.remember_file(ChatId(1), "AgAD", "first");
+        let duplicate = |stats: &QueueStatistics, chat, file| {
+            stats.duplicate_of(ChatId(chat), file).map(|recent| (recent.item_id.clone(), recent.done))
+        };
+
+        assert_eq!(duplicate(&stats, 1, "AgAD"), Some(("first".to_string(), false)));
+        assert_eq!(duplicate(&stats, 2, "AgAD"), None);
+        assert_eq!(duplicate(&stats, 1, "AgAE"), None);
+
+        stats.set_processing(&QueueItem::for_tests("first")).await;
+        stats.increment_processed().await;
+        assert_eq!(duplicate(&stats, 1, "AgAD"), Some(("first".to_string(), true)));
+
+        stats.recent_files.get_mut("1:AgAD").unwrap().queued_at -= DUPLICATE_WINDOW;
+        assert_eq!(duplicate(&stats, 1, "AgAD"), None);
+
+        // A file whose item didn't produce a transcript can be sent again
+        stats.remember_file(ChatId(1), "AgAF", "failed");
+        stats.set_processing(&QueueItem::for_tests("failed")).await;
+        stats.increment_failed().await;
+        assert_eq!(duplicate(&stats, 1, "AgAF"), None);
+    }
+
+    #[tokio::test]
+    async fn test_admit_holds_a_place() {
+        let (bot, config) = (Bot::new("token"), BotConfig::for_tests());
+        let queue: QueueSender = Arc::new(crate::fair_queue::FairQueue::with_capacity(Some(1)));
+        let stats = QueueStats::default();
+        let options = ProcessingOptions::default();
+
+        // A file still downloading keeps the next one out
+        let first = admit(&bot, ChatId(1), &options, &queue, &stats, &config).await.unwrap();
+        assert!(matches!(admit(&bot, ChatId(1), &options, &queue, &stats, &config).await, Err(BotError::QueueFull)));
+
+        // Giving up on it, e.g. after a failed download, frees the place
+        drop(first);
+        let second = admit(&bot, ChatId(1), &options, &queue, &stats, &config).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut item = QueueItem::for_tests("second");
+        item.media = Arc::new(MediaFile::new(dir.path().join("second.bin"), 0));
+        enqueue_admitted(&queue, &stats, item, second).await.unwrap();
+        assert_eq!(stats.read().await.reserved.load(Ordering::SeqCst), 0);
+        assert!(queue.is_full(0).await);
+    }
+
+    #[tokio::test]
+    async fn test_unqueue() {
+        let mut stats = QueueStatistics::default();
+        stats.increment_queued().await;
+        stats.remember_file(ChatId(1), "AgAD", "lost");
+
+        stats.unqueue("lost");
+        assert_eq!((stats.total_queued, stats.current_queue_size), (0, 0));
+        assert!(stats.duplicate_of(ChatId(1), "AgAD").is_none());
+    }
+
+    #[test]
+    fn test_media_file_removed_with_last_holder() {
+        let path = tempfile::NamedTempFile::new().unwrap().into_temp_path().keep().unwrap();
+        let media = Arc::new(MediaFile::new(path.clone(), 0));
+        let parked = Arc::clone(&media);
+
+        drop(media);
+        assert!(path.exists());
+        drop(parked);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_rerun_provider() {
+        let config = BotConfig {
+            stt_provider: SttProvider::Deepgram,
+            stt_fallbacks: vec![SttProvider::Google, SttProvider::Whisper],
+            deepgram_api_key: Some("key".to_string()),
+            openai_api_key: Some("key".to_string()),
+            elevenlabs_api_key: Some("key".to_string()),
+            ..BotConfig::for_tests()
+        };
+
+        // Failover order first, skipping providers without credentials
+        assert_eq!(rerun_provider(SttProvider::Deepgram, &config), Some(SttProvider::Whisper));
+        assert_eq!(rerun_provider(SttProvider::Whisper, &config), Some(SttProvider::Deepgram));
+
+        let single = BotConfig { deepgram_api_key: Some("key".to_string()), ..BotConfig::for_tests() };
+        assert_eq!(rerun_provider(SttProvider::Deepgram, &single), None);
+    }
+
+    #[test]
+    fn test_reconcile_duration() {
+        assert_eq!(reconcile_duration(60, 61.4), (60, false));
+        assert_eq!(reconcile_duration(0, 42.6), (43, false));
+        // Claims 5 s, actually two hours
+        assert_eq!(reconcile_duration(5, 7200.0), (7200, true));
+        // Short clips need more than 10 s of difference to be flagged
+        assert_eq!(reconcile_duration(4, 12.0), (4, false));
+        assert_eq!(reconcile_duration(600, 120.0), (120, true));
+    }
+
+    #[test]
+    fn test_recheck_duration() {
+        let config = BotConfig { quota_minutes_per_month: Some(10), max_duration_secs: Some(3600), ..BotConfig::for_tests() };
+        let mut quotas = quota::QuotaData { month: quota::current_month(), ..Default::default() };
+        quotas.record_usage(teloxide::types::UserId(42), None, 9 * 60);
+
+        // A document claims no duration, so nothing was checked before download
+        let mut document = QueueItem::for_tests("document");
+        document.duration_secs = 0;
+        assert!(matches!(recheck_duration(&document, 3 * 3600, &config, &mut quotas), Err(BotError::TooLong(10800, 3600))));
+        assert!(matches!(recheck_duration(&document, 120, &config, &mut quotas), Err(BotError::QuotaExceeded(60))));
+        assert!(recheck_duration(&document, 45, &config, &mut quotas).is_ok());
+
+        // What the pre-check already let through isn't refused again
+        let voice = QueueItem::for_tests("voice");
+        assert!(recheck_duration(&voice, 30, &config, &mut quotas).is_ok());
+        assert!(matches!(recheck_duration(&voice, 90, &config, &mut quotas), Err(BotError::QuotaExceeded(60))));
+
+        let admin = BotConfig { admin_user_ids: [teloxide::types::UserId(42)].into(), ..config.clone() };
+        assert!(recheck_duration(&document, 120, &admin, &mut quotas).is_ok());
+        assert!(matches!(recheck_duration(&document, 7200, &admin, &mut quotas), Err(BotError::TooLong(..))));
+    }
+
+    #[test]
+    fn test_metadata_line() {
+        let transcription = Transcription {
+            language: Some("RU".to_string()),
+            ..Transcription::from_text("привет как дела")
+        };
+        assert_eq!(
+            metadata_line(&transcription, SttProvider::Deepgram, 161, Locale::En),
+            "ℹ️ ru · 2:41 · deepgram · 3 words"
+        );
+        assert_eq!(format_duration(3725), "1:02:05");
+        assert_eq!(language_line(&transcription, 161), "🗣 Russian · 2:41");
+
+        let scored = Transcription {
+            segments: vec![crate::stt::Segment { start_secs: 0.0, end_secs: 2.0, text: String::new(), confidence: Some(0.42) }],
+            ..Transcription::from_text("hi")
+        };
+        assert_eq!(
+            metadata_line(&scored, SttProvider::Whisper, 2, Locale::En),
+            "ℹ️ unknown language · 0:02 · whisper · 1 word · 42% confidence"
+        );
+    }
+
+    #[test]
+    fn test_audio_debug_text() {
+        use crate::audio::{AudioError, ConvertedAudio, DebugConversion};
+
+        let converted = ConvertedAudio { data: vec![0; 32000], format: "pcm".to_string(), sample_rate: 16000, channels: 1 };
+        let debug = DebugConversion {
+            result: Ok(converted),
+            converter: "ffmpeg",
+            codec: "pcm_s16le",
+            log: "ffmpeg -i voice.ogg".to_string(),
+            elapsed: Duration::from_millis(420),
+        };
+        assert_eq!(
+            audio_debug_text("voice.ogg", SttProvider::Deepgram, &debug),
+            "🔬 Audio debug: voice.ogg\nProvider: deepgram\nConverter: ffmpeg, 0.4s\n\
+             Output: pcm, pcm_s16le, 16000 Hz, 1 ch, 32000 bytes, 1.0s\nffmpeg's log is attached."
+        );
+
+        let failed = DebugConversion {
+            result: Err(AudioError::ConversionFailed("FFmpeg exited with exit status: 1".to_string())),
+            log: String::new(),
+            ..debug
+        };
+        assert!(audio_debug_text("voice.ogg", SttProvider::Deepgram, &failed)
+            .ends_with("\n❌ Audio conversion failed: FFmpeg exited with exit status: 1"));
+    }
+
+    #[test]
+    fn test_low_confidence_warning() {
+        let mut config = BotConfig::for_tests();
+        let transcription = |confidence| Transcription {
+            segments: vec![crate::stt::Segment { start_secs: 0.0, end_secs: 2.0, text: String::new(), confidence: Some(confidence) }],
+            ..Transcription::from_text("hi")
+        };
+        assert_eq!(low_confidence_warning(&transcription(0.3), &config, Locale::En), None);
+
+        config.confidence_warning_threshold = Some(0.5);
+        assert_eq!(
+            low_confidence_warning(&transcription(0.3), &config, Locale::En).as_deref(),
+            Some("⚠️ Low confidence transcription (30%) — the audio may be unclear, check important details")
+        );
+        assert_eq!(low_confidence_warning(&transcription(0.8), &config, Locale::En), None);
+        assert_eq!(low_confidence_warning(&Transcription::from_text("hi"), &config, Locale::En), None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_local_server_file() {
+        // A local Bot API server reports absolute paths on its own disk
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file_7.oga");
+        std::fs::write(&path, b"OggS voice").unwrap();
+
+        let mut data = Vec::new();
+        fetch_telegram_file(&Bot::new("token"), path.to_str().unwrap(), &mut data).await.unwrap();
+        assert_eq!(data, b"OggS voice");
+        assert!(fetch_telegram_file(&Bot::new("token"), "/nonexistent/file_8.oga", &mut data).await.is_err());
+    }
+
+    #[test]
+    fn test_transcription_heading() {
+        assert_eq!(transcription_heading(Some("ru"), Locale::En), "📝 *Transcription \\(Russian\\):*");
+        assert_eq!(transcription_heading(None, Locale::En), "📝 *Transcription:*");
+        assert_eq!(transcription_heading(None, Locale::Ru), "📝 *Расшифровка:*");
+    }
+
+    #[test]
+    fn test_render_footer() {
+        let config = BotConfig::for_tests();
+        let footer = render_footer(
+            "transcribed by @OurTeamBot via {provider} ({model}), {duration} of {language} — /help",
+            &Transcription::from_text("hi"),
+            SttProvider::Deepgram,
+            &config,
+            &ProcessingOptions::default(),
+            75,
+        );
+        assert_eq!(footer, "transcribed by @OurTeamBot via deepgram (nova-3), 1:15 of unknown — /help");
+    }
+
+    #[test]
+    fn test_render_reply() {
+        let config = BotConfig::for_tests();
+        let transcription = Transcription { language: Some("en".to_string()), ..Transcription::from_text("Hi there.") };
+        let reply = render_reply(
+            "{emoji} {text}\n\n⏱ {seconds}s · {provider} · {heading}",
+            "Hi there\\.",
+            &transcription,
+            SttProvider::Whisper,
+            &config,
+            &ProcessingOptions::default(),
+            42,
+        );
+        assert_eq!(reply, "📝 Hi there\\.\n\n⏱ 42s · whisper · Transcription \\(English\\)");
+    }
 }
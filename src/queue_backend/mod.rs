@@ -0,0 +1,98 @@
+pub mod redis;
+
+use crate::Result;
+use crate::fair_queue::FairQueue;
+use crate::queue::QueueItem;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Where queued items wait, from `QUEUE_BACKEND`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendConfig {
+    /// In this process, kept on disk across restarts.
+    Local,
+    /// A Redis list shared by every instance pointing at it.
+    Redis(redis::RedisUrl),
+}
+
+/// The processing queue between the handlers and the worker.
+///
+/// Items are delivered at least once: `pop` leases an item to the worker,
+/// and one that isn't `ack`ed in time (its instance died, say) is handed
+/// out again.
+pub trait QueueBackend: Send + Sync {
+    /// Queues an item. It's lost only if this fails.
+    fn push(&self, item: QueueItem) -> BoxFuture<'_, Result<()>>;
+
+    /// Waits for the next item.
+    fn pop(&self) -> BoxFuture<'_, QueueItem>;
+
+    /// Marks a popped item as done, so it isn't delivered again.
+    fn ack<'a>(&'a self, id: &'a str) -> BoxFuture<'a, ()>;
+
+    /// Takes a waiting item out of the queue, e.g. when it's cancelled.
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, ()>;
+
+    /// Whether the queue is at capacity, counting `held` places promised
+    /// to files that aren't pushed yet.
+    fn is_full(&self, held: usize) -> BoxFuture<'_, bool>;
+
+    /// Waits until the queue is below capacity.
+    fn wait_for_space(&self) -> BoxFuture<'_, ()>;
+
+    /// Takes out the longest-waiting item, to make room for a new one.
+    fn pop_oldest(&self) -> BoxFuture<'_, Option<QueueItem>>;
+
+    /// Ids of the waiting items, next to be served first.
+    fn order(&self) -> BoxFuture<'_, Result<Vec<String>>>;
+
+    /// Whether only this process sees the queue; its items are then saved
+    /// to disk to survive a restart.
+    fn is_local(&self) -> bool;
+}
+
+/// The in-process queue. Popped items can't be lost to another instance,
+/// so there is nothing to acknowledge, and cancelled items are dropped
+/// when the worker reaches them.
+impl QueueBackend for FairQueue<QueueItem> {
+    fn push(&self, item: QueueItem) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            FairQueue::push(self, item.user_id, item.options.priority, item);
+            Ok(())
+        })
+    }
+
+    fn pop(&self) -> BoxFuture<'_, QueueItem> {
+        Box::pin(FairQueue::pop(self))
+    }
+
+    fn ack<'a>(&'a self, _id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+
+    fn remove<'a>(&'a self, _id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+
+    fn is_full(&self, held: usize) -> BoxFuture<'_, bool> {
+        Box::pin(async move { FairQueue::is_full(self, held) })
+    }
+
+    fn wait_for_space(&self) -> BoxFuture<'_, ()> {
+        Box::pin(FairQueue::wait_for_space(self))
+    }
+
+    fn pop_oldest(&self) -> BoxFuture<'_, Option<QueueItem>> {
+        Box::pin(async move { FairQueue::pop_oldest(self) })
+    }
+
+    fn order(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+        Box::pin(async move { Ok(FairQueue::order(self, |item| item.id.clone())) })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,461 @@
+use super::{BoxFuture, QueueBackend};
+use crate::persistence::{self, PendingItemData};
+use crate::queue::QueueItem;
+use crate::{BotError, Result};
+use ::redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use ::redis::{AsyncCommands, IntoConnectionInfo, RedisError};
+use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::Bot;
+use tokio::task::JoinHandle;
+
+const KEY_PREFIX: &str = "tg-stt:queue";
+/// How long the blocking pop waits before asking again, in seconds.
+const POP_TIMEOUT_SECS: f64 = 5.0;
+/// Pause after Redis couldn't be reached.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How often a deferred file checks for room.
+const SPACE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// A reply taking longer than this means the connection hung. Blocking
+/// pops wait well under it.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A parsed `REDIS_URL`: `redis://[[user]:password@]host[:port][/db]`, or
+/// `rediss://` for TLS.
+#[derive(Debug, Clone)]
+pub struct RedisUrl {
+    url: String,
+    info: ::redis::ConnectionInfo,
+}
+
+impl PartialEq for RedisUrl {
+    fn eq(&self, other: &Self) -> bool {
+        self.url == other.url
+    }
+}
+
+impl RedisUrl {
+    pub fn parse(url: &str) -> std::result::Result<Self, String> {
+        let url = url.trim();
+        if !url.starts_with("redis://") && !url.starts_with("rediss://") {
+            return Err("expected a redis:// or rediss:// URL".to_string());
+        }
+        let info = url.into_connection_info().map_err(|e| e.to_string())?;
+        Ok(Self { url: url.to_string(), info })
+    }
+
+    /// `host:port`, without credentials, for logs.
+    pub fn address(&self) -> String {
+        self.info.addr.to_string()
+    }
+
+    /// Opens a connection shared by concurrent commands, which reconnects
+    /// on its own after a failure.
+    pub async fn connect(&self) -> std::result::Result<ConnectionManager, RedisError> {
+        let client = ::redis::Client::open(self.info.clone())?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(CONNECT_TIMEOUT)
+            .set_response_timeout(RESPONSE_TIMEOUT)
+            .set_number_of_retries(2);
+        ConnectionManager::new_with_config(client, config).await
+    }
+}
+
+/// A queued item as stored in Redis. Its media stays in the shared
+/// `data/queue` directory.
+#[derive(Serialize, Deserialize)]
+struct StoredItem {
+    item: PendingItemData,
+    size: u64,
+}
+
+/// The Redis keys, each under `KEY_PREFIX`:
+/// - `pending`: list of waiting item ids, pushed left and popped right
+/// - `processing`: list of ids leased to a worker
+/// - `items`: hash of id to `StoredItem` JSON
+/// - `leases`: hash of id to lease deadline, in Unix milliseconds
+struct Keys {
+    pending: String,
+    processing: String,
+    items: String,
+    leases: String,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Self {
+            pending: format!("{}:pending", KEY_PREFIX),
+            processing: format!("{}:processing", KEY_PREFIX),
+            items: format!("{}:items", KEY_PREFIX),
+            leases: format!("{}:leases", KEY_PREFIX),
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// The connection for short commands, shared with the lease renewals and
+/// the reaper.
+struct Client {
+    connection: ConnectionManager,
+    keys: Keys,
+    visibility_timeout: Duration,
+}
+
+impl Client {
+    fn connection(&self) -> ConnectionManager {
+        self.connection.clone()
+    }
+
+    /// Gives the worker holding `id` another visibility timeout.
+    async fn renew_lease(&self, id: &str) -> std::result::Result<(), RedisError> {
+        let deadline = now_ms() + self.visibility_timeout.as_millis() as i64;
+        self.connection().hset(&self.keys.leases, id, deadline).await
+    }
+
+    async fn forget(&self, id: &str) -> std::result::Result<(), RedisError> {
+        let mut connection = self.connection();
+        connection.hdel::<_, _, ()>(&self.keys.leases, id).await?;
+        connection.hdel(&self.keys.items, id).await
+    }
+
+    /// Puts items whose lease ran out back at the front of the queue.
+    async fn requeue_expired(&self) -> std::result::Result<(), RedisError> {
+        let mut connection = self.connection();
+        let now = now_ms();
+        let processing: Vec<String> = connection.lrange(&self.keys.processing, 0, -1).await?;
+        for id in processing {
+            let deadline: Option<i64> = connection.hget(&self.keys.leases, &id).await?;
+            match deadline {
+                Some(deadline) if deadline > now => {}
+                // Its worker stopped between taking it and leasing it
+                None => {
+                    let deadline = now + self.visibility_timeout.as_millis() as i64;
+                    connection.hset_nx::<_, _, _, ()>(&self.keys.leases, &id, deadline).await?;
+                }
+                Some(_) => {
+                    // Only one instance gets to move it
+                    let moved: i64 = connection.lrem(&self.keys.processing, 1, &id).await?;
+                    if moved == 1 {
+                        connection.hdel::<_, _, ()>(&self.keys.leases, &id).await?;
+                        connection.rpush::<_, _, ()>(&self.keys.pending, &id).await?;
+                        warn!("Queue item {} wasn't finished within its visibility timeout, queued again", id);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A queue in Redis that several instances push to and pop from. Items are
+/// served in arrival order, priority ones first; taking turns between
+/// senders is left to the local queue.
+pub struct RedisQueue {
+    client: Arc<Client>,
+    bot: Bot,
+    capacity: Option<usize>,
+    /// Where the items' media is, on a disk the instances share.
+    queue_dir: PathBuf,
+    /// Used for the blocking pop only, so it doesn't stall other commands.
+    blocking: ConnectionManager,
+    /// Lease renewals of the items this instance is processing.
+    heartbeats: std::sync::Mutex<HashMap<String, JoinHandle<()>>>,
+    reaper: JoinHandle<()>,
+}
+
+impl RedisQueue {
+    /// Connects to Redis and starts returning expired items to the queue.
+    pub async fn connect(
+        url: RedisUrl,
+        bot: Bot,
+        capacity: Option<usize>,
+        visibility_timeout: Duration,
+        queue_dir: PathBuf,
+    ) -> Result<Self> {
+        let client = Arc::new(Client { connection: url.connect().await?, keys: Keys::default(), visibility_timeout });
+        let blocking = url.connect().await?;
+        info!("Using the Redis queue at {}", url.address());
+
+        let reaper_client = client.clone();
+        let reaper = tokio::spawn(async move {
+            let mut interval = tokio::time::interval((visibility_timeout / 3).max(Duration::from_secs(1)));
+            loop {
+                interval.tick().await;
+                if let Err(e) = reaper_client.requeue_expired().await {
+                    warn!("Failed to check the Redis queue for expired items: {}", e);
+                }
+            }
+        });
+
+        Ok(Self {
+            client,
+            bot,
+            capacity,
+            queue_dir,
+            blocking,
+            heartbeats: std::sync::Mutex::new(HashMap::new()),
+            reaper,
+        })
+    }
+
+    async fn len(&self) -> std::result::Result<usize, RedisError> {
+        self.client.connection().llen(&self.client.keys.pending).await
+    }
+
+    /// Loads a popped item. None if it was removed in the meantime.
+    async fn load(&self, id: &str) -> std::result::Result<Option<QueueItem>, RedisError> {
+        let json: Option<String> = self.client.connection().hget(&self.client.keys.items, id).await?;
+        let Some(json) = json else {
+            return Ok(None);
+        };
+        match serde_json::from_str::<StoredItem>(&json) {
+            Ok(stored) => Ok(Some(QueueItem::from_pending(self.bot.clone(), stored.item, stored.size, &self.queue_dir))),
+            Err(e) => {
+                warn!("Failed to parse queue item {} from Redis: {}, dropping it", id, e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn start_heartbeat(&self, id: &str) {
+        let client = self.client.clone();
+        let item_id = id.to_string();
+        let heartbeat = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(client.visibility_timeout / 3);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = client.renew_lease(&item_id).await {
+                    warn!("Failed to renew the lease on queue item {}: {}", item_id, e);
+                }
+            }
+        });
+        if let Some(old) = self.heartbeats.lock().expect("heartbeat lock poisoned").insert(id.to_string(), heartbeat) {
+            old.abort();
+        }
+    }
+
+    async fn try_pop(&self) -> std::result::Result<Option<QueueItem>, RedisError> {
+        let keys = &self.client.keys;
+        let popped: Option<String> =
+            self.blocking.clone().brpoplpush(&keys.pending, &keys.processing, POP_TIMEOUT_SECS).await?;
+        let Some(id) = popped else {
+            return Ok(None);
+        };
+        self.client.renew_lease(&id).await?;
+        match self.load(&id).await? {
+            Some(item) => {
+                self.start_heartbeat(&id);
+                Ok(Some(item))
+            }
+            None => {
+                self.client.connection().lrem::<_, _, ()>(&keys.processing, 1, &id).await?;
+                self.client.forget(&id).await?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Stops the reaper and the lease renewals; items still leased go back to
+/// the queue once their visibility timeout runs out.
+impl Drop for RedisQueue {
+    fn drop(&mut self) {
+        self.reaper.abort();
+        for heartbeat in self.heartbeats.lock().expect("heartbeat lock poisoned").values() {
+            heartbeat.abort();
+        }
+    }
+}
+
+impl From<RedisError> for BotError {
+    fn from(error: RedisError) -> Self {
+        BotError::QueueBackend(error.to_string())
+    }
+}
+
+impl QueueBackend for RedisQueue {
+    fn push(&self, item: QueueItem) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let keys = &self.client.keys;
+            let stored = StoredItem { item: item.pending_data(), size: item.media.size() };
+            let json = serde_json::to_string(&stored)
+                .map_err(|e| BotError::Config(format!("JSON serialization error: {}", e)))?;
+            let mut connection = self.client.connection();
+            connection.hset::<_, _, _, ()>(&keys.items, &item.id, json).await?;
+            // Popped from the right, so priority items go next
+            if item.options.priority {
+                connection.rpush::<_, _, ()>(&keys.pending, &item.id).await?;
+            } else {
+                connection.lpush::<_, _, ()>(&keys.pending, &item.id).await?;
+            }
+            // Whichever instance pops it deletes the media when done
+            item.media.keep_on_disk();
+            Ok(())
+        })
+    }
+
+    fn pop(&self) -> BoxFuture<'_, QueueItem> {
+        Box::pin(async move {
+            loop {
+                match self.try_pop().await {
+                    Ok(Some(item)) => return item,
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Failed to take an item from the Redis queue: {}, retrying in {}s", e, RETRY_DELAY.as_secs());
+                        tokio::time::sleep(RETRY_DELAY).await;
+                    }
+                }
+            }
+        })
+    }
+
+    fn ack<'a>(&'a self, id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let Some(heartbeat) = self.heartbeats.lock().expect("heartbeat lock poisoned").remove(id) {
+                heartbeat.abort();
+            }
+            let result = async {
+                self.client.connection().lrem::<_, _, ()>(&self.client.keys.processing, 1, id).await?;
+                self.client.forget(id).await
+            };
+            if let Err(e) = result.await {
+                warn!("Failed to mark queue item {} done in Redis, it may be processed again: {}", id, e);
+            }
+        })
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let result = async {
+                let mut connection = self.client.connection();
+                let removed: i64 = connection.lrem(&self.client.keys.pending, 0, id).await?;
+                if removed == 1 {
+                    connection.hdel::<_, _, ()>(&self.client.keys.items, id).await?;
+                    tokio::fs::remove_file(persistence::pending_media_path(&self.queue_dir, id)).await.ok();
+                }
+                Ok::<_, RedisError>(())
+            };
+            if let Err(e) = result.await {
+                warn!("Failed to remove queue item {} from Redis: {}", id, e);
+            }
+        })
+    }
+
+    fn is_full(&self, held: usize) -> BoxFuture<'_, bool> {
+        Box::pin(async move {
+            let Some(capacity) = self.capacity else {
+                return false;
+            };
+            match self.len().await {
+                Ok(len) => len + held >= capacity,
+                Err(e) => {
+                    warn!("Failed to read the Redis queue length: {}", e);
+                    false
+                }
+            }
+        })
+    }
+
+    fn wait_for_space(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            while self.is_full(0).await {
+                tokio::time::sleep(SPACE_POLL_INTERVAL).await;
+            }
+        })
+    }
+
+    fn pop_oldest(&self) -> BoxFuture<'_, Option<QueueItem>> {
+        Box::pin(async move {
+            let result = async {
+                let popped: Option<String> = self.client.connection().rpop(&self.client.keys.pending, None).await?;
+                let Some(id) = popped else {
+                    return Ok(None);
+                };
+                let item = self.load(&id).await?;
+                self.client.forget(&id).await?;
+                Ok::<_, RedisError>(item)
+            };
+            result.await.unwrap_or_else(|e| {
+                warn!("Failed to drop the oldest item from the Redis queue: {}", e);
+                None
+            })
+        })
+    }
+
+    fn order(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+        Box::pin(async move {
+            let ids: Vec<String> = self.client.connection().lrange(&self.client.keys.pending, 0, -1).await?;
+            Ok(ids.into_iter().rev().collect())
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url = RedisUrl::parse("redis://:s3cret@cache.internal:6380/2").unwrap();
+        assert_eq!(url.address(), "cache.internal:6380");
+        assert_eq!((url.info.redis.password.as_deref(), url.info.redis.db), (Some("s3cret"), 2));
+
+        let url = RedisUrl::parse("redis://localhost").unwrap();
+        assert_eq!((url.address(), url.info.redis.password, url.info.redis.db), ("localhost:6379".to_string(), None, 0));
+
+        assert!(RedisUrl::parse("rediss://cache.internal").is_ok());
+        assert!(RedisUrl::parse("redis://localhost/cache").is_err());
+        assert!(RedisUrl::parse("localhost:6379").is_err());
+    }
+
+    /// Runs against the Redis at `REDIS_TEST_URL`, skipped when it's unset.
+    /// Point it at a scratch database: the queue keys there are cleared.
+    #[tokio::test]
+    async fn test_against_redis() {
+        let Ok(url) = std::env::var("REDIS_TEST_URL") else {
+            return;
+        };
+        let url = RedisUrl::parse(&url).unwrap();
+        let keys = Keys::default();
+        let mut connection = url.connect().await.unwrap();
+        connection.del::<_, ()>(&[&keys.pending, &keys.processing, &keys.items, &keys.leases]).await.unwrap();
+
+        let bot = Bot::new("token");
+        let timeout = Duration::from_secs(3);
+        let queue = RedisQueue::connect(url.clone(), bot.clone(), Some(2), timeout, PathBuf::from("queue")).await.unwrap();
+        queue.push(QueueItem::for_tests("first")).await.unwrap();
+        queue.push(QueueItem::for_tests("second")).await.unwrap();
+        assert!(queue.is_full(0).await);
+        assert_eq!(queue.order().await.unwrap(), ["first", "second"]);
+
+        // Popped and acked: gone for good
+        assert_eq!(queue.pop().await.id, "first");
+        queue.ack("first").await;
+        assert_eq!(queue.order().await.unwrap(), ["second"]);
+
+        // The instance dies while holding an item, and another one gets it
+        // once the lease runs out
+        assert_eq!(queue.pop().await.id, "second");
+        drop(queue);
+        let survivor = RedisQueue::connect(url, bot, Some(2), timeout, PathBuf::from("queue")).await.unwrap();
+        let requeued = tokio::time::timeout(Duration::from_secs(20), survivor.pop()).await.unwrap();
+        assert_eq!(requeued.id, "second");
+        survivor.ack("second").await;
+        let processing: usize = connection.llen(&keys.processing).await.unwrap();
+        let stored: usize = connection.hlen(&keys.items).await.unwrap();
+        assert_eq!((processing, stored), (0, 0));
+    }
+}
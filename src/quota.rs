@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use chrono::{Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::UserId;
+use tokio::sync::RwLock;
+
+pub type QuotaStore = Arc<RwLock<QuotaData>>;
+
+/// Per-user monthly audio minutes, persisted in `data/quotas.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct QuotaData {
+    /// Accounting month in `YYYY-MM` form; usage and grants reset when it changes.
+    pub month: String,
+    pub users: HashMap<u64, UserQuota>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct UserQuota {
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub used_seconds: u64,
+    /// Extra minutes granted by an admin for the current month.
+    #[serde(default)]
+    pub granted_minutes: u64,
+    /// Per-user monthly limit overriding `QUOTA_MINUTES_PER_MONTH`.
+    #[serde(default)]
+    pub limit_minutes: Option<u64>,
+}
+
+pub fn current_month() -> String {
+    let now = Utc::now();
+    format!("{:04}-{:02}", now.year(), now.month())
+}
+
+impl QuotaData {
+    /// Resets usage and grants if the stored month is not the current one.
+    /// Returns true if anything changed.
+    pub fn roll_month(&mut self, month: &str) -> bool {
+        if self.month == month {
+            return false;
+        }
+        self.month = month.to_string();
+        for quota in self.users.values_mut() {
+            quota.used_seconds = 0;
+            quota.granted_minutes = 0;
+        }
+        true
+    }
+
+    /// Total allowance in seconds for a user, or None if unlimited.
+    pub fn allowance_seconds(&self, user_id: UserId, default_limit_minutes: Option<u64>) -> Option<u64> {
+        let quota = self.users.get(&user_id.0);
+        let limit = quota.and_then(|q| q.limit_minutes).or(default_limit_minutes)?;
+        let granted = quota.map(|q| q.granted_minutes).unwrap_or(0);
+        Some(limit.saturating_add(granted).saturating_mul(60))
+    }
+
+    pub fn used_seconds(&self, user_id: UserId) -> u64 {
+        self.users.get(&user_id.0).map(|q| q.used_seconds).unwrap_or(0)
+    }
+
+    /// Remaining seconds for a user, or None if unlimited.
+    pub fn remaining_seconds(&self, user_id: UserId, default_limit_minutes: Option<u64>) -> Option<u64> {
+        self.allowance_seconds(user_id, default_limit_minutes)
+            .map(|allowance| allowance.saturating_sub(self.used_seconds(user_id)))
+    }
+
+    pub fn record_usage(&mut self, user_id: UserId, username: Option<&str>, seconds: u64) {
+        let quota = self.users.entry(user_id.0).or_default();
+        quota.used_seconds = quota.used_seconds.saturating_add(seconds);
+        if let Some(name) = username {
+            quota.username = Some(name.to_string());
+        }
+    }
+
+    pub fn remember_username(&mut self, user_id: UserId, username: &str) {
+        self.users.entry(user_id.0).or_default().username = Some(username.to_string());
+    }
+
+    pub fn grant_minutes(&mut self, user_id: UserId, minutes: u64) {
+        let quota = self.users.entry(user_id.0).or_default();
+        quota.granted_minutes = quota.granted_minutes.saturating_add(minutes);
+    }
+
+    pub fn set_limit(&mut self, user_id: UserId, minutes: u64) {
+        self.users.entry(user_id.0).or_default().limit_minutes = Some(minutes);
+    }
+
+    /// Resolves `@username` or a numeric user ID to a known user.
+    pub fn resolve_user(&self, target: &str) -> Option<UserId> {
+        let target = target.trim();
+        if let Ok(id) = target.parse::<u64>() {
+            return Some(UserId(id));
+        }
+        let name = target.trim_start_matches('@');
+        self.users
+            .iter()
+            .find(|(_, q)| q.username.as_deref().is_some_and(|u| u.eq_ignore_ascii_case(name)))
+            .map(|(&id, _)| UserId(id))
+    }
+}
+
+pub fn format_minutes(seconds: u64) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grant_extends_allowance() {
+        let mut data = QuotaData::default();
+        let user = UserId(42);
+        data.record_usage(user, Some("alice"), 600);
+
+        assert_eq!(data.remaining_seconds(user, Some(10)), Some(0));
+        data.grant_minutes(user, 5);
+        assert_eq!(data.remaining_seconds(user, Some(10)), Some(300));
+        assert_eq!(data.remaining_seconds(user, None), None);
+
+        data.set_limit(user, 60);
+        assert_eq!(data.remaining_seconds(user, Some(10)), Some(65 * 60 - 600));
+    }
+
+    #[test]
+    fn test_huge_grants_saturate() {
+        let mut data = QuotaData::default();
+        let user = UserId(42);
+        data.set_limit(user, u64::MAX);
+        data.grant_minutes(user, u64::MAX);
+        data.grant_minutes(user, 1);
+        assert_eq!(data.allowance_seconds(user, None), Some(u64::MAX));
+        data.record_usage(user, None, u64::MAX);
+        data.record_usage(user, None, 1);
+        assert_eq!(data.remaining_seconds(user, None), Some(0));
+    }
+
+    #[test]
+    fn test_roll_month_resets_usage_and_grants() {
+        let mut data = QuotaData { month: "2026-01".to_string(), ..Default::default() };
+        let user = UserId(7);
+        data.record_usage(user, None, 120);
+        data.grant_minutes(user, 30);
+        data.set_limit(user, 100);
+
+        assert!(data.roll_month("2026-02"));
+        let quota = &data.users[&7];
+        assert_eq!(quota.used_seconds, 0);
+        assert_eq!(quota.granted_minutes, 0);
+        assert_eq!(quota.limit_minutes, Some(100));
+        assert!(!data.roll_month("2026-02"));
+    }
+
+    #[test]
+    fn test_resolve_user() {
+        let mut data = QuotaData::default();
+        data.remember_username(UserId(99), "Bob");
+
+        assert_eq!(data.resolve_user("@bob"), Some(UserId(99)));
+        assert_eq!(data.resolve_user("12345"), Some(UserId(12345)));
+        assert_eq!(data.resolve_user("@nobody"), None);
+    }
+}
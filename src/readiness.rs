@@ -0,0 +1,186 @@
+use crate::stt::{SttError, SttProvider};
+use crate::{BotConfig, CurrentProvider, SharedConfig};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+
+/// Longest a single check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Provider key checks call paid APIs; probes in between reuse the result.
+const PROVIDER_CHECK_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Passed with an optional note, or failed with the reason.
+type CheckResult = Result<Option<String>, String>;
+
+/// Outcome of one readiness check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: CheckResult) -> Self {
+        match result {
+            Ok(detail) => Self { name, ok: true, detail },
+            Err(detail) => Self { name, ok: false, detail: Some(detail) },
+        }
+    }
+}
+
+/// What `/ready` needs from the running bot.
+#[derive(Clone)]
+pub struct Probe {
+    pub bot: Bot,
+    pub config: SharedConfig,
+    pub current_provider: CurrentProvider,
+    pub worker: Arc<tokio::task::JoinHandle<()>>,
+}
+
+static PROVIDER_CHECK: Mutex<Option<(SttProvider, Instant, CheckResult)>> = Mutex::new(None);
+
+async fn with_timeout(check: impl std::future::Future<Output = CheckResult>) -> CheckResult {
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(format!("no answer within {}s", CHECK_TIMEOUT.as_secs())))
+}
+
+async fn check_ffmpeg() -> CheckResult {
+    match tokio::task::spawn_blocking(crate::audio::is_ffmpeg_available).await {
+        Ok(true) => Ok(None),
+        _ => Err("ffmpeg not found".to_string()),
+    }
+}
+
+async fn check_telegram(bot: &Bot) -> CheckResult {
+    let me = bot.get_me().await.map_err(|e| e.to_string())?;
+    Ok(me.username.clone().map(|name| format!("@{}", name)))
+}
+
+/// A cheap authenticated request that fails on a bad key. Providers
+/// without one pass with a note.
+async fn check_provider_key(provider: SttProvider, config: &BotConfig) -> CheckResult {
+    let rejected = |e: SttError| match e {
+        SttError::Authentication => "API key rejected".to_string(),
+        e => e.to_string(),
+    };
+    match provider {
+        SttProvider::Whisper => {
+            let api_key = config.openai_api_key.as_deref().ok_or("OPENAI_API_KEY not set")?;
+            let response = crate::http::client()
+                .get(format!("{}/models", config.openai_base_url))
+                .bearer_auth(api_key)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            match response.status().as_u16() {
+                401 | 403 => Err("API key rejected".to_string()),
+                _ => Ok(None),
+            }
+        }
+        SttProvider::ElevenLabs => {
+            let api_key = config.elevenlabs_api_key.as_deref().ok_or("ELEVENLABS_API_KEY not set")?;
+            crate::stt::elevenlabs::get_user_credits(api_key, &config.elevenlabs_base_url).await.map_err(rejected)?;
+            Ok(None)
+        }
+        SttProvider::Deepgram => {
+            let api_key = config.deepgram_api_key.as_deref().ok_or("DEEPGRAM_API_KEY not set")?;
+            crate::stt::deepgram::get_balance(api_key, &config.deepgram_base_url).await.map_err(rejected)?;
+            Ok(None)
+        }
+        SttProvider::LocalWhisper => match &config.whisper_model_path {
+            Some(path) if std::path::Path::new(path).exists() => Ok(None),
+            Some(path) => Err(format!("model {} not found", path)),
+            None => Err("WHISPER_MODEL_PATH not set".to_string()),
+        },
+        SttProvider::AssemblyAi => {
+            let api_key = config.assemblyai_api_key.as_deref().ok_or("ASSEMBLYAI_API_KEY not set")?;
+            let response = crate::http::client()
+                .get("https://api.assemblyai.com/v2/transcript?limit=1")
+                .header("Authorization", api_key)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            match response.status().as_u16() {
+                401 | 403 => Err("API key rejected".to_string()),
+                _ => Ok(None),
+            }
+        }
+        SttProvider::Mock => Ok(Some("mock provider, nothing to check".to_string())),
+        SttProvider::Google | SttProvider::Azure | SttProvider::Vosk => {
+            if config.has_provider_key(provider) {
+                Ok(Some("credentials set, not verified".to_string()))
+            } else {
+                Err("credentials not set".to_string())
+            }
+        }
+    }
+}
+
+/// The key check for `provider`, cached for `PROVIDER_CHECK_TTL`.
+async fn cached_provider_check(provider: SttProvider, config: &BotConfig) -> CheckResult {
+    if let Some((checked, at, result)) = PROVIDER_CHECK.lock().expect("provider check lock poisoned").as_ref()
+        && *checked == provider
+        && at.elapsed() < PROVIDER_CHECK_TTL
+    {
+        return result.clone();
+    }
+    let result = with_timeout(check_provider_key(provider, config)).await;
+    *PROVIDER_CHECK.lock().expect("provider check lock poisoned") = Some((provider, Instant::now(), result.clone()));
+    result
+}
+
+/// Runs every readiness check.
+pub async fn run(probe: &Probe) -> Vec<Check> {
+    let config = probe.config.read().await.clone();
+    let provider = *probe.current_provider.read().await;
+    let worker = if probe.worker.is_finished() { Err("queue worker stopped".to_string()) } else { Ok(None) };
+
+    let (ffmpeg, telegram, provider_key) = tokio::join!(
+        with_timeout(check_ffmpeg()),
+        with_timeout(check_telegram(&probe.bot)),
+        cached_provider_check(provider, &config),
+    );
+    vec![
+        Check::new("ffmpeg", ffmpeg),
+        Check::new("telegram", telegram),
+        Check::new(provider.as_str(), provider_key),
+        Check::new("queue_worker", worker),
+    ]
+}
+
+/// The `/ready` body and whether every check passed.
+pub fn report(checks: &[Check]) -> (serde_json::Value, bool) {
+    let ready = checks.iter().all(|check| check.ok);
+    let body = serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": checks,
+    });
+    (body, ready)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let checks = vec![
+            Check::new("ffmpeg", Ok(None)),
+            Check::new("telegram", Ok(Some("@stt_bot".to_string()))),
+        ];
+        let (body, ready) = report(&checks);
+        assert!(ready);
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["checks"][1]["detail"], "@stt_bot");
+        assert!(body["checks"][0].get("detail").is_none());
+
+        let (body, ready) = report(&[Check::new("queue_worker", Err("queue worker stopped".to_string()))]);
+        assert!(!ready);
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(body["checks"][0]["ok"], false);
+    }
+}
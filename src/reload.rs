@@ -0,0 +1,132 @@
+use crate::{BotConfig, CurrentProvider, Result, SharedConfig, persistence};
+use tracing::{error, info};
+
+/// Carries over settings that were used to set up connections and the
+/// queue at startup; they only change with a restart.
+fn keep_startup_settings(old: &BotConfig, new: &mut BotConfig) {
+    new.telegram_token = old.telegram_token.clone();
+    new.proxy_url = old.proxy_url.clone();
+    new.telegram_proxy_url = old.telegram_proxy_url.clone();
+    new.telegram_api_url = old.telegram_api_url.clone();
+    new.queue_capacity = old.queue_capacity;
+    new.queue_backend = old.queue_backend.clone();
+    new.queue_visibility_timeout_secs = old.queue_visibility_timeout_secs;
+    new.queue_worker_only = old.queue_worker_only;
+    new.storage_backend = old.storage_backend.clone();
+    new.pushgateway_url = old.pushgateway_url.clone();
+    new.pushgateway_job = old.pushgateway_job.clone();
+    new.pushgateway_interval_secs = old.pushgateway_interval_secs;
+}
+
+/// What a reload changed, for the reply and the log.
+fn changes(old: &BotConfig, new: &BotConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if old.stt_provider != new.stt_provider || old.stt_fallbacks != new.stt_fallbacks {
+        changed.push("provider");
+    }
+    if old.openai_api_key != new.openai_api_key
+        || old.elevenlabs_api_key != new.elevenlabs_api_key
+        || old.deepgram_api_key != new.deepgram_api_key
+        || old.google_credentials_json != new.google_credentials_json
+        || old.azure_speech_key != new.azure_speech_key
+    {
+        changed.push("API keys");
+    }
+    if old.whisper_model != new.whisper_model
+        || old.elevenlabs_model != new.elevenlabs_model
+        || old.google_model != new.google_model
+        || old.google_use_enhanced != new.google_use_enhanced
+    {
+        changed.push("models");
+    }
+    if old.bot_password != new.bot_password {
+        changed.push("password");
+    }
+    if old.admin_user_ids != new.admin_user_ids {
+        changed.push("admins");
+    }
+    if old.quota_minutes_per_month != new.quota_minutes_per_month
+        || old.max_file_size_mb != new.max_file_size_mb
+        || old.max_duration_secs != new.max_duration_secs
+        || old.url_allowed_domains != new.url_allowed_domains
+        || old.url_max_file_mb != new.url_max_file_mb
+        || old.spam_repeat_limit != new.spam_repeat_limit
+        || old.spam_window_minutes != new.spam_window_minutes
+        || old.min_free_disk_mb != new.min_free_disk_mb
+        || old.provider_budgets != new.provider_budgets
+        || old.monthly_budget_usd != new.monthly_budget_usd
+    {
+        changed.push("limits");
+    }
+    changed
+}
+
+/// Re-reads `.env` and the environment and swaps in the new configuration
+/// without touching the queue. An invalid configuration is rejected and the
+/// current one kept. Returns a summary of what changed.
+pub async fn reload(config: &SharedConfig, current_provider: &CurrentProvider) -> Result<String> {
+    dotenvy::dotenv_override().ok();
+    let mut new = BotConfig::from_env()?;
+
+    let mut config = config.write().await;
+    keep_startup_settings(&config, &mut new);
+    let changed = changes(&config, &new);
+
+    // A changed STT_PROVIDER wins over /setprovider, and so does losing the key
+    let mut provider = current_provider.write().await;
+    if new.stt_provider != config.stt_provider || !new.has_provider_key(*provider) {
+        *provider = new.stt_provider;
+        if let Err(e) = persistence::save_runtime_config(new.stt_provider).await {
+            error!("Failed to persist provider after reload: {}", e);
+        }
+    }
+
+    *config = new;
+    let summary = if changed.is_empty() {
+        "no changes to provider, keys, password, admins or limits".to_string()
+    } else {
+        format!("updated {}", changed.join(", "))
+    };
+    info!("Configuration reloaded: {}", summary);
+    Ok(summary)
+}
+
+/// Reloads the configuration on SIGHUP.
+#[cfg(unix)]
+pub async fn on_sighup(config: SharedConfig, current_provider: CurrentProvider) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+        if let Err(e) = reload(&config, &current_provider).await {
+            error!("Configuration reload failed, keeping the current one: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_keeps_startup_settings() {
+        let old = BotConfig { queue_capacity: Some(50), ..BotConfig::for_tests() };
+        let mut new = BotConfig {
+            telegram_token: "other".to_string(),
+            queue_capacity: Some(10),
+            bot_password: Some("secret".to_string()),
+            max_file_size_mb: Some(50),
+            ..BotConfig::for_tests()
+        };
+        keep_startup_settings(&old, &mut new);
+        assert_eq!(new.telegram_token, old.telegram_token);
+        assert_eq!(new.queue_capacity, Some(50));
+        assert_eq!(changes(&old, &new), ["password", "limits"]);
+        assert!(changes(&old, &old.clone()).is_empty());
+    }
+}
@@ -1,78 +1,317 @@
-use std::path::Path;
-use log::{info, error};
-use chrono::{DateTime, Utc};
+//! The request log: one JSON line per processed file in
+//! `REQUEST_LOG_DIR/requests.jsonl`, rotated daily and past
+//! `REQUEST_LOG_MAX_MB`, and exported as CSV by /export.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
-use teloxide::types::UserId;
-use crate::{BotError, Result};
-
-const LOGS_DIR: &str = "data/logs";
-const LOG_FILE: &str = "data/logs/transcription_requests.log";
-
-pub async fn log_transcription_request(
-    user_id: UserId,
-    username: Option<&str>,
-    audio_length: usize,
-) -> Result<()> {
-    // Create logs directory if it doesn't exist
-    if let Some(parent) = Path::new(LOG_FILE).parent() {
-        if !parent.exists() {
-            tokio::fs::create_dir_all(parent).await.map_err(BotError::Io)?;
-            info!("Created logs directory: {}", parent.display());
+use crate::{BotConfig, BotError, Result, queue::QueueItem};
+
+const LOG_FILE: &str = "requests.jsonl";
+/// Rotated logs are `requests-<time>.jsonl`.
+const ROTATED_PREFIX: &str = "requests-";
+/// The comma-separated log written before the JSON one; still exported.
+const LEGACY_LOG_FILE: &str = "transcription_requests.log";
+const LEGACY_TIMESTAMP_FORMAT: &str = "%Y-%m-%d-%H-%M-%S";
+
+/// Columns of the `/export` CSV.
+const CSV_HEADER: &str = "timestamp,item_id,user_id,username,chat_id,duration_secs,file_bytes,provider,outcome,latency_ms,error";
+
+/// One processed file. Legacy entries leave out what wasn't logged then.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RequestLogEntry {
+    pub at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_id: Option<String>,
+    pub user_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+    /// Decoded length when transcribed, else the length Telegram reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+    pub file_bytes: u64,
+    /// Provider that transcribed the file, or the chat's choice if it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// `transcribed`, `no_speech`, `silent`, `music`, `repeated`,
+    /// `cancelled`, `quota_exceeded`, `budget_exhausted` or `failed`.
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RequestLogEntry {
+    /// An entry for `item` with `outcome`; the caller fills in the rest.
+    pub fn new(item: &QueueItem, outcome: &str, latency: Duration) -> Self {
+        Self {
+            at: Utc::now(),
+            item_id: Some(item.id.clone()),
+            user_id: item.user_id.0,
+            username: item.username.clone(),
+            chat_id: Some(item.chat_id.0),
+            duration_secs: Some(item.duration_secs as u64),
+            file_bytes: item.media.size(),
+            provider: item.options.provider.map(|provider| provider.as_str().to_string()),
+            outcome: outcome.to_string(),
+            latency_ms: Some(latency.as_millis() as u64),
+            error: None,
         }
     }
+}
 
-    // Format timestamp
-    let now: DateTime<Utc> = Utc::now();
-    let timestamp = now.format("%Y-%m-%d-%H-%M-%S").to_string();
+/// Parses a line of the legacy comma-separated log, where every entry was a
+/// transcribed file.
+fn parse_legacy_line(line: &str) -> Option<RequestLogEntry> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if fields.len() < 5 {
+        return None;
+    }
+    Some(RequestLogEntry {
+        at: NaiveDateTime::parse_from_str(fields[0], LEGACY_TIMESTAMP_FORMAT).ok()?.and_utc(),
+        item_id: None,
+        user_id: fields[1].parse().ok()?,
+        username: Some(fields[2]).filter(|name| !name.is_empty()).map(str::to_string),
+        chat_id: fields.get(5).and_then(|field| field.parse().ok()),
+        duration_secs: fields.get(6).and_then(|field| field.parse().ok()),
+        file_bytes: fields[3].parse().ok()?,
+        provider: Some(fields[4].to_string()),
+        outcome: "transcribed".to_string(),
+        latency_ms: fields.get(7).and_then(|field| field.parse().ok()),
+        error: None,
+    })
+}
 
-    // Format log entry
-    let log_entry = if let Some(username) = username {
-        format!("{}, {}, {}, {}\n", timestamp, user_id.0, username, audio_length)
-    } else {
-        format!("{}, {}, , {}\n", timestamp, user_id.0, audio_length)
+/// Moves `requests.jsonl` aside when it was last written on an earlier day
+/// or has grown past `REQUEST_LOG_MAX_MB`, keeping the newest
+/// `REQUEST_LOG_KEEP` rotated files.
+async fn rotate_if_needed(dir: &Path, config: &BotConfig, now: DateTime<Utc>) -> Result<()> {
+    let current = dir.join(LOG_FILE);
+    let Ok(metadata) = tokio::fs::metadata(&current).await else {
+        return Ok(());
     };
+    let modified: DateTime<Utc> = metadata.modified().map(Into::into).unwrap_or(now);
+    let too_large = config.request_log_max_mb.is_some_and(|mb| metadata.len() >= mb * 1024 * 1024);
+    if !too_large && modified.date_naive() == now.date_naive() {
+        return Ok(());
+    }
 
-    // Append to log file
-    match OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(LOG_FILE)
-        .await
-    {
-        Ok(mut file) => {
-            if let Err(e) = file.write_all(log_entry.as_bytes()).await {
-                error!("Failed to write to transcription log: {}", e);
-                return Err(BotError::Io(e));
-            }
+    // Named after the last entry in it, not the one starting the next file
+    let rotated = dir.join(format!("{}{}.jsonl", ROTATED_PREFIX, modified.format("%Y%m%d-%H%M%S-%3f")));
+    tokio::fs::rename(&current, &rotated).await.map_err(BotError::Io)?;
+    info!("Rotated the request log to {}", rotated.display());
 
-            if let Err(e) = file.flush().await {
-                error!("Failed to flush transcription log: {}", e);
-                return Err(BotError::Io(e));
-            }
+    let mut rotated_files = rotated_logs(dir).await?;
+    let excess = rotated_files.len().saturating_sub(config.request_log_keep);
+    for old in rotated_files.drain(..excess) {
+        if let Err(e) = tokio::fs::remove_file(&old).await {
+            warn!("Failed to remove old request log {}: {}", old.display(), e);
+        }
+    }
+    Ok(())
+}
 
-            info!("Logged transcription request for user {}: {} bytes", user_id.0, audio_length);
-            Ok(())
+/// Rotated logs in `dir`, oldest first.
+async fn rotated_logs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(BotError::Io(e)),
+    };
+    while let Some(entry) = entries.next_entry().await.map_err(BotError::Io)? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(ROTATED_PREFIX) && name.ends_with(".jsonl") {
+            files.push(entry.path());
         }
-        Err(e) => {
-            error!("Failed to open transcription log file: {}", e);
-            Err(BotError::Io(e))
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Appends `entry` to the request log in `REQUEST_LOG_DIR`.
+pub async fn append(entry: &RequestLogEntry, config: &BotConfig) -> Result<()> {
+    let dir = &config.request_log_dir;
+    if !dir.exists() {
+        tokio::fs::create_dir_all(dir).await.map_err(BotError::Io)?;
+        info!("Created logs directory: {}", dir.display());
+    }
+    rotate_if_needed(dir, config, entry.at).await?;
+
+    let mut line = serde_json::to_string(entry)
+        .map_err(|e| BotError::Config(format!("Failed to serialize request log entry: {}", e)))?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(dir.join(LOG_FILE)).await.map_err(BotError::Io)?;
+    file.write_all(line.as_bytes()).await.map_err(BotError::Io)?;
+    file.flush().await.map_err(BotError::Io)?;
+    Ok(())
+}
+
+/// Every entry in `dir`, oldest first: the legacy log, rotated logs and the
+/// current one.
+pub async fn read_all(dir: &Path) -> Result<Vec<RequestLogEntry>> {
+    let mut entries = Vec::new();
+    match tokio::fs::read_to_string(dir.join(LEGACY_LOG_FILE)).await {
+        Ok(log) => entries.extend(log.lines().filter_map(parse_legacy_line)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(BotError::Io(e)),
+    }
+
+    let mut files = rotated_logs(dir).await?;
+    files.push(dir.join(LOG_FILE));
+    for path in files {
+        let log = match tokio::fs::read_to_string(&path).await {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(BotError::Io(e)),
+        };
+        for line in log.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping a malformed line in {}: {}", path.display(), e),
+            }
         }
     }
+    entries.sort_by_key(|entry| entry.at);
+    Ok(entries)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional_field<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// `entries` as CSV, optionally only one month (`YYYY-MM`).
+pub fn to_csv(entries: &[RequestLogEntry], month: Option<&str>) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for entry in entries {
+        if month.is_some_and(|month| entry.at.format("%Y-%m").to_string() != month) {
+            continue;
+        }
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{}\n",
+            entry.at.format("%Y-%m-%dT%H:%M:%SZ"),
+            csv_field(entry.item_id.as_deref().unwrap_or_default()),
+            entry.user_id,
+            csv_field(entry.username.as_deref().unwrap_or_default()),
+            optional_field(entry.chat_id),
+            optional_field(entry.duration_secs),
+            entry.file_bytes,
+            csv_field(entry.provider.as_deref().unwrap_or_default()),
+            csv_field(&entry.outcome),
+            optional_field(entry.latency_ms),
+            csv_field(entry.error.as_deref().unwrap_or_default())
+        ));
+    }
+    csv
+}
+
+/// The request log as CSV for `/export`.
+pub async fn export_csv(config: &BotConfig, month: Option<&str>) -> Result<String> {
+    Ok(to_csv(&read_all(&config.request_log_dir).await?, month))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
-    use tempfile::TempDir;
+    use chrono::TimeZone;
+
+    fn entry(at: DateTime<Utc>, outcome: &str) -> RequestLogEntry {
+        RequestLogEntry {
+            at,
+            item_id: Some("item-1".to_string()),
+            user_id: 42,
+            username: Some("ann".to_string()),
+            chat_id: Some(-100),
+            duration_secs: Some(31),
+            file_bytes: 2048,
+            provider: Some("deepgram".to_string()),
+            outcome: outcome.to_string(),
+            latency_ms: Some(1500),
+            error: None,
+        }
+    }
 
     #[tokio::test]
     async fn test_log_transcription_request() {
-        let temp_dir = TempDir::new().unwrap();
-        let temp_path = temp_dir.path().join("test_log.txt");
+        let dir = tempfile::tempdir().unwrap();
+        let config = BotConfig { request_log_dir: dir.path().join("logs"), ..BotConfig::for_tests() };
 
-        // This is a basic test structure - actual testing would require
-        // modifying the module to accept custom log paths
+        let logged = entry(Utc.with_ymd_and_hms(2026, 3, 1, 12, 30, 5).unwrap(), "transcribed");
+        append(&logged, &config).await.unwrap();
+
+        let content = tokio::fs::read_to_string(config.request_log_dir.join(LOG_FILE)).await.unwrap();
+        assert_eq!(content.lines().count(), 1);
+        let line: serde_json::Value = serde_json::from_str(content.trim_end()).unwrap();
+        assert_eq!(line["user_id"], 42);
+        assert_eq!(line["provider"], "deepgram");
+        assert_eq!(line["outcome"], "transcribed");
+        assert_eq!(read_all(&config.request_log_dir).await.unwrap(), [logged]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_legacy_line() {
+        let parsed = parse_legacy_line("2026-03-01-12-30-05, 42, ann, 2048, deepgram, -100, 31, 1500").unwrap();
+        assert_eq!(parsed, RequestLogEntry { item_id: None, ..entry(Utc.with_ymd_and_hms(2026, 3, 1, 12, 30, 5).unwrap(), "transcribed") });
+        let old = parse_legacy_line("2026-02-28-23-59-59, 7, , 100, whisper").unwrap();
+        assert_eq!((old.username, old.chat_id, old.latency_ms), (None, None, None));
+        assert_eq!(parse_legacy_line("not an entry"), None);
+    }
+
+    #[tokio::test]
+    async fn test_append_rotate_and_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = BotConfig {
+            request_log_dir: dir.path().to_path_buf(),
+            request_log_max_mb: None,
+            request_log_keep: 1,
+            ..BotConfig::for_tests()
+        };
+        tokio::fs::write(dir.path().join(LEGACY_LOG_FILE), "2026-02-28-23-59-59, 7, , 100, whisper\n").await.unwrap();
+
+        let now = Utc::now();
+        append(&entry(now, "transcribed"), &config).await.unwrap();
+        let failed = RequestLogEntry { error: Some("STT provider error: 500, \"down\"".to_string()), ..entry(now, "failed") };
+        append(&failed, &config).await.unwrap();
+        assert!(rotated_logs(dir.path()).await.unwrap().is_empty());
+
+        // Each new day starts a new file; only the newest rotated one is kept
+        let next_day = now + chrono::Duration::days(1);
+        append(&entry(next_day, "music"), &config).await.unwrap();
+        let log = std::fs::File::options().write(true).open(dir.path().join(LOG_FILE)).unwrap();
+        log.set_modified(next_day.into()).unwrap();
+        append(&entry(now + chrono::Duration::days(3), "silent"), &config).await.unwrap();
+        let rotated = rotated_logs(dir.path()).await.unwrap();
+        assert_eq!(rotated.len(), 1);
+        let rotated_name = rotated[0].file_name().unwrap().to_string_lossy().into_owned();
+        assert!(rotated_name.starts_with(&format!("{}{}", ROTATED_PREFIX, next_day.format("%Y%m%d"))));
+
+        let entries = read_all(dir.path()).await.unwrap();
+        let outcomes: Vec<_> = entries.iter().map(|entry| (entry.user_id, entry.outcome.as_str())).collect();
+        assert_eq!(outcomes, [(7, "transcribed"), (42, "music"), (42, "silent")]);
+
+        let csv = to_csv(&[failed], None);
+        assert_eq!(
+            csv.lines().nth(1).unwrap(),
+            format!(
+                "{},item-1,42,ann,-100,31,2048,deepgram,failed,1500,\"STT provider error: 500, \"\"down\"\"\"",
+                now.format("%Y-%m-%dT%H:%M:%SZ")
+            )
+        );
+        assert_eq!(to_csv(&entries, Some("2026-02")).lines().count(), 2);
+    }
+}
@@ -0,0 +1,109 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+use teloxide::types::UserId;
+
+/// Per-user access beyond `ADMIN_USER_IDS`. Authorized users are persisted
+/// in `data/authorized_users.json`, banned ones in `data/roles.json`.
+#[derive(Debug, Default)]
+pub struct Roles {
+    /// Users who entered `BOT_PASSWORD` or were authorized by an admin,
+    /// with when that happened.
+    pub authorized: HashMap<UserId, DateTime<Utc>>,
+    /// Users whose messages are ignored, password or not.
+    pub banned: HashSet<UserId>,
+}
+
+impl Roles {
+    /// Drops authorizations older than `ttl`. Returns true if any expired.
+    pub fn expire(&mut self, ttl: Option<Duration>, now: DateTime<Utc>) -> bool {
+        let Some(ttl) = ttl else {
+            return false;
+        };
+        let before = self.authorized.len();
+        self.authorized.retain(|_, &mut at| now - at < ttl);
+        self.authorized.len() != before
+    }
+}
+
+/// An admin's change to a user's role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoleChange {
+    Ban,
+    Unban,
+    Authorize,
+    Revoke,
+}
+
+impl RoleChange {
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::Ban => "ban",
+            Self::Unban => "unban",
+            Self::Authorize => "authorize",
+            Self::Revoke => "revoke",
+        }
+    }
+
+    /// Message key for the state the change leaves the user in.
+    pub fn state_key(&self) -> &'static str {
+        match self {
+            Self::Ban => "role.banned",
+            Self::Unban => "role.unbanned",
+            Self::Authorize => "role.authorized",
+            Self::Revoke => "role.revoked",
+        }
+    }
+
+    /// Applies the change. Banning also revokes and authorizing also
+    /// unbans. Returns false when nothing changed.
+    pub fn apply(&self, roles: &mut Roles, user: UserId) -> bool {
+        match self {
+            Self::Ban => {
+                let revoked = roles.authorized.remove(&user).is_some();
+                roles.banned.insert(user) || revoked
+            }
+            Self::Unban => roles.banned.remove(&user),
+            Self::Authorize => {
+                let unbanned = roles.banned.remove(&user);
+                roles.authorized.insert(user, Utc::now()).is_none() || unbanned
+            }
+            Self::Revoke => roles.authorized.remove(&user).is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_changes() {
+        let mut roles = Roles::default();
+        let user = UserId(42);
+
+        assert!(RoleChange::Authorize.apply(&mut roles, user));
+        assert!(!RoleChange::Authorize.apply(&mut roles, user));
+
+        assert!(RoleChange::Ban.apply(&mut roles, user));
+        assert!(roles.banned.contains(&user) && !roles.authorized.contains_key(&user));
+
+        assert!(RoleChange::Authorize.apply(&mut roles, user));
+        assert!(roles.authorized.contains_key(&user) && !roles.banned.contains(&user));
+
+        assert!(RoleChange::Revoke.apply(&mut roles, user));
+        assert!(!RoleChange::Unban.apply(&mut roles, user));
+    }
+
+    #[test]
+    fn test_expire() {
+        let now = Utc::now();
+        let mut roles = Roles::default();
+        roles.authorized.insert(UserId(1), now - Duration::hours(30));
+        roles.authorized.insert(UserId(2), now - Duration::hours(2));
+
+        assert!(!roles.expire(None, now));
+        assert!(roles.expire(Some(Duration::hours(24)), now));
+        assert_eq!(roles.authorized.keys().collect::<Vec<_>>(), [&UserId(2)]);
+        assert!(!roles.expire(Some(Duration::hours(24)), now));
+    }
+}
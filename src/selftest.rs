@@ -0,0 +1,144 @@
+use crate::{BotConfig, audio, queue, stt};
+use std::time::{Duration, Instant};
+
+/// Length of the generated sample clip.
+const SAMPLE_SECS: u32 = 2;
+const SAMPLE_RATE: u32 = 16000;
+
+/// Outcome of one pipeline stage.
+pub struct Stage {
+    pub name: &'static str,
+    pub elapsed: Duration,
+    pub result: Result<String, String>,
+}
+
+/// Builds a short 16 kHz mono WAV with a few tone bursts. The bot ships no
+/// recorded speech, so providers are expected to return an empty or
+/// near-empty transcript; the point is that every stage completes.
+pub fn sample_wav() -> Vec<u8> {
+    let samples: Vec<i16> = (0..SAMPLE_SECS * SAMPLE_RATE)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            // 250 ms bursts with pauses, loosely speech-shaped
+            if t % 0.4 < 0.25 {
+                ((t * 220.0 * std::f32::consts::TAU).sin() * 6000.0) as i16
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    audio::decode::wav_bytes(&samples, SAMPLE_RATE, 1)
+}
+
+/// Runs the sample through conversion, the provider and reply formatting,
+/// stopping at the first failing stage.
+pub async fn run(config: &BotConfig, provider: stt::SttProvider) -> Vec<Stage> {
+    let mut stages = vec![Stage {
+        name: "download",
+        elapsed: Duration::ZERO,
+        result: Ok("skipped, using embedded sample".to_string()),
+    }];
+
+    let started = Instant::now();
+    let sample = match tempfile::NamedTempFile::new().and_then(|mut file| {
+        std::io::Write::write_all(&mut file, &sample_wav())?;
+        Ok(file)
+    }) {
+        Ok(sample) => sample,
+        Err(e) => {
+            let result = Err(format!("Failed to write the sample: {}", e));
+            stages.push(Stage { name: "conversion", elapsed: started.elapsed(), result });
+            return stages;
+        }
+    };
+    let converted = audio::convert_for_stt(
+        sample.path(),
+        "selftest.wav",
+        provider,
+        audio::ConversionOptions { timeout: config.ffmpeg_timeout(), ..Default::default() },
+    ).await;
+    let converted = match converted {
+        Ok(converted) => {
+            stages.push(Stage {
+                name: "conversion",
+                elapsed: started.elapsed(),
+                result: Ok(format!("{} bytes of {}", converted.data.len(), converted.format)),
+            });
+            converted
+        }
+        Err(e) => {
+            stages.push(Stage { name: "conversion", elapsed: started.elapsed(), result: Err(e.to_string()) });
+            return stages;
+        }
+    };
+
+    let started = Instant::now();
+    let options = stt::SttOptions::from_config(config);
+    let transcription = match stt::transcribe(&converted, provider, config, &options).await {
+        Ok(transcription) => {
+            stages.push(Stage {
+                name: "transcription",
+                elapsed: started.elapsed(),
+                result: Ok(format!("{} via {}", provider.as_str(), provider.model_for(&options))),
+            });
+            transcription
+        }
+        Err(e) => {
+            stages.push(Stage { name: "transcription", elapsed: started.elapsed(), result: Err(e.to_string()) });
+            return stages;
+        }
+    };
+
+    let started = Instant::now();
+    let body = queue::render_transcript_body(&transcription, config);
+    stages.push(Stage {
+        name: "formatting",
+        elapsed: started.elapsed(),
+        result: Ok(format!("{} chars", body.len())),
+    });
+
+    stages
+}
+
+/// Plain-text report with one line per stage.
+pub fn report(stages: &[Stage]) -> String {
+    let passed = stages.iter().all(|s| s.result.is_ok());
+    let mut lines = vec![format!("🧪 Self-test {}", if passed { "passed" } else { "FAILED" })];
+    for stage in stages {
+        let (icon, detail) = match &stage.result {
+            Ok(detail) => ("✅", detail),
+            Err(error) => ("❌", error),
+        };
+        lines.push(format!("{} {} ({} ms): {}", icon, stage.name, stage.elapsed.as_millis(), detail));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_wav_is_valid() {
+        let wav = sample_wav();
+        let audio = audio::ConvertedAudio { data: wav, format: "wav".to_string(), sample_rate: SAMPLE_RATE, channels: 1 };
+        let samples = audio::analyze::samples_from_converted(&audio).unwrap();
+        assert_eq!(samples.len(), (SAMPLE_SECS * SAMPLE_RATE) as usize);
+
+        let stats = audio::analyze::compute_stats(&samples, SAMPLE_RATE);
+        assert!(!audio::analyze::is_effectively_silent(&stats, audio::analyze::DEFAULT_SILENCE_THRESHOLD_DB));
+    }
+
+    #[test]
+    fn test_report() {
+        let stages = vec![
+            Stage { name: "conversion", elapsed: Duration::from_millis(12), result: Ok("ok".to_string()) },
+            Stage { name: "transcription", elapsed: Duration::from_millis(900), result: Err("401".to_string()) },
+        ];
+        assert_eq!(
+            report(&stages),
+            "🧪 Self-test FAILED\n✅ conversion (12 ms): ok\n❌ transcription (900 ms): 401"
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+use tokio::sync::RwLock;
+use crate::i18n::{self, Locale};
+
+pub type ChatSettingsStore = Arc<RwLock<HashMap<ChatId, ChatSettings>>>;
+
+/// Behavior flags chosen per chat, persisted in `data/chat_settings.json`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ChatSettings {
+    /// Treat media as telephone audio: keep 8 kHz and use phone-call models.
+    #[serde(default)]
+    pub phone_call: bool,
+    /// Append a line with language, duration, provider and word count.
+    #[serde(default)]
+    pub show_metadata: bool,
+    /// Prefix transcripts with the detected language and duration.
+    #[serde(default)]
+    pub language_line: bool,
+    /// Attach a `.json` file with words, timestamps, speakers and confidences.
+    #[serde(default)]
+    pub json_attachment: bool,
+    /// Attach `.srt` and `.vtt` subtitles to transcripts of videos.
+    #[serde(default)]
+    pub subtitles: bool,
+    /// Mask profanity in transcripts.
+    #[serde(default)]
+    pub profanity_filter: bool,
+    /// Never reply to or quote the sender's message; usage is still
+    /// attributed to them internally.
+    #[serde(default)]
+    pub anonymous: bool,
+    /// Media kinds the bot transcribes in this chat; all of them when unset.
+    #[serde(default)]
+    pub allowed_media: Option<Vec<MediaKind>>,
+    /// Refuse forwarded media regardless of kind.
+    #[serde(default)]
+    pub block_forwarded: bool,
+    /// Reply sent instead of the default when media is refused.
+    #[serde(default)]
+    pub media_refusal: Option<String>,
+    /// ISO 639-1 code passed to providers; auto-detected when unset.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Overrides `GROUP_MODE` for this group.
+    #[serde(default)]
+    pub group_mode: Option<GroupMode>,
+    /// Overrides `AUDIO_PREPROCESS` for this chat.
+    #[serde(default)]
+    pub preprocess: Option<crate::audio::Preprocess>,
+    /// Language of the bot's messages; each sender's Telegram language
+    /// when unset.
+    #[serde(default)]
+    pub locale: Option<crate::i18n::Locale>,
+    /// In groups, skip queue and progress messages and only post results.
+    #[serde(default)]
+    pub quiet: bool,
+    /// Transcribe with this provider instead of the bot-wide one.
+    #[serde(default)]
+    pub provider: Option<crate::stt::SttProvider>,
+    /// Overrides `REPLY_TEMPLATE` for this chat.
+    #[serde(default)]
+    pub reply_template: Option<String>,
+    /// Hours in which transcripts are held back or posted silently.
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    /// Forum topics with settings of their own, by thread id. Topics not
+    /// listed follow the chat's settings.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub topics: HashMap<i32, ChatSettings>,
+}
+
+/// What happens to a transcript finished during quiet hours.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietMode {
+    /// Held back and posted when the quiet hours end.
+    #[default]
+    Delay,
+    /// Posted right away without a notification.
+    Silent,
+}
+
+/// A daily window such as 23:00-07:00, in the chat's UTC offset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// Minutes after midnight.
+    pub start: u16,
+    pub end: u16,
+    #[serde(default)]
+    pub utc_offset_minutes: i16,
+    #[serde(default)]
+    pub mode: QuietMode,
+}
+
+fn parse_clock(s: &str) -> Option<u16> {
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?);
+    (hours < 24 && minutes < 60 && s.len() <= 5).then_some(hours * 60 + minutes)
+}
+
+/// `+3`, `UTC-4`, `+05:30` as minutes east of UTC.
+fn parse_offset(s: &str) -> Option<i16> {
+    let s = s.to_lowercase();
+    let s = s.strip_prefix("utc").or_else(|| s.strip_prefix("gmt")).unwrap_or(&s);
+    if s.is_empty() {
+        return Some(0);
+    }
+    let (sign, rest) = match s.split_at(1) {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let (hours, minutes) = (hours.parse::<i16>().ok()?, minutes.parse::<i16>().ok()?);
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+fn format_clock(minutes: u16) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+impl QuietHours {
+    /// Parses `/quiethours` arguments: `23:00-07:00 [UTC+3] [delay|silent]`.
+    pub fn parse(args: &str) -> Option<Self> {
+        let mut tokens = args.split_whitespace();
+        let (start, end) = tokens.next()?.split_once('-')?;
+        let mut hours = Self { start: parse_clock(start)?, end: parse_clock(end)?, utc_offset_minutes: 0, mode: QuietMode::Delay };
+        if hours.start == hours.end {
+            return None;
+        }
+        for token in tokens {
+            match token.to_lowercase().as_str() {
+                "delay" | "hold" => hours.mode = QuietMode::Delay,
+                "silent" | "silently" => hours.mode = QuietMode::Silent,
+                other => hours.utc_offset_minutes = parse_offset(other)?,
+            }
+        }
+        Some(hours)
+    }
+
+    /// How long until the quiet hours end, if `now` falls inside them.
+    pub fn remaining(&self, now: chrono::DateTime<chrono::Utc>) -> Option<std::time::Duration> {
+        use chrono::Timelike;
+
+        let local = now + chrono::Duration::minutes(self.utc_offset_minutes as i64);
+        let minute = (local.hour() * 60 + local.minute()) as u16;
+        let inside = if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        };
+        if !inside {
+            return None;
+        }
+        let second_of_day = minute as i64 * 60 + local.second() as i64;
+        let until_end = (self.end as i64 * 60 - second_of_day).rem_euclid(24 * 60 * 60);
+        Some(std::time::Duration::from_secs(until_end as u64))
+    }
+
+    /// When the quiet hours end, e.g. `07:00 UTC+03:00`.
+    pub fn end_label(&self) -> String {
+        format!("{} {}", format_clock(self.end), self.offset_label())
+    }
+
+    fn offset_label(&self) -> String {
+        let offset = self.utc_offset_minutes;
+        if offset == 0 {
+            return "UTC".to_string();
+        }
+        let sign = if offset < 0 { '-' } else { '+' };
+        format!("UTC{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+    }
+
+    /// e.g. `23:00-07:00 UTC+03:00, transcripts held until the end`
+    pub fn describe(&self, locale: Locale) -> String {
+        let mode = match self.mode {
+            QuietMode::Delay => i18n::t(locale, "quiet_hours.delay"),
+            QuietMode::Silent => i18n::t(locale, "quiet_hours.silent"),
+        };
+        format!("{}-{} {}, {}", format_clock(self.start), format_clock(self.end), self.offset_label(), mode)
+    }
+}
+
+/// Which media the bot transcribes unprompted in group chats. Replying to
+/// media with `/transcribe` works in every mode.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupMode {
+    /// Every supported media message.
+    #[default]
+    All,
+    /// Media whose caption mentions the bot, or media someone replies to
+    /// with a mention of the bot.
+    Mention,
+    /// Only media someone replies to with `/transcribe`.
+    Reply,
+}
+
+impl GroupMode {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "all" => Some(Self::All),
+            "mention" | "mentions" => Some(Self::Mention),
+            "reply" | "replies" => Some(Self::Reply),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Mention => "mention",
+            Self::Reply => "reply",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Voice,
+    VideoNote,
+    Audio,
+    Video,
+    Document,
+    /// GIF-style clips; Telegram sends them as MP4, some with sound.
+    Animation,
+}
+
+impl MediaKind {
+    pub const ALL: [MediaKind; 6] = [Self::Voice, Self::VideoNote, Self::Audio, Self::Video, Self::Document, Self::Animation];
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "voice" => Some(Self::Voice),
+            "videonote" | "video_note" | "circle" => Some(Self::VideoNote),
+            "audio" => Some(Self::Audio),
+            "video" => Some(Self::Video),
+            "document" | "file" => Some(Self::Document),
+            "animation" | "gif" => Some(Self::Animation),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Voice => "voice",
+            Self::VideoNote => "videonote",
+            Self::Audio => "audio",
+            Self::Video => "video",
+            Self::Document => "document",
+            Self::Animation => "animation",
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn allows_media(&self, kind: MediaKind, forwarded: bool) -> bool {
+        if forwarded && self.block_forwarded {
+            return false;
+        }
+        self.allowed_media.as_ref().is_none_or(|kinds| kinds.contains(&kind))
+    }
+
+    /// Human-readable summary of the media policy, e.g. `voice, videonote (no forwards)`.
+    pub fn media_policy(&self, locale: Locale) -> String {
+        let kinds = match &self.allowed_media {
+            Some(kinds) => kinds.iter().map(MediaKind::as_str).collect::<Vec<_>>().join(", "),
+            None => i18n::t(locale, "media.all").to_string(),
+        };
+        if self.block_forwarded {
+            i18n::tf(locale, "media.no_forwards", &[("kinds", kinds)])
+        } else {
+            kinds
+        }
+    }
+}
+
+/// Settings in a forum topic: its own if it has them, else the chat's.
+pub async fn get_for(store: &ChatSettingsStore, chat_id: ChatId, topic: Option<i32>) -> ChatSettings {
+    let store = store.read().await;
+    let Some(chat) = store.get(&chat_id) else {
+        return ChatSettings::default();
+    };
+    match topic.and_then(|topic| chat.topics.get(&topic)) {
+        Some(settings) => settings.clone(),
+        None => ChatSettings { topics: HashMap::new(), ..chat.clone() },
+    }
+}
+
+/// Stores settings changed in `topic`, or chat-wide without one.
+pub fn put(store: &mut HashMap<ChatId, ChatSettings>, chat_id: ChatId, topic: Option<i32>, settings: ChatSettings) {
+    let chat = store.entry(chat_id).or_default();
+    match topic {
+        Some(topic) => {
+            chat.topics.insert(topic, settings);
+        }
+        None => *chat = ChatSettings { topics: std::mem::take(&mut chat.topics), ..settings },
+    }
+}
+
+/// Parses `on`/`off` style arguments of settings commands.
+pub fn parse_toggle(arg: &str) -> Option<bool> {
+    match arg.trim().to_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Some(true),
+        "off" | "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn toggle_label(enabled: bool, locale: Locale) -> &'static str {
+    i18n::t(locale, if enabled { "toggle.on" } else { "toggle.off" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toggle() {
+        assert_eq!(parse_toggle(" ON "), Some(true));
+        assert_eq!(parse_toggle("off"), Some(false));
+        assert_eq!(parse_toggle(""), None);
+        assert_eq!(parse_toggle("maybe"), None);
+    }
+
+    #[test]
+    fn test_group_mode_from_str() {
+        assert_eq!(GroupMode::from_str(" Mention "), Some(GroupMode::Mention));
+        assert_eq!(GroupMode::from_str("replies"), Some(GroupMode::Reply));
+        assert_eq!(GroupMode::from_str("all"), Some(GroupMode::All));
+        assert_eq!(GroupMode::from_str("never"), None);
+    }
+
+    #[test]
+    fn test_allows_media() {
+        let open = ChatSettings::default();
+        assert!(open.allows_media(MediaKind::Document, true));
+
+        let strict = ChatSettings {
+            allowed_media: Some(vec![MediaKind::Voice, MediaKind::VideoNote]),
+            block_forwarded: true,
+            ..Default::default()
+        };
+        assert!(strict.allows_media(MediaKind::Voice, false));
+        assert!(!strict.allows_media(MediaKind::Voice, true));
+        assert!(!strict.allows_media(MediaKind::Video, false));
+        assert_eq!(strict.media_policy(Locale::En), "voice, videonote (no forwards)");
+    }
+
+    #[tokio::test]
+    async fn test_topic_settings() {
+        let store: ChatSettingsStore = Arc::new(RwLock::new(HashMap::new()));
+        let chat = ChatId(-100);
+        put(&mut *store.write().await, chat, None, ChatSettings { quiet: true, ..Default::default() });
+
+        // A topic starts from the chat's settings and then keeps its own
+        let mut topic = get_for(&store, chat, Some(7)).await;
+        assert!(topic.quiet);
+        topic.group_mode = Some(GroupMode::All);
+        put(&mut *store.write().await, chat, Some(7), topic);
+        put(&mut *store.write().await, chat, None, ChatSettings { quiet: false, ..Default::default() });
+
+        assert_eq!(get_for(&store, chat, Some(7)).await.group_mode, Some(GroupMode::All));
+        assert!(get_for(&store, chat, Some(7)).await.quiet);
+        assert_eq!(get_for(&store, chat, Some(8)).await.group_mode, None);
+        assert!(!get_for(&store, chat, None).await.quiet);
+        assert!(get_for(&store, chat, None).await.topics.is_empty());
+        assert_eq!(store.read().await[&chat].topics.len(), 1);
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        use chrono::TimeZone;
+        let at = |hour, minute| chrono::Utc.with_ymd_and_hms(2026, 3, 1, hour, minute, 0).unwrap();
+
+        let night = QuietHours::parse("23:00-07:00").unwrap();
+        assert_eq!(night.remaining(at(23, 30)), Some(std::time::Duration::from_secs(7 * 3600 + 30 * 60)));
+        assert_eq!(night.remaining(at(6, 59)), Some(std::time::Duration::from_secs(60)));
+        assert_eq!(night.remaining(at(7, 0)), None);
+        assert_eq!(night.remaining(at(12, 0)), None);
+
+        // 20:00 UTC is 23:00 in UTC+3
+        let moscow = QuietHours::parse("23:00-07:00 UTC+3 silent").unwrap();
+        assert_eq!(moscow.mode, QuietMode::Silent);
+        assert!(moscow.remaining(at(20, 0)).is_some());
+        assert!(moscow.remaining(at(4, 0)).is_none());
+        assert_eq!(moscow.describe(Locale::En), "23:00-07:00 UTC+03:00, transcripts posted without a notification");
+        assert_eq!(QuietHours::parse("13:00-14:30 -04:30").unwrap().end_label(), "14:30 UTC-04:30");
+
+        assert_eq!(QuietHours::parse("9:00-9:00"), None);
+        assert_eq!(QuietHours::parse("25:00-07:00"), None);
+        assert_eq!(QuietHours::parse("23:00-07:00 sometimes"), None);
+        assert_eq!(QuietHours::parse(""), None);
+    }
+
+    #[test]
+    fn test_provider_round_trip() {
+        let settings = ChatSettings { provider: Some(crate::stt::SttProvider::Deepgram), ..Default::default() };
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(json.contains(r#""provider":"deepgram""#));
+        assert_eq!(serde_json::from_str::<ChatSettings>(&json).unwrap(), settings);
+        // Settings saved before the field existed
+        assert_eq!(serde_json::from_str::<ChatSettings>("{}").unwrap().provider, None);
+    }
+}
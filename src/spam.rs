@@ -0,0 +1,157 @@
+//! Repeated-clip throttling: the same recording sent over and over, in one
+//! chat or across many, is transcribed a few times and then refused.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use teloxide::types::{ChatId, UserId};
+use tokio::sync::RwLock;
+
+use crate::BotConfig;
+use crate::queue::QueueItem;
+
+pub type SpamStore = Arc<RwLock<SpamTracker>>;
+
+/// Distinct clips remembered; past this, ones not seen within the window
+/// are forgotten.
+const MAX_CLIPS: usize = 10_000;
+/// Senders listed by /spam.
+const REPORT_OFFENDERS: usize = 10;
+
+/// Recent transcriptions by clip fingerprint, and who was refused. Kept in
+/// memory only.
+#[derive(Default)]
+pub struct SpamTracker {
+    /// When each clip was let through, and for which item, oldest first.
+    clips: HashMap<u64, Vec<(DateTime<Utc>, String)>>,
+    offenders: HashMap<UserId, Offender>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Offender {
+    pub user_info: String,
+    /// Copies refused since the bot started.
+    pub refused: u32,
+    pub chats: HashSet<ChatId>,
+    pub last_refused: DateTime<Utc>,
+}
+
+impl SpamTracker {
+    /// Counts a transcription of the clip with `fingerprint` for `item`.
+    /// Returns false, and records the sender, when the clip already went
+    /// through `SPAM_REPEAT_LIMIT` times within `SPAM_WINDOW_MINUTES`.
+    /// Retries and re-runs of an item don't count again.
+    pub fn admit(&mut self, fingerprint: u64, item: &QueueItem, config: &BotConfig, now: DateTime<Utc>) -> bool {
+        let Some(limit) = config.spam_repeat_limit else {
+            return true;
+        };
+        let window = chrono::Duration::minutes(config.spam_window_minutes as i64);
+
+        let seen = self.clips.entry(fingerprint).or_default();
+        seen.retain(|(at, _)| now - *at < window);
+        if seen.iter().any(|(_, id)| *id == item.id) {
+            return true;
+        }
+        if seen.len() < limit as usize {
+            seen.push((now, item.id.clone()));
+            if self.clips.len() > MAX_CLIPS {
+                self.clips.retain(|_, seen| seen.last().is_some_and(|(at, _)| now - *at < window));
+            }
+            return true;
+        }
+
+        let offender = self.offenders.entry(item.user_id).or_insert_with(|| Offender {
+            user_info: item.user_info.clone(),
+            refused: 0,
+            chats: HashSet::new(),
+            last_refused: now,
+        });
+        offender.user_info = item.user_info.clone();
+        offender.refused += 1;
+        offender.chats.insert(item.chat_id);
+        offender.last_refused = now;
+        false
+    }
+
+    /// Text of the admin `/spam` report: who sent the most refused copies.
+    pub fn report(&self, config: &BotConfig) -> String {
+        let Some(limit) = config.spam_repeat_limit else {
+            return "🔁 Repeated-clip throttling is off; set SPAM_REPEAT_LIMIT to turn it on.".to_string();
+        };
+        let mut text = format!(
+            "🔁 Repeated clips: each is transcribed at most {} time{} per {} min\n",
+            limit,
+            if limit == 1 { "" } else { "s" },
+            config.spam_window_minutes
+        );
+        if self.offenders.is_empty() {
+            text.push_str("\nNo copies refused since the bot started.");
+            return text;
+        }
+
+        let mut offenders: Vec<_> = self.offenders.iter().collect();
+        offenders.sort_by(|a, b| b.1.refused.cmp(&a.1.refused).then(b.1.last_refused.cmp(&a.1.last_refused)));
+        for (user_id, offender) in offenders.iter().take(REPORT_OFFENDERS) {
+            text.push_str(&format!(
+                "\n• {} ({}): {} cop{} refused in {} chat{}, last {}",
+                offender.user_info,
+                user_id,
+                offender.refused,
+                if offender.refused == 1 { "y" } else { "ies" },
+                offender.chats.len(),
+                if offender.chats.len() == 1 { "" } else { "s" },
+                offender.last_refused.format("%Y-%m-%d %H:%M UTC")
+            ));
+        }
+        if offenders.len() > REPORT_OFFENDERS {
+            text.push_str(&format!("\n…and {} more", offenders.len() - REPORT_OFFENDERS));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, user: u64, chat: i64) -> QueueItem {
+        let mut item = QueueItem::for_tests(id);
+        item.chat_id = ChatId(chat);
+        item.user_info = format!("user{}", user);
+        item.user_id = UserId(user);
+        item
+    }
+
+    #[test]
+    fn test_repeated_clip_throttled() {
+        let config = BotConfig { spam_repeat_limit: Some(2), spam_window_minutes: 60, ..BotConfig::for_tests() };
+        let now = Utc::now();
+        let mut tracker = SpamTracker::default();
+
+        assert!(tracker.admit(42, &item("a", 1, 10), &config, now));
+        assert!(tracker.admit(42, &item("b", 2, 20), &config, now));
+        // A retry of an item already let through isn't a new copy
+        assert!(tracker.admit(42, &item("a", 1, 10), &config, now));
+        assert!(!tracker.admit(42, &item("c", 3, 30), &config, now));
+        assert!(!tracker.admit(42, &item("d", 3, 40), &config, now));
+        assert!(tracker.admit(7, &item("e", 3, 40), &config, now));
+        // Once the window has passed the clip goes through again
+        assert!(tracker.admit(42, &item("f", 3, 40), &config, now + chrono::Duration::minutes(61)));
+
+        let offender = &tracker.offenders[&UserId(3)];
+        assert_eq!((offender.refused, offender.chats.len()), (2, 2));
+        let report = tracker.report(&config);
+        assert!(report.contains("user3 (3): 2 copies refused in 2 chats"), "{}", report);
+        assert!(!report.contains("user1"));
+    }
+
+    #[test]
+    fn test_throttling_off() {
+        let config = BotConfig::for_tests();
+        let mut tracker = SpamTracker::default();
+        for id in ["a", "b", "c", "d"] {
+            assert!(tracker.admit(42, &item(id, 1, 10), &config, Utc::now()));
+        }
+        assert!(tracker.report(&config).contains("off"));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::queue_backend::BoxFuture;
+use crate::queue_backend::redis::RedisUrl;
+use crate::{BotError, Result};
+use tracing::info;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const REDIS_KEY_PREFIX: &str = "tg-stt:state";
+
+/// Where the bot's state (users, chat settings, quotas, history, spend)
+/// is kept, from `STORAGE_BACKEND`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageConfig {
+    /// JSON files in this directory.
+    Json(PathBuf),
+    /// One Redis key per document, for instances without a persistent disk
+    /// or sharing state.
+    Redis(RedisUrl),
+    /// A SQLite database file, for atomic saves without running a server.
+    Sqlite(PathBuf),
+}
+
+/// Keeps named JSON documents, e.g. `quotas`. The pending queue's media
+/// always stays on disk.
+pub trait Storage: Send + Sync {
+    /// The document called `name`; None if it was never saved.
+    fn load<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>>;
+
+    fn save<'a>(&'a self, name: &'a str, contents: String) -> BoxFuture<'a, Result<()>>;
+
+    /// Where `name` is kept, for logs.
+    fn location(&self, name: &str) -> String;
+}
+
+/// One `<name>.json` file per document in a directory.
+pub struct JsonFiles {
+    dir: PathBuf,
+}
+
+impl JsonFiles {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+impl Storage for JsonFiles {
+    fn load<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            match tokio::fs::read_to_string(self.path(name)).await {
+                Ok(contents) => Ok(Some(contents)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(BotError::Io(e)),
+            }
+        })
+    }
+
+    fn save<'a>(&'a self, name: &'a str, contents: String) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir).await.map_err(BotError::Io)?;
+            tokio::fs::write(self.path(name), contents).await.map_err(BotError::Io)
+        })
+    }
+
+    fn location(&self, name: &str) -> String {
+        self.path(name).display().to_string()
+    }
+}
+
+/// Documents as Redis strings under `REDIS_KEY_PREFIX`.
+pub struct RedisStorage {
+    connection: ConnectionManager,
+}
+
+impl RedisStorage {
+    pub async fn connect(url: RedisUrl) -> Result<Self> {
+        let connection = url.connect().await?;
+        info!("Keeping state in Redis at {}", url.address());
+        Ok(Self { connection })
+    }
+
+    fn key(name: &str) -> String {
+        format!("{}:{}", REDIS_KEY_PREFIX, name)
+    }
+}
+
+impl Storage for RedisStorage {
+    fn load<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move { Ok(self.connection.clone().get(Self::key(name)).await?) })
+    }
+
+    fn save<'a>(&'a self, name: &'a str, contents: String) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { Ok(self.connection.clone().set(Self::key(name), contents).await?) })
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("Redis key {}", Self::key(name))
+    }
+}
+
+/// Schema changes, in order; a database at `user_version` n has had the
+/// first n applied. Only ever append.
+const SQLITE_MIGRATIONS: &[&str] = &[
+    "CREATE TABLE documents (
+        name TEXT PRIMARY KEY,
+        contents TEXT NOT NULL
+    )",
+    "ALTER TABLE documents ADD COLUMN saved_at TEXT",
+];
+
+/// Documents as rows of a `documents` table in a SQLite database.
+pub struct SqliteStorage {
+    path: PathBuf,
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens the database, creating it if needed, and brings its schema up
+    /// to date.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let opened = path.clone();
+        let connection = tokio::task::spawn_blocking(move || {
+            if let Some(dir) = opened.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir).map_err(BotError::Io)?;
+            }
+            let mut connection = rusqlite::Connection::open(&opened)?;
+            migrate(&mut connection)?;
+            Ok::<_, BotError>(connection)
+        })
+        .await
+        .map_err(|e| BotError::Io(std::io::Error::other(e)))??;
+        info!("Keeping state in SQLite at {}", path.display());
+        Ok(Self { path, connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    /// Runs `query` on the connection off the async runtime.
+    async fn with_connection<T, F>(&self, query: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || query(&connection.lock().expect("SQLite lock poisoned")))
+            .await
+            .map_err(|e| BotError::Io(std::io::Error::other(e)))?
+            .map_err(BotError::from)
+    }
+}
+
+/// Applies the migrations the database hasn't had yet, each in its own
+/// transaction together with the version bump.
+fn migrate(connection: &mut rusqlite::Connection) -> rusqlite::Result<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in SQLITE_MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn load<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        let name = name.to_string();
+        Box::pin(self.with_connection(move |connection| {
+            use rusqlite::OptionalExtension;
+            connection
+                .query_row("SELECT contents FROM documents WHERE name = ?1", [&name], |row| row.get(0))
+                .optional()
+        }))
+    }
+
+    fn save<'a>(&'a self, name: &'a str, contents: String) -> BoxFuture<'a, Result<()>> {
+        let name = name.to_string();
+        Box::pin(self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO documents (name, contents, saved_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (name) DO UPDATE SET contents = excluded.contents, saved_at = excluded.saved_at",
+                (&name, &contents, chrono::Utc::now().to_rfc3339()),
+            )?;
+            Ok(())
+        }))
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("{} in {}", name, self.path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_json_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonFiles::new(dir.path().join("data"));
+
+        assert_eq!(storage.load("quotas").await.unwrap(), None);
+        storage.save("quotas", "{}".to_string()).await.unwrap();
+        assert_eq!(storage.load("quotas").await.unwrap().as_deref(), Some("{}"));
+        assert!(storage.location("quotas").ends_with("quotas.json"));
+        assert_eq!(RedisStorage::key("quotas"), "tg-stt:state:quotas");
+    }
+
+    #[tokio::test]
+    async fn test_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data").join("state.sqlite3");
+        let storage = SqliteStorage::open(&path).await.unwrap();
+
+        assert_eq!(storage.load("quotas").await.unwrap(), None);
+        storage.save("quotas", "{}".to_string()).await.unwrap();
+        storage.save("quotas", r#"{"month":"2026-10"}"#.to_string()).await.unwrap();
+        storage.save("history", "[]".to_string()).await.unwrap();
+        assert_eq!(storage.load("quotas").await.unwrap().as_deref(), Some(r#"{"month":"2026-10"}"#));
+        assert!(storage.location("quotas").ends_with("state.sqlite3"));
+        drop(storage);
+
+        // Reopening keeps the documents and doesn't migrate again
+        let storage = SqliteStorage::open(&path).await.unwrap();
+        assert_eq!(storage.load("history").await.unwrap().as_deref(), Some("[]"));
+        let version: usize = storage
+            .with_connection(|connection| connection.query_row("PRAGMA user_version", [], |row| row.get(0)))
+            .await
+            .unwrap();
+        assert_eq!(version, SQLITE_MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_sqlite_migrates_old_schema() {
+        let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+        connection.execute_batch(SQLITE_MIGRATIONS[0]).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        connection.execute("INSERT INTO documents (name, contents) VALUES ('roles', '{}')", []).unwrap();
+
+        migrate(&mut connection).unwrap();
+        let (contents, saved_at): (String, Option<String>) = connection
+            .query_row("SELECT contents, saved_at FROM documents WHERE name = 'roles'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((contents.as_str(), saved_at), ("{}", None));
+    }
+}
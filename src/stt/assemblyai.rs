@@ -0,0 +1,352 @@
+use super::{Chapter, Segment, SttError, SttOptions, Transcription, Word};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
+use crate::audio::ConvertedAudio;
+use tracing::{debug, info};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// How often a submitted job is asked whether it's done.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Deserialize)]
+struct UploadResponse {
+    upload_url: String,
+}
+
+#[derive(Deserialize)]
+struct AaiWord {
+    text: String,
+    /// Milliseconds, like every AssemblyAI offset.
+    start: u64,
+    confidence: Option<f32>,
+    speaker: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AaiUtterance {
+    speaker: String,
+    text: String,
+    start: u64,
+    end: u64,
+    confidence: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct AaiChapter {
+    start: u64,
+    end: u64,
+    headline: String,
+    summary: String,
+}
+
+/// A transcript job, as returned when it's submitted and when polled.
+#[derive(Deserialize)]
+struct AaiTranscript {
+    id: String,
+    /// `queued`, `processing`, `completed` or `error`
+    status: String,
+    text: Option<String>,
+    language_code: Option<String>,
+    #[serde(default)]
+    words: Option<Vec<AaiWord>>,
+    #[serde(default)]
+    utterances: Option<Vec<AaiUtterance>>,
+    #[serde(default)]
+    chapters: Option<Vec<AaiChapter>>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AaiErrorResponse {
+    error: Option<String>,
+}
+
+/// Optional AssemblyAI analyses, from `ASSEMBLYAI_SPEAKER_LABELS` and
+/// `ASSEMBLYAI_AUTO_CHAPTERS`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Features {
+    pub speaker_labels: bool,
+    pub auto_chapters: bool,
+}
+
+fn secs(ms: u64) -> f32 {
+    ms as f32 / 1000.0
+}
+
+fn api_error(status: reqwest::StatusCode, body: &str) -> SttError {
+    match status.as_u16() {
+        401 | 403 => SttError::Authentication,
+        429 => SttError::RateLimit,
+        500..=599 => SttError::ServiceUnavailable,
+        _ => {
+            let message = serde_json::from_str::<AaiErrorResponse>(body).ok().and_then(|e| e.error);
+            SttError::Api(message.unwrap_or_else(|| format!("HTTP {}: {}", status, body)))
+        }
+    }
+}
+
+/// Reads a JSON reply, mapping error statuses to `SttError`.
+async fn parse<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T, SttError> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(api_error(status, &body));
+    }
+    serde_json::from_str(&body)
+        .map_err(|e| SttError::InvalidResponse(format!("Failed to parse AssemblyAI response: {}", e)))
+}
+
+/// The finished job as a transcript. With speaker labels the text is one
+/// `Speaker A: ...` line per turn, as for call recordings.
+fn to_transcription(transcript: AaiTranscript) -> Transcription {
+    let words = transcript
+        .words
+        .unwrap_or_default()
+        .into_iter()
+        .map(|w| Word {
+            text: w.text,
+            confidence: w.confidence,
+            start_secs: Some(secs(w.start)),
+            speaker: w.speaker.map(|speaker| format!("Speaker {}", speaker)),
+        })
+        .collect();
+    let utterances = transcript.utterances.unwrap_or_default();
+    let text = if utterances.is_empty() {
+        transcript.text.unwrap_or_default().trim().to_string()
+    } else {
+        utterances
+            .iter()
+            .map(|u| format!("Speaker {}: {}", u.speaker, u.text.trim()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let segments = utterances
+        .into_iter()
+        .map(|u| Segment { start_secs: secs(u.start), end_secs: secs(u.end), text: u.text.trim().to_string(), confidence: u.confidence })
+        .collect();
+    let chapters = transcript
+        .chapters
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| Chapter { start_secs: secs(c.start), end_secs: secs(c.end), headline: c.headline, summary: c.summary })
+        .collect();
+
+    Transcription {
+        language: transcript.language_code,
+        words,
+        segments,
+        chapters,
+        ..Transcription::from_text(text)
+    }
+}
+
+/// Uploads the audio, submits a transcript job and polls it until it's
+/// done or `timeout` has passed.
+async fn transcribe_at(
+    base_url: &str,
+    audio: &ConvertedAudio,
+    api_key: &str,
+    options: &SttOptions<'_>,
+    features: Features,
+    timeout: Option<Duration>,
+    poll_interval: Duration,
+) -> Result<Transcription, SttError> {
+    let model = super::SttProvider::AssemblyAi.model();
+    info!(
+        "Starting transcription provider=assemblyai model={} bytes={} format={}",
+        model,
+        audio.data.len(),
+        audio.format
+    );
+
+    let client = crate::http::client();
+    let started = Instant::now();
+
+    let upload: UploadResponse = parse(
+        client
+            .post(format!("{}/upload", base_url))
+            .header("Authorization", api_key)
+            .header("Content-Type", "application/octet-stream")
+            .body(audio.data.clone())
+            .send()
+            .await?,
+    )
+    .await?;
+
+    let mut request = json!({
+        "audio_url": upload.upload_url,
+        "speech_model": model,
+        "filter_profanity": options.profanity_filter,
+        "speaker_labels": features.speaker_labels,
+        "auto_chapters": features.auto_chapters,
+    });
+    match options.language {
+        Some(language) => request["language_code"] = json!(language),
+        None => request["language_detection"] = json!(true),
+    }
+    let mut transcript: AaiTranscript = parse(
+        client
+            .post(format!("{}/transcript", base_url))
+            .header("Authorization", api_key)
+            .json(&request)
+            .send()
+            .await?,
+    )
+    .await?;
+    debug!("AssemblyAI transcript {} submitted", transcript.id);
+
+    loop {
+        match transcript.status.as_str() {
+            "completed" => break,
+            "error" => {
+                return Err(SttError::Api(transcript.error.unwrap_or_else(|| "transcription failed".to_string())));
+            }
+            _ if timeout.is_some_and(|timeout| started.elapsed() + poll_interval > timeout) => {
+                return Err(SttError::Timeout);
+            }
+            _ => {}
+        }
+        tokio::time::sleep(poll_interval).await;
+        transcript = parse(
+            client
+                .get(format!("{}/transcript/{}", base_url, transcript.id))
+                .header("Authorization", api_key)
+                .send()
+                .await?,
+        )
+        .await?;
+    }
+
+    let transcription = to_transcription(transcript);
+    info!(
+        "Transcription complete provider=assemblyai model={} chars={} secs={:.1}",
+        model,
+        transcription.text.len(),
+        started.elapsed().as_secs_f32()
+    );
+    Ok(transcription)
+}
+
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    api_key: &str,
+    base_url: &str,
+    options: &SttOptions<'_>,
+    features: Features,
+    timeout: Option<Duration>,
+) -> Result<Transcription, SttError> {
+    transcribe_at(base_url, audio, api_key, options, features, timeout, POLL_INTERVAL).await
+}
+
+/// AssemblyAI's asynchronous transcription: upload, submit, poll.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        config.assemblyai_api_key.as_ref().map(|_| ()).ok_or_else(|| "ASSEMBLYAI_API_KEY required for AssemblyAI".to_string())
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        AudioFormat::FLAC
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            let api_key = config.assemblyai_api_key.as_ref()
+                .ok_or_else(|| SttError::Api("AssemblyAI API key not configured".to_string()))?;
+            let features = Features {
+                speaker_labels: config.assemblyai_speaker_labels,
+                auto_chapters: config.assemblyai_auto_chapters,
+            };
+            let timeout = config.stt_timeout_secs.map(Duration::from_secs);
+            transcribe(audio, api_key, &config.assemblyai_base_url, options, features, timeout).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::Filter;
+
+    #[test]
+    fn test_to_transcription() {
+        let transcript: AaiTranscript = serde_json::from_str(
+            r#"{
+                "id": "job", "status": "completed", "text": "Hi there. Hello.", "language_code": "en",
+                "words": [
+                    {"text": "Hi", "start": 100, "end": 300, "confidence": 0.9, "speaker": "A"},
+                    {"text": "there.", "start": 300, "end": 600, "confidence": 0.8, "speaker": "A"},
+                    {"text": "Hello.", "start": 1200, "end": 1500, "confidence": 0.95, "speaker": "B"}
+                ],
+                "utterances": [
+                    {"speaker": "A", "text": "Hi there.", "start": 100, "end": 600, "confidence": 0.85, "words": []},
+                    {"speaker": "B", "text": "Hello.", "start": 1200, "end": 1500, "confidence": 0.95, "words": []}
+                ],
+                "chapters": [{"start": 100, "end": 1500, "headline": "Greetings", "summary": "Two people say hello.", "gist": "Hello"}]
+            }"#,
+        )
+        .unwrap();
+        let transcription = to_transcription(transcript);
+
+        assert_eq!(transcription.text, "Speaker A: Hi there.\nSpeaker B: Hello.");
+        assert_eq!(transcription.words[2].speaker.as_deref(), Some("Speaker B"));
+        assert_eq!(transcription.words[2].start_secs, Some(1.2));
+        assert_eq!((transcription.segments[1].start_secs, transcription.segments[1].end_secs), (1.2, 1.5));
+        assert_eq!(transcription.chapters[0].headline, "Greetings");
+        assert_eq!(transcription.language.as_deref(), Some("en"));
+
+        let plain: AaiTranscript =
+            serde_json::from_str(r#"{"id": "job", "status": "completed", "text": " Hi. ", "utterances": null, "chapters": null}"#)
+                .unwrap();
+        assert_eq!(to_transcription(plain).text, "Hi.");
+    }
+
+    #[tokio::test]
+    async fn test_polls_until_completed() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        let upload = warp::post()
+            .and(warp::path!("v2" / "upload"))
+            .map(|| warp::reply::json(&json!({"upload_url": "https://cdn.example/audio"})));
+        let submit = warp::post()
+            .and(warp::path!("v2" / "transcript"))
+            .and(warp::body::json())
+            .map(|request: serde_json::Value| {
+                assert_eq!(request["audio_url"], "https://cdn.example/audio");
+                assert_eq!(request["language_detection"], true);
+                warp::reply::json(&json!({"id": "job", "status": "queued"}))
+            });
+        let poll = warp::get().and(warp::path!("v2" / "transcript" / String)).map(move |id: String| {
+            assert_eq!(id, "job");
+            let reply = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                json!({"id": "job", "status": "processing"})
+            } else {
+                json!({"id": "job", "status": "completed", "text": "Done.", "language_code": "en"})
+            };
+            warp::reply::json(&reply)
+        });
+        let (address, server) = warp::serve(upload.or(submit).or(poll)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let audio = ConvertedAudio { data: vec![0; 32], format: "flac".to_string(), sample_rate: 16000, channels: 1 };
+        let config = BotConfig::for_tests();
+        let options = SttOptions::from_config(&config);
+        let base_url = format!("http://{}/v2", address);
+        let transcription = transcribe_at(&base_url, &audio, "key", &options, Features::default(), None, Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        assert_eq!(transcription.text, "Done.");
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,423 @@
+use super::{Segment, SttError, SttOptions, Transcription, Word};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
+use crate::audio::ConvertedAudio;
+use tracing::{debug, info};
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+
+/// The short-audio REST endpoint rejects anything longer than this.
+const SHORT_AUDIO_MAX_SECS: f32 = 60.0;
+
+/// Short-audio offsets and durations are in 100 ns ticks.
+const TICKS_PER_SEC: f32 = 10_000_000.0;
+
+/// Locales for languages whose region code isn't simply the upper-cased
+/// language code (`ru` -> `ru-RU` needs no entry).
+const LOCALES: &[(&str, &str)] = &[
+    ("ar", "ar-EG"),
+    ("cs", "cs-CZ"),
+    ("da", "da-DK"),
+    ("el", "el-GR"),
+    ("en", "en-US"),
+    ("fa", "fa-IR"),
+    ("he", "he-IL"),
+    ("hi", "hi-IN"),
+    ("ja", "ja-JP"),
+    ("ka", "ka-GE"),
+    ("kk", "kk-KZ"),
+    ("ko", "ko-KR"),
+    ("no", "nb-NO"),
+    ("pt", "pt-BR"),
+    ("sv", "sv-SE"),
+    ("uk", "uk-UA"),
+    ("vi", "vi-VN"),
+    ("zh", "zh-CN"),
+];
+
+// Short-audio REST response (`format=detailed`)
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ShortResponse {
+    recognition_status: String,
+    #[serde(default)]
+    offset: u64,
+    #[serde(default)]
+    duration: u64,
+    #[serde(default)]
+    n_best: Vec<ShortHypothesis>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ShortHypothesis {
+    display: String,
+    confidence: Option<f32>,
+}
+
+// Fast transcription response
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FastResponse {
+    #[serde(default)]
+    combined_phrases: Vec<FastCombinedPhrase>,
+    #[serde(default)]
+    phrases: Vec<FastPhrase>,
+}
+
+#[derive(Deserialize)]
+struct FastCombinedPhrase {
+    text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FastPhrase {
+    offset_milliseconds: u64,
+    duration_milliseconds: u64,
+    text: String,
+    locale: Option<String>,
+    confidence: Option<f32>,
+    #[serde(default)]
+    words: Vec<FastWord>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FastWord {
+    text: String,
+    offset_milliseconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct AzureErrorResponse {
+    error: Option<AzureErrorDetails>,
+}
+
+#[derive(Deserialize)]
+struct AzureErrorDetails {
+    message: String,
+}
+
+/// Azure locale (`ru-RU`) for an ISO 639-1 code.
+fn locale_for(language: &str) -> String {
+    LOCALES
+        .iter()
+        .find(|(code, _)| *code == language)
+        .map(|(_, locale)| locale.to_string())
+        .unwrap_or_else(|| format!("{}-{}", language, language.to_uppercase()))
+}
+
+/// Where the two endpoints of a Speech resource live.
+struct Hosts {
+    short_audio: String,
+    fast: String,
+}
+
+impl Hosts {
+    /// The region's own hosts, or `base_url` for both.
+    fn new(region: &str, base_url: Option<&str>) -> Self {
+        match base_url {
+            Some(base_url) => Self { short_audio: base_url.to_string(), fast: base_url.to_string() },
+            None => Self {
+                short_audio: format!("https://{}.stt.speech.microsoft.com", region),
+                fast: format!("https://{}.api.cognitive.microsoft.com", region),
+            },
+        }
+    }
+}
+
+/// Transcribes with Azure Speech. Clips up to a minute go to the short-audio
+/// endpoint; longer recordings use fast transcription, which recognizes
+/// continuously and returns the whole result in one response without
+/// staging audio in Blob Storage as batch transcription would.
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    api_key: &str,
+    region: &str,
+    base_url: Option<&str>,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    info!(
+        "Starting transcription provider=azure bytes={} format={}",
+        audio.data.len(),
+        audio.format
+    );
+
+    if audio.format != "wav" {
+        return Err(SttError::Api("Azure requires WAV audio".to_string()));
+    }
+
+    let bytes_per_sec = (audio.sample_rate * 2 * audio.channels as u32).max(1) as f32;
+    let duration_secs = audio.data.len() as f32 / bytes_per_sec;
+
+    let hosts = Hosts::new(region, base_url);
+    let transcription = if duration_secs <= SHORT_AUDIO_MAX_SECS {
+        transcribe_short(audio, api_key, &hosts.short_audio, options).await?
+    } else {
+        transcribe_fast(audio, api_key, &hosts.fast, options).await?
+    };
+
+    info!("Transcription complete provider=azure chars={}", transcription.text.len());
+    Ok(transcription)
+}
+
+/// Azure masks profanity unless told otherwise; only do it when asked.
+fn profanity_mode(options: &SttOptions<'_>) -> &'static str {
+    if options.profanity_filter { "masked" } else { "raw" }
+}
+
+async fn transcribe_short(
+    audio: &ConvertedAudio,
+    api_key: &str,
+    host: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    let locale = locale_for(options.language.unwrap_or("en"));
+    debug!("Sending request to Azure short-audio recognition ({})", locale);
+
+    let response = crate::http::client()
+        .post(format!("{}/speech/recognition/conversation/cognitiveservices/v1", host))
+        .query(&[("language", locale.as_str()), ("format", "detailed"), ("profanity", profanity_mode(options))])
+        .header("Ocp-Apim-Subscription-Key", api_key)
+        .header(
+            "Content-Type",
+            format!("audio/wav; codecs=audio/pcm; samplerate={}", audio.sample_rate),
+        )
+        .body(audio.data.clone())
+        .send()
+        .await?;
+
+    let body = check_status(response).await?;
+    let short: ShortResponse = serde_json::from_str(&body)
+        .map_err(|e| SttError::InvalidResponse(format!("Failed to parse Azure response: {}", e)))?;
+    parse_short(short, locale)
+}
+
+fn parse_short(short: ShortResponse, locale: String) -> Result<Transcription, SttError> {
+    match short.recognition_status.as_str() {
+        "Success" => {}
+        // Nothing recognizable, not an error
+        "NoMatch" | "InitialSilenceTimeout" | "BabbleTimeout" => {
+            return Ok(Transcription::default());
+        }
+        other => return Err(SttError::Api(format!("Azure recognition failed: {}", other))),
+    }
+
+    let confidence = short.n_best.first().and_then(|h| h.confidence);
+    let mut hypotheses = short.n_best.into_iter().map(|h| h.display.trim().to_string());
+    let text = hypotheses.next().unwrap_or_default();
+    let alternatives = hypotheses.filter(|alt| !alt.is_empty() && *alt != text).collect();
+
+    let start_secs = short.offset as f32 / TICKS_PER_SEC;
+    let segments = vec![Segment {
+        start_secs,
+        end_secs: start_secs + short.duration as f32 / TICKS_PER_SEC,
+        text: text.clone(),
+        confidence,
+    }];
+
+    Ok(Transcription {
+        text,
+        alternatives,
+        words: Vec::new(),
+        language: Some(locale),
+        segments,
+        chapters: Vec::new(),
+    })
+}
+
+async fn transcribe_fast(
+    audio: &ConvertedAudio,
+    api_key: &str,
+    host: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    // Without locales Azure identifies the language itself
+    let mut definition = match options.language {
+        Some(language) => serde_json::json!({ "locales": [locale_for(language)] }),
+        None => serde_json::json!({}),
+    };
+    definition["profanityFilterMode"] = serde_json::json!(if options.profanity_filter { "Masked" } else { "None" });
+    debug!("Sending request to Azure fast transcription ({})", definition);
+
+    let audio_part = Part::bytes(audio.data.clone())
+        .file_name("audio.wav")
+        .mime_str("audio/wav")
+        .map_err(|e| SttError::Api(format!("Failed to create audio part: {}", e)))?;
+    let form = Form::new()
+        .part("audio", audio_part)
+        .text("definition", definition.to_string());
+
+    let response = crate::http::client()
+        .post(format!("{}/speechtotext/transcriptions:transcribe", host))
+        .query(&[("api-version", "2024-11-15")])
+        .header("Ocp-Apim-Subscription-Key", api_key)
+        .multipart(form)
+        .send()
+        .await?;
+
+    let body = check_status(response).await?;
+    let fast: FastResponse = serde_json::from_str(&body)
+        .map_err(|e| SttError::InvalidResponse(format!("Failed to parse Azure response: {}", e)))?;
+    Ok(parse_fast(fast))
+}
+
+fn parse_fast(fast: FastResponse) -> Transcription {
+    let text = fast
+        .combined_phrases
+        .iter()
+        .map(|phrase| phrase.text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let language = fast.phrases.iter().find_map(|phrase| phrase.locale.clone());
+    let segments = fast
+        .phrases
+        .iter()
+        .map(|phrase| Segment {
+            start_secs: phrase.offset_milliseconds as f32 / 1000.0,
+            end_secs: (phrase.offset_milliseconds + phrase.duration_milliseconds) as f32 / 1000.0,
+            text: phrase.text.trim().to_string(),
+            confidence: phrase.confidence,
+        })
+        .collect();
+    let words = fast
+        .phrases
+        .into_iter()
+        .flat_map(|phrase| {
+            // Azure scores whole phrases; each word carries its phrase's score
+            let confidence = phrase.confidence;
+            phrase.words.into_iter().map(move |w| Word {
+                text: w.text,
+                confidence,
+                start_secs: w.offset_milliseconds.map(|ms| ms as f32 / 1000.0),
+                speaker: None,
+            })
+        })
+        .collect();
+
+    Transcription {
+        text,
+        alternatives: Vec::new(),
+        words,
+        language,
+        segments,
+        chapters: Vec::new(),
+    }
+}
+
+/// Returns the body of a successful response, or maps the failure.
+async fn check_status(response: reqwest::Response) -> Result<String, SttError> {
+    let status = response.status();
+    debug!("Azure API response status: {}", status);
+
+    let body = response.text().await?;
+    if status.is_success() {
+        return Ok(body);
+    }
+
+    let message = serde_json::from_str::<AzureErrorResponse>(&body)
+        .ok()
+        .and_then(|e| e.error)
+        .map(|e| e.message)
+        .unwrap_or(body);
+
+    match status.as_u16() {
+        401 | 403 => Err(SttError::Authentication),
+        429 => Err(SttError::RateLimit),
+        503 => Err(SttError::ServiceUnavailable),
+        _ => Err(SttError::Api(format!("HTTP {}: {}", status, message))),
+    }
+}
+
+/// Azure Speech.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        match (&config.azure_speech_key, &config.azure_speech_region) {
+            (Some(_), Some(_)) => Ok(()),
+            _ => Err("AZURE_SPEECH_KEY and AZURE_SPEECH_REGION required for Azure".to_string()),
+        }
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        // Azure Speech takes 16-bit PCM WAV at 8 or 16 kHz
+        AudioFormat::WAV
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            let (Some(api_key), Some(region)) = (&config.azure_speech_key, &config.azure_speech_region) else {
+                return Err(SttError::Api("Azure Speech key or region not configured".to_string()));
+            };
+            transcribe(audio, api_key, region, config.azure_speech_base_url.as_deref(), options).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_for() {
+        assert_eq!(locale_for("ru"), "ru-RU");
+        assert_eq!(locale_for("en"), "en-US");
+        assert_eq!(locale_for("uk"), "uk-UA");
+    }
+
+    #[test]
+    fn test_parse_short() {
+        let json = r#"{
+            "RecognitionStatus": "Success",
+            "Offset": 1800000,
+            "Duration": 21000000,
+            "NBest": [
+                {"Confidence": 0.97, "Lexical": "hello there", "Display": "Hello there."},
+                {"Confidence": 0.81, "Lexical": "hello their", "Display": "Hello their."}
+            ]
+        }"#;
+        let short: ShortResponse = serde_json::from_str(json).unwrap();
+        let transcription = parse_short(short, "en-US".to_string()).unwrap();
+        assert_eq!(transcription.text, "Hello there.");
+        assert_eq!(transcription.alternatives, ["Hello their."]);
+        assert_eq!((transcription.segments[0].start_secs, transcription.segments[0].end_secs), (0.18, 2.28));
+
+        let silent: ShortResponse = serde_json::from_str(r#"{"RecognitionStatus": "NoMatch"}"#).unwrap();
+        assert!(parse_short(silent, "en-US".to_string()).unwrap().text.is_empty());
+    }
+
+    #[test]
+    fn test_parse_fast() {
+        let json = r#"{
+            "durationMilliseconds": 5200,
+            "combinedPhrases": [{"text": "Hi there. Bye."}],
+            "phrases": [
+                {"offsetMilliseconds": 0, "durationMilliseconds": 900, "text": "Hi there.", "locale": "en-US", "confidence": 0.9,
+                 "words": [{"text": "Hi", "offsetMilliseconds": 0, "durationMilliseconds": 300},
+                           {"text": "there.", "offsetMilliseconds": 320, "durationMilliseconds": 500}]},
+                {"offsetMilliseconds": 4000, "durationMilliseconds": 600, "text": "Bye.", "locale": "en-US", "confidence": 0.7,
+                 "words": [{"text": "Bye.", "offsetMilliseconds": 4000, "durationMilliseconds": 600}]}
+            ]
+        }"#;
+        let fast: FastResponse = serde_json::from_str(json).unwrap();
+        let transcription = parse_fast(fast);
+        assert_eq!(transcription.text, "Hi there. Bye.");
+        assert_eq!(transcription.language.as_deref(), Some("en-US"));
+        assert_eq!(transcription.words.len(), 3);
+        assert_eq!(transcription.words[2].start_secs, Some(4.0));
+        assert_eq!(transcription.words[2].confidence, Some(0.7));
+        assert_eq!(transcription.segments[1].text, "Bye.");
+        assert_eq!(transcription.segments[1].end_secs, 4.6);
+    }
+}
@@ -0,0 +1,155 @@
+use super::{Segment, Transcription, Word};
+
+/// Speaker labels for call recordings, by source channel.
+pub const CHANNEL_LABELS: [&str; 2] = ["Caller", "Callee"];
+
+/// A pause longer than this between two words of the same speaker starts a
+/// new turn, so the other side can be slotted in between.
+const TURN_GAP_SECS: f32 = 1.5;
+
+struct Turn {
+    start_secs: f32,
+    speaker: usize,
+    text: String,
+}
+
+/// Splits one channel's transcript into turns using word start times.
+/// Without timestamps the whole transcript becomes a single turn.
+fn turns(transcription: &Transcription, speaker: usize) -> Vec<Turn> {
+    let timed = !transcription.words.is_empty()
+        && transcription.words.iter().all(|w| w.start_secs.is_some());
+    if !timed {
+        let text = transcription.text.trim();
+        if text.is_empty() {
+            return Vec::new();
+        }
+        return vec![Turn { start_secs: 0.0, speaker, text: text.to_string() }];
+    }
+
+    let mut turns: Vec<Turn> = Vec::new();
+    let mut last_start = f32::NEG_INFINITY;
+    for word in &transcription.words {
+        let start = word.start_secs.unwrap_or_default();
+        match turns.last_mut() {
+            Some(turn) if start - last_start <= TURN_GAP_SECS => {
+                turn.text.push(' ');
+                turn.text.push_str(&word.text);
+            }
+            _ => turns.push(Turn { start_secs: start, speaker, text: word.text.clone() }),
+        }
+        last_start = start;
+    }
+    turns
+}
+
+/// Merges per-channel transcripts into one labelled conversation, ordered
+/// by when each turn starts.
+pub fn interleave(channels: &[Transcription]) -> String {
+    let mut all: Vec<Turn> = channels
+        .iter()
+        .enumerate()
+        .flat_map(|(speaker, transcription)| turns(transcription, speaker))
+        .collect();
+    all.sort_by(|a, b| a.start_secs.total_cmp(&b.start_secs));
+
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for turn in all {
+        match lines.last_mut() {
+            Some((speaker, text)) if *speaker == turn.speaker => {
+                text.push(' ');
+                text.push_str(&turn.text);
+            }
+            _ => lines.push((turn.speaker, turn.text)),
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|(speaker, text)| {
+            let label = CHANNEL_LABELS.get(speaker).copied().unwrap_or("Speaker");
+            format!("{}: {}", label, text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// All words of all channels, labelled with their speaker and ordered by
+/// start time.
+pub fn merge_words(channels: &[Transcription]) -> Vec<Word> {
+    let mut words: Vec<Word> = channels
+        .iter()
+        .enumerate()
+        .flat_map(|(speaker, transcription)| {
+            let label = CHANNEL_LABELS.get(speaker).copied().unwrap_or("Speaker");
+            transcription.words.iter().map(move |w| Word {
+                speaker: Some(label.to_string()),
+                ..w.clone()
+            })
+        })
+        .collect();
+    words.sort_by(|a, b| a.start_secs.unwrap_or_default().total_cmp(&b.start_secs.unwrap_or_default()));
+    words
+}
+
+/// All segments of all channels ordered by start time, each prefixed with
+/// its speaker like the lines of [`interleave`].
+pub fn merge_segments(channels: &[Transcription]) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = channels
+        .iter()
+        .enumerate()
+        .flat_map(|(speaker, transcription)| {
+            let label = CHANNEL_LABELS.get(speaker).copied().unwrap_or("Speaker");
+            transcription.segments.iter().map(move |s| Segment {
+                text: format!("{}: {}", label, s.text),
+                ..s.clone()
+            })
+        })
+        .collect();
+    segments.sort_by(|a, b| a.start_secs.total_cmp(&b.start_secs));
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timed(words: &[(&str, f32)]) -> Transcription {
+        Transcription {
+            text: words.iter().map(|(w, _)| *w).collect::<Vec<_>>().join(" "),
+            words: words
+                .iter()
+                .map(|&(text, start)| Word { text: text.to_string(), confidence: None, start_secs: Some(start), speaker: None })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_interleave_by_timestamp() {
+        let caller = timed(&[("Hi,", 0.0), ("it's", 0.3), ("me.", 0.5), ("Tomorrow", 4.0), ("then.", 4.4)]);
+        let callee = timed(&[("Oh", 1.5), ("hello!", 1.8), ("Sure.", 5.5)]);
+
+        assert_eq!(
+            interleave(&[caller, callee]),
+            "Caller: Hi, it's me.\nCallee: Oh hello!\nCaller: Tomorrow then.\nCallee: Sure."
+        );
+    }
+
+    #[test]
+    fn test_interleave_without_timestamps() {
+        let caller = Transcription::from_text("Hello there.");
+        let callee = Transcription::from_text("  ");
+
+        assert_eq!(interleave(&[caller, callee]), "Caller: Hello there.");
+    }
+
+    #[test]
+    fn test_merge_words() {
+        let caller = timed(&[("Hi", 0.0), ("bye", 3.0)]);
+        let callee = timed(&[("hey", 1.0)]);
+
+        let merged = merge_words(&[caller, callee]);
+        let order: Vec<_> = merged.iter().map(|w| (w.text.as_str(), w.speaker.as_deref())).collect();
+        assert_eq!(order, [("Hi", Some("Caller")), ("hey", Some("Callee")), ("bye", Some("Caller"))]);
+    }
+}
@@ -0,0 +1,151 @@
+use super::Transcription;
+
+/// How the two results of `CONSENSUS_PROVIDERS` become one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConsensusStrategy {
+    /// Keep the result the providers scored higher.
+    #[default]
+    Confidence,
+    /// Align the two word by word and fill in words one of them dropped.
+    Merge,
+}
+
+impl ConsensusStrategy {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "confidence" | "best" => Some(Self::Confidence),
+            "merge" | "diff" => Some(Self::Merge),
+            _ => None,
+        }
+    }
+}
+
+/// Word grids bigger than this (about an hour against an hour) aren't
+/// aligned; the higher-confidence result is kept as is.
+const MAX_ALIGNMENT_CELLS: usize = 4_000_000;
+
+/// Combines the results of the two consensus providers. Returns the
+/// transcription and whether it is based on `second`; the base provides the
+/// timings, and without scores on either side `first` wins.
+pub fn combine(first: Transcription, second: Transcription, strategy: ConsensusStrategy) -> (Transcription, bool) {
+    // An empty result usually means the provider missed the speech
+    if second.text.trim().is_empty() {
+        return (first, false);
+    }
+    if first.text.trim().is_empty() {
+        return (second, true);
+    }
+
+    let second_is_base = match (first.confidence(), second.confidence()) {
+        (Some(a), Some(b)) => b > a,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+    let (base, other) = if second_is_base { (second, first) } else { (first, second) };
+
+    let mut combined = match strategy {
+        ConsensusStrategy::Confidence => base,
+        ConsensusStrategy::Merge => Transcription { text: merge_text(&base.text, &other.text), ..base },
+    };
+    if combined.language.is_none() {
+        combined.language = other.language;
+    }
+    (combined, second_is_base)
+}
+
+/// Word used for comparing: lowercase, without surrounding punctuation.
+fn comparable(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+/// `base`, with the words `other` heard where `base` heard nothing. Where
+/// both heard something different, `base` wins.
+fn merge_text(base: &str, other: &str) -> String {
+    let base_words: Vec<&str> = base.split_whitespace().collect();
+    let other_words: Vec<&str> = other.split_whitespace().collect();
+    if base_words.len() * other_words.len() > MAX_ALIGNMENT_CELLS {
+        return base.to_string();
+    }
+    let a: Vec<String> = base_words.iter().map(|w| comparable(w)).collect();
+    let b: Vec<String> = other_words.iter().map(|w| comparable(w)).collect();
+
+    // Longest common subsequence lengths of every pair of suffixes
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    // Walk the alignment, collecting each run where the two disagree
+    let mut merged = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            merged.push(base_words[i]);
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (start_i, start_j) = (i, j);
+        while (i < a.len() || j < b.len()) && !(i < a.len() && j < b.len() && a[i] == b[j]) {
+            if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        if i > start_i {
+            merged.extend(&base_words[start_i..i]);
+        } else {
+            merged.extend(&other_words[start_j..j]);
+        }
+    }
+    merged.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stt::Segment;
+
+    fn scored(text: &str, confidence: f32) -> Transcription {
+        Transcription {
+            segments: vec![Segment { start_secs: 0.0, end_secs: 1.0, text: text.to_string(), confidence: Some(confidence) }],
+            ..Transcription::from_text(text)
+        }
+    }
+
+    #[test]
+    fn test_combine_by_confidence() {
+        let (result, second) = combine(scored("hello word", 0.7), scored("hello world", 0.9), ConsensusStrategy::Confidence);
+        assert!(second);
+        assert_eq!(result.text, "hello world");
+
+        // Unscored results keep the first; empty ones lose
+        let (result, second) = combine(Transcription::from_text("a"), Transcription::from_text("b"), ConsensusStrategy::Confidence);
+        assert_eq!((result.text.as_str(), second), ("a", false));
+        let (result, second) = combine(Transcription::default(), scored("b", 0.1), ConsensusStrategy::Merge);
+        assert_eq!((result.text.as_str(), second), ("b", true));
+    }
+
+    #[test]
+    fn test_merge_text() {
+        // Words the base dropped are filled in; disagreements keep the base
+        assert_eq!(
+            merge_text("Meet me at the station at five.", "meet me at the train station at 5"),
+            "Meet me at the train station at five."
+        );
+        assert_eq!(merge_text("call mom", "call mom tomorrow please"), "call mom tomorrow please");
+        assert_eq!(merge_text("one two three", ""), "one two three");
+
+        let (result, _) = combine(scored("see you there", 0.9), scored("see you over there", 0.6), ConsensusStrategy::Merge);
+        assert_eq!(result.text, "see you over there");
+        assert_eq!(result.segments[0].text, "see you there");
+    }
+}
@@ -1,21 +1,44 @@
-use super::SttError;
+use super::{Segment, SttError, SttOptions, Transcription, Word};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
 use crate::audio::ConvertedAudio;
-use log::{debug, info};
+use tracing::{debug, info};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
 struct DgAlternative {
     transcript: String,
+    #[serde(default)]
+    words: Vec<DgWord>,
+}
+
+#[derive(Deserialize)]
+struct DgWord {
+    word: String,
+    punctuated_word: Option<String>,
+    confidence: Option<f32>,
+    start: Option<f32>,
 }
 
 #[derive(Deserialize)]
 struct DgChannel {
     alternatives: Vec<DgAlternative>,
+    detected_language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DgUtterance {
+    start: f32,
+    end: f32,
+    transcript: String,
+    confidence: Option<f32>,
 }
 
 #[derive(Deserialize)]
 struct DgResults {
     channels: Vec<DgChannel>,
+    #[serde(default)]
+    utterances: Vec<DgUtterance>,
 }
 
 #[derive(Deserialize)]
@@ -50,9 +73,16 @@ struct DgBalancesResp {
     balances: Vec<DgBalance>,
 }
 
-pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String, SttError> {
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    api_key: &str,
+    base_url: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    let model = super::SttProvider::Deepgram.model_for(options);
     info!(
-        "Starting transcription provider=deepgram model=nova-3 bytes={} format={}",
+        "Starting transcription provider=deepgram model={} bytes={} format={}",
+        model,
         audio.data.len(),
         audio.format
     );
@@ -63,19 +93,28 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
         ));
     }
 
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
 
-    debug!("Sending request to Deepgram /v1/listen (nova-3)");
+    debug!("Sending request to Deepgram /listen ({})", model);
 
+    let alternatives = options.max_alternatives.max(1).to_string();
+    let sample_rate = audio.sample_rate.to_string();
+    let language = match options.language {
+        Some(language) => ("language", language),
+        None => ("detect_language", "true"),
+    };
     let response = client
-        .post("https://api.deepgram.com/v1/listen")
+        .post(format!("{}/listen", base_url))
         .query(&[
-            ("model", "nova-3"),
+            ("model", model),
             ("smart_format", "true"),
-            ("detect_language", "true"),
+            ("utterances", "true"),
+            language,
             ("encoding", "linear16"),
-            ("sample_rate", "16000"),
+            ("sample_rate", sample_rate.as_str()),
             ("channels", "1"),
+            ("alternatives", alternatives.as_str()),
+            ("profanity_filter", if options.profanity_filter { "true" } else { "false" }),
         ])
         .header("Authorization", format!("Token {}", api_key))
         .header("Content-Type", "audio/l16")
@@ -92,20 +131,61 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
         let dg: DgResponse = serde_json::from_str(&body)
             .map_err(|e| SttError::InvalidResponse(format!("Failed to parse Deepgram response: {}", e)))?;
 
-        let transcript = dg
+        let segments = dg
+            .results
+            .utterances
+            .into_iter()
+            .map(|u| Segment {
+                start_secs: u.start,
+                end_secs: u.end,
+                text: u.transcript.trim().to_string(),
+                confidence: u.confidence,
+            })
+            .collect();
+
+        let (hypotheses, language) = dg
             .results
             .channels
             .into_iter()
             .next()
-            .and_then(|ch| ch.alternatives.into_iter().next())
-            .map(|alt| alt.transcript)
+            .map(|ch| (ch.alternatives, ch.detected_language))
             .unwrap_or_default();
+        let mut hypotheses = hypotheses.into_iter();
+
+        let (transcript, words) = match hypotheses.next() {
+            Some(best) => {
+                let words = best
+                    .words
+                    .into_iter()
+                    .map(|w| Word {
+                        text: w.punctuated_word.unwrap_or(w.word),
+                        confidence: w.confidence,
+                        start_secs: w.start,
+                        speaker: None,
+                    })
+                    .collect();
+                (best.transcript.trim().to_string(), words)
+            }
+            None => (String::new(), Vec::new()),
+        };
+        let alternatives = hypotheses
+            .map(|alt| alt.transcript.trim().to_string())
+            .filter(|alt| !alt.is_empty() && *alt != transcript)
+            .collect();
 
         info!(
-            "Transcription complete provider=deepgram model=nova-3 chars={}",
+            "Transcription complete provider=deepgram model={} chars={}",
+            model,
             transcript.len()
         );
-        Ok(transcript.trim().to_string())
+        Ok(Transcription {
+            text: transcript,
+            alternatives,
+            words,
+            language,
+            segments,
+            chapters: Vec::new(),
+        })
     } else {
         let error_body = response.text().await?;
 
@@ -123,14 +203,14 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
     }
 }
 
-pub async fn get_balance(api_key: &str) -> Result<DgBalance, SttError> {
+pub async fn get_balance(api_key: &str, base_url: &str) -> Result<DgBalance, SttError> {
     info!("Getting Deepgram balance");
 
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
     let auth = format!("Token {}", api_key);
 
     let projects_resp = client
-        .get("https://api.deepgram.com/v1/projects")
+        .get(format!("{}/projects", base_url))
         .header("Authorization", &auth)
         .send()
         .await?;
@@ -159,10 +239,7 @@ pub async fn get_balance(api_key: &str) -> Result<DgBalance, SttError> {
         .ok_or_else(|| SttError::Api("No Deepgram projects found for this API key".to_string()))?;
 
     let balances_resp = client
-        .get(format!(
-            "https://api.deepgram.com/v1/projects/{}/balances",
-            project_id
-        ))
+        .get(format!("{}/projects/{}/balances", base_url, project_id))
         .header("Authorization", &auth)
         .send()
         .await?;
@@ -189,3 +266,29 @@ pub async fn get_balance(api_key: &str) -> Result<DgBalance, SttError> {
         .next()
         .ok_or_else(|| SttError::Api("No balances returned for Deepgram project".to_string()))
 }
+
+/// Deepgram's pre-recorded audio API.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        config.deepgram_api_key.as_ref().map(|_| ()).ok_or_else(|| "DEEPGRAM_API_KEY required for Deepgram".to_string())
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        AudioFormat::PCM
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            let api_key = config.deepgram_api_key.as_ref()
+                .ok_or_else(|| SttError::Api("Deepgram API key not configured".to_string()))?;
+            transcribe(audio, api_key, &config.deepgram_base_url, options).await
+        })
+    }
+}
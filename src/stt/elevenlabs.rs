@@ -1,14 +1,30 @@
-use super::SttError;
+use super::{Segment, SttError, SttOptions, Transcription, Word};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
 use crate::audio::ConvertedAudio;
-use log::{debug, info};
+use tracing::{debug, info};
 use reqwest::multipart::{Form, Part};
 use serde::Deserialize;
 
+/// Models `ELEVENLABS_MODEL_ID` may name.
+pub const MODELS: [&str; 2] = ["scribe_v1", "scribe_v1_experimental"];
+
 #[derive(Deserialize)]
 struct ElevenLabsResponse {
     text: String,
+    language_code: Option<String>,
     #[serde(default)]
-    success: bool,
+    words: Vec<ElevenLabsWord>,
+}
+
+#[derive(Deserialize)]
+struct ElevenLabsWord {
+    text: String,
+    start: Option<f32>,
+    end: Option<f32>,
+    /// `word`, `spacing` or `audio_event`
+    #[serde(rename = "type")]
+    kind: String,
 }
 
 #[derive(Deserialize)]
@@ -28,9 +44,15 @@ pub struct ElevenLabsUser {
     pub subscription: ElevenLabsSubscription,
 }
 
-pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String, SttError> {
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    api_key: &str,
+    base_url: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
     info!(
-        "Starting transcription provider=elevenlabs model=scribe_v1_experimental bytes={} format={}",
+        "Starting transcription provider=elevenlabs model={} bytes={} format={}",
+        options.elevenlabs_model,
         audio.data.len(),
         audio.format
     );
@@ -42,7 +64,7 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
         ));
     }
 
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
     
     // Create multipart form data
     let audio_part = Part::bytes(audio.data.clone())
@@ -50,16 +72,19 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
         .mime_str("audio/pcm")
         .map_err(|e| SttError::Api(format!("Failed to create audio part: {}", e)))?;
     
-    let form = Form::new()
-        .text("model_id", "scribe_v1_experimental")
+    let mut form = Form::new()
+        .text("model_id", options.elevenlabs_model.to_string())
         .text("file_format", "pcm_s16le_16")
-        .text("timestamps_granularity", "none")
+        .text("timestamps_granularity", "word")
         .part("file", audio_part);
+    if let Some(language) = options.language {
+        form = form.text("language_code", language);
+    }
 
     debug!("Sending multipart request to ElevenLabs STT API");
 
     let response = client
-        .post("https://api.elevenlabs.io/v1/speech-to-text")
+        .post(format!("{}/speech-to-text", base_url))
         .header("xi-api-key", api_key)
         .multipart(form)
         .send()
@@ -74,18 +99,26 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
         // Try to parse as JSON first
         if let Ok(stt_response) = serde_json::from_str::<ElevenLabsResponse>(&response_text) {
             info!(
-                "Transcription complete provider=elevenlabs model=scribe_v1_experimental chars={}",
+                "Transcription complete provider=elevenlabs model={} chars={}",
+                options.elevenlabs_model,
                 stt_response.text.len()
             );
-            return Ok(stt_response.text.trim().to_string());
+            let (words, segments) = timed_words(&stt_response.words);
+            return Ok(Transcription {
+                language: stt_response.language_code,
+                words,
+                segments,
+                ..Transcription::from_text(stt_response.text.trim())
+            });
         }
 
         // If not JSON, treat as plain text
         info!(
-            "Transcription complete provider=elevenlabs model=scribe_v1_experimental chars={} (plain text)",
+            "Transcription complete provider=elevenlabs model={} chars={} (plain text)",
+            options.elevenlabs_model,
             response_text.len()
         );
-        Ok(response_text.trim().to_string())
+        Ok(Transcription::from_text(response_text.trim()))
     } else {
         let error_text = response.text().await?;
         
@@ -108,13 +141,13 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
     }
 }
 
-pub async fn get_user_credits(api_key: &str) -> Result<ElevenLabsUser, SttError> {
+pub async fn get_user_credits(api_key: &str, base_url: &str) -> Result<ElevenLabsUser, SttError> {
     info!("Getting ElevenLabs user credits");
 
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
 
     let response = client
-        .get("https://api.elevenlabs.io/v1/user")
+        .get(format!("{}/user", base_url))
         .header("xi-api-key", api_key)
         .send()
         .await?;
@@ -155,10 +188,79 @@ pub async fn get_user_credits(api_key: &str) -> Result<ElevenLabsUser, SttError>
     }
 }
 
+/// Words with start times, plus one segment per sentence.
+fn timed_words(raw: &[ElevenLabsWord]) -> (Vec<Word>, Vec<Segment>) {
+    let mut words = Vec::new();
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut open = false;
+
+    for word in raw.iter().filter(|w| w.kind == "word") {
+        let (start, end) = (word.start.unwrap_or_default(), word.end.unwrap_or_default());
+        words.push(Word { text: word.text.clone(), confidence: None, start_secs: word.start, speaker: None });
+
+        match segments.last_mut() {
+            Some(segment) if open => {
+                segment.text.push(' ');
+                segment.text.push_str(&word.text);
+                segment.end_secs = end;
+            }
+            _ => segments.push(Segment { start_secs: start, end_secs: end, text: word.text.clone(), confidence: None }),
+        }
+        open = !word.text.ends_with(['.', '!', '?']);
+    }
+    (words, segments)
+}
+
+/// ElevenLabs speech-to-text.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        config.elevenlabs_api_key.as_ref().map(|_| ()).ok_or_else(|| "ELEVENLABS_API_KEY required for ElevenLabs".to_string())
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        // Raw samples, and only at 16 kHz
+        AudioFormat::PCM.fixed_rate()
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            let api_key = config.elevenlabs_api_key.as_ref()
+                .ok_or_else(|| SttError::Api("ElevenLabs API key not configured".to_string()))?;
+            transcribe(audio, api_key, &config.elevenlabs_base_url, options).await
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_timed_words() {
+        let json = r#"[
+            {"text": "Hi.", "start": 0.1, "end": 0.4, "type": "word"},
+            {"text": " ", "start": 0.4, "end": 0.6, "type": "spacing"},
+            {"text": "(laughs)", "start": 0.6, "end": 1.0, "type": "audio_event"},
+            {"text": "Bye", "start": 1.2, "end": 1.5, "type": "word"},
+            {"text": "now.", "start": 1.6, "end": 1.9, "type": "word"}
+        ]"#;
+        let raw: Vec<ElevenLabsWord> = serde_json::from_str(json).unwrap();
+        let (words, segments) = timed_words(&raw);
+
+        assert_eq!(words.len(), 3);
+        assert_eq!(words[1].start_secs, Some(1.2));
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].text, "Bye now.");
+        assert_eq!((segments[1].start_secs, segments[1].end_secs), (1.2, 1.9));
+    }
+
     #[tokio::test]
     async fn test_transcribe_invalid_format() {
         let audio = ConvertedAudio {
@@ -168,7 +270,7 @@ mod tests {
             channels: 1,
         };
         
-        let result = transcribe(&audio, "test_key").await;
+        let result = transcribe(&audio, "test_key", "https://api.elevenlabs.io/v1", &SttOptions::from_config(&crate::BotConfig::for_tests())).await;
         assert!(result.is_err());
         
         if let Err(SttError::Api(msg)) = result {
@@ -0,0 +1,134 @@
+use super::{SttError, SttOptions, SttProvider, Transcription};
+use crate::{BotConfig, audio::ConvertedAudio};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock, RwLock};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Audio an engine takes: format, encoding and layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub format: &'static str,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub codec: &'static str,
+    /// ffmpeg muxer (`-f`) that produces `format`.
+    pub muxer: &'static str,
+    /// Whether 8 kHz call recordings can be sent at their own rate.
+    pub telephone_rate: bool,
+}
+
+impl AudioFormat {
+    /// Raw PCM s16le samples, 16 kHz mono.
+    pub const PCM: Self = Self { format: "pcm", sample_rate: 16000, channels: 1, codec: "pcm_s16le", muxer: "s16le", telephone_rate: true };
+    /// 16-bit PCM WAV, 16 kHz mono.
+    pub const WAV: Self = Self { format: "wav", sample_rate: 16000, channels: 1, codec: "pcm_s16le", muxer: "wav", telephone_rate: true };
+    /// FLAC, 16 kHz mono.
+    pub const FLAC: Self = Self { format: "flac", sample_rate: 16000, channels: 1, codec: "flac", muxer: "flac", telephone_rate: true };
+
+    /// The same format, always at its default sample rate.
+    pub const fn fixed_rate(self) -> Self {
+        Self { telephone_rate: false, ..self }
+    }
+}
+
+/// A speech-to-text backend. Each provider has a built-in one;
+/// `register` swaps in another, e.g. a mock in tests.
+pub trait SttEngine: Send + Sync {
+    /// Checks `config` has what the engine needs, naming the missing
+    /// setting if not.
+    fn check_config(&self, config: &BotConfig) -> Result<(), String>;
+
+    /// The audio format to convert files to for this engine.
+    fn audio_format(&self) -> AudioFormat;
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>>;
+}
+
+static REGISTRY: OnceLock<RwLock<HashMap<SttProvider, Arc<dyn SttEngine>>>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<HashMap<SttProvider, Arc<dyn SttEngine>>> {
+    REGISTRY.get_or_init(|| {
+        let engines: [(SttProvider, Arc<dyn SttEngine>); 9] = [
+            (SttProvider::Whisper, Arc::new(super::whisper::Engine)),
+            (SttProvider::ElevenLabs, Arc::new(super::elevenlabs::Engine)),
+            (SttProvider::Google, Arc::new(super::google::Engine)),
+            (SttProvider::Deepgram, Arc::new(super::deepgram::Engine)),
+            (SttProvider::LocalWhisper, Arc::new(super::local_whisper::Engine)),
+            (SttProvider::Azure, Arc::new(super::azure::Engine)),
+            (SttProvider::Vosk, Arc::new(super::vosk::Engine)),
+            (SttProvider::Mock, Arc::new(super::mock::Engine)),
+            (SttProvider::AssemblyAi, Arc::new(super::assemblyai::Engine)),
+        ];
+        RwLock::new(HashMap::from(engines))
+    })
+}
+
+/// The engine that handles `provider`.
+pub fn engine(provider: SttProvider) -> Arc<dyn SttEngine> {
+    registry().read().expect("engine registry lock poisoned")[&provider].clone()
+}
+
+/// Makes `engine` handle `provider` from now on, returning the one it
+/// replaces.
+#[allow(dead_code)] // only tests swap engines so far
+pub fn register(provider: SttProvider, engine: Arc<dyn SttEngine>) -> Arc<dyn SttEngine> {
+    registry()
+        .write()
+        .expect("engine registry lock poisoned")
+        .insert(provider, engine)
+        .expect("every provider has an engine")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every request with the same text.
+    struct MockEngine;
+
+    impl SttEngine for MockEngine {
+        // As Vosk's, so tests running meanwhile see the same providers configured
+        fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+            super::super::vosk::Engine.check_config(config)
+        }
+
+        fn audio_format(&self) -> AudioFormat {
+            AudioFormat::PCM
+        }
+
+        fn transcribe<'a>(
+            &'a self,
+            _audio: &'a ConvertedAudio,
+            _config: &'a BotConfig,
+            _options: &'a SttOptions<'_>,
+        ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+            Box::pin(async { Ok(Transcription::from_text("mocked")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_engine_transcribes() {
+        let config = BotConfig::for_tests();
+        let audio = ConvertedAudio { data: vec![0; 32], format: "pcm".to_string(), sample_rate: 16000, channels: 1 };
+        // Vosk: no other test transcribes with it, and both engines take PCM
+        let builtin = register(SttProvider::Vosk, Arc::new(MockEngine));
+        let result = super::super::transcribe(&audio, SttProvider::Vosk, &config, &SttOptions::from_config(&config)).await;
+        register(SttProvider::Vosk, builtin);
+        assert_eq!(result.unwrap().text, "mocked");
+    }
+
+    #[test]
+    fn test_builtin_formats() {
+        assert_eq!(engine(SttProvider::Google).audio_format(), AudioFormat::FLAC);
+        assert!(!engine(SttProvider::ElevenLabs).audio_format().telephone_rate);
+        assert!(engine(SttProvider::Deepgram).audio_format().telephone_rate);
+    }
+}
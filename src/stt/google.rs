@@ -1,9 +1,25 @@
-use super::SttError;
+use super::{Segment, SttError, SttOptions, Transcription, Word};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
 use crate::audio::ConvertedAudio;
-use log::{debug, info};
+use tracing::{debug, info};
 use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
 use serde::{Deserialize, Serialize};
-use base64::Engine;
+use base64::Engine as _;
+
+/// Models `GOOGLE_MODEL` may name.
+pub const MODELS: [&str; 8] = [
+    "default",
+    "latest_long",
+    "latest_short",
+    "command_and_search",
+    "phone_call",
+    "video",
+    "medical_dictation",
+    "medical_conversation",
+];
+/// Models that have an enhanced variant.
+pub const ENHANCED_MODELS: [&str; 2] = ["phone_call", "video"];
 
 #[derive(Serialize)]
 struct GoogleSttRequest {
@@ -22,6 +38,18 @@ struct RecognitionConfig {
     audio_channel_count: u8,
     #[serde(rename = "enableAutomaticPunctuation")]
     enable_automatic_punctuation: bool,
+    #[serde(rename = "maxAlternatives")]
+    max_alternatives: u8,
+    #[serde(rename = "enableWordConfidence")]
+    enable_word_confidence: bool,
+    #[serde(rename = "enableWordTimeOffsets")]
+    enable_word_time_offsets: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(rename = "useEnhanced", skip_serializing_if = "std::ops::Not::not")]
+    use_enhanced: bool,
+    #[serde(rename = "profanityFilter", skip_serializing_if = "std::ops::Not::not")]
+    profanity_filter: bool,
 }
 
 #[derive(Serialize)]
@@ -37,12 +65,26 @@ struct GoogleSttResponse {
 #[derive(Deserialize)]
 struct SpeechRecognitionResult {
     alternatives: Vec<SpeechRecognitionAlternative>,
+    #[serde(rename = "languageCode")]
+    language_code: Option<String>,
+    #[serde(rename = "resultEndTime")]
+    result_end_time: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct SpeechRecognitionAlternative {
     transcript: String,
     confidence: Option<f32>,
+    #[serde(default)]
+    words: Vec<WordInfo>,
+}
+
+#[derive(Deserialize)]
+struct WordInfo {
+    word: String,
+    confidence: Option<f32>,
+    #[serde(rename = "startTime")]
+    start_time: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -53,28 +95,26 @@ struct GoogleErrorResponse {
 #[derive(Deserialize)]
 struct GoogleErrorDetails {
     message: String,
-    code: Option<i32>,
-    status: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct GoogleCredentials {
-    #[serde(rename = "type")]
-    credential_type: String,
-    project_id: String,
     private_key_id: String,
     private_key: String,
     client_email: String,
-    client_id: String,
-    auth_uri: String,
     token_uri: String,
-    auth_provider_x509_cert_url: String,
-    client_x509_cert_url: String,
 }
 
-pub async fn transcribe(audio: &ConvertedAudio, credentials_json: &str) -> Result<String, SttError> {
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    credentials_json: &str,
+    base_url: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    let model = super::SttProvider::Google.model_for(options);
     info!(
-        "Starting transcription provider=google model=default bytes={} format={}",
+        "Starting transcription provider=google model={} bytes={} format={}",
+        model,
         audio.data.len(),
         audio.format
     );
@@ -101,24 +141,27 @@ pub async fn transcribe(audio: &ConvertedAudio, credentials_json: &str) -> Resul
         config: RecognitionConfig {
             encoding: encoding.to_string(),
             sample_rate_hertz: audio.sample_rate,
-            language_code: "en-US".to_string(),
+            language_code: options.language.unwrap_or("en-US").to_string(),
             audio_channel_count: audio.channels,
             enable_automatic_punctuation: true,
+            max_alternatives: options.max_alternatives.max(1),
+            enable_word_confidence: options.word_confidence,
+            enable_word_time_offsets: options.word_timestamps,
+            model: (model != "default").then(|| model.to_string()),
+            use_enhanced: options.phone_call || options.google_use_enhanced,
+            profanity_filter: options.profanity_filter,
         },
         audio: AudioContent {
             content: audio_content,
         },
     };
 
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
     
     debug!("Sending request to Google Cloud STT API");
 
     let response = client
-        .post(&format!(
-            "https://speech.googleapis.com/v1/speech:recognize?key={}",
-            extract_project_key(&credentials)?
-        ))
+        .post(format!("{}/speech:recognize", base_url))
         .header(AUTHORIZATION, format!("Bearer {}", access_token))
         .header(CONTENT_TYPE, "application/json")
         .json(&request)
@@ -131,18 +174,64 @@ pub async fn transcribe(audio: &ConvertedAudio, credentials_json: &str) -> Resul
     if status.is_success() {
         let stt_response: GoogleSttResponse = response.json().await?;
         
-        let transcription = stt_response
+        let mut words = Vec::new();
+        let mut segments = Vec::new();
+        let mut language = None;
+        let results: Vec<Vec<String>> = stt_response
             .results
-            .and_then(|results| results.into_iter().next())
-            .and_then(|result| result.alternatives.into_iter().next())
-            .map(|alt| alt.transcript)
-            .unwrap_or_default();
+            .unwrap_or_default()
+            .into_iter()
+            .map(|result| {
+                if language.is_none() {
+                    language = result.language_code;
+                }
+                let mut alternatives = result.alternatives.into_iter();
+                let best = alternatives.next();
+                if let Some(best) = &best {
+                    // Each result covers the audio since the previous one ended
+                    let start_secs = segments.last().map_or(0.0, |s: &Segment| s.end_secs);
+                    let end_secs = result
+                        .result_end_time
+                        .as_deref()
+                        .and_then(parse_duration)
+                        .unwrap_or(start_secs);
+                    segments.push(Segment {
+                        start_secs,
+                        end_secs,
+                        text: best.transcript.trim().to_string(),
+                        confidence: best.confidence,
+                    });
+                    words.extend(best.words.iter().map(|w| Word {
+                        text: w.word.clone(),
+                        confidence: w.confidence,
+                        start_secs: w.start_time.as_deref().and_then(parse_duration),
+                        speaker: None,
+                    }));
+                }
+                best.into_iter().chain(alternatives).map(|alt| alt.transcript).collect()
+            })
+            .collect();
+
+        let transcription = join_hypotheses(&results, 0);
+        let max_rank = results.iter().map(Vec::len).max().unwrap_or(0);
+        let alternatives = (1..max_rank)
+            .map(|rank| join_hypotheses(&results, rank))
+            .filter(|alt| *alt != transcription)
+            .collect();
 
         info!(
-            "Transcription complete provider=google model=default chars={}",
+            "Transcription complete provider=google model={} chars={}",
+            model,
             transcription.len()
         );
-        Ok(transcription.trim().to_string())
+        Ok(Transcription {
+            text: transcription,
+            alternatives,
+            words,
+            language,
+            segments,
+            chapters: Vec::new(),
+        })
     } else {
         let error_text = response.text().await?;
         
@@ -161,32 +250,182 @@ pub async fn transcribe(audio: &ConvertedAudio, credentials_json: &str) -> Resul
     }
 }
 
-async fn get_access_token(_credentials: &GoogleCredentials) -> Result<String, SttError> {
-    // For simplicity, we'll use service account credentials directly
-    // In production, you might want to implement proper JWT token generation
-    
-    // This is a simplified implementation - you would need to implement
-    // JWT token creation and exchange for access token
-    // For now, we'll assume the credentials contain a direct access token
-    // or use the client_email as a placeholder
-    
-    // Note: In a real implementation, you'd need to:
-    // 1. Create a JWT with the service account private key
-    // 2. Exchange it for an access token at the token_uri
-    
-    Ok("placeholder_token".to_string())
+/// Google returns one result per consecutive stretch of audio, each with its
+/// own ranked alternatives. Builds the full transcript for a given rank,
+/// falling back to the best hypothesis where a result has fewer alternatives.
+fn join_hypotheses(results: &[Vec<String>], rank: usize) -> String {
+    results
+        .iter()
+        .filter_map(|alternatives| alternatives.get(rank).or_else(|| alternatives.first()))
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses protobuf JSON durations such as `"1.500s"`.
+fn parse_duration(s: &str) -> Option<f32> {
+    s.strip_suffix('s')?.parse().ok()
+}
+
+/// Scope needed for Speech-to-Text.
+const TOKEN_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// Lifetime requested for signed assertions; Google caps it at one hour.
+const ASSERTION_LIFETIME_SECS: i64 = 3600;
+
+/// Tokens are refreshed this long before they expire, so a request never
+/// goes out with one that lapses in flight.
+const REFRESH_MARGIN_SECS: i64 = 60;
+
+#[derive(Serialize)]
+struct JwtClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+struct CachedToken {
+    client_email: String,
+    access_token: String,
+    expires_at: i64,
+}
+
+impl CachedToken {
+    fn is_fresh_for(&self, client_email: &str, now: i64) -> bool {
+        self.client_email == client_email && now < self.expires_at - REFRESH_MARGIN_SECS
+    }
+}
+
+static TOKEN_CACHE: tokio::sync::Mutex<Option<CachedToken>> = tokio::sync::Mutex::const_new(None);
+
+/// Returns a cached OAuth2 access token for the service account, or signs a
+/// new JWT assertion and exchanges it at `token_uri`.
+async fn get_access_token(credentials: &GoogleCredentials) -> Result<String, SttError> {
+    // Held across the exchange so concurrent requests don't all refresh
+    let mut cache = TOKEN_CACHE.lock().await;
+    let now = chrono::Utc::now().timestamp();
+
+    if let Some(cached) = cache.as_ref().filter(|c| c.is_fresh_for(&credentials.client_email, now)) {
+        return Ok(cached.access_token.clone());
+    }
+
+    debug!("Requesting Google access token for {}", credentials.client_email);
+
+    let claims = JwtClaims {
+        iss: &credentials.client_email,
+        scope: TOKEN_SCOPE,
+        aud: &credentials.token_uri,
+        iat: now,
+        exp: now + ASSERTION_LIFETIME_SECS,
+    };
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(credentials.private_key.as_bytes())
+        .map_err(|e| SttError::Api(format!("Invalid Google private key: {}", e)))?;
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some(credentials.private_key_id.clone());
+    let assertion = jsonwebtoken::encode(&header, &claims, &key)
+        .map_err(|e| SttError::Api(format!("Failed to sign Google token request: {}", e)))?;
+
+    let response = crate::http::client()
+        .post(&credentials.token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", assertion.as_str()),
+        ])
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await?;
+        return Err(match status.as_u16() {
+            400 | 401 | 403 => SttError::Authentication,
+            _ => SttError::Api(format!("Token exchange failed: HTTP {}: {}", status, error_text)),
+        });
+    }
+
+    let token: TokenResponse = response.json().await?;
+    *cache = Some(CachedToken {
+        client_email: credentials.client_email.clone(),
+        access_token: token.access_token.clone(),
+        expires_at: now + token.expires_in,
+    });
+    info!("Obtained Google access token, valid for {}s", token.expires_in);
+
+    Ok(token.access_token)
 }
 
-fn extract_project_key(credentials: &GoogleCredentials) -> Result<String, SttError> {
-    // Extract API key from project_id or use a configured API key
-    // This is simplified - in practice you'd configure this separately
-    Ok(credentials.project_id.clone())
+/// Google Cloud Speech-to-Text.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        config.google_credentials_json.as_ref().map(|_| ()).ok_or_else(|| "GOOGLE_CREDENTIALS_JSON required for Google".to_string())
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        // Google Cloud STT prefers FLAC or linear16
+        AudioFormat::FLAC
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            let credentials = config.google_credentials_json.as_ref()
+                .ok_or_else(|| SttError::Api("Google credentials not configured".to_string()))?;
+            transcribe(audio, credentials, &config.google_speech_base_url, options).await
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_join_hypotheses() {
+        let results = vec![
+            vec!["hello there".to_string(), "hello their".to_string()],
+            vec![" general kenobi".to_string()],
+        ];
+
+        assert_eq!(join_hypotheses(&results, 0), "hello there general kenobi");
+        assert_eq!(join_hypotheses(&results, 1), "hello their general kenobi");
+        assert_eq!(join_hypotheses(&[], 0), "");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1.500s"), Some(1.5));
+        assert_eq!(parse_duration("0s"), Some(0.0));
+        assert_eq!(parse_duration("1.5"), None);
+    }
+
+    #[test]
+    fn test_cached_token_freshness() {
+        let cached = CachedToken {
+            client_email: "bot@project.iam.gserviceaccount.com".to_string(),
+            access_token: "token".to_string(),
+            expires_at: 10_000,
+        };
+
+        assert!(cached.is_fresh_for("bot@project.iam.gserviceaccount.com", 9_000));
+        assert!(!cached.is_fresh_for("bot@project.iam.gserviceaccount.com", 9_950));
+        assert!(!cached.is_fresh_for("other@project.iam.gserviceaccount.com", 9_000));
+    }
+
     #[test]
     fn test_encoding_mapping() {
         // Test that we correctly map audio formats to Google STT encodings
@@ -204,7 +443,8 @@ mod tests {
             channels: 1,
         };
         
-        let result = transcribe(&audio, invalid_json).await;
+        let options = SttOptions { max_alternatives: 1, word_confidence: false, phone_call: false, word_timestamps: false, language: None, profanity_filter: false, whisper_model: "whisper-1", elevenlabs_model: "scribe_v1", google_model: "default", google_use_enhanced: false, vosk_model: "vosk" };
+        let result = transcribe(&audio, invalid_json, "https://speech.googleapis.com/v1", &options).await;
         assert!(result.is_err());
     }
 }
\ No newline at end of file
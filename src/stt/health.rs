@@ -0,0 +1,185 @@
+use super::{SttError, SttProvider};
+use crate::BotConfig;
+use tracing::{info, warn};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Failure streak and circuit breaker state of one provider.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProviderHealth {
+    pub consecutive_failures: u32,
+    /// Set while the breaker is open; requests go to other providers until
+    /// then, after which one is let through to test the provider again.
+    pub open_until: Option<Instant>,
+    pub last_error: Option<String>,
+    /// Times the breaker opened since startup.
+    pub trips: u64,
+}
+
+impl ProviderHealth {
+    pub fn is_open(&self, now: Instant) -> bool {
+        self.open_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Health of every provider that has been called since startup.
+#[derive(Debug, Default)]
+pub struct HealthRegistry {
+    providers: Vec<(SttProvider, ProviderHealth)>,
+}
+
+impl HealthRegistry {
+    fn entry(&mut self, provider: SttProvider) -> &mut ProviderHealth {
+        let index = match self.providers.iter().position(|(p, _)| *p == provider) {
+            Some(index) => index,
+            None => {
+                self.providers.push((provider, ProviderHealth::default()));
+                self.providers.len() - 1
+            }
+        };
+        &mut self.providers[index].1
+    }
+
+    pub fn get(&self, provider: SttProvider) -> ProviderHealth {
+        self.providers.iter().find(|(p, _)| *p == provider).map(|(_, h)| h.clone()).unwrap_or_default()
+    }
+
+    pub fn record_success(&mut self, provider: SttProvider) {
+        let health = self.entry(provider);
+        if health.open_until.is_some() {
+            info!("Provider {} recovered, closing its circuit breaker", provider.as_str());
+        }
+        health.consecutive_failures = 0;
+        health.open_until = None;
+    }
+
+    /// Counts a failure; `threshold` failures in a row open the breaker for
+    /// `cooldown`. A failed trial after the cooldown opens it again at once.
+    pub fn record_failure(&mut self, provider: SttProvider, error: String, threshold: Option<u32>, cooldown: Duration, now: Instant) {
+        let health = self.entry(provider);
+        health.consecutive_failures += 1;
+        health.last_error = Some(error);
+        if let Some(threshold) = threshold
+            && health.consecutive_failures >= threshold
+            && !health.is_open(now)
+        {
+            warn!(
+                "Provider {} failed {} times in a row, routing around it for {}s",
+                provider.as_str(),
+                health.consecutive_failures,
+                cooldown.as_secs()
+            );
+            health.open_until = Some(now + cooldown);
+            health.trips += 1;
+        }
+    }
+}
+
+static HEALTH: Mutex<HealthRegistry> = Mutex::new(HealthRegistry { providers: Vec::new() });
+
+fn registry() -> std::sync::MutexGuard<'static, HealthRegistry> {
+    HEALTH.lock().expect("provider health lock poisoned")
+}
+
+/// Failures that say the provider, not the audio, is the problem.
+fn counts_against(error: &SttError) -> bool {
+    error.is_transient() || matches!(error, SttError::Authentication)
+}
+
+/// Updates the provider's health after a transcription request.
+pub fn record<T>(provider: SttProvider, result: &Result<T, SttError>, config: &BotConfig) {
+    match result {
+        Ok(_) => registry().record_success(provider),
+        Err(e) if counts_against(e) => registry().record_failure(
+            provider,
+            e.to_string(),
+            config.circuit_breaker_failures,
+            Duration::from_secs(config.circuit_breaker_cooldown_secs),
+            Instant::now(),
+        ),
+        Err(_) => {}
+    }
+}
+
+/// Whether requests should go to the provider; false while its breaker is open.
+pub fn is_available(provider: SttProvider) -> bool {
+    !registry().get(provider).is_open(Instant::now())
+}
+
+pub fn get(provider: SttProvider) -> ProviderHealth {
+    registry().get(provider)
+}
+
+/// One line per provider for `/status`.
+pub fn report(providers: &[SttProvider]) -> String {
+    let now = Instant::now();
+    let mut lines = vec!["🩺 Provider health:".to_string()];
+    for &provider in providers {
+        let health = get(provider);
+        let state = match health.open_until {
+            Some(until) if now < until => format!(
+                "⛔ circuit open, retrying in {}s",
+                until.duration_since(now).as_secs().max(1)
+            ),
+            _ if health.consecutive_failures > 0 => format!(
+                "⚠️ {} failure{} in a row",
+                health.consecutive_failures,
+                if health.consecutive_failures == 1 { "" } else { "s" }
+            ),
+            _ => "✅ healthy".to_string(),
+        };
+        let last_error = match (&health.last_error, health.consecutive_failures) {
+            (Some(error), 1..) => format!(" (last error: {})", error),
+            _ => String::new(),
+        };
+        lines.push(format!("• {}: {}{}", provider.as_str(), state, last_error));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut registry = HealthRegistry::default();
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(60);
+        let fail = |registry: &mut HealthRegistry, at| {
+            registry.record_failure(SttProvider::Deepgram, "Service unavailable".to_string(), Some(3), cooldown, at)
+        };
+
+        fail(&mut registry, now);
+        fail(&mut registry, now);
+        assert!(!registry.get(SttProvider::Deepgram).is_open(now));
+        fail(&mut registry, now);
+        let health = registry.get(SttProvider::Deepgram);
+        assert!(health.is_open(now));
+        assert_eq!((health.consecutive_failures, health.trips), (3, 1));
+
+        // After the cooldown a failed trial opens it again right away
+        let later = now + cooldown + Duration::from_secs(1);
+        assert!(!registry.get(SttProvider::Deepgram).is_open(later));
+        fail(&mut registry, later);
+        assert!(registry.get(SttProvider::Deepgram).is_open(later));
+        assert_eq!(registry.get(SttProvider::Deepgram).trips, 2);
+
+        registry.record_success(SttProvider::Deepgram);
+        let health = registry.get(SttProvider::Deepgram);
+        assert!(!health.is_open(later));
+        assert_eq!(health.consecutive_failures, 0);
+        assert_eq!(registry.get(SttProvider::Whisper), ProviderHealth::default());
+    }
+
+    #[test]
+    fn test_disabled_breaker_never_opens() {
+        let mut registry = HealthRegistry::default();
+        let now = Instant::now();
+        for _ in 0..10 {
+            registry.record_failure(SttProvider::Azure, "Rate limit exceeded".to_string(), None, Duration::from_secs(60), now);
+        }
+        assert!(!registry.get(SttProvider::Azure).is_open(now));
+        assert_eq!(registry.get(SttProvider::Azure).consecutive_failures, 10);
+    }
+}
@@ -0,0 +1,103 @@
+/// (ISO 639-1, ISO 639-2/3, English name) for languages commonly seen in chats.
+/// Providers report languages in different forms: Deepgram `ru`, Google
+/// `ru-ru`, ElevenLabs `rus`, Whisper `russian`.
+const LANGUAGES: &[(&str, &str, &str)] = &[
+    ("ar", "ara", "Arabic"),
+    ("be", "bel", "Belarusian"),
+    ("bg", "bul", "Bulgarian"),
+    ("cs", "ces", "Czech"),
+    ("da", "dan", "Danish"),
+    ("de", "deu", "German"),
+    ("el", "ell", "Greek"),
+    ("en", "eng", "English"),
+    ("es", "spa", "Spanish"),
+    ("fa", "fas", "Persian"),
+    ("fi", "fin", "Finnish"),
+    ("fr", "fra", "French"),
+    ("he", "heb", "Hebrew"),
+    ("hi", "hin", "Hindi"),
+    ("hu", "hun", "Hungarian"),
+    ("id", "ind", "Indonesian"),
+    ("it", "ita", "Italian"),
+    ("ja", "jpn", "Japanese"),
+    ("ka", "kat", "Georgian"),
+    ("kk", "kaz", "Kazakh"),
+    ("ko", "kor", "Korean"),
+    ("nl", "nld", "Dutch"),
+    ("no", "nor", "Norwegian"),
+    ("pl", "pol", "Polish"),
+    ("pt", "por", "Portuguese"),
+    ("ro", "ron", "Romanian"),
+    ("ru", "rus", "Russian"),
+    ("sk", "slk", "Slovak"),
+    ("sr", "srp", "Serbian"),
+    ("sv", "swe", "Swedish"),
+    ("th", "tha", "Thai"),
+    ("tr", "tur", "Turkish"),
+    ("uk", "ukr", "Ukrainian"),
+    ("uz", "uzb", "Uzbek"),
+    ("vi", "vie", "Vietnamese"),
+    ("zh", "zho", "Chinese"),
+];
+
+fn lookup(language: &str) -> Option<&'static (&'static str, &'static str, &'static str)> {
+    let language = language.trim().to_lowercase();
+    let base = language.split(['-', '_']).next().unwrap_or_default();
+    LANGUAGES
+        .iter()
+        .find(|(short, long, name)| base == *short || base == *long || language == name.to_lowercase())
+}
+
+/// ISO 639-1 code for a known language given as a code, locale or English
+/// name (`ru`, `ru-RU`, `rus`, `russian`).
+pub fn code(language: &str) -> Option<&'static str> {
+    lookup(language).map(|(short, _, _)| *short)
+}
+
+/// All known ISO 639-1 codes, for usage hints.
+pub fn known_codes() -> Vec<&'static str> {
+    LANGUAGES.iter().map(|(short, _, _)| *short).collect()
+}
+
+/// Human-readable name for a provider-reported language. Unknown codes are
+/// shown upper-cased, unknown names capitalized.
+pub fn display_name(language: &str) -> String {
+    if let Some((_, _, name)) = lookup(language) {
+        return name.to_string();
+    }
+
+    let language = language.trim().to_lowercase();
+    let base = language.split(['-', '_']).next().unwrap_or_default();
+
+    if base.len() <= 3 {
+        return language.to_uppercase();
+    }
+    let mut chars = language.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(display_name("ru"), "Russian");
+        assert_eq!(display_name("ru-RU"), "Russian");
+        assert_eq!(display_name("rus"), "Russian");
+        assert_eq!(display_name("russian"), "Russian");
+        assert_eq!(display_name("xx"), "XX");
+        assert_eq!(display_name("klingon"), "Klingon");
+    }
+
+    #[test]
+    fn test_code() {
+        assert_eq!(code("ru-RU"), Some("ru"));
+        assert_eq!(code("German"), Some("de"));
+        assert_eq!(code("ukr"), Some("uk"));
+        assert_eq!(code("klingon"), None);
+    }
+}
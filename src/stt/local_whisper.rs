@@ -0,0 +1,168 @@
+use super::{Segment, SttError, SttOptions, Transcription};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
+use crate::audio::ConvertedAudio;
+use tracing::{debug, info};
+use serde::Deserialize;
+use std::process::Stdio;
+
+/// Output of whisper.cpp's `-oj` (JSON) writer.
+#[derive(Deserialize)]
+struct WhisperCppOutput {
+    result: Option<WhisperCppResult>,
+    transcription: Vec<WhisperCppSegment>,
+}
+
+#[derive(Deserialize)]
+struct WhisperCppResult {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WhisperCppSegment {
+    /// In milliseconds.
+    offsets: WhisperCppOffsets,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct WhisperCppOffsets {
+    from: u64,
+    to: u64,
+}
+
+/// Transcribes locally by running the whisper.cpp CLI on a 16 kHz mono WAV.
+/// No audio leaves the machine.
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    binary: &str,
+    model_path: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    info!(
+        "Starting transcription provider=local-whisper model={} bytes={} format={}",
+        model_path,
+        audio.data.len(),
+        audio.format
+    );
+
+    let dir = tempfile::tempdir()
+        .map_err(|e| SttError::Api(format!("Failed to create temp directory: {}", e)))?;
+    let input_path = dir.path().join("input.wav");
+    let output_prefix = dir.path().join("output");
+
+    tokio::fs::write(&input_path, &audio.data).await
+        .map_err(|e| SttError::Api(format!("Failed to write audio for whisper.cpp: {}", e)))?;
+
+    let mut cmd = tokio::process::Command::new(binary);
+    cmd.arg("-m").arg(model_path)
+        .arg("-f").arg(&input_path)
+        .arg("-l").arg(options.language.unwrap_or("auto"))
+        .arg("-oj")
+        .arg("-of").arg(&output_prefix)
+        .arg("-np")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    debug!("Running whisper.cpp command: {:?}", cmd);
+
+    let output = cmd.output().await
+        .map_err(|e| SttError::Api(format!("Failed to run whisper.cpp ({}): {}", binary, e)))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(SttError::Api(format!("whisper.cpp failed: {}", stderr.trim())));
+    }
+
+    let json = tokio::fs::read_to_string(output_prefix.with_extension("json")).await
+        .map_err(|e| SttError::InvalidResponse(format!("Failed to read whisper.cpp output: {}", e)))?;
+    let transcription = parse_output(&json)?;
+
+    info!(
+        "Transcription complete provider=local-whisper chars={}",
+        transcription.text.len()
+    );
+    Ok(transcription)
+}
+
+fn parse_output(json: &str) -> Result<Transcription, SttError> {
+    let output: WhisperCppOutput = serde_json::from_str(json)
+        .map_err(|e| SttError::InvalidResponse(format!("Failed to parse whisper.cpp output: {}", e)))?;
+
+    let text = output
+        .transcription
+        .iter()
+        .map(|segment| segment.text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let segments = output
+        .transcription
+        .into_iter()
+        .filter(|segment| !segment.text.trim().is_empty())
+        .map(|segment| Segment {
+            start_secs: segment.offsets.from as f32 / 1000.0,
+            end_secs: segment.offsets.to as f32 / 1000.0,
+            text: segment.text.trim().to_string(),
+            confidence: None,
+        })
+        .collect();
+
+    Ok(Transcription {
+        language: output.result.and_then(|r| r.language),
+        segments,
+        ..Transcription::from_text(text)
+    })
+}
+
+/// whisper.cpp on this machine.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        config.whisper_model_path.as_ref().map(|_| ()).ok_or_else(|| "WHISPER_MODEL_PATH required for local Whisper".to_string())
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        // whisper.cpp only reads 16 kHz mono 16-bit WAV
+        AudioFormat::WAV.fixed_rate()
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            let model_path = config.whisper_model_path.as_ref()
+                .ok_or_else(|| SttError::Api("Whisper model path not configured".to_string()))?;
+            transcribe(audio, &config.whisper_cpp_bin, model_path, options).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let json = r#"{
+            "systeminfo": "AVX = 1",
+            "result": {"language": "en"},
+            "transcription": [
+                {"timestamps": {"from": "00:00:00,000", "to": "00:00:02,000"}, "offsets": {"from": 0, "to": 2000}, "text": " Hello there."},
+                {"timestamps": {"from": "00:00:02,000", "to": "00:00:03,500"}, "offsets": {"from": 2000, "to": 3500}, "text": " How are you?"}
+            ]
+        }"#;
+
+        let transcription = parse_output(json).unwrap();
+        assert_eq!(transcription.text, "Hello there. How are you?");
+        assert_eq!(transcription.language.as_deref(), Some("en"));
+        assert_eq!(transcription.segments[1].start_secs, 2.0);
+        assert_eq!(transcription.segments[1].end_secs, 3.5);
+    }
+}
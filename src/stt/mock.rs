@@ -0,0 +1,120 @@
+use super::{SttError, SttOptions, Transcription};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
+use crate::audio::ConvertedAudio;
+use tracing::info;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// What every mock transcription says.
+pub const MOCK_TEXT: &str = "This is a mock transcription.";
+
+/// Requests the mock has answered, for spreading its failures.
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the `n`th request (from 1) fails at `rate`. Failures are spread
+/// evenly rather than drawn at random, so a load test is repeatable: at
+/// 0.25 every fourth request fails.
+fn fails(n: u64, rate: f64) -> bool {
+    (n as f64 * rate).floor() > ((n - 1) as f64 * rate).floor()
+}
+
+/// A description of the audio the mock was sent, e.g.
+/// `wav, 16000 Hz, 1 ch, 64044 bytes, 2.0s`.
+fn describe(audio: &ConvertedAudio) -> String {
+    let mut description = format!("{}, {} Hz, {} ch, {} bytes", audio.format, audio.sample_rate, audio.channels, audio.data.len());
+    // 16-bit samples; a WAV header is 44 bytes
+    let pcm_bytes = match audio.format.as_str() {
+        "pcm" => Some(audio.data.len()),
+        "wav" => Some(audio.data.len().saturating_sub(44)),
+        _ => None,
+    };
+    if let Some(bytes) = pcm_bytes {
+        let secs = bytes as f64 / (2.0 * audio.sample_rate as f64 * audio.channels.max(1) as f64);
+        description.push_str(&format!(", {:.1}s", secs));
+    }
+    description
+}
+
+/// Answers with `MOCK_TEXT` after `MOCK_STT_LATENCY_MS`, failing the
+/// `MOCK_STT_FAILURE_RATE` share of requests as an unavailable provider
+/// would.
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    latency: Duration,
+    failure_rate: f64,
+    echo_metadata: bool,
+) -> Result<Transcription, SttError> {
+    tokio::time::sleep(latency).await;
+
+    let n = REQUESTS.fetch_add(1, Ordering::Relaxed) + 1;
+    if fails(n, failure_rate) {
+        info!("Mock STT failing request {}", n);
+        return Err(SttError::ServiceUnavailable);
+    }
+
+    let text = if echo_metadata {
+        format!("{} ({})", MOCK_TEXT, describe(audio))
+    } else {
+        MOCK_TEXT.to_string()
+    };
+    Ok(Transcription { language: Some("en".to_string()), ..Transcription::from_text(&text) })
+}
+
+/// Canned answers, for running the bot without a provider account.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    // Only when named in STT_PROVIDER, so it never stands in for a real one
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        if config.stt_provider == super::SttProvider::Mock || config.stt_fallbacks.contains(&super::SttProvider::Mock) {
+            Ok(())
+        } else {
+            Err("the mock provider is only used when STT_PROVIDER names it".to_string())
+        }
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        AudioFormat::WAV
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        _options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            transcribe(
+                audio,
+                Duration::from_millis(config.mock_stt_latency_ms),
+                config.mock_stt_failure_rate,
+                config.mock_stt_echo,
+            )
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fails() {
+        let failed: Vec<u64> = (1..=8).filter(|&n| fails(n, 0.25)).collect();
+        assert_eq!(failed, vec![4, 8]);
+        assert!((1..=100).all(|n| !fails(n, 0.0)));
+        assert!((1..=100).all(|n| fails(n, 1.0)));
+    }
+
+    #[tokio::test]
+    async fn test_echoes_metadata() {
+        let audio = ConvertedAudio { data: vec![0; 44 + 32000], format: "wav".to_string(), sample_rate: 16000, channels: 1 };
+
+        let plain = transcribe(&audio, Duration::ZERO, 0.0, false).await.unwrap();
+        assert_eq!(plain.text, MOCK_TEXT);
+        let echoed = transcribe(&audio, Duration::ZERO, 0.0, true).await.unwrap();
+        assert_eq!(echoed.text, format!("{} (wav, 16000 Hz, 1 ch, 32044 bytes, 1.0s)", MOCK_TEXT));
+    }
+}
@@ -2,14 +2,31 @@ pub mod elevenlabs;
 pub mod whisper;
 pub mod google;
 pub mod deepgram;
+pub mod local_whisper;
+pub mod azure;
+pub mod assemblyai;
+pub mod vosk;
+pub mod mock;
+pub mod channels;
+pub mod consensus;
+pub mod engine;
+pub mod health;
+pub mod language;
 
 use crate::{audio::ConvertedAudio, BotConfig};
+use tracing::warn;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
 
+/// Default for `STT_TIMEOUT_SECONDS`.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
 #[derive(Error, Debug)]
 pub enum SttError {
     #[error("HTTP request failed: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(reqwest::Error),
+    #[error("Request timed out")]
+    Timeout,
     #[error("API error: {0}")]
     Api(String),
     #[error("Invalid response format: {0}")]
@@ -22,21 +39,249 @@ pub enum SttError {
     ServiceUnavailable,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl From<reqwest::Error> for SttError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() { Self::Timeout } else { Self::Http(e) }
+    }
+}
+
+impl SttError {
+    /// Errors worth retrying with another provider: the audio is fine, the
+    /// provider just can't take it right now.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::RateLimit | Self::ServiceUnavailable | Self::Timeout => true,
+            Self::Http(e) => e.is_connect(),
+            _ => false,
+        }
+    }
+}
+
+/// Result of a transcription request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Transcription {
+    pub text: String,
+    /// Lower-ranked hypotheses for the whole recording, best first, when the
+    /// provider returns them.
+    pub alternatives: Vec<String>,
+    /// Word-level detail of the best hypothesis, when the provider returns it.
+    pub words: Vec<Word>,
+    /// Spoken language as the provider reports it (`en`, `en-us`, `english`).
+    pub language: Option<String>,
+    /// Timed stretches of the best hypothesis (sentences, utterances or
+    /// phrases, depending on the provider), in order.
+    pub segments: Vec<Segment>,
+    /// Topic sections with a headline and summary, when the provider was
+    /// asked for them (AssemblyAI's auto chapters).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    /// Offsets from the start of the audio, in seconds.
+    pub start_secs: f32,
+    pub end_secs: f32,
+    pub headline: String,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    /// Offsets from the start of the audio, in seconds.
+    pub start_secs: f32,
+    pub end_secs: f32,
+    pub text: String,
+    pub confidence: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Word {
+    pub text: String,
+    pub confidence: Option<f32>,
+    /// Offset from the start of the audio, in seconds.
+    pub start_secs: Option<f32>,
+    /// Who said it, when speakers are known (e.g. call recording channels).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,
+}
+
+impl Transcription {
+    pub fn from_text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Joins transcripts of consecutive fixed-length pieces of one recording,
+    /// shifting word timestamps by each piece's offset. Alternatives are
+    /// per piece and don't survive the join; the first detected language wins.
+    pub fn concat(parts: Vec<Transcription>, piece_secs: f32) -> Self {
+        Self::concat_at(
+            parts
+                .into_iter()
+                .enumerate()
+                .map(|(i, part)| (i as f32 * piece_secs, part))
+                .collect(),
+        )
+    }
+
+    /// Like [`Transcription::concat`] for pieces of varying length, each
+    /// given with its offset in seconds.
+    pub fn concat_at(parts: Vec<(f32, Transcription)>) -> Self {
+        let mut joined = Self::default();
+        for (offset, part) in parts {
+            let text = part.text.trim();
+            if !text.is_empty() {
+                if !joined.text.is_empty() {
+                    joined.text.push(' ');
+                }
+                joined.text.push_str(text);
+            }
+
+            if joined.language.is_none() {
+                joined.language = part.language;
+            }
+
+            joined.words.extend(part.words.into_iter().map(|w| Word {
+                start_secs: w.start_secs.map(|s| s + offset),
+                ..w
+            }));
+            joined.segments.extend(part.segments.into_iter().map(|s| Segment {
+                start_secs: s.start_secs + offset,
+                end_secs: s.end_secs + offset,
+                ..s
+            }));
+            joined.chapters.extend(part.chapters.into_iter().map(|c| Chapter {
+                start_secs: c.start_secs + offset,
+                end_secs: c.end_secs + offset,
+                ..c
+            }));
+        }
+        joined
+    }
+
+    /// Overall confidence of the best hypothesis (0.0–1.0): the average of
+    /// the segment scores weighted by length, or of the word scores when
+    /// segments have none. None when the provider reports neither.
+    pub fn confidence(&self) -> Option<f32> {
+        let scored: Vec<(f32, f32)> = self
+            .segments
+            .iter()
+            .filter_map(|s| s.confidence.map(|c| (c, (s.end_secs - s.start_secs).max(0.1))))
+            .collect();
+        let scored = if scored.is_empty() {
+            self.words.iter().filter_map(|w| w.confidence.map(|c| (c, 1.0))).collect()
+        } else {
+            scored
+        };
+        let weight: f32 = scored.iter().map(|(_, weight)| weight).sum();
+        (weight > 0.0).then(|| scored.iter().map(|(c, weight)| c * weight).sum::<f32>() / weight)
+    }
+}
+
+/// Request options shared by all providers; each uses what it supports.
+#[derive(Debug, Clone, Copy)]
+pub struct SttOptions<'a> {
+    /// Number of hypotheses to request (1 = best only).
+    pub max_alternatives: u8,
+    /// Ask for per-word confidence where it isn't returned by default.
+    pub word_confidence: bool,
+    /// Use the provider's phone-call model for 8 kHz call recordings.
+    pub phone_call: bool,
+    /// Ask for per-word start times where they aren't returned by default.
+    pub word_timestamps: bool,
+    /// ISO 639-1 code of the spoken language; auto-detected when unset.
+    pub language: Option<&'static str>,
+    /// Ask the provider to mask profanity, where it can.
+    pub profanity_filter: bool,
+    /// Model for the Whisper endpoint, from `WHISPER_MODEL`.
+    pub whisper_model: &'a str,
+    /// ElevenLabs model, from `ELEVENLABS_MODEL_ID`.
+    pub elevenlabs_model: &'a str,
+    /// Google recognition model, from `GOOGLE_MODEL`; the phone-call preset
+    /// overrides it.
+    pub google_model: &'a str,
+    /// Ask Google for its enhanced variant of the model.
+    pub google_use_enhanced: bool,
+    /// Vosk model name, from `VOSK_MODEL`.
+    pub vosk_model: &'a str,
+}
+
+impl<'a> SttOptions<'a> {
+    pub fn from_config(config: &'a BotConfig) -> Self {
+        Self {
+            max_alternatives: config.stt_alternatives,
+            word_confidence: config.low_confidence_threshold.is_some(),
+            phone_call: false,
+            word_timestamps: false,
+            language: None,
+            profanity_filter: false,
+            whisper_model: &config.whisper_model,
+            elevenlabs_model: config.elevenlabs_model,
+            google_model: config.google_model,
+            google_use_enhanced: config.google_use_enhanced,
+            vosk_model: &config.vosk_model,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SttProvider {
     Whisper,
     ElevenLabs,
     Google,
     Deepgram,
+    /// whisper.cpp running on this machine.
+    LocalWhisper,
+    Azure,
+    /// Vosk running in-process; needs the `vosk` build feature.
+    Vosk,
+    /// Canned text for development and load tests; no provider is called.
+    Mock,
+    /// AssemblyAI's asynchronous jobs: upload, submit, poll.
+    AssemblyAi,
+}
+
+/// Stored by name, as in `STT_PROVIDER`.
+impl Serialize for SttProvider {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for SttProvider {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::from_str(&name).ok_or_else(|| serde::de::Error::custom(format!("unknown provider '{}'", name)))
+    }
 }
 
 impl SttProvider {
+    pub const ALL: [SttProvider; 9] = [
+        Self::Deepgram,
+        Self::Whisper,
+        Self::ElevenLabs,
+        Self::Google,
+        Self::Azure,
+        Self::AssemblyAi,
+        Self::LocalWhisper,
+        Self::Vosk,
+        Self::Mock,
+    ];
+
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
             "whisper" => Some(Self::Whisper),
             "elevenlabs" => Some(Self::ElevenLabs),
             "google" => Some(Self::Google),
             "deepgram" => Some(Self::Deepgram),
+            "local-whisper" | "whisper-cpp" => Some(Self::LocalWhisper),
+            "azure" => Some(Self::Azure),
+            "vosk" => Some(Self::Vosk),
+            "mock" => Some(Self::Mock),
+            "assemblyai" => Some(Self::AssemblyAi),
             _ => None,
         }
     }
@@ -47,6 +292,58 @@ impl SttProvider {
             Self::ElevenLabs => "elevenlabs",
             Self::Google => "google",
             Self::Deepgram => "deepgram",
+            Self::LocalWhisper => "local-whisper",
+            Self::Azure => "azure",
+            Self::Vosk => "vosk",
+            Self::Mock => "mock",
+            Self::AssemblyAi => "assemblyai",
+        }
+    }
+
+    /// Model used for a request with the given options.
+    pub fn model_for<'a>(&self, options: &SttOptions<'a>) -> &'a str {
+        match self {
+            Self::Google if options.phone_call => "phone_call",
+            Self::Deepgram if options.phone_call => "nova-2-phonecall",
+            Self::Google => options.google_model,
+            Self::Whisper => options.whisper_model,
+            Self::ElevenLabs => options.elevenlabs_model,
+            Self::Vosk => options.vosk_model,
+            _ => self.model(),
+        }
+    }
+
+    /// The model for /status, e.g. `video (enhanced)`.
+    pub fn model_label(&self, options: &SttOptions<'_>) -> String {
+        let model = self.model_for(options);
+        if *self == Self::Google && (options.phone_call || options.google_use_enhanced) {
+            format!("{} (enhanced)", model)
+        } else {
+            model.to_string()
+        }
+    }
+
+    /// Providers that transcribe in a default language (English) unless told
+    /// which one is spoken.
+    pub fn needs_language(&self) -> bool {
+        matches!(self, Self::Google | Self::Azure)
+    }
+
+    /// Providers that identify the spoken language on their own.
+    pub fn detects_language(&self) -> bool {
+        matches!(self, Self::Deepgram | Self::Whisper | Self::ElevenLabs | Self::LocalWhisper | Self::AssemblyAi)
+    }
+
+    /// Longest audio the provider accepts in one request, for those with a
+    /// limit: Whisper's 25 MB upload cap (~13 min of 16 kHz WAV) and Google's
+    /// one minute for synchronous recognition. Azure's fast transcription
+    /// takes up to two hours.
+    pub fn max_chunk_secs(&self) -> Option<u32> {
+        match self {
+            Self::Whisper => Some(600),
+            Self::Google => Some(55),
+            Self::Azure => Some(7000),
+            _ => None,
         }
     }
 
@@ -56,35 +353,125 @@ impl SttProvider {
             Self::ElevenLabs => "scribe_v1_experimental",
             Self::Google => "default",
             Self::Deepgram => "nova-3",
+            Self::LocalWhisper => "whisper.cpp",
+            Self::Azure => "azure-speech",
+            Self::Vosk => "vosk",
+            Self::Mock => "mock",
+            Self::AssemblyAi => "best",
         }
     }
 }
 
+/// Transcribes with the engine registered for `provider`.
 pub async fn transcribe(
     audio: &ConvertedAudio,
     provider: SttProvider,
     config: &BotConfig,
-) -> Result<String, SttError> {
-    match provider {
-        SttProvider::Whisper => {
-            let api_key = config.openai_api_key.as_ref()
-                .ok_or_else(|| SttError::Api("OpenAI API key not configured".to_string()))?;
-            whisper::transcribe(audio, api_key).await
-        }
-        SttProvider::ElevenLabs => {
-            let api_key = config.elevenlabs_api_key.as_ref()
-                .ok_or_else(|| SttError::Api("ElevenLabs API key not configured".to_string()))?;
-            elevenlabs::transcribe(audio, api_key).await
-        }
-        SttProvider::Google => {
-            let credentials = config.google_credentials_json.as_ref()
-                .ok_or_else(|| SttError::Api("Google credentials not configured".to_string()))?;
-            google::transcribe(audio, credentials).await
-        }
-        SttProvider::Deepgram => {
-            let api_key = config.deepgram_api_key.as_ref()
-                .ok_or_else(|| SttError::Api("Deepgram API key not configured".to_string()))?;
-            deepgram::transcribe(audio, api_key).await
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    let result = engine::engine(provider).transcribe(audio, config, options).await;
+    health::record(provider, &result, config);
+    result
+}
+
+/// Tries each provider of `chain` in order, moving on to the next when one
+/// fails with a transient error. `prepare` produces audio in the format each
+/// provider expects. Returns the transcription and the provider that made it.
+pub async fn transcribe_with_failover<E, F, Fut>(
+    chain: &[SttProvider],
+    config: &BotConfig,
+    options: &SttOptions<'_>,
+    prepare: F,
+) -> Result<(Transcription, SttProvider), E>
+where
+    E: From<SttError>,
+    F: Fn(SttProvider) -> Fut,
+    Fut: std::future::Future<Output = Result<ConvertedAudio, E>>,
+{
+    let mut providers = chain.iter().copied().peekable();
+    while let Some(provider) = providers.next() {
+        let audio = prepare(provider).await?;
+        match transcribe(&audio, provider, config, options).await {
+            Ok(transcription) => return Ok((transcription, provider)),
+            Err(e) if e.is_transient() && providers.peek().is_some() => {
+                warn!("Provider {} failed ({}), failing over", provider.as_str(), e);
+            }
+            Err(e) => return Err(e.into()),
         }
     }
+    Err(SttError::Api("No STT provider available".to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concat_offsets_words() {
+        let first = Transcription {
+            text: "hello ".to_string(),
+            words: vec![Word { text: "hello".to_string(), confidence: None, start_secs: Some(1.0), speaker: None }],
+            ..Default::default()
+        };
+        let second = Transcription {
+            text: "world".to_string(),
+            alternatives: vec!["whirled".to_string()],
+            words: vec![Word { text: "world".to_string(), confidence: None, start_secs: Some(0.5), speaker: None }],
+            language: Some("en".to_string()),
+            segments: vec![Segment { start_secs: 0.5, end_secs: 1.0, text: "world".to_string(), confidence: Some(0.9) }],
+            chapters: vec![Chapter { start_secs: 0.0, end_secs: 1.0, headline: "World".to_string(), summary: String::new() }],
+        };
+
+        let joined = Transcription::concat(vec![first, Transcription::default(), second], 60.0);
+        assert_eq!(joined.text, "hello world");
+        assert_eq!(joined.words[1].start_secs, Some(120.5));
+        assert_eq!((joined.segments[0].start_secs, joined.segments[0].end_secs), (120.5, 121.0));
+        assert_eq!((joined.chapters[0].start_secs, joined.chapters[0].end_secs), (120.0, 121.0));
+        assert!(joined.alternatives.is_empty());
+        assert_eq!(joined.language.as_deref(), Some("en"));
+    }
+
+    #[test]
+    fn test_confidence() {
+        let segment = |start_secs, end_secs, confidence| Segment { start_secs, end_secs, text: String::new(), confidence };
+        let word = |confidence| Word { text: String::new(), confidence, start_secs: None, speaker: None };
+
+        let transcription = Transcription {
+            segments: vec![segment(0.0, 3.0, Some(0.9)), segment(3.0, 4.0, Some(0.5)), segment(4.0, 9.0, None)],
+            words: vec![word(Some(0.1))],
+            ..Default::default()
+        };
+        assert!((transcription.confidence().unwrap() - 0.8).abs() < 0.001);
+
+        let words_only = Transcription { words: vec![word(Some(0.4)), word(Some(0.8)), word(None)], ..Default::default() };
+        assert!((words_only.confidence().unwrap() - 0.6).abs() < 0.001);
+        assert_eq!(Transcription::from_text("hi").confidence(), None);
+    }
+
+    #[test]
+    fn test_model_for() {
+        let config = BotConfig {
+            whisper_model: "gpt-4o-transcribe".to_string(),
+            google_model: "video",
+            google_use_enhanced: true,
+            ..BotConfig::for_tests()
+        };
+        let options = SttOptions::from_config(&config);
+
+        assert_eq!(SttProvider::Whisper.model_for(&options), "gpt-4o-transcribe");
+        assert_eq!(SttProvider::ElevenLabs.model_for(&options), "scribe_v1_experimental");
+        assert_eq!(SttProvider::Google.model_label(&options), "video (enhanced)");
+        let call = SttOptions { phone_call: true, ..options };
+        assert_eq!(SttProvider::Google.model_label(&call), "phone_call (enhanced)");
+        assert_eq!(SttProvider::Deepgram.model_label(&options), "nova-3");
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(SttError::Timeout.is_transient());
+        assert!(SttError::RateLimit.is_transient());
+        assert!(SttError::ServiceUnavailable.is_transient());
+        assert!(!SttError::Authentication.is_transient());
+        assert!(!SttError::Api("bad audio".to_string()).is_transient());
+    }
 }
@@ -0,0 +1,324 @@
+use super::{Segment, SttError, SttOptions, Transcription, Word};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
+use crate::audio::ConvertedAudio;
+use tracing::{info, warn};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Where models named in `VOSK_MODEL` are downloaded from.
+const MODELS_URL: &str = "https://alphacephei.com/vosk/models";
+
+/// Longest a model download may take.
+const MODEL_DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Bytes of 16-bit PCM fed to the recognizer at a time (a quarter second at 16 kHz).
+#[cfg(feature = "vosk")]
+const CHUNK_BYTES: usize = 8000;
+
+/// One utterance as Vosk reports it with word output enabled.
+#[derive(Deserialize, Default)]
+struct VoskResult {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    result: Vec<VoskWord>,
+}
+
+#[derive(Deserialize)]
+struct VoskWord {
+    word: String,
+    start: f32,
+    end: f32,
+    conf: f32,
+}
+
+/// Directory of the named model inside `VOSK_MODEL_DIR`.
+fn model_path(model_dir: &str, model: &str) -> PathBuf {
+    Path::new(model_dir).join(model)
+}
+
+/// Joins the JSON results of successive utterances into one transcription,
+/// one segment per utterance.
+fn parse_results(results: &[String]) -> Result<Transcription, SttError> {
+    let mut transcription = Transcription::default();
+    for json in results {
+        let result: VoskResult = serde_json::from_str(json)
+            .map_err(|e| SttError::InvalidResponse(format!("Failed to parse Vosk result: {}", e)))?;
+        let text = result.text.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if let (Some(first), Some(last)) = (result.result.first(), result.result.last()) {
+            let confidence = result.result.iter().map(|w| w.conf).sum::<f32>() / result.result.len() as f32;
+            transcription.segments.push(Segment {
+                start_secs: first.start,
+                end_secs: last.end,
+                text: text.to_string(),
+                confidence: Some(confidence),
+            });
+        }
+        transcription.words.extend(result.result.into_iter().map(|w| Word {
+            text: w.word,
+            confidence: Some(w.conf),
+            start_secs: Some(w.start),
+            speaker: None,
+        }));
+
+        if !transcription.text.is_empty() {
+            transcription.text.push(' ');
+        }
+        transcription.text.push_str(text);
+    }
+    Ok(transcription)
+}
+
+/// Transcribes 16-bit mono PCM offline with Vosk, downloading the model on
+/// first use. The audio is streamed through the recognizer in small chunks,
+/// like a live microphone, and each finished utterance becomes a segment.
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    model_dir: &str,
+    model: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
+    if !cfg!(feature = "vosk") {
+        return Err(SttError::Api("This build has no Vosk support; rebuild with --features vosk".to_string()));
+    }
+
+    info!(
+        "Starting transcription provider=vosk model={} bytes={} format={}",
+        model,
+        audio.data.len(),
+        audio.format
+    );
+    if let Some(language) = options.language {
+        warn!("Vosk transcribes in its model's language, ignoring requested language {}", language);
+    }
+
+    let path = ensure_model(model_dir, model).await?;
+    let data = audio.data.clone();
+    let sample_rate = audio.sample_rate;
+    let results = tokio::task::spawn_blocking(move || ffi::recognize(&path, &data, sample_rate))
+        .await
+        .map_err(|e| SttError::Api(format!("Vosk task failed: {}", e)))??;
+
+    let transcription = parse_results(&results)?;
+    info!("Transcription complete provider=vosk model={} chars={}", model, transcription.text.len());
+    Ok(transcription)
+}
+
+/// Returns the model's directory, downloading and unpacking it first if it
+/// isn't there yet. Concurrent first uses wait for one download.
+async fn ensure_model(model_dir: &str, model: &str) -> Result<PathBuf, SttError> {
+    static DOWNLOAD: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    let path = model_path(model_dir, model);
+    if path.is_dir() {
+        return Ok(path);
+    }
+
+    let _guard = DOWNLOAD.lock().await;
+    if path.is_dir() {
+        return Ok(path);
+    }
+
+    let url = format!("{}/{}.zip", MODELS_URL, model);
+    info!("Downloading Vosk model {} from {}", model, url);
+    // Models run to hundreds of megabytes; the usual request timeout is too short
+    let response = crate::http::client().get(&url).timeout(MODEL_DOWNLOAD_TIMEOUT).send().await?;
+    if !response.status().is_success() {
+        return Err(SttError::Api(format!("Failed to download Vosk model {}: HTTP {}", model, response.status())));
+    }
+    let archive = response.bytes().await?;
+
+    let dir = PathBuf::from(model_dir);
+    tokio::task::spawn_blocking(move || -> Result<(), SttError> {
+        std::fs::create_dir_all(&dir).map_err(|e| SttError::Api(format!("Failed to create {}: {}", dir.display(), e)))?;
+        // Unpack next to the final location so a half-extracted model is never picked up
+        let staging = tempfile::tempdir_in(&dir).map_err(|e| SttError::Api(format!("Failed to create temp directory: {}", e)))?;
+        zip::ZipArchive::new(std::io::Cursor::new(archive))
+            .and_then(|mut zip| zip.extract(staging.path()))
+            .map_err(|e| SttError::InvalidResponse(format!("Failed to unpack Vosk model: {}", e)))?;
+        // Archives hold a single top-level directory named after the model
+        let unpacked = staging.path().join(staging_root(staging.path())?);
+        std::fs::rename(&unpacked, dir.join(unpacked.file_name().unwrap_or_default()))
+            .map_err(|e| SttError::Api(format!("Failed to install Vosk model: {}", e)))
+    })
+    .await
+    .map_err(|e| SttError::Api(format!("Vosk model install failed: {}", e)))??;
+
+    if !path.is_dir() {
+        return Err(SttError::Api(format!("Vosk archive for {} didn't contain a {} directory", model, model)));
+    }
+    info!("Installed Vosk model {} in {}", model, path.display());
+    Ok(path)
+}
+
+fn staging_root(staging: &Path) -> Result<PathBuf, SttError> {
+    std::fs::read_dir(staging)
+        .map_err(|e| SttError::Api(format!("Failed to read unpacked model: {}", e)))?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.path().is_dir())
+        .map(|entry| PathBuf::from(entry.file_name()))
+        .ok_or_else(|| SttError::InvalidResponse("Vosk archive has no model directory".to_string()))
+}
+
+/// Minimal bindings to libvosk's C API.
+#[cfg(feature = "vosk")]
+mod ffi {
+    use super::{CHUNK_BYTES, SttError};
+    use std::ffi::{CStr, CString, c_char, c_float, c_int};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    #[repr(C)]
+    struct VoskModel {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    struct VoskRecognizer {
+        _private: [u8; 0],
+    }
+
+    #[link(name = "vosk")]
+    unsafe extern "C" {
+        fn vosk_set_log_level(level: c_int);
+        fn vosk_model_new(path: *const c_char) -> *mut VoskModel;
+        fn vosk_recognizer_new(model: *mut VoskModel, sample_rate: c_float) -> *mut VoskRecognizer;
+        fn vosk_recognizer_set_words(recognizer: *mut VoskRecognizer, words: c_int);
+        fn vosk_recognizer_accept_waveform(recognizer: *mut VoskRecognizer, data: *const c_char, length: c_int) -> c_int;
+        fn vosk_recognizer_result(recognizer: *mut VoskRecognizer) -> *const c_char;
+        fn vosk_recognizer_final_result(recognizer: *mut VoskRecognizer) -> *const c_char;
+        fn vosk_recognizer_free(recognizer: *mut VoskRecognizer);
+    }
+
+    /// The loaded model; loading takes seconds, so it is kept for the life
+    /// of the process. Vosk models are safe to share between recognizers.
+    struct LoadedModel {
+        path: PathBuf,
+        model: *mut VoskModel,
+    }
+
+    unsafe impl Send for LoadedModel {}
+
+    static MODEL: Mutex<Option<LoadedModel>> = Mutex::new(None);
+
+    fn load_model(path: &Path) -> Result<*mut VoskModel, SttError> {
+        let mut loaded = MODEL.lock().expect("vosk model lock poisoned");
+        if let Some(loaded) = loaded.as_ref().filter(|loaded| loaded.path == path) {
+            return Ok(loaded.model);
+        }
+
+        let c_path = CString::new(path.to_string_lossy().as_bytes())
+            .map_err(|_| SttError::Api("Vosk model path contains a NUL byte".to_string()))?;
+        // SAFETY: c_path is a valid C string; a null return means failure
+        let model = unsafe {
+            vosk_set_log_level(-1);
+            vosk_model_new(c_path.as_ptr())
+        };
+        if model.is_null() {
+            return Err(SttError::Api(format!("Vosk couldn't load the model in {}", path.display())));
+        }
+        // A replaced model is leaked rather than freed under a running recognizer
+        *loaded = Some(LoadedModel { path: path.to_path_buf(), model });
+        Ok(model)
+    }
+
+    /// Feeds the PCM through a fresh recognizer and returns the JSON result
+    /// of every utterance, the final one last.
+    pub(super) fn recognize(path: &Path, pcm: &[u8], sample_rate: u32) -> Result<Vec<String>, SttError> {
+        let model = load_model(path)?;
+
+        // SAFETY: model stays alive for the process; the recognizer is freed
+        // below and the result strings are copied before the next call
+        unsafe {
+            let recognizer = vosk_recognizer_new(model, sample_rate as c_float);
+            if recognizer.is_null() {
+                return Err(SttError::Api("Vosk couldn't create a recognizer".to_string()));
+            }
+            vosk_recognizer_set_words(recognizer, 1);
+
+            let mut results = Vec::new();
+            for chunk in pcm.chunks(CHUNK_BYTES) {
+                if vosk_recognizer_accept_waveform(recognizer, chunk.as_ptr().cast(), chunk.len() as c_int) == 1 {
+                    results.push(CStr::from_ptr(vosk_recognizer_result(recognizer)).to_string_lossy().into_owned());
+                }
+            }
+            results.push(CStr::from_ptr(vosk_recognizer_final_result(recognizer)).to_string_lossy().into_owned());
+
+            vosk_recognizer_free(recognizer);
+            Ok(results)
+        }
+    }
+}
+
+#[cfg(not(feature = "vosk"))]
+mod ffi {
+    use super::SttError;
+    use std::path::Path;
+
+    pub(super) fn recognize(_path: &Path, _pcm: &[u8], _sample_rate: u32) -> Result<Vec<String>, SttError> {
+        Err(SttError::Api("This build has no Vosk support; rebuild with --features vosk".to_string()))
+    }
+}
+
+/// Vosk, in-process.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, _config: &BotConfig) -> Result<(), String> {
+        if cfg!(feature = "vosk") {
+            Ok(())
+        } else {
+            Err("Vosk needs a build with --features vosk".to_string())
+        }
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        AudioFormat::PCM
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            transcribe(audio, &config.vosk_model_dir, &config.vosk_model, options).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_results() {
+        let results = [
+            r#"{"result": [{"conf": 1.0, "end": 0.9, "start": 0.3, "word": "hello"}, {"conf": 0.5, "end": 1.4, "start": 0.9, "word": "there"}], "text": "hello there"}"#.to_string(),
+            r#"{"text": ""}"#.to_string(),
+            r#"{"result": [{"conf": 0.8, "end": 3.0, "start": 2.5, "word": "bye"}], "text": "bye"}"#.to_string(),
+        ];
+        let transcription = parse_results(&results).unwrap();
+
+        assert_eq!(transcription.text, "hello there bye");
+        assert_eq!(transcription.words.len(), 3);
+        assert_eq!(transcription.words[1].start_secs, Some(0.9));
+        assert_eq!(transcription.segments.len(), 2);
+        assert_eq!((transcription.segments[0].start_secs, transcription.segments[0].end_secs), (0.3, 1.4));
+        assert_eq!(transcription.segments[0].confidence, Some(0.75));
+        assert_eq!(transcription.segments[1].text, "bye");
+
+        assert!(parse_results(&["not json".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_model_path() {
+        assert_eq!(model_path("data/vosk", "vosk-model-small-en-us-0.15"), PathBuf::from("data/vosk/vosk-model-small-en-us-0.15"));
+    }
+}
@@ -1,19 +1,47 @@
-use super::SttError;
+use super::{Segment, SttError, SttOptions, Transcription};
+use super::engine::{AudioFormat, BoxFuture, SttEngine};
+use crate::BotConfig;
 use crate::audio::ConvertedAudio;
-use log::{debug, info};
+use tracing::{debug, info};
 use reqwest::multipart;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
-#[derive(Serialize)]
-struct WhisperRequest {
-    model: String,
-    response_format: String,
-    temperature: f32,
+/// Models OpenAI's own endpoint transcribes with, for checking
+/// `WHISPER_MODEL`; compatible servers have their own.
+pub const OPENAI_MODELS: [&str; 3] = ["whisper-1", "gpt-4o-transcribe", "gpt-4o-mini-transcribe"];
+
+/// Segment timing comes with `verbose_json`, which the GPT-4o models don't
+/// offer.
+fn response_format(model: &str) -> &'static str {
+    if model.starts_with("gpt-4o") { "json" } else { "verbose_json" }
 }
 
 #[derive(Deserialize)]
 struct WhisperResponse {
     text: String,
+    language: Option<String>,
+    #[serde(default)]
+    segments: Vec<WhisperSegment>,
+}
+
+#[derive(Deserialize)]
+struct WhisperSegment {
+    start: f32,
+    end: f32,
+    text: String,
+    avg_logprob: Option<f32>,
+}
+
+impl From<WhisperSegment> for Segment {
+    fn from(segment: WhisperSegment) -> Self {
+        Segment {
+            start_secs: segment.start,
+            end_secs: segment.end,
+            text: segment.text.trim().to_string(),
+            // Mean token log-probability; its exponent is a 0-1 score
+            confidence: segment.avg_logprob.map(f32::exp),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -24,19 +52,24 @@ struct WhisperErrorResponse {
 #[derive(Deserialize)]
 struct WhisperErrorDetails {
     message: String,
-    #[serde(rename = "type")]
-    error_type: Option<String>,
-    code: Option<String>,
 }
 
-pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String, SttError> {
+/// Sends the audio to the transcriptions endpoint under `base_url`, which is
+/// OpenAI's or any compatible server's (Groq, Together, faster-whisper).
+pub async fn transcribe(
+    audio: &ConvertedAudio,
+    api_key: &str,
+    base_url: &str,
+    options: &SttOptions<'_>,
+) -> Result<Transcription, SttError> {
     info!(
-        "Starting transcription provider=whisper model=whisper-1 bytes={} format={}",
+        "Starting transcription provider=whisper model={} bytes={} format={}",
+        options.whisper_model,
         audio.data.len(),
         audio.format
     );
 
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
     
     // Prepare the file part - Whisper expects the file to have proper extension
     let filename = match audio.format.as_str() {
@@ -53,16 +86,19 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
         .mime_str(get_mime_type(&audio.format))
         .map_err(|e| SttError::InvalidResponse(format!("Invalid mime type: {}", e)))?;
 
-    let form = multipart::Form::new()
+    let mut form = multipart::Form::new()
         .part("file", file_part)
-        .text("model", "whisper-1")
-        .text("response_format", "text")
+        .text("model", options.whisper_model.to_string())
+        .text("response_format", response_format(options.whisper_model))
         .text("temperature", "0.0");
+    if let Some(language) = options.language {
+        form = form.text("language", language);
+    }
 
-    debug!("Sending request to OpenAI Whisper API");
+    debug!("Sending request to Whisper API at {}", base_url);
 
     let response = client
-        .post("https://api.openai.com/v1/audio/transcriptions")
+        .post(format!("{}/audio/transcriptions", base_url))
         .header("Authorization", format!("Bearer {}", api_key))
         .multipart(form)
         .send()
@@ -72,12 +108,19 @@ pub async fn transcribe(audio: &ConvertedAudio, api_key: &str) -> Result<String,
     debug!("Whisper API response status: {}", status);
 
     if status.is_success() {
-        let transcription = response.text().await?;
+        let body = response.text().await?;
+        let whisper: WhisperResponse = serde_json::from_str(&body)
+            .map_err(|e| SttError::InvalidResponse(format!("Failed to parse Whisper response: {}", e)))?;
         info!(
-            "Transcription complete provider=whisper model=whisper-1 chars={}",
-            transcription.len()
+            "Transcription complete provider=whisper model={} chars={}",
+            options.whisper_model,
+            whisper.text.len()
         );
-        Ok(transcription.trim().to_string())
+        Ok(Transcription {
+            language: whisper.language,
+            segments: whisper.segments.into_iter().map(Segment::from).collect(),
+            ..Transcription::from_text(whisper.text.trim())
+        })
     } else {
         let error_text = response.text().await?;
         
@@ -108,10 +151,65 @@ fn get_mime_type(format: &str) -> &'static str {
     }
 }
 
+/// OpenAI's transcriptions endpoint, or a compatible server's.
+pub struct Engine;
+
+impl SttEngine for Engine {
+    fn check_config(&self, config: &BotConfig) -> Result<(), String> {
+        config.openai_api_key.as_ref().map(|_| ()).ok_or_else(|| "OPENAI_API_KEY required for Whisper".to_string())
+    }
+
+    fn audio_format(&self) -> AudioFormat {
+        // Whisper accepts MP3, but let's use WAV for consistency
+        AudioFormat::WAV
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a ConvertedAudio,
+        config: &'a BotConfig,
+        options: &'a SttOptions<'_>,
+    ) -> BoxFuture<'a, Result<Transcription, SttError>> {
+        Box::pin(async move {
+            let api_key = config.openai_api_key.as_ref()
+                .ok_or_else(|| SttError::Api("OpenAI API key not configured".to_string()))?;
+            transcribe(audio, api_key, &config.openai_base_url, options).await
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_response_format() {
+        assert_eq!(response_format("whisper-1"), "verbose_json");
+        assert_eq!(response_format("whisper-large-v3-turbo"), "verbose_json");
+        assert_eq!(response_format("gpt-4o-mini-transcribe"), "json");
+    }
+
+    #[test]
+    fn test_segment_from_verbose_json() {
+        let json = r#"{
+            "task": "transcribe",
+            "language": "english",
+            "duration": 4.2,
+            "text": "Hello there. Bye.",
+            "segments": [
+                {"id": 0, "start": 0.0, "end": 2.5, "text": " Hello there.", "avg_logprob": -0.1},
+                {"id": 1, "start": 2.5, "end": 4.2, "text": " Bye.", "avg_logprob": 0.0}
+            ]
+        }"#;
+        let response: WhisperResponse = serde_json::from_str(json).unwrap();
+        let segments: Vec<Segment> = response.segments.into_iter().map(Segment::from).collect();
+
+        assert_eq!(segments[0].text, "Hello there.");
+        assert_eq!(segments[1].start_secs, 2.5);
+        assert!((segments[0].confidence.unwrap() - 0.905).abs() < 0.001);
+        assert_eq!(segments[1].confidence, Some(1.0));
+    }
+
     #[test]
     fn test_mime_type_mapping() {
         assert_eq!(get_mime_type("wav"), "audio/wav");
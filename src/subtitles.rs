@@ -0,0 +1,138 @@
+use crate::stt::{Segment, Transcription};
+use std::fmt::Write;
+
+/// Subtitle formats attached to transcripts of videos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    pub const ALL: [SubtitleFormat; 2] = [Self::Srt, Self::Vtt];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Srt => "srt",
+            Self::Vtt => "vtt",
+        }
+    }
+
+    /// SRT separates milliseconds with a comma, WebVTT with a dot.
+    fn timestamp(&self, secs: f32) -> String {
+        let millis = (secs.max(0.0) * 1000.0).round() as u64;
+        let separator = match self {
+            Self::Srt => ',',
+            Self::Vtt => '.',
+        };
+        format!(
+            "{:02}:{:02}:{:02}{}{:03}",
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            separator,
+            millis % 1000
+        )
+    }
+}
+
+/// Renders the transcript's segments as a subtitle file, one cue per
+/// segment. None when the provider returned no timing.
+pub fn render(transcription: &Transcription, format: SubtitleFormat) -> Option<String> {
+    let cues: Vec<&Segment> = transcription
+        .segments
+        .iter()
+        .filter(|s| !s.text.trim().is_empty())
+        .collect();
+    if cues.is_empty() {
+        return None;
+    }
+
+    let mut out = String::new();
+    if format == SubtitleFormat::Vtt {
+        out.push_str("WEBVTT\n\n");
+    }
+    for (i, cue) in cues.iter().enumerate() {
+        if format == SubtitleFormat::Srt {
+            let _ = writeln!(out, "{}", i + 1);
+        }
+        // Players skip cues that end before they start
+        let end_secs = cue.end_secs.max(cue.start_secs);
+        let _ = writeln!(
+            out,
+            "{} --> {}\n{}\n",
+            format.timestamp(cue.start_secs),
+            format.timestamp(end_secs),
+            cue.text.trim()
+        );
+    }
+    Some(out)
+}
+
+/// One `[mm:ss] text` line per segment, with hours once the audio runs
+/// past an hour. None when the provider returned no timing.
+pub fn timestamped_lines(transcription: &Transcription) -> Option<String> {
+    let segments: Vec<&Segment> = transcription
+        .segments
+        .iter()
+        .filter(|s| !s.text.trim().is_empty())
+        .collect();
+    let with_hours = segments.last()?.start_secs >= 3600.0;
+
+    let lines: Vec<String> = segments
+        .iter()
+        .map(|segment| {
+            let secs = segment.start_secs.max(0.0) as u64;
+            let stamp = if with_hours {
+                format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+            } else {
+                format!("{:02}:{:02}", secs / 60, secs % 60)
+            };
+            format!("[{}] {}", stamp, segment.text.trim())
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_secs: f32, end_secs: f32, text: &str) -> Segment {
+        Segment { start_secs, end_secs, text: text.to_string(), confidence: None }
+    }
+
+    #[test]
+    fn test_render() {
+        let transcription = Transcription {
+            segments: vec![segment(0.0, 2.5, "Hello there."), segment(3661.25, 3663.0, " Bye. ")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render(&transcription, SubtitleFormat::Srt).unwrap(),
+            "1\n00:00:00,000 --> 00:00:02,500\nHello there.\n\n2\n01:01:01,250 --> 01:01:03,000\nBye.\n\n"
+        );
+        assert_eq!(
+            render(&transcription, SubtitleFormat::Vtt).unwrap(),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:02.500\nHello there.\n\n01:01:01.250 --> 01:01:03.000\nBye.\n\n"
+        );
+        assert!(render(&Transcription::from_text("untimed"), SubtitleFormat::Srt).is_none());
+    }
+
+    #[test]
+    fn test_timestamped_lines() {
+        let short = Transcription {
+            segments: vec![segment(0.0, 2.5, "Hello there."), segment(75.9, 80.0, " Bye. ")],
+            ..Default::default()
+        };
+        assert_eq!(timestamped_lines(&short).unwrap(), "[00:00] Hello there.\n[01:15] Bye.");
+
+        let long = Transcription {
+            segments: vec![segment(5.0, 6.0, "Start."), segment(3725.0, 3726.0, "End.")],
+            ..Default::default()
+        };
+        assert_eq!(timestamped_lines(&long).unwrap(), "[0:00:05] Start.\n[1:02:05] End.");
+        assert!(timestamped_lines(&Transcription::from_text("untimed")).is_none());
+    }
+}
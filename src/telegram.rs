@@ -0,0 +1,89 @@
+use tracing::warn;
+use std::time::Duration;
+use teloxide::{ApiError, RequestError};
+use teloxide::requests::{Output, Request};
+use teloxide::types::ChatId;
+
+/// Attempts per request, the first included.
+const MAX_ATTEMPTS: u32 = 4;
+/// Wait before the first retry of a network failure; doubled each time.
+const NETWORK_BACKOFF: Duration = Duration::from_secs(1);
+/// Flood waits longer than this fail the request rather than stall the queue.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// Pause between the parts of a long transcript. Telegram allows about one
+/// message a second in a chat and 20 a minute in groups.
+pub fn part_interval(chat_id: ChatId) -> Duration {
+    if chat_id.is_user() { Duration::from_secs(1) } else { Duration::from_secs(3) }
+}
+
+/// How long to wait before retrying after `error`, or None to give up.
+fn retry_delay(error: &RequestError, attempt: u32) -> Option<Duration> {
+    if attempt >= MAX_ATTEMPTS {
+        return None;
+    }
+    match error {
+        RequestError::RetryAfter(wait) if *wait <= MAX_RETRY_AFTER => Some(*wait),
+        RequestError::Network(_) | RequestError::Io(_) => Some(NETWORK_BACKOFF * 2u32.pow(attempt - 1)),
+        _ => None,
+    }
+}
+
+/// Whether Telegram rejected a message's MarkdownV2 markup.
+pub fn is_markup_error(error: &RequestError) -> bool {
+    match error {
+        RequestError::Api(ApiError::CantParseEntities) => true,
+        // Newer messages carry the offset, e.g. "...: Can't find end of the entity starting at byte offset 12"
+        RequestError::Api(ApiError::Unknown(message)) => message.contains("can't parse entities"),
+        _ => false,
+    }
+}
+
+/// Sends `request`, waiting out Telegram's flood control (`RetryAfter`)
+/// and retrying network failures with backoff.
+pub async fn send<R>(request: R) -> Result<Output<R>, RequestError>
+where
+    R: Request<Err = RequestError>,
+{
+    let mut attempt = 1;
+    loop {
+        match request.send_ref().await {
+            Err(e) => match retry_delay(&e, attempt) {
+                Some(delay) => {
+                    warn!("Telegram request failed ({}), retrying in {:.1}s", e, delay.as_secs_f32());
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(e),
+            },
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let flood = RequestError::RetryAfter(Duration::from_secs(7));
+        assert_eq!(retry_delay(&flood, 1), Some(Duration::from_secs(7)));
+        assert_eq!(retry_delay(&flood, MAX_ATTEMPTS), None);
+        assert_eq!(retry_delay(&RequestError::RetryAfter(Duration::from_secs(600)), 1), None);
+
+        let io = RequestError::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert_eq!(retry_delay(&io, 1), Some(Duration::from_secs(1)));
+        assert_eq!(retry_delay(&io, 3), Some(Duration::from_secs(4)));
+
+        assert_eq!(retry_delay(&RequestError::Api(ApiError::MessageTextIsEmpty), 1), None);
+    }
+
+    #[test]
+    fn test_is_markup_error() {
+        assert!(is_markup_error(&RequestError::Api(ApiError::CantParseEntities)));
+        let detailed = "Bad Request: can't parse entities: Character '.' is reserved and must be escaped".to_string();
+        assert!(is_markup_error(&RequestError::Api(ApiError::Unknown(detailed))));
+        assert!(!is_markup_error(&RequestError::Api(ApiError::MessageTextIsEmpty)));
+    }
+}
@@ -0,0 +1,103 @@
+/// Placeholders filled in `REPLY_TEMPLATE`.
+pub const REPLY_PLACEHOLDERS: &[&str] =
+    &["emoji", "text", "heading", "provider", "model", "duration", "seconds", "language", "words", "confidence"];
+
+/// One piece of a template.
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Literal(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a template into literal text and `{name}` placeholders. Braces
+/// around anything but a plain name are kept as text.
+fn parse(template: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let name_end = rest[start + 1..].find('}').map(|end| start + 1 + end);
+        match name_end {
+            Some(end) if is_name(&rest[start + 1..end]) => {
+                if start > 0 {
+                    parts.push(Part::Literal(&rest[..start]));
+                }
+                parts.push(Part::Placeholder(&rest[start + 1..end]));
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                parts.push(Part::Literal(&rest[..=start]));
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest));
+    }
+    parts
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+/// Checks a configured template only uses placeholders from `known`.
+pub fn validate(template: &str, known: &[&str]) -> Result<(), String> {
+    for part in parse(template) {
+        if let Part::Placeholder(name) = part
+            && !known.contains(&name)
+        {
+            return Err(format!("unknown placeholder {{{}}}, expected one of {{{}}}", name, known.join("}, {")));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a `REPLY_TEMPLATE` or `/template`: known placeholders, and
+/// `{text}` somewhere.
+pub fn validate_reply(template: &str) -> Result<(), String> {
+    validate(template, REPLY_PLACEHOLDERS)?;
+    if !template.contains("{text}") {
+        return Err("it must contain {text}".to_string());
+    }
+    Ok(())
+}
+
+/// Fills the placeholders of `template` from `values`; `literal` is applied
+/// to the text between them (MarkdownV2 escaping, say). Placeholders without
+/// a value are left as written.
+pub fn render(template: &str, values: &[(&str, String)], literal: impl Fn(&str) -> String) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    for part in parse(template) {
+        match part {
+            Part::Literal(text) => rendered.push_str(&literal(text)),
+            Part::Placeholder(name) => match values.iter().find(|(key, _)| *key == name) {
+                Some((_, value)) => rendered.push_str(value),
+                None => rendered.push_str(&literal(&format!("{{{}}}", name))),
+            },
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let values = [("emoji", "📝".to_string()), ("text", "hello".to_string()), ("seconds", "42".to_string())];
+        let rendered = render("{emoji} {text}\n\n⏱ {seconds}s · {unknown} {not a placeholder}", &values, str::to_string);
+        assert_eq!(rendered, "📝 hello\n\n⏱ 42s · {unknown} {not a placeholder}");
+
+        let escaped = render("[{text}].", &values, |s| s.replace('.', "\\.").replace('[', "\\[").replace(']', "\\]"));
+        assert_eq!(escaped, "\\[hello\\]\\.");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("{emoji} {text} via {provider}", REPLY_PLACEHOLDERS).is_ok());
+        let error = validate("{text} {speaker}", REPLY_PLACEHOLDERS).unwrap_err();
+        assert!(error.starts_with("unknown placeholder {speaker}"));
+        assert_eq!(validate_reply("{emoji} via {provider}").unwrap_err(), "it must contain {text}");
+    }
+}
@@ -0,0 +1,312 @@
+pub mod redact;
+
+use crate::stt::Transcription;
+
+/// Cleanup applied to every transcript after STT; each step is set in config.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Formatting {
+    /// Capitalize the first letter of each sentence.
+    pub capitalize: bool,
+    /// Drop hesitation sounds like "um" and "uh".
+    pub remove_fillers: bool,
+    /// Write spelled-out English numbers as digits ("twenty five" → "25").
+    pub normalize_numbers: bool,
+    /// Start a new paragraph where the speaker paused at least this long;
+    /// None keeps the text on one line.
+    pub paragraph_pause_secs: Option<f32>,
+}
+
+/// Hesitation sounds removed by `remove_fillers`, in English and Russian.
+const FILLERS: &[&str] = &["um", "umm", "uh", "uhh", "uhm", "er", "erm", "hmm", "эм", "эмм", "ээ", "эээ", "хм", "ммм"];
+
+/// Runs the enabled steps over the text, alternatives and segments, and
+/// drops filler words from the word list, so attachments match the reply.
+pub fn apply(transcription: &mut Transcription, formatting: &Formatting) {
+    // Paragraphs come first, while the text still matches the segments
+    if let Some(pause_secs) = formatting.paragraph_pause_secs {
+        break_paragraphs(transcription, pause_secs);
+    }
+
+    transcription.text = format_text(&transcription.text, formatting);
+    for alternative in &mut transcription.alternatives {
+        *alternative = format_text(alternative, formatting);
+    }
+    for segment in &mut transcription.segments {
+        segment.text = format_text(&segment.text, formatting);
+    }
+    if formatting.remove_fillers {
+        transcription.words.retain(|word| !is_filler(&word.text));
+    }
+}
+
+fn format_text(text: &str, formatting: &Formatting) -> String {
+    if !formatting.remove_fillers && !formatting.normalize_numbers && !formatting.capitalize {
+        return text.to_string();
+    }
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let mut line = line.to_string();
+        if formatting.remove_fillers {
+            line = remove_fillers(&line);
+        }
+        if formatting.normalize_numbers {
+            line = normalize_numbers(&line);
+        }
+        lines.push(line);
+    }
+    let text = lines.join("\n");
+    if formatting.capitalize { capitalize_sentences(&text) } else { text }
+}
+
+/// Rebuilds the text from the segments with a blank line wherever the gap
+/// between two of them is at least `pause_secs`. Left alone when the
+/// segments don't hold the same words as the text (speaker-labelled
+/// call recordings, for one).
+fn break_paragraphs(transcription: &mut Transcription, pause_secs: f32) {
+    let segments: Vec<_> = transcription.segments.iter().filter(|s| !s.text.trim().is_empty()).collect();
+    if segments.len() < 2 {
+        return;
+    }
+    let segment_words = segments.iter().flat_map(|s| s.text.split_whitespace());
+    if !segment_words.eq(transcription.text.split_whitespace()) {
+        return;
+    }
+
+    let mut text = segments[0].text.trim().to_string();
+    for pair in segments.windows(2) {
+        text.push_str(if pair[1].start_secs - pair[0].end_secs >= pause_secs { "\n\n" } else { " " });
+        text.push_str(pair[1].text.trim());
+    }
+    transcription.text = text;
+}
+
+fn is_filler(token: &str) -> bool {
+    let core = token.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+    FILLERS.contains(&core.as_str())
+}
+
+/// Drops filler words from one line. A sentence end the filler carried
+/// ("that's it, um.") moves to the word before it.
+fn remove_fillers(line: &str) -> String {
+    let mut kept: Vec<String> = Vec::new();
+    for token in line.split_whitespace() {
+        if !is_filler(token) {
+            kept.push(token.to_string());
+            continue;
+        }
+        let trailing = &token[token.trim_end_matches(|c: char| !c.is_alphanumeric()).len()..];
+        if let Some(last) = kept.last_mut()
+            && let Some(end) = trailing.chars().find(|c| matches!(c, '.' | '?' | '!'))
+        {
+            let trimmed = last.trim_end_matches([',', ';', ':']).len();
+            last.truncate(trimmed);
+            if !last.ends_with(['.', '?', '!']) {
+                last.push(end);
+            }
+        }
+    }
+    kept.join(" ")
+}
+
+/// Upper-cases the first letter of the text and of every sentence after
+/// `.`, `?` or `!` followed by a space, and at the start of each line.
+fn capitalize_sentences(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut at_start = true;
+    let mut after_end = false;
+    for c in text.chars() {
+        if at_start && c.is_alphanumeric() {
+            result.extend(c.to_uppercase());
+            at_start = false;
+            continue;
+        }
+        if c == '\n' || (after_end && c.is_whitespace()) {
+            at_start = true;
+        }
+        after_end = matches!(c, '.' | '?' | '!');
+        result.push(c);
+    }
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumberWord {
+    /// zero to nine
+    Unit(u64),
+    /// ten to nineteen
+    Teen(u64),
+    /// twenty, thirty, … ninety
+    Tens(u64),
+    Hundred,
+    /// thousand, million, billion
+    Scale(u64),
+}
+
+fn number_word(word: &str) -> Option<NumberWord> {
+    use NumberWord::*;
+    const UNITS: [&str; 10] = ["zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine"];
+    const TEENS: [&str; 10] =
+        ["ten", "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen"];
+    const TENS: [&str; 8] = ["twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety"];
+
+    let word = word.to_lowercase();
+    let word = word.as_str();
+    if let Some(n) = UNITS.iter().position(|&w| w == word) {
+        return Some(Unit(n as u64));
+    }
+    if let Some(n) = TEENS.iter().position(|&w| w == word) {
+        return Some(Teen(10 + n as u64));
+    }
+    if let Some(n) = TENS.iter().position(|&w| w == word) {
+        return Some(Tens(20 + 10 * n as u64));
+    }
+    match word {
+        "hundred" => Some(Hundred),
+        "thousand" => Some(Scale(1_000)),
+        "million" => Some(Scale(1_000_000)),
+        "billion" => Some(Scale(1_000_000_000)),
+        _ => None,
+    }
+}
+
+/// Whether `next` can continue a number that so far ends in `prev`;
+/// `last_scale` is the smallest scale word used so far.
+fn can_follow(prev: Option<NumberWord>, next: NumberWord, last_scale: u64) -> bool {
+    use NumberWord::*;
+    match (prev, next) {
+        (None, Hundred | Scale(_)) => false,
+        (None, _) => true,
+        (Some(Tens(_)), Unit(n)) => n > 0,
+        (Some(Unit(n) | Teen(n)), Hundred) => n > 0,
+        (Some(Hundred | Scale(_)), Unit(n)) => n > 0,
+        (Some(Hundred | Scale(_)), Teen(_) | Tens(_)) => true,
+        (Some(Unit(_) | Teen(_) | Tens(_) | Hundred), Scale(scale)) => scale < last_scale,
+        _ => false,
+    }
+}
+
+/// The longest number spelled out at the start of `tokens`: how many tokens
+/// it takes, its value and any punctuation after it. Single words below ten
+/// are left as words ("one of them").
+fn number_run<'a>(tokens: &[&'a str]) -> Option<(usize, u64, &'a str)> {
+    let (mut total, mut current) = (0u64, 0u64);
+    let mut prev = None;
+    let mut last_scale = u64::MAX;
+    let mut words = 0;
+    let mut best = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let core = token.trim_end_matches(|c: char| !c.is_alphanumeric());
+        let trailing = &token[core.len()..];
+        // "one hundred and five"
+        if core.eq_ignore_ascii_case("and") && trailing.is_empty() && matches!(prev, Some(NumberWord::Hundred | NumberWord::Scale(_))) {
+            continue;
+        }
+        let Some(parts) = core.split('-').map(number_word).collect::<Option<Vec<_>>>() else {
+            break;
+        };
+        for part in parts {
+            if !can_follow(prev, part, last_scale) {
+                return best;
+            }
+            match part {
+                NumberWord::Unit(n) | NumberWord::Teen(n) | NumberWord::Tens(n) => current += n,
+                NumberWord::Hundred => current *= 100,
+                NumberWord::Scale(scale) => {
+                    total += current * scale;
+                    current = 0;
+                    last_scale = scale;
+                }
+            }
+            prev = Some(part);
+            words += 1;
+        }
+
+        let value = total + current;
+        if words > 1 || value >= 10 {
+            best = Some((i + 1, value, trailing));
+        }
+        if !trailing.is_empty() {
+            break;
+        }
+    }
+    best
+}
+
+/// Writes spelled-out English numbers in one line as digits.
+fn normalize_numbers(line: &str) -> String {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match number_run(&tokens[i..]) {
+            Some((used, value, trailing)) => {
+                result.push(format!("{}{}", value, trailing));
+                i += used;
+            }
+            None => {
+                result.push(tokens[i].to_string());
+                i += 1;
+            }
+        }
+    }
+    result.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stt::{Segment, Word};
+
+    fn segment(start_secs: f32, end_secs: f32, text: &str) -> Segment {
+        Segment { start_secs, end_secs, text: text.to_string(), confidence: None }
+    }
+
+    #[test]
+    fn test_remove_fillers() {
+        assert_eq!(remove_fillers("So, um, I think uh we're done, um."), "So, I think we're done.");
+        assert_eq!(remove_fillers("Эм, ну давай ээ завтра"), "ну давай завтра");
+        assert_eq!(remove_fillers("umbrella, hummus"), "umbrella, hummus");
+    }
+
+    #[test]
+    fn test_normalize_numbers() {
+        assert_eq!(normalize_numbers("I have twenty five dollars"), "I have 25 dollars");
+        assert_eq!(normalize_numbers("one hundred and five people, twenty-one of them"), "105 people, 21 of them");
+        assert_eq!(normalize_numbers("two million three hundred thousand"), "2300000");
+        assert_eq!(normalize_numbers("Fifteen."), "15.");
+        // Lone small numbers and lists stay words
+        assert_eq!(normalize_numbers("one of the two, one two three"), "one of the two, one two three");
+        assert_eq!(normalize_numbers("one and two"), "one and two");
+    }
+
+    #[test]
+    fn test_capitalize_sentences() {
+        assert_eq!(capitalize_sentences("hello. how are you?fine! it costs 3.5 euros"), "Hello. How are you?fine! It costs 3.5 euros");
+        assert_eq!(capitalize_sentences("привет\n\nпока"), "Привет\n\nПока");
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut transcription = Transcription {
+            segments: vec![segment(0.0, 2.0, "um, hello there."), segment(2.3, 4.0, "twenty guests came."), segment(7.0, 9.0, "bye now.")],
+            words: vec![
+                Word { text: "um".to_string(), confidence: None, start_secs: Some(0.0), speaker: None },
+                Word { text: "hello".to_string(), confidence: None, start_secs: Some(0.5), speaker: None },
+            ],
+            ..Transcription::from_text("um, hello there. twenty guests came. bye now.")
+        };
+        let formatting =
+            Formatting { capitalize: true, remove_fillers: true, normalize_numbers: true, paragraph_pause_secs: Some(2.0) };
+        apply(&mut transcription, &formatting);
+
+        assert_eq!(transcription.text, "Hello there. 20 guests came.\n\nBye now.");
+        assert_eq!(transcription.segments[0].text, "Hello there.");
+        assert_eq!(transcription.words.len(), 1);
+
+        // Off by default
+        let mut untouched = Transcription::from_text("um, twenty");
+        apply(&mut untouched, &Formatting::default());
+        assert_eq!(untouched.text, "um, twenty");
+    }
+}
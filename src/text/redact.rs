@@ -0,0 +1,87 @@
+use crate::stt::Transcription;
+
+/// Words masked only when they stand alone, since they start ordinary words
+/// too (`assume`, `cocktail`).
+const WORDS: &[&str] = &[
+    "ass", "arse", "cock", "cocks", "dick", "dicks", "piss", "twat", "wank", "сука", "суки", "суку", "сукой",
+];
+
+/// Stems masked at the start of any word, including after one of `PREFIXES`.
+const STEMS: &[&str] = &[
+    "fuck", "shit", "cunt", "bitch", "bastard", "asshole", "dickhead", "wanker", "хуй", "хуе", "хуё", "хуя",
+    "пизд", "еба", "ёба", "ебл", "ебу", "ебн", "ебё", "бля", "мудак", "мудил", "залуп", "пидор", "пидар",
+    "сучар", "сучк", "долбоё", "долбое",
+];
+
+/// Word beginnings that turn a stem into another word of the same kind
+/// (`motherfucker`, `охуеть`, `заебал`).
+const PREFIXES: &[&str] = &[
+    "mother", "bull", "за", "вы", "на", "от", "отъ", "по", "при", "рас", "раз", "у", "до", "съ", "под",
+    "пере", "о", "об", "въ", "недо", "ни",
+];
+
+fn is_profane(word: &str) -> bool {
+    let word = word.to_lowercase();
+    let has_stem = |word: &str| STEMS.iter().any(|stem| word.starts_with(stem));
+    WORDS.contains(&word.as_str())
+        || has_stem(&word)
+        || PREFIXES.iter().any(|prefix| word.strip_prefix(prefix).is_some_and(has_stem))
+}
+
+/// Replaces every letter but the first of each profane word with `*`,
+/// leaving everything else as it was.
+pub fn mask(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, masked: &mut String| {
+        if is_profane(word) {
+            let mut chars = word.chars();
+            masked.extend(chars.next());
+            masked.extend(chars.map(|_| '*'));
+        } else {
+            masked.push_str(word);
+        }
+        word.clear();
+    };
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut masked);
+            masked.push(c);
+        }
+    }
+    flush(&mut word, &mut masked);
+    masked
+}
+
+/// Masks the text, alternatives, words and segments alike, so attachments
+/// and later buttons show the same text as the reply.
+pub fn mask_transcription(transcription: &mut Transcription) {
+    transcription.text = mask(&transcription.text);
+    for alternative in &mut transcription.alternatives {
+        *alternative = mask(alternative);
+    }
+    for word in &mut transcription.words {
+        word.text = mask(&word.text);
+    }
+    for segment in &mut transcription.segments {
+        segment.text = mask(&segment.text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("What the fuck, Fucking hell!"), "What the f***, F****** hell!");
+        assert_eq!(mask("motherfucker bullshit"), "m*********** b*******");
+        assert_eq!(mask("Ну нахуй, заебал уже, сука"), "Ну н****, з***** уже, с***");
+        // Ordinary words sharing letters are left alone
+        assert_eq!(mask("assume the class passes, хлебать, сто рублей"), "assume the class passes, хлебать, сто рублей");
+        assert_eq!(mask("ass"), "a**");
+    }
+}
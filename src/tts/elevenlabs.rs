@@ -0,0 +1,53 @@
+use super::{Speech, TtsError};
+use tracing::{debug, info};
+use serde::{Deserialize, Serialize};
+
+const MODEL: &str = "eleven_multilingual_v2";
+/// "Rachel", one of the premade voices every account has.
+const DEFAULT_VOICE: &str = "21m00Tcm4TlvDzPBjR1f";
+
+#[derive(Serialize)]
+struct SpeechRequest<'a> {
+    text: &'a str,
+    model_id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    detail: Option<serde_json::Value>,
+}
+
+/// Reads `text` aloud with the given voice ID. The audio comes back as MP3,
+/// which Telegram also accepts for voice messages.
+pub async fn synthesize(text: &str, api_key: &str, voice: Option<&str>) -> Result<Speech, TtsError> {
+    let voice = voice.unwrap_or(DEFAULT_VOICE);
+    info!("Starting speech synthesis provider=elevenlabs model={} voice={} chars={}", MODEL, voice, text.len());
+
+    let response = crate::http::client()
+        .post(format!("https://api.elevenlabs.io/v1/text-to-speech/{}", voice))
+        .query(&[("output_format", "mp3_44100_128")])
+        .header("xi-api-key", api_key)
+        .json(&SpeechRequest { text, model_id: MODEL })
+        .send()
+        .await?;
+
+    let status = response.status();
+    debug!("ElevenLabs TTS response status: {}", status);
+    if !status.is_success() {
+        let body = response.text().await?;
+        return Err(match status.as_u16() {
+            401 => TtsError::Authentication,
+            429 => TtsError::RateLimit,
+            _ => match serde_json::from_str::<ErrorResponse>(&body).ok().and_then(|e| e.detail) {
+                // `detail` is a string or a {status, message} object
+                Some(serde_json::Value::String(message)) => TtsError::Api(message),
+                Some(detail) => TtsError::Api(detail["message"].as_str().map_or_else(|| detail.to_string(), str::to_string)),
+                None => TtsError::Api(format!("HTTP {}: {}", status, body)),
+            },
+        });
+    }
+
+    let data = response.bytes().await?.to_vec();
+    info!("Speech synthesis complete provider=elevenlabs bytes={}", data.len());
+    Ok(Speech { data, file_name: "speech.mp3" })
+}
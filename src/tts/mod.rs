@@ -0,0 +1,104 @@
+pub mod elevenlabs;
+pub mod openai;
+
+use crate::BotConfig;
+use thiserror::Error;
+
+/// Longest text read back in one voice message; OpenAI accepts up to 4096
+/// characters per request.
+pub const MAX_CHARS: usize = 4000;
+
+#[derive(Error, Debug)]
+pub enum TtsError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("API error: {0}")]
+    Api(String),
+    #[error("Authentication failed")]
+    Authentication,
+    #[error("Rate limit exceeded")]
+    RateLimit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtsProvider {
+    ElevenLabs,
+    OpenAi,
+}
+
+impl TtsProvider {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "elevenlabs" => Some(Self::ElevenLabs),
+            "openai" => Some(Self::OpenAi),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ElevenLabs => "elevenlabs",
+            Self::OpenAi => "openai",
+        }
+    }
+
+    /// API key the provider uses; shared with transcription.
+    pub fn api_key<'a>(&self, config: &'a BotConfig) -> Option<&'a str> {
+        match self {
+            Self::ElevenLabs => config.elevenlabs_api_key.as_deref(),
+            Self::OpenAi => config.openai_api_key.as_deref(),
+        }
+    }
+}
+
+/// Synthesized speech, ready to send as a voice message.
+pub struct Speech {
+    pub data: Vec<u8>,
+    pub file_name: &'static str,
+}
+
+/// Reads `text` aloud with the provider in `TTS_PROVIDER`.
+pub async fn synthesize(text: &str, provider: TtsProvider, config: &BotConfig) -> Result<Speech, TtsError> {
+    let api_key = provider.api_key(config).ok_or(TtsError::Authentication)?;
+    let voice = config.tts_voice.as_deref();
+    match provider {
+        TtsProvider::ElevenLabs => elevenlabs::synthesize(text, api_key, voice).await,
+        TtsProvider::OpenAi => openai::synthesize(text, &config.openai_base_url, api_key, voice).await,
+    }
+}
+
+/// The start of `text` that fits in one request, cut at a sentence or word
+/// boundary; true if anything was left out.
+pub fn truncate(text: &str, max_chars: usize) -> (&str, bool) {
+    let Some((end, _)) = text.char_indices().nth(max_chars) else {
+        return (text, false);
+    };
+    let head = &text[..end];
+    let cut = head
+        .rfind(['.', '!', '?', '\n'])
+        .map(|i| i + 1)
+        .filter(|&i| i > end / 2)
+        .or_else(|| head.rfind(char::is_whitespace))
+        .unwrap_or(end);
+    (head[..cut].trim_end(), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("Short enough.", 100), ("Short enough.", false));
+        assert_eq!(truncate("One sentence. Then another one", 25), ("One sentence.", true));
+        assert_eq!(truncate("no sentence ends in here at all", 20), ("no sentence ends in", true));
+        assert_eq!(truncate("привет мир", 8), ("привет", true));
+    }
+
+    #[test]
+    fn test_provider_names() {
+        assert_eq!(TtsProvider::from_str("OpenAI"), Some(TtsProvider::OpenAi));
+        assert_eq!(TtsProvider::from_str(TtsProvider::ElevenLabs.as_str()), Some(TtsProvider::ElevenLabs));
+        assert_eq!(TtsProvider::from_str("google"), None);
+    }
+}
@@ -0,0 +1,57 @@
+use super::{Speech, TtsError};
+use tracing::{debug, info};
+use serde::{Deserialize, Serialize};
+
+const MODEL: &str = "tts-1";
+const DEFAULT_VOICE: &str = "alloy";
+
+#[derive(Serialize)]
+struct SpeechRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+    voice: &'a str,
+    /// Ogg Opus, which Telegram plays as a voice message.
+    response_format: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    message: String,
+}
+
+/// Reads `text` aloud through the speech endpoint under `base_url`.
+pub async fn synthesize(text: &str, base_url: &str, api_key: &str, voice: Option<&str>) -> Result<Speech, TtsError> {
+    let voice = voice.unwrap_or(DEFAULT_VOICE);
+    info!("Starting speech synthesis provider=openai model={} voice={} chars={}", MODEL, voice, text.len());
+
+    let request = SpeechRequest { model: MODEL, input: text, voice, response_format: "opus" };
+    let response = crate::http::client()
+        .post(format!("{}/audio/speech", base_url))
+        .bearer_auth(api_key)
+        .json(&request)
+        .send()
+        .await?;
+
+    let status = response.status();
+    debug!("Speech response status: {}", status);
+    if !status.is_success() {
+        let body = response.text().await?;
+        return Err(match status.as_u16() {
+            401 => TtsError::Authentication,
+            429 => TtsError::RateLimit,
+            _ => match serde_json::from_str::<ErrorResponse>(&body) {
+                Ok(error) => TtsError::Api(error.error.message),
+                Err(_) => TtsError::Api(format!("HTTP {}: {}", status, body)),
+            },
+        });
+    }
+
+    let data = response.bytes().await?.to_vec();
+    info!("Speech synthesis complete provider=openai bytes={}", data.len());
+    Ok(Speech { data, file_name: "speech.ogg" })
+}